@@ -0,0 +1,149 @@
+//!
+//!
+//! Provides an opt-in, append-only audit log of vminfo queries
+//!
+//! Each query is recorded as a single JSON line so the log can be consumed by standard log tooling
+//!
+//!
+
+use std::{
+	fs::{self, OpenOptions},
+	io::Write,
+	path::PathBuf,
+};
+
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, VMInfoResult};
+
+///
+/// defines a single entry in the audit log
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditRecord {
+	/// RFC3339 timestamp (UTC) of when the query was run
+	pub timestamp: String,
+	/// the query operand(s) (hostnames or regular expression) submitted
+	pub operand: Vec<String>,
+	/// whether the query operand was interpreted as a regular expression
+	pub match_regexp: bool,
+	/// the Azure tenant the query was run against
+	pub tenant_id: String,
+	/// the identity (user principal name or app id) taken from the access token, if it could be determined
+	pub identity: Option<String>,
+	/// number of Virtual Machines returned from the query
+	pub result_count: u64,
+	/// whether the query completed successfully
+	pub success: bool,
+	/// the error message for failed queries
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+impl AuditRecord {
+	///
+	/// creates a new audit record timestamped with the current time
+	///
+	pub fn new(
+		operand: &[String],
+		match_regexp: bool,
+		tenant_id: &str,
+		identity: Option<String>,
+	) -> Self {
+		Self {
+			timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+			operand: operand.to_vec(),
+			match_regexp,
+			tenant_id: tenant_id.to_string(),
+			identity,
+			result_count: 0,
+			success: false,
+			error: None,
+		}
+	}
+}
+
+///
+/// an audit log which appends one JSON line per query to a file on disk
+///
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+	file_path: PathBuf,
+}
+
+impl AuditLog {
+	///
+	/// creates a new AuditLog which will write to the file at the provided path
+	///
+	pub fn new(path: &str) -> Self {
+		Self {
+			file_path: PathBuf::from(path),
+		}
+	}
+
+	///
+	/// appends a record to the audit log, creating the log file (and parent directories) if needed
+	///
+	pub fn append(&self, record: &AuditRecord) -> VMInfoResult<()> {
+		if let Some(parent) = self.file_path.parent() {
+			if !parent.as_os_str().is_empty() && !parent.exists() {
+				fs::create_dir_all(parent)
+					.map_err(|err| error::other(Some(err), "failed to create audit log directory path"))?;
+			}
+		}
+
+		let mut line = serde_json::to_string(record)
+			.map_err(|err| error::other(Some(err), "failed to generate JSON for audit record"))?;
+		line.push('\n');
+
+		let mut log_file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.file_path)
+			.map_err(|err| error::other(Some(err), "failed to open audit log file"))?;
+
+		log_file
+			.write_all(line.as_bytes())
+			.map_err(|err| error::other(Some(err), "failed to write record to audit log"))?;
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod audit_log_tests {
+	#[test]
+	fn appends_json_line_with_expected_fields() {
+		use super::{AuditLog, AuditRecord};
+
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("audit.log");
+		let log = AuditLog::new(path.to_str().unwrap());
+
+		let mut record = AuditRecord::new(
+			&["linux-01".to_string()],
+			false,
+			"tenant-xyz",
+			Some("someone@example.com".to_string()),
+		);
+		record.result_count = 1;
+		record.success = true;
+
+		log.append(&record).unwrap();
+		log.append(&record).unwrap();
+
+		let contents = std::fs::read_to_string(&path).unwrap();
+		let lines: Vec<&str> = contents.lines().collect();
+		assert_eq!(lines.len(), 2);
+
+		let entry: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+		assert_eq!(entry["operand"], serde_json::json!(["linux-01"]));
+		assert_eq!(entry["tenant_id"], "tenant-xyz");
+		assert_eq!(entry["identity"], "someone@example.com");
+		assert_eq!(entry["result_count"], 1);
+		assert_eq!(entry["success"], true);
+		assert!(entry["timestamp"].as_str().unwrap().ends_with('Z'));
+		assert!(entry.get("error").is_none());
+	}
+}
@@ -4,24 +4,66 @@
 //!
 //!
 
-use super::error::{auth, client_config, Error, VMInfoResult};
+use super::error::{auth, client_config, request, Error, VMInfoResult};
+use crate::cloud::AzureEnvironment;
 use crate::error::AuthErrorKind;
 use crate::AuthTokens;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
 use oauth2::basic::{
 	BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse, BasicTokenType,
 };
 use oauth2::devicecode::StandardDeviceAuthorizationResponse;
 use oauth2::{
-	basic::BasicClient, reqwest::http_client, AccessToken, AuthUrl, ClientId, ClientSecret,
-	ExtraTokenFields, RefreshToken, Scope, TokenResponse, TokenType, TokenUrl,
+	basic::BasicClient, AccessToken, AuthUrl, ClientCredentialsTokenRequest, ClientId, ClientSecret,
+	ExtraTokenFields, HttpRequest, HttpResponse, RefreshToken, Scope, TokenResponse, TokenType,
+	TokenUrl,
 };
 use oauth2::{
-	helpers, Client, DeviceAuthorizationUrl, EmptyExtraTokenFields, StandardRevocableToken,
-	StandardTokenResponse,
+	helpers, Client, DeviceAuthorizationUrl, EmptyExtraTokenFields, ErrorResponseType,
+	RequestTokenError, StandardErrorResponse, StandardRevocableToken, StandardTokenResponse,
 };
-use serde::{Deserialize, Serialize};
+use reqwest::tls::Version;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::io::Read;
 use std::time::Duration;
 
+///
+/// default tolerance applied when comparing token expiry times against the local clock
+///
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+///
+/// the v2.0 scope requested by the client_credentials flow when the v2.0 token endpoint of the public cloud is used
+///
+pub const MANAGEMENT_DEFAULT_SCOPE: &str = "https://management.azure.com/.default";
+
+///
+/// the managed identity token endpoint of the Azure Instance Metadata Service (IMDS), reachable only from Azure hosts
+///
+pub const IMDS_TOKEN_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+
+///
+/// the IMDS API version used for managed identity token requests
+///
+const IMDS_API_VERSION: &str = "2018-02-01";
+
+///
+/// the application (client) ID of the Azure CLI, which requests the tokens borrowed by login_azure_cli
+///
+pub const AZURE_CLI_CLIENT_ID: &str = "04b07795-8ddb-461a-bbee-02f9e1bf7b46";
+
+///
+/// the client_assertion_type of a JWT client assertion signed with a certificate
+///
+const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+///
+/// how long a client assertion may be used for after it is signed
+///
+const CLIENT_ASSERTION_LIFETIME_SECS: i64 = 600;
+
 ///
 /// Custom Token Response type to replace the StandardTokenResponse provided by oauth2-rs. This is required because Microsoft is not in compliance with the RFC spec for oauth2.0
 ///
@@ -35,8 +77,22 @@ where
 	#[serde(bound = "TT: TokenType")]
 	#[serde(deserialize_with = "helpers::deserialize_untagged_enum_case_insensitive")]
 	token_type: TT,
-	#[serde(skip_serializing_if = "Option::is_none")]
-	expires_in: Option<String>,
+	#[serde(
+		default,
+		deserialize_with = "deserialize_seconds",
+		skip_serializing_if = "Option::is_none"
+	)]
+	expires_in: Option<u64>,
+	///
+	/// the absolute expiry (seconds since the epoch) returned by the v1 token endpoint in place of (or alongside)
+	/// expires_in
+	///
+	#[serde(
+		default,
+		deserialize_with = "deserialize_seconds",
+		skip_serializing_if = "Option::is_none"
+	)]
+	expires_on: Option<u64>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	refresh_token: Option<RefreshToken>,
 	#[serde(rename = "scope")]
@@ -64,12 +120,25 @@ where
 			access_token,
 			token_type,
 			expires_in: None,
+			expires_on: None,
 			refresh_token: None,
 			scopes: None,
 			extra_fields,
 		}
 	}
 
+	///
+	/// when the access token expires. the absolute expires_on (v1 token endpoint) is preferred, otherwise the relative
+	/// expires_in (v2 token endpoint) is counted from now, so this should be read as soon as the response is received
+	///
+	pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+		match (self.expires_on, self.expires_in) {
+			(Some(expires_on), _) => Utc.timestamp_opt(expires_on as i64, 0).single(),
+			(None, Some(expires_in)) => Some(Utc::now() + chrono::Duration::seconds(expires_in as i64)),
+			(None, None) => None,
+		}
+	}
+
 	///
 	/// Sets the value of the access token in an instance of the 'AzureTokenResponse'
 	///
@@ -88,7 +157,8 @@ where
 	/// set the expire time for an instance of the 'AzureTokenResponse'
 	///
 	pub fn set_expires_in(&mut self, expires_in: Option<&Duration>) {
-		self.expires_in = expires_in.map(|exp| Duration::as_secs(exp).to_string());
+		self.expires_in = expires_in.map(Duration::as_secs);
+		self.expires_on = None;
 	}
 
 	///
@@ -138,14 +208,17 @@ where
 		&self.token_type
 	}
 	///
-	/// get the expire time for an 'AzureTokenResponse' as a 'Duration'
+	/// get the expire time for an 'AzureTokenResponse' as a 'Duration'. when only the absolute expires_on was returned
+	/// the time remaining until then is used
 	///
 	fn expires_in(&self) -> Option<Duration> {
-		self.expires_in.as_ref().map(|exp| {
-			let expires_in_number: u64 = exp.parse::<u64>().unwrap();
-
-			Duration::from_secs(expires_in_number)
-		})
+		match (self.expires_in, self.expires_on) {
+			(Some(expires_in), _) => Some(Duration::from_secs(expires_in)),
+			(None, Some(_)) => self
+				.expires_at()
+				.map(|expires_at| (expires_at - Utc::now()).to_std().unwrap_or(Duration::ZERO)),
+			(None, None) => None,
+		}
 	}
 	///
 	/// get the associated refresh token for an 'AzureTokenResponse'
@@ -170,16 +243,13 @@ where
 	/// defines a method to perform a conversion from a StandardTokenResponse to the custom 'AzureTokenResponse'
 	///
 	fn from(st: StandardTokenResponse<EF, TT>) -> Self {
-		let expire_time_string = st
-			.expires_in()
-			.map(|exp| Duration::as_secs(&exp).to_string());
-
 		let extra_fields: EF = st.extra_fields().clone();
 
 		AzureTokenResponse {
 			access_token: st.access_token().clone(),
 			token_type: st.token_type().clone(),
-			expires_in: expire_time_string,
+			expires_in: st.expires_in().map(|exp| exp.as_secs()),
+			expires_on: None,
 			refresh_token: st.refresh_token().map(|r| r.clone()),
 			scopes: st.scopes().map(|s| s.clone()),
 			extra_fields: extra_fields,
@@ -187,6 +257,32 @@ where
 	}
 }
 
+///
+/// deserializer for a number of seconds which Azure returns as either a JSON number (v2 token endpoint) or a string
+/// (v1 token endpoint)
+///
+fn deserialize_seconds<'de, D>(d: D) -> Result<Option<u64>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum Seconds {
+		Number(u64),
+		Text(String),
+	}
+
+	match Option::<Seconds>::deserialize(d)? {
+		Some(Seconds::Number(s)) => Ok(Some(s)),
+		Some(Seconds::Text(s)) => s
+			.trim()
+			.parse::<u64>()
+			.map(Some)
+			.map_err(serde::de::Error::custom),
+		None => Ok(None),
+	}
+}
+
 ///
 /// alias for AzureTokenResponse type
 ///
@@ -210,12 +306,19 @@ pub type AzureClient = Client<
 ///
 /// Defines the list of available authentication methods supported by lib_vminfo
 ///
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Method {
 	/// Devicecode interactive authentication method as defined by [RFC-8628](https://www.rfc-editor.org/rfc/rfc8628#section-3.4)
 	DeviceCode,
 	/// Client Credentials non-interactive authentication method as defined by [RFC-6749](https://www.rfc-editor.org/rfc/rfc6749#section-4.4)
 	ClientCredentials,
+	/// Managed Identity authentication method using the token endpoint of the Azure Instance Metadata Service
+	ManagedIdentity,
+	/// Azure CLI authentication method, borrowing the access token of the account logged in with `az login`
+	AzureCli,
+	/// Client Credentials authentication method using a certificate (a signed JWT client assertion) in place of a client
+	/// secret, as defined by [RFC-7523](https://www.rfc-editor.org/rfc/rfc7523#section-2.2)
+	ClientCertificate,
 }
 
 impl std::fmt::Display for Method {
@@ -223,6 +326,9 @@ impl std::fmt::Display for Method {
 		match *self {
 			Method::DeviceCode => write!(f, "DeviceCode"),
 			Method::ClientCredentials => write!(f, "ClientCredentials"),
+			Method::ManagedIdentity => write!(f, "ManagedIdentity"),
+			Method::AzureCli => write!(f, "AzureCli"),
+			Method::ClientCertificate => write!(f, "ClientCertificate"),
 		}
 	}
 }
@@ -240,10 +346,181 @@ pub struct AzCredentials {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	/// OPTIONAL: Azure Client Password
 	pub client_secret: Option<String>,
+	/// OPTIONAL: the certificate used to authenticate in place of a client secret (only its path is stored)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub certificate: Option<ClientCertificate>,
+	/// OPTIONAL: the method the credentials were obtained with, when it can not be told from the stored secrets (ie:
+	/// ManagedIdentity, which stores none)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub method: Option<Method>,
 	/// Relevant Azure Access and Refresh Tokens
 	pub tokens: AuthTokens,
 }
 
+///
+/// a certificate registered with an app registration, used to sign client assertions in place of a client secret
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ClientCertificate {
+	/// path to a PEM file holding the certificate (and its private key, unless private_key_path is set)
+	pub certificate_path: String,
+	/// OPTIONAL: path to a PEM file holding the private key of the certificate
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub private_key_path: Option<String>,
+}
+
+impl ClientCertificate {
+	///
+	/// creates a certificate read from the provided PEM file(s)
+	///
+	pub fn new(certificate_path: &str, private_key_path: Option<&str>) -> Self {
+		Self {
+			certificate_path: certificate_path.to_string(),
+			private_key_path: private_key_path.map(String::from),
+		}
+	}
+}
+
+///
+/// Defines the subset of claims vminfo reads from an Azure access token (JWT)
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TokenClaims {
+	/// the tenant that issued the token
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub tid: Option<String>,
+	/// the object ID of the authenticated principal
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub oid: Option<String>,
+	/// the user principal name (user tokens only)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub upn: Option<String>,
+	/// the unique name of the principal (v1.0 user tokens)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub unique_name: Option<String>,
+	/// the application (client) ID that requested the token
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub appid: Option<String>,
+	/// the expiry time of the token in seconds since the unix epoch
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub exp: Option<i64>,
+}
+
+impl TokenClaims {
+	///
+	/// gets the most human readable identity available in the claims (upn > unique_name > appid > oid)
+	///
+	pub fn identity(&self) -> Option<String> {
+		self
+			.upn
+			.clone()
+			.or_else(|| self.unique_name.clone())
+			.or_else(|| self.appid.clone())
+			.or_else(|| self.oid.clone())
+	}
+
+	///
+	/// gets the expiry time of the token (if the exp claim is present)
+	///
+	pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+		self.exp.and_then(|exp| Utc.timestamp_opt(exp, 0).single())
+	}
+}
+
+///
+/// determines whether a token expiring at `expires_at` should be treated as expired, allowing for `skew` of clock drift
+///
+/// tokens expiring within the skew window are considered expired so they are refreshed before Azure starts rejecting them
+///
+pub fn is_expired(expires_at: &DateTime<Utc>, skew: Duration) -> bool {
+	let skew = chrono::Duration::from_std(skew).unwrap_or_else(|_| chrono::Duration::zero());
+
+	Utc::now() + skew >= *expires_at
+}
+
+///
+/// determines whether a token that was rejected as expired by Azure still appears valid beyond the skew tolerance
+/// according to the local clock, which indicates that the local clock is out of sync
+///
+pub fn is_clock_skewed(expires_at: &DateTime<Utc>, skew: Duration) -> bool {
+	let skew = chrono::Duration::from_std(skew).unwrap_or_else(|_| chrono::Duration::zero());
+
+	Utc::now() + skew < *expires_at
+}
+
+///
+/// decodes the claims from the payload of an access token **without** verifying its signature
+///
+/// this is only suitable for reading informational claims from a token that was obtained directly from Azure
+///
+pub fn decode_token_claims(access_token: &str) -> VMInfoResult<TokenClaims> {
+	let payload = match access_token.split('.').nth(1) {
+		Some(p) => p,
+		_ => Err(auth(
+			None::<Error>,
+			AuthErrorKind::BadCredentials,
+			"access token is not a valid JWT",
+		))?,
+	};
+
+	let decoded = URL_SAFE_NO_PAD
+		.decode(payload.trim_end_matches('='))
+		.map_err(|err| {
+			auth(
+				Some(err),
+				AuthErrorKind::BadCredentials,
+				"could not decode access token payload",
+			)
+		})?;
+
+	serde_json::from_slice::<TokenClaims>(&decoded).map_err(|err| {
+		auth(
+			Some(err),
+			AuthErrorKind::BadCredentials,
+			"could not parse access token claims",
+		)
+	})
+}
+
+///
+/// determines whether a value is a GUID in its canonical hyphenated form (ie: an Azure tenant or client ID)
+///
+pub fn is_guid(value: &str) -> bool {
+	let groups: Vec<&str> = value.split('-').collect();
+
+	groups.len() == 5
+		&& groups
+			.iter()
+			.zip([8, 4, 4, 4, 12])
+			.all(|(g, len)| g.len() == len && g.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+///
+/// checks that an access token was issued by the configured tenant (its tid claim), so that a token left over from
+/// another tenant is never used to query the wrong tenant's resources
+///
+/// tokens which can not be decoded or carry no tid claim, and tenants configured by domain name rather than ID, are
+/// not checked
+///
+pub fn verify_token_tenant(access_token: &str, tenant_id: &str) -> VMInfoResult<()> {
+	if !is_guid(tenant_id) {
+		return Ok(());
+	}
+
+	match decode_token_claims(access_token).ok().and_then(|c| c.tid) {
+		Some(tid) if !tid.eq_ignore_ascii_case(tenant_id) => Err(auth(
+			None::<Error>,
+			AuthErrorKind::BadCredentials,
+			format!(
+				"access token was issued for tenant {} but tenant {} is configured. please login again",
+				tid, tenant_id
+			)
+			.as_str(),
+		)),
+		_ => Ok(()),
+	}
+}
+
 ///
 /// Authentication configuration object
 ///
@@ -257,6 +534,20 @@ pub struct Configuration {
 	pub client_secret: Option<String>,
 	/// A list of resource/API scopes to ask for from the authorization server
 	pub scopes: Vec<Scope>,
+	/// The minimum TLS version allowed for connections to the authorization server
+	pub min_tls_version: Version,
+	/// The interval between polls for a device code access token (when not set, the interval suggested by the
+	/// authorization server is used)
+	pub poll_interval: Option<Duration>,
+	/// Whether the client_credentials flow uses the v2.0 token endpoint with the '.default' scope instead of the v1.0
+	/// endpoint with a resource, so that both flows obtain tokens from the same v2.0 endpoint
+	pub use_v2_endpoint: bool,
+	/// (optionally) a certificate used to sign client assertions for non-interactive authentication without a secret
+	pub client_certificate: Option<ClientCertificate>,
+	/// the Azure cloud whose authority tokens are requested from, and whose management resource they are issued for
+	pub environment: AzureEnvironment,
+	/// (optionally) the managed identity token endpoint used in place of IMDS_TOKEN_ENDPOINT (ie: a local mock of IMDS)
+	pub imds_endpoint: Option<String>,
 }
 
 impl Configuration {
@@ -269,8 +560,62 @@ impl Configuration {
 			client_id: client_id.to_string(),
 			client_secret: client_secret.to_owned(),
 			scopes: Configuration::default().scopes,
+			min_tls_version: crate::transport::DEFAULT_MIN_TLS_VERSION,
+			poll_interval: None,
+			use_v2_endpoint: false,
+			client_certificate: None,
+			environment: AzureEnvironment::Public,
+			imds_endpoint: None,
 		}
 	}
+
+	///
+	/// sets the minimum TLS version allowed for connections to the authorization server
+	///
+	pub fn with_min_tls_version(mut self, min_tls_version: Version) -> Self {
+		self.min_tls_version = min_tls_version;
+		self
+	}
+
+	///
+	/// sets the interval between polls for a device code access token
+	///
+	pub fn with_poll_interval(mut self, poll_interval: Option<Duration>) -> Self {
+		self.poll_interval = poll_interval;
+		self
+	}
+
+	///
+	/// sets whether the client_credentials flow uses the v2.0 token endpoint and scope (default: v1.0 and resource)
+	///
+	pub fn with_v2_endpoint(mut self, use_v2_endpoint: bool) -> Self {
+		self.use_v2_endpoint = use_v2_endpoint;
+		self
+	}
+
+	///
+	/// sets the certificate used by the client_credentials flow in place of a client secret
+	///
+	pub fn with_client_certificate(mut self, client_certificate: Option<ClientCertificate>) -> Self {
+		self.client_certificate = client_certificate;
+		self
+	}
+
+	///
+	/// sets the Azure cloud to authenticate against (default: the public cloud)
+	///
+	pub fn with_environment(mut self, environment: AzureEnvironment) -> Self {
+		self.environment = environment;
+		self
+	}
+
+	///
+	/// sets the managed identity token endpoint used in place of the Azure Instance Metadata Service (None uses IMDS)
+	///
+	pub fn with_imds_endpoint(mut self, imds_endpoint: Option<String>) -> Self {
+		self.imds_endpoint = imds_endpoint;
+		self
+	}
 }
 
 impl Default for Configuration {
@@ -279,13 +624,97 @@ impl Default for Configuration {
 			tenant_id: "XXX".to_string(),
 			client_id: "XXX".to_string(),
 			client_secret: Some("XXX".to_string()),
-			scopes: vec![Scope::new(
-				"https://management.core.windows.net/".to_string(),
-			)],
+			scopes: vec![Scope::new(AzureEnvironment::Public.resource().to_string())],
+			min_tls_version: crate::transport::DEFAULT_MIN_TLS_VERSION,
+			poll_interval: None,
+			use_v2_endpoint: false,
+			client_certificate: None,
+			environment: AzureEnvironment::Public,
+			imds_endpoint: None,
+		}
+	}
+}
+
+///
+/// builds the HTTP client used for requests to the authorization server, refusing TLS versions older than
+/// min_tls_version. like the oauth2 http_client, redirects are never followed
+///
+fn http_client(
+	min_tls_version: Version,
+) -> impl Fn(HttpRequest) -> Result<HttpResponse, oauth2::reqwest::Error<reqwest::Error>> {
+	move |req: HttpRequest| {
+		let client = crate::transport::http_client_builder(min_tls_version)
+			.redirect(reqwest::redirect::Policy::none())
+			.build()
+			.map_err(oauth2::reqwest::Error::Reqwest)?;
+
+		let mut request_builder = client.request(req.method, req.url.as_str()).body(req.body);
+		for (name, value) in &req.headers {
+			request_builder = request_builder.header(name.as_str(), value.as_bytes());
 		}
+
+		let mut resp = client
+			.execute(
+				request_builder
+					.build()
+					.map_err(oauth2::reqwest::Error::Reqwest)?,
+			)
+			.map_err(oauth2::reqwest::Error::Reqwest)?;
+
+		let mut body = Vec::new();
+		resp
+			.read_to_end(&mut body)
+			.map_err(oauth2::reqwest::Error::Io)?;
+
+		Ok(HttpResponse {
+			status_code: resp.status(),
+			headers: resp.headers().to_owned(),
+			body,
+		})
 	}
 }
 
+///
+/// the non-blocking equivalent of http_client, used by the async authorization flows
+///
+#[cfg(feature = "async")]
+async fn async_http_client(
+	min_tls_version: Version,
+	req: HttpRequest,
+) -> Result<HttpResponse, oauth2::reqwest::Error<reqwest::Error>> {
+	let client = crate::transport::async_http_client_builder(min_tls_version)
+		.redirect(reqwest::redirect::Policy::none())
+		.build()
+		.map_err(oauth2::reqwest::Error::Reqwest)?;
+
+	let mut request_builder = client.request(req.method, req.url.as_str()).body(req.body);
+	for (name, value) in &req.headers {
+		request_builder = request_builder.header(name.as_str(), value.as_bytes());
+	}
+
+	let resp = client
+		.execute(
+			request_builder
+				.build()
+				.map_err(oauth2::reqwest::Error::Reqwest)?,
+		)
+		.await
+		.map_err(oauth2::reqwest::Error::Reqwest)?;
+
+	let status_code = resp.status();
+	let headers = resp.headers().to_owned();
+	let body = resp
+		.bytes()
+		.await
+		.map_err(oauth2::reqwest::Error::Reqwest)?;
+
+	Ok(HttpResponse {
+		status_code,
+		headers,
+		body: body.to_vec(),
+	})
+}
+
 ///
 /// performs a non-interactive login using a client_id and password (secret)
 ///
@@ -298,17 +727,55 @@ impl Default for Configuration {
 /// ```
 ///
 pub fn login_non_interactive(conf: &Configuration) -> VMInfoResult<AuthTokens> {
-	let token_url: String = format!(
-		"https://login.microsoftonline.com/{}/oauth2/token",
-		conf.tenant_id
-	);
+	request_client_credentials(conf, http_client(conf.min_tls_version))
+}
+
+///
+/// performs a non-interactive login like login_non_interactive, without blocking the calling thread
+///
+#[cfg(feature = "async")]
+pub async fn login_non_interactive_async(conf: &Configuration) -> VMInfoResult<AuthTokens> {
+	let client = client_credentials_client(conf)?;
+	let token_result = client_credentials_request(&client, conf)
+		.request_async(|req| async_http_client(conf.min_tls_version, req))
+		.await
+		.map_err(client_credentials_error)?;
+
+	Ok(issued_tokens(&token_result))
+}
+
+///
+/// requests tokens with the client_credentials flow using the provided http client. the v1.0 endpoint is sent the
+/// management resource, while the v2.0 endpoint is sent the management '.default' scope
+///
+fn request_client_credentials<F, RE>(
+	conf: &Configuration,
+	http_client: F,
+) -> VMInfoResult<AuthTokens>
+where
+	F: FnOnce(HttpRequest) -> Result<HttpResponse, RE>,
+	RE: std::error::Error + Send + Sync + 'static,
+{
+	let client = client_credentials_client(conf)?;
+	let token_result = client_credentials_request(&client, conf)
+		.request(http_client)
+		.map_err(client_credentials_error)?;
+
+	Ok(issued_tokens(&token_result))
+}
+
+///
+/// creates the oauth2 client for the client_credentials flow, using the v1.0 or v2.0 token endpoint
+///
+fn client_credentials_client(conf: &Configuration) -> VMInfoResult<AzureClient> {
+	let token_url: String = client_credentials_token_url(conf);
 
 	let client_secret: Option<ClientSecret> = match &conf.client_secret {
 		Some(secret) => Some(ClientSecret::new(secret.clone())),
 		_ => None,
 	};
 
-	let client = AzureClient::new(
+	Ok(AzureClient::new(
 		ClientId::new(conf.client_id.clone()),
 		client_secret,
 		AuthUrl::new("http://authorize/".to_string()).map_err(|err| {
@@ -325,26 +792,459 @@ pub fn login_non_interactive(conf: &Configuration) -> VMInfoResult<AuthTokens> {
 				"could not parse token url. it is likely invalid",
 			)
 		})?),
-	);
+	))
+}
 
-	let token_result = client
-		.exchange_client_credentials()
-		.add_extra_param("resource", "https://management.core.windows.net/")
-		.request(http_client)
-		.map_err(|err| {
-			auth(
-				Some(err),
-				AuthErrorKind::BadCredentials,
-				"invalid tenant_id and client_id or secret combination provided",
-			)
-		})?;
+///
+/// the v1.0 or v2.0 token endpoint of the tenant
+///
+fn client_credentials_token_url(conf: &Configuration) -> String {
+	if conf.use_v2_endpoint {
+		format!(
+			"{}/{}/oauth2/v2.0/token",
+			conf.environment.login_authority(),
+			conf.tenant_id
+		)
+	} else {
+		format!(
+			"{}/{}/oauth2/token",
+			conf.environment.login_authority(),
+			conf.tenant_id
+		)
+	}
+}
 
-	Ok(AuthTokens {
+///
+/// builds a client_credentials token request. the v1.0 endpoint is sent the management resource, while the v2.0
+/// endpoint is sent the management '.default' scope
+///
+fn client_credentials_request<'a>(
+	client: &'a AzureClient,
+	conf: &Configuration,
+) -> ClientCredentialsTokenRequest<'a, BasicErrorResponse, BasicAzureTokenResponse, BasicTokenType>
+{
+	let token_request = client.exchange_client_credentials();
+	if conf.use_v2_endpoint {
+		token_request.add_scope(Scope::new(conf.environment.default_scope()))
+	} else {
+		token_request.add_extra_param("resource", conf.environment.resource())
+	}
+}
+
+///
+/// the access token (and refresh token, if one was issued) from a token response
+///
+fn issued_tokens(token_result: &BasicAzureTokenResponse) -> AuthTokens {
+	AuthTokens {
 		access_token: token_result.access_token().secret().to_owned(),
 		refresh_token: match token_result.refresh_token() {
 			Some(rt) => Some(rt.secret().to_owned()),
 			_ => None,
 		},
+		expires_at: token_result.expires_at(),
+	}
+}
+
+///
+/// maps a failed client_credentials token request to a vminfo Error
+///
+/// any response from the authorization server is treated as a credential problem, while failures to reach the server
+/// at all are reported as request errors so they are not mistaken for a bad secret
+///
+fn client_credentials_error<RE>(err: RequestTokenError<RE, BasicErrorResponse>) -> Error
+where
+	RE: std::error::Error + Send + Sync + 'static,
+{
+	match err {
+		RequestTokenError::Request(_) => request(
+			Some(err),
+			None,
+			"failed to reach the Azure authorization server",
+		),
+		_ => oauth_error(
+			err,
+			AuthErrorKind::BadCredentials,
+			"invalid tenant_id and client_id or secret combination provided",
+		),
+	}
+}
+
+///
+/// builds an authentication error for a failed token request. when the authorization server responded with an OAuth2
+/// error, its code (ie: invalid_grant or consent_required) is kept on the error and added to the message along with
+/// the first line of its description
+///
+fn oauth_error<RE, T>(
+	err: RequestTokenError<RE, StandardErrorResponse<T>>,
+	kind: AuthErrorKind,
+	message: &str,
+) -> Error
+where
+	RE: std::error::Error + Send + Sync + 'static,
+	T: ErrorResponseType + AsRef<str> + Send + Sync + 'static,
+{
+	let (code, description) = match &err {
+		RequestTokenError::ServerResponse(resp) => (
+			resp.error().as_ref().to_string(),
+			resp
+				.error_description()
+				.and_then(|d| d.lines().next())
+				.map(|d| d.trim().to_string()),
+		),
+		_ => return auth(Some(err), kind, message),
+	};
+
+	let message = match description {
+		Some(description) => format!("{} ({}: {})", message, code, description),
+		None => format!("{} ({})", message, code),
+	};
+
+	auth(Some(err), kind, &message).with_oauth_error_code(&code)
+}
+
+///
+/// performs a non-interactive login using a client_id and a certificate, like login_non_interactive without a secret.
+/// a JWT client assertion signed (RS256) with the private key of the certificate is sent in place of the client secret
+///
+/// ## Example
+///
+/// ```ignore
+/// let tokens = auth::login_client_credentials_cert(
+///     &Configuration::new(tenant_id, client_id, &None)
+///         .with_client_certificate(Some(ClientCertificate::new("/etc/vminfo/sp.pem", None))),
+/// )?;
+///
+/// println!("{:?}", tokens);
+/// ```
+///
+pub fn login_client_credentials_cert(conf: &Configuration) -> VMInfoResult<AuthTokens> {
+	request_client_credentials_cert(conf, http_client(conf.min_tls_version))
+}
+
+///
+/// requests tokens with the client_credentials flow using a signed client assertion and the provided http client
+///
+fn request_client_credentials_cert<F, RE>(
+	conf: &Configuration,
+	http_client: F,
+) -> VMInfoResult<AuthTokens>
+where
+	F: FnOnce(HttpRequest) -> Result<HttpResponse, RE>,
+	RE: std::error::Error + Send + Sync + 'static,
+{
+	let certificate = conf.client_certificate.as_ref().ok_or_else(|| {
+		client_config(
+			None::<Error>,
+			"no client certificate was configured for certificate authentication",
+		)
+	})?;
+	let assertion = client_assertion(
+		certificate,
+		&conf.client_id,
+		&client_credentials_token_url(conf),
+		Utc::now(),
+	)?;
+
+	// the client is built without a secret, so only the client_id is sent alongside the assertion
+	let conf = Configuration {
+		client_secret: None,
+		..conf.clone()
+	};
+	let client = client_credentials_client(&conf)?;
+	let token_result = client_credentials_request(&client, &conf)
+		.add_extra_param("client_assertion_type", CLIENT_ASSERTION_TYPE)
+		.add_extra_param("client_assertion", assertion)
+		.request(http_client)
+		.map_err(client_credentials_error)?;
+
+	Ok(issued_tokens(&token_result))
+}
+
+///
+/// builds a JWT client assertion for the token endpoint (audience), signed with the private key of the certificate. the
+/// SHA-1 thumbprint of the certificate (x5t) tells Azure which registered certificate verifies it
+///
+fn client_assertion(
+	certificate: &ClientCertificate,
+	client_id: &str,
+	audience: &str,
+	now: DateTime<Utc>,
+) -> VMInfoResult<String> {
+	use openssl::hash::MessageDigest;
+	use openssl::pkey::PKey;
+	use openssl::sign::Signer;
+	use openssl::x509::X509;
+
+	let read_pem = |path: &str| {
+		std::fs::read(path).map_err(|err| {
+			client_config(
+				Some(err),
+				format!("could not read the client certificate file '{}'", path).as_str(),
+			)
+		})
+	};
+	let certificate_pem = read_pem(&certificate.certificate_path)?;
+	let key_pem = match &certificate.private_key_path {
+		Some(path) => read_pem(path)?,
+		None => certificate_pem.clone(),
+	};
+
+	let x509 = X509::from_pem(&certificate_pem).map_err(|err| {
+		client_config(
+			Some(err),
+			"could not parse the PEM encoded client certificate",
+		)
+	})?;
+	let key = PKey::private_key_from_pem(&key_pem).map_err(|err| {
+		client_config(
+			Some(err),
+			"could not parse the PEM encoded private key of the client certificate",
+		)
+	})?;
+	let thumbprint = x509.digest(MessageDigest::sha1()).map_err(|err| {
+		client_config(
+			Some(err),
+			"could not compute the client certificate thumbprint",
+		)
+	})?;
+
+	let header = serde_json::json!({
+		"alg": "RS256",
+		"typ": "JWT",
+		"x5t": URL_SAFE_NO_PAD.encode(thumbprint),
+	});
+	let claims = serde_json::json!({
+		"aud": audience,
+		"iss": client_id,
+		"sub": client_id,
+		"jti": format!("{:032x}", rand::random::<u128>()),
+		"nbf": now.timestamp(),
+		"exp": now.timestamp() + CLIENT_ASSERTION_LIFETIME_SECS,
+	});
+	let signing_input = format!(
+		"{}.{}",
+		URL_SAFE_NO_PAD.encode(header.to_string()),
+		URL_SAFE_NO_PAD.encode(claims.to_string())
+	);
+
+	let signature = Signer::new(MessageDigest::sha256(), &key)
+		.and_then(|mut signer| {
+			signer.update(signing_input.as_bytes())?;
+			signer.sign_to_vec()
+		})
+		.map_err(|err| client_config(Some(err), "could not sign the client assertion"))?;
+
+	Ok(format!(
+		"{}.{}",
+		signing_input,
+		URL_SAFE_NO_PAD.encode(signature)
+	))
+}
+
+///
+/// the subset of an IMDS managed identity token response vminfo reads (IMDS sends expires_in as a string, so the
+/// response is not a standard OAuth2 token response)
+///
+#[derive(Debug, Deserialize)]
+struct ManagedIdentityToken {
+	access_token: String,
+	#[serde(default, deserialize_with = "deserialize_seconds")]
+	expires_in: Option<u64>,
+	#[serde(default, deserialize_with = "deserialize_seconds")]
+	expires_on: Option<u64>,
+}
+
+///
+/// performs a non-interactive login with the managed identity of the Azure host (VM, function, container) this runs on,
+/// using the Instance Metadata Service. no secret is needed and no refresh token is issued
+///
+/// a user-assigned identity is selected by setting the client_id of the configuration to its client ID. any other
+/// client_id (ie: an empty one) uses the system-assigned identity
+///
+/// ## Example
+///
+/// ```ignore
+/// let tokens = auth::login_managed_identity(&Configuration::new("", "", &None))?;
+///
+/// println!("{:?}", tokens);
+/// ```
+///
+pub fn login_managed_identity(conf: &Configuration) -> VMInfoResult<AuthTokens> {
+	request_managed_identity(conf, http_client(conf.min_tls_version))
+}
+
+///
+/// requests a management token from IMDS using the provided http client
+///
+fn request_managed_identity<F, RE>(conf: &Configuration, http_client: F) -> VMInfoResult<AuthTokens>
+where
+	F: FnOnce(HttpRequest) -> Result<HttpResponse, RE>,
+	RE: std::error::Error + Send + Sync + 'static,
+{
+	let endpoint = conf.imds_endpoint.as_deref().unwrap_or(IMDS_TOKEN_ENDPOINT);
+	let mut url = oauth2::url::Url::parse(endpoint)
+		.map_err(|err| client_config(Some(err), "invalid managed identity token endpoint"))?;
+	url
+		.query_pairs_mut()
+		.append_pair("api-version", IMDS_API_VERSION)
+		.append_pair("resource", conf.environment.resource());
+	if is_guid(&conf.client_id) {
+		url
+			.query_pairs_mut()
+			.append_pair("client_id", &conf.client_id);
+	}
+
+	let mut headers = oauth2::http::HeaderMap::new();
+	headers.insert("Metadata", oauth2::http::HeaderValue::from_static("true"));
+
+	let resp = http_client(HttpRequest {
+		url,
+		method: oauth2::http::Method::GET,
+		headers,
+		body: Vec::new(),
+	})
+	.map_err(|err| {
+		request(
+			Some(err),
+			None,
+			"could not reach the Azure Instance Metadata Service. managed identity login is only available on Azure hosts",
+		)
+	})?;
+
+	if !resp.status_code.is_success() {
+		let err: RequestTokenError<RE, BasicErrorResponse> =
+			match serde_json::from_slice::<BasicErrorResponse>(&resp.body) {
+				Ok(error_response) => RequestTokenError::ServerResponse(error_response),
+				Err(_) => {
+					RequestTokenError::Other(format!("unexpected response status {}", resp.status_code))
+				}
+			};
+		return Err(oauth_error(
+			err,
+			AuthErrorKind::BadCredentials,
+			"failed to obtain an access token for the managed identity",
+		));
+	}
+
+	let token: ManagedIdentityToken = serde_json::from_slice(&resp.body).map_err(|err| {
+		auth(
+			Some(err),
+			AuthErrorKind::BadRequest,
+			"could not parse the managed identity token response",
+		)
+	})?;
+
+	Ok(AuthTokens {
+		access_token: token.access_token,
+		refresh_token: None,
+		expires_at: match (token.expires_on, token.expires_in) {
+			(Some(expires_on), _) => Utc.timestamp_opt(expires_on as i64, 0).single(),
+			(None, Some(expires_in)) => Some(Utc::now() + chrono::Duration::seconds(expires_in as i64)),
+			(None, None) => None,
+		},
+	})
+}
+
+///
+/// the subset of `az account get-access-token` output vminfo reads. expiresOn is a local time, while expires_on (a unix
+/// timestamp) is only output by newer versions of the Azure CLI
+///
+#[derive(Debug, Deserialize)]
+struct AzureCliToken {
+	#[serde(rename = "accessToken")]
+	access_token: String,
+	#[serde(rename = "expiresOn")]
+	expires_on_local: Option<String>,
+	expires_on: Option<i64>,
+}
+
+///
+/// borrows the access token of the account logged in to the Azure CLI by running `az account get-access-token`. the
+/// tenant of the configuration is requested when it is a tenant ID, otherwise the default tenant of the Azure CLI is
+/// used
+///
+/// the Azure CLI refreshes its own tokens, so no refresh token is returned. a missing `az` binary, a failed command or
+/// an expired token is reported as BadCredentials, which is resolved by running `az login`
+///
+/// ## Example
+///
+/// ```ignore
+/// let tokens = auth::login_azure_cli(&Configuration::new("", AZURE_CLI_CLIENT_ID, &None))?;
+///
+/// println!("{:?}", tokens);
+/// ```
+///
+pub fn login_azure_cli(conf: &Configuration) -> VMInfoResult<AuthTokens> {
+	let mut command = std::process::Command::new("az");
+	command.args([
+		"account",
+		"get-access-token",
+		"--resource",
+		conf.environment.resource(),
+		"--output",
+		"json",
+	]);
+	if is_guid(&conf.tenant_id) {
+		command.args(["--tenant", &conf.tenant_id]);
+	}
+
+	let output = command.output().map_err(|err| {
+		auth(
+			Some(err),
+			AuthErrorKind::BadCredentials,
+			"the Azure CLI ('az') could not be run. install it and run 'az login'",
+		)
+	})?;
+
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		return Err(auth(
+			None::<Error>,
+			AuthErrorKind::BadCredentials,
+			format!(
+				"'az account get-access-token' failed, run 'az login' and try again: {}",
+				stderr.lines().next().unwrap_or("").trim()
+			)
+			.as_str(),
+		));
+	}
+
+	azure_cli_tokens(&output.stdout, Utc::now())
+}
+
+///
+/// reads the access token from `az account get-access-token` output, refusing a token which expired before now
+///
+fn azure_cli_tokens(output: &[u8], now: DateTime<Utc>) -> VMInfoResult<AuthTokens> {
+	let token: AzureCliToken = serde_json::from_slice(output).map_err(|err| {
+		auth(
+			Some(err),
+			AuthErrorKind::BadRequest,
+			"could not parse the access token returned by the Azure CLI",
+		)
+	})?;
+
+	let expires_at: Option<DateTime<Utc>> = match (token.expires_on, &token.expires_on_local) {
+		(Some(timestamp), _) => Utc.timestamp_opt(timestamp, 0).single(),
+		(None, Some(local)) => chrono::NaiveDateTime::parse_from_str(local, "%Y-%m-%d %H:%M:%S%.f")
+			.ok()
+			.and_then(|naive| chrono::Local.from_local_datetime(&naive).single())
+			.map(|expires_at| expires_at.with_timezone(&Utc)),
+		(None, None) => None,
+	};
+
+	if matches!(expires_at, Some(expires_at) if expires_at <= now) {
+		return Err(auth(
+			None::<Error>,
+			AuthErrorKind::BadCredentials,
+			"the Azure CLI access token has expired, run 'az login' and try again",
+		));
+	}
+
+	Ok(AuthTokens {
+		access_token: token.access_token,
+		refresh_token: None,
+		expires_at,
 	})
 }
 
@@ -361,12 +1261,14 @@ pub fn login_non_interactive(conf: &Configuration) -> VMInfoResult<AuthTokens> {
 ///
 pub fn login_interactive(conf: &Configuration) -> VMInfoResult<AuthTokens> {
 	let token_url: String = format!(
-		"https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+		"{}/{}/oauth2/v2.0/token",
+		conf.environment.login_authority(),
 		conf.tenant_id
 	);
 
 	let device_code_url = DeviceAuthorizationUrl::new(format!(
-		"https://login.microsoftonline.com/{}/oauth2/v2.0/devicecode",
+		"{}/{}/oauth2/v2.0/devicecode",
+		conf.environment.login_authority(),
 		conf.tenant_id
 	))
 	.map_err(|err| {
@@ -401,13 +1303,13 @@ pub fn login_interactive(conf: &Configuration) -> VMInfoResult<AuthTokens> {
 			)
 		})?
 		.add_scopes(vec![
-			Scope::new("https://management.core.windows.net/user_impersonation".to_string()),
+			Scope::new(conf.environment.user_impersonation_scope()),
 			Scope::new("offline_access".to_string()),
 		])
-		.request(http_client)
+		.request(http_client(conf.min_tls_version))
 		.map_err(|err| {
-			auth(
-				Some(err),
+			oauth_error(
+				err,
 				AuthErrorKind::BadRequest,
 				"failed to get device code details for interactive authentication",
 			)
@@ -422,11 +1324,15 @@ pub fn login_interactive(conf: &Configuration) -> VMInfoResult<AuthTokens> {
 	let token_req_result = client
 		.exchange_device_access_token(&details)
 		.add_extra_param("code", details.device_code().secret().to_string())
-		.request(http_client, std::thread::sleep, None);
+		.request(
+			http_client(conf.min_tls_version),
+			|interval| std::thread::sleep(poll_interval(interval, conf.poll_interval)),
+			None,
+		);
 
 	let token_result = token_req_result.map_err(|err| {
-		auth(
-			Some(err),
+		oauth_error(
+			err,
 			AuthErrorKind::BadRequest,
 			"could not authenticate user with devicecode auth",
 		)
@@ -438,16 +1344,31 @@ pub fn login_interactive(conf: &Configuration) -> VMInfoResult<AuthTokens> {
 			Some(rt) => Some(rt.secret().to_owned()),
 			_ => None,
 		},
+		expires_at: token_result
+			.expires_in()
+			.and_then(|expires_in| chrono::Duration::from_std(expires_in).ok())
+			.map(|expires_in| Utc::now() + expires_in),
 	})
 }
 
+///
+/// the time to wait before the next device code access token poll. a configured interval is used unless the
+/// authorization server asks for a longer one (ie: after a slow_down response)
+///
+fn poll_interval(server_interval: Duration, configured: Option<Duration>) -> Duration {
+	match configured {
+		Some(configured) => configured.max(server_interval),
+		None => server_interval,
+	}
+}
+
 ///
 /// performs a token refresh provided a valid refresh token
 ///
 /// ## Example
 ///
 /// ```ignore
-/// let tokens = auth::exchange_refresh_tokens("XXXXXXX-XXXXXX-XXXX-XXX", "XXXXXX-XXXX-XXX-XX", Some("ABC".to_string()))?;
+/// let tokens = auth::exchange_refresh_tokens("XXXXXXX-XXXXXX-XXXX-XXX", "XXXXXX-XXXX-XXX-XX", Some("ABC".to_string()), Version::TLS_1_2, AzureEnvironment::Public)?;
 ///
 /// println!("{:?}", tokens);
 /// ```
@@ -456,19 +1377,64 @@ pub fn exchange_refresh_tokens(
 	tenant_id: &str,
 	client_id: &str,
 	refresh_token: Option<String>,
+	min_tls_version: Version,
+	environment: AzureEnvironment,
+) -> VMInfoResult<AuthTokens> {
+	let (client, refresh_token) =
+		refresh_token_client(tenant_id, client_id, refresh_token, environment)?;
+	let token_result = client
+		.exchange_refresh_token(&refresh_token)
+		.request(http_client(min_tls_version))
+		.map_err(refresh_token_error)?;
+
+	refreshed_tokens(&token_result)
+}
+
+///
+/// performs a token refresh like exchange_refresh_tokens, without blocking the calling thread
+///
+#[cfg(feature = "async")]
+pub async fn exchange_refresh_tokens_async(
+	tenant_id: &str,
+	client_id: &str,
+	refresh_token: Option<String>,
+	min_tls_version: Version,
+	environment: AzureEnvironment,
 ) -> VMInfoResult<AuthTokens> {
+	let (client, refresh_token) =
+		refresh_token_client(tenant_id, client_id, refresh_token, environment)?;
+	let token_result = client
+		.exchange_refresh_token(&refresh_token)
+		.request_async(|req| async_http_client(min_tls_version, req))
+		.await
+		.map_err(refresh_token_error)?;
+
+	refreshed_tokens(&token_result)
+}
+
+///
+/// creates the oauth2 client used to exchange a refresh token, failing when there is no refresh token to exchange
+///
+fn refresh_token_client(
+	tenant_id: &str,
+	client_id: &str,
+	refresh_token: Option<String>,
+	environment: AzureEnvironment,
+) -> VMInfoResult<(AzureClient, RefreshToken)> {
 	let token_url: String = format!(
-		"https://login.microsoftonline.com/{}/oauth2/token",
+		"{}/{}/oauth2/token",
+		environment.login_authority(),
 		tenant_id
 	);
 
-	if refresh_token.is_none() {
-		Err(auth(
+	let refresh_token = match refresh_token {
+		Some(rt) => RefreshToken::new(rt),
+		None => Err(auth(
 			None::<Error>,
 			AuthErrorKind::MissingToken,
 			"missing required refresh token to perform access token refresh",
-		))?
-	}
+		))?,
+	};
 
 	let client = AzureClient::new(
 		ClientId::new(client_id.to_string()),
@@ -484,35 +1450,498 @@ pub fn exchange_refresh_tokens(
 		})?),
 	);
 
-	let token_result: AzureTokenResponse<EmptyExtraTokenFields, BasicTokenType>;
-	if let Some(rt) = refresh_token {
-		token_result = client
-			.exchange_refresh_token(&RefreshToken::new(rt))
-			.request(http_client)
-			.map_err(|err| {
-				auth(
-					Some(err),
-					AuthErrorKind::BadRefresh,
-					"refresh token provided could not be used to obtain a new access token",
-				)
-			})?;
-
-		Ok(AuthTokens {
-			access_token: token_result.access_token.secret().to_string(),
-			refresh_token: match token_result.refresh_token() {
-				Some(rt) => Some(rt.secret().to_owned()),
-				_ => Err(auth(
-					None::<Error>,
-					AuthErrorKind::MissingToken,
-					"no refresh token supplied with login ... this is unusable",
-				))?,
+	Ok((client, refresh_token))
+}
+
+///
+/// maps a failed refresh token exchange to a vminfo Error
+///
+fn refresh_token_error<RE>(err: RequestTokenError<RE, BasicErrorResponse>) -> Error
+where
+	RE: std::error::Error + Send + Sync + 'static,
+{
+	oauth_error(
+		err,
+		AuthErrorKind::BadRefresh,
+		"refresh token provided could not be used to obtain a new access token",
+	)
+}
+
+///
+/// the tokens issued by a refresh token exchange. a new refresh token must be issued for the tokens to be usable
+///
+fn refreshed_tokens(token_result: &BasicAzureTokenResponse) -> VMInfoResult<AuthTokens> {
+	Ok(AuthTokens {
+		access_token: token_result.access_token.secret().to_string(),
+		refresh_token: match token_result.refresh_token() {
+			Some(rt) => Some(rt.secret().to_owned()),
+			_ => Err(auth(
+				None::<Error>,
+				AuthErrorKind::MissingToken,
+				"no refresh token supplied with login ... this is unusable",
+			))?,
+		},
+		expires_at: token_result.expires_at(),
+	})
+}
+
+#[cfg(test)]
+mod auth_tests {
+	use crate::testing::jwt as make_token;
+
+	#[test]
+	fn configured_poll_interval_is_used() {
+		use super::{poll_interval, Configuration};
+		use std::time::Duration;
+
+		let conf = Configuration::new("tenant", "client", &None)
+			.with_poll_interval(Some(Duration::from_secs(15)));
+
+		assert_eq!(
+			poll_interval(Duration::from_secs(5), conf.poll_interval),
+			Duration::from_secs(15)
+		);
+		// a longer interval requested by the server (slow_down) is still respected
+		assert_eq!(
+			poll_interval(Duration::from_secs(20), conf.poll_interval),
+			Duration::from_secs(20)
+		);
+		assert_eq!(
+			poll_interval(Duration::from_secs(5), None),
+			Duration::from_secs(5)
+		);
+	}
+
+	#[test]
+	fn v1_token_response_expires_on() {
+		use super::BasicAzureTokenResponse;
+		use chrono::{TimeZone, Utc};
+		use oauth2::TokenResponse;
+
+		// the v1 endpoint returns every number as a string, and the absolute expiry as expires_on
+		let expires_on = Utc::now().timestamp() + 3600;
+		let resp: BasicAzureTokenResponse = serde_json::from_str(&format!(
+			r#"{{"token_type": "Bearer", "expires_in": "3599", "expires_on": "{}", "access_token": "token"}}"#,
+			expires_on
+		))
+		.unwrap();
+		assert_eq!(resp.expires_at(), Utc.timestamp_opt(expires_on, 0).single());
+		assert_eq!(resp.expires_in().unwrap().as_secs(), 3599);
+
+		// without expires_in, the time remaining until expires_on is used
+		let resp: BasicAzureTokenResponse = serde_json::from_str(&format!(
+			r#"{{"token_type": "Bearer", "expires_on": "{}", "access_token": "token"}}"#,
+			expires_on
+		))
+		.unwrap();
+		let expires_in = resp.expires_in().unwrap().as_secs();
+		assert!((3590..=3600).contains(&expires_in));
+	}
+
+	#[test]
+	fn v2_token_response_expires_in() {
+		use super::BasicAzureTokenResponse;
+		use chrono::Utc;
+		use oauth2::TokenResponse;
+
+		let resp: BasicAzureTokenResponse = serde_json::from_str(
+			r#"{"token_type": "Bearer", "expires_in": 3599, "ext_expires_in": 3599, "access_token": "token"}"#,
+		)
+		.unwrap();
+		assert_eq!(resp.expires_in().unwrap().as_secs(), 3599);
+
+		let remaining = (resp.expires_at().unwrap() - Utc::now()).num_seconds();
+		assert!((3590..=3599).contains(&remaining));
+
+		let resp: BasicAzureTokenResponse =
+			serde_json::from_str(r#"{"token_type": "Bearer", "access_token": "token"}"#).unwrap();
+		assert_eq!(resp.expires_in(), None);
+		assert_eq!(resp.expires_at(), None);
+	}
+
+	#[test]
+	fn token_for_another_tenant_is_rejected() {
+		use super::verify_token_tenant;
+		use crate::error::{AuthErrorKind, Kind};
+
+		let tenant = "72f988bf-86f1-41af-91ab-2d7cd011db47";
+		let token = make_token(serde_json::json!({ "tid": "00000000-0000-0000-0000-000000000001" }));
+
+		assert_eq!(
+			verify_token_tenant(&token, tenant).unwrap_err().kind(),
+			Kind::AuthenticationError(AuthErrorKind::BadCredentials)
+		);
+		assert!(verify_token_tenant(&make_token(serde_json::json!({ "tid": tenant })), tenant).is_ok());
+		// tenants configured by domain name can not be compared with the tid claim
+		assert!(verify_token_tenant(&token, "contoso.onmicrosoft.com").is_ok());
+	}
+
+	#[test]
+	fn token_expiring_within_skew_window_is_expired() {
+		use super::{decode_token_claims, is_clock_skewed, is_expired, DEFAULT_CLOCK_SKEW};
+
+		let exp = chrono::Utc::now().timestamp() + 120;
+		let claims = decode_token_claims(&make_token(serde_json::json!({ "exp": exp }))).unwrap();
+		let expires_at = claims.expires_at().unwrap();
+
+		assert!(is_expired(&expires_at, DEFAULT_CLOCK_SKEW));
+		assert!(!is_expired(&expires_at, std::time::Duration::from_secs(0)));
+		assert!(!is_clock_skewed(&expires_at, DEFAULT_CLOCK_SKEW));
+	}
+
+	#[test]
+	fn token_expiring_beyond_skew_window_is_valid() {
+		use super::{decode_token_claims, is_clock_skewed, is_expired, DEFAULT_CLOCK_SKEW};
+
+		let exp = chrono::Utc::now().timestamp() + 3600;
+		let claims = decode_token_claims(&make_token(serde_json::json!({ "exp": exp }))).unwrap();
+		let expires_at = claims.expires_at().unwrap();
+
+		assert!(!is_expired(&expires_at, DEFAULT_CLOCK_SKEW));
+		assert!(is_clock_skewed(&expires_at, DEFAULT_CLOCK_SKEW));
+	}
+
+	#[test]
+	fn bad_secret_maps_to_bad_credentials() {
+		use super::client_credentials_error;
+		use crate::error::{AuthErrorKind, Kind};
+		use oauth2::basic::{BasicErrorResponse, BasicErrorResponseType};
+		use oauth2::RequestTokenError;
+
+		let resp = BasicErrorResponse::new(
+			BasicErrorResponseType::InvalidClient,
+			Some("AADSTS7000215: Invalid client secret provided.".to_string()),
+			None,
+		);
+		let err = client_credentials_error(RequestTokenError::<
+			oauth2::reqwest::Error<reqwest::Error>,
+			BasicErrorResponse,
+		>::ServerResponse(resp));
+
+		assert_eq!(
+			err.kind(),
+			Kind::AuthenticationError(AuthErrorKind::BadCredentials)
+		);
+		assert_eq!(err.oauth_error_code(), Some("invalid_client"));
+	}
+
+	#[test]
+	fn consent_required_code_is_preserved() {
+		use super::client_credentials_error;
+		use oauth2::basic::{BasicErrorResponse, BasicErrorResponseType};
+		use oauth2::RequestTokenError;
+
+		let resp = BasicErrorResponse::new(
+			BasicErrorResponseType::Extension("consent_required".to_string()),
+			Some("AADSTS65001: The user or administrator has not consented to use the application.\r\nTrace ID: 0000".to_string()),
+			None,
+		);
+		let err = client_credentials_error(RequestTokenError::<
+			oauth2::reqwest::Error<reqwest::Error>,
+			BasicErrorResponse,
+		>::ServerResponse(resp));
+
+		assert_eq!(err.oauth_error_code(), Some("consent_required"));
+		assert!(err.to_string().ends_with(
+			"(consent_required: AADSTS65001: The user or administrator has not consented to use the application.)"
+		));
+	}
+
+	#[test]
+	fn v2_client_credentials_sends_default_scope() {
+		use super::{request_client_credentials, Configuration, MANAGEMENT_DEFAULT_SCOPE};
+		use oauth2::url::form_urlencoded;
+		use oauth2::{HttpRequest, HttpResponse};
+		use std::collections::HashMap;
+
+		let login = |use_v2_endpoint: bool| {
+			let mut sent: Option<HttpRequest> = None;
+			let tokens = request_client_credentials(
+				&Configuration::new("tenant", "client", &Some("secret".to_string()))
+					.with_v2_endpoint(use_v2_endpoint),
+				|req: HttpRequest| {
+					sent = Some(req);
+					Ok::<_, std::io::Error>(HttpResponse {
+						status_code: oauth2::http::StatusCode::OK,
+						headers: oauth2::http::HeaderMap::new(),
+						body: br#"{"token_type": "Bearer", "expires_in": 3599, "access_token": "token"}"#
+							.to_vec(),
+					})
+				},
+			)
+			.unwrap();
+			assert_eq!(tokens.access_token, "token");
+			let expires_in = (tokens.expires_at.unwrap() - chrono::Utc::now()).num_seconds();
+			assert!((3590..=3600).contains(&expires_in));
+
+			let sent = sent.unwrap();
+			let params: HashMap<String, String> =
+				form_urlencoded::parse(&sent.body).into_owned().collect();
+			(sent.url.to_string(), params)
+		};
+
+		let (url, params) = login(true);
+		assert_eq!(
+			url,
+			"https://login.microsoftonline.com/tenant/oauth2/v2.0/token"
+		);
+		assert_eq!(
+			params.get("scope").map(String::as_str),
+			Some(MANAGEMENT_DEFAULT_SCOPE)
+		);
+		assert!(!params.contains_key("resource"));
+
+		// the v1.0 endpoint and resource are used by default
+		let (url, params) = login(false);
+		assert_eq!(url, "https://login.microsoftonline.com/tenant/oauth2/token");
+		assert_eq!(
+			params.get("resource").map(String::as_str),
+			Some("https://management.core.windows.net/")
+		);
+		assert!(!params.contains_key("scope"));
+	}
+
+	#[test]
+	fn sovereign_cloud_tokens_use_its_authority() {
+		use super::{request_client_credentials, Configuration};
+		use crate::cloud::AzureEnvironment;
+		use oauth2::url::form_urlencoded;
+		use oauth2::{HttpRequest, HttpResponse};
+		use std::collections::HashMap;
+
+		let login = |environment: AzureEnvironment, use_v2_endpoint: bool| {
+			let mut sent: Option<HttpRequest> = None;
+			request_client_credentials(
+				&Configuration::new("tenant", "client", &Some("secret".to_string()))
+					.with_v2_endpoint(use_v2_endpoint)
+					.with_environment(environment),
+				|req: HttpRequest| {
+					sent = Some(req);
+					Ok::<_, std::io::Error>(HttpResponse {
+						status_code: oauth2::http::StatusCode::OK,
+						headers: oauth2::http::HeaderMap::new(),
+						body: br#"{"token_type": "Bearer", "access_token": "token"}"#.to_vec(),
+					})
+				},
+			)
+			.unwrap();
+
+			let sent = sent.unwrap();
+			let params: HashMap<String, String> =
+				form_urlencoded::parse(&sent.body).into_owned().collect();
+			(sent.url.to_string(), params)
+		};
+
+		let (url, params) = login(AzureEnvironment::UsGov, true);
+		assert_eq!(
+			url,
+			"https://login.microsoftonline.us/tenant/oauth2/v2.0/token"
+		);
+		assert_eq!(
+			params.get("scope").map(String::as_str),
+			Some("https://management.usgovcloudapi.net/.default")
+		);
+
+		let (url, params) = login(AzureEnvironment::China, false);
+		assert_eq!(url, "https://login.chinacloudapi.cn/tenant/oauth2/token");
+		assert_eq!(
+			params.get("resource").map(String::as_str),
+			Some("https://management.core.chinacloudapi.cn/")
+		);
+	}
+
+	#[test]
+	fn managed_identity_requests_management_token_from_imds() {
+		use super::{request_managed_identity, Configuration};
+		use oauth2::{HttpRequest, HttpResponse};
+
+		let mut sent: Option<HttpRequest> = None;
+		let tokens =
+			request_managed_identity(&Configuration::new("", "", &None), |req: HttpRequest| {
+				sent = Some(req);
+				Ok::<_, std::io::Error>(HttpResponse {
+					status_code: oauth2::http::StatusCode::OK,
+					headers: oauth2::http::HeaderMap::new(),
+					body: br#"{"access_token": "token", "expires_in": "3599", "expires_on": "1700003599", "token_type": "Bearer"}"#
+						.to_vec(),
+				})
+			})
+			.unwrap();
+		assert_eq!(tokens.access_token, "token");
+		assert!(tokens.refresh_token.is_none());
+		assert_eq!(tokens.expires_at.unwrap().timestamp(), 1700003599);
+
+		let sent = sent.unwrap();
+		assert_eq!(sent.method, oauth2::http::Method::GET);
+		assert_eq!(sent.headers.get("Metadata").unwrap(), "true");
+		assert_eq!(
+			sent.url.as_str(),
+			"http://169.254.169.254/metadata/identity/oauth2/token?api-version=2018-02-01&resource=https%3A%2F%2Fmanagement.core.windows.net%2F"
+		);
+	}
+
+	#[test]
+	fn managed_identity_error_keeps_oauth_code() {
+		use super::{request_managed_identity, Configuration};
+		use crate::error::{AuthErrorKind, Kind};
+		use oauth2::{HttpRequest, HttpResponse};
+
+		let client_id = "00000000-0000-0000-0000-000000000001";
+		let err = request_managed_identity(
+			&Configuration::new("", client_id, &None),
+			|req: HttpRequest| {
+				assert!(req
+					.url
+					.as_str()
+					.ends_with(&format!("&client_id={}", client_id)));
+				Ok::<_, std::io::Error>(HttpResponse {
+					status_code: oauth2::http::StatusCode::BAD_REQUEST,
+					headers: oauth2::http::HeaderMap::new(),
+					body: br#"{"error": "invalid_request", "error_description": "Identity not found"}"#
+						.to_vec(),
+				})
 			},
-		})
-	} else {
-		Err(auth(
-			None::<Error>,
-			AuthErrorKind::BadRefresh,
-			"access token is expired and failed to exchange refresh token. reauthentication is required",
-		))?
+		)
+		.unwrap_err();
+
+		assert_eq!(
+			err.kind(),
+			Kind::AuthenticationError(AuthErrorKind::BadCredentials)
+		);
+		assert_eq!(err.oauth_error_code(), Some("invalid_request"));
+	}
+
+	#[test]
+	fn azure_cli_token_is_read() {
+		use super::azure_cli_tokens;
+		use chrono::{TimeZone, Utc};
+
+		let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+		let output = br#"{"accessToken": "token", "expiresOn": "2023-11-14 23:13:20.000000", "expires_on": 1700003600, "subscription": "sub", "tenant": "tenant", "tokenType": "Bearer"}"#;
+
+		let tokens = azure_cli_tokens(output, now).unwrap();
+		assert_eq!(tokens.access_token, "token");
+		assert!(tokens.refresh_token.is_none());
+	}
+
+	#[test]
+	fn expired_azure_cli_token_is_refused() {
+		use super::azure_cli_tokens;
+		use crate::error::{AuthErrorKind, Kind};
+		use chrono::{TimeZone, Utc};
+
+		let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+		let err = azure_cli_tokens(
+			br#"{"accessToken": "token", "expires_on": 1699996400}"#,
+			now,
+		)
+		.unwrap_err();
+		assert_eq!(
+			err.kind(),
+			Kind::AuthenticationError(AuthErrorKind::BadCredentials)
+		);
+		assert!(err.to_string().contains("az login"));
+
+		// older versions of the Azure CLI only output a local expiry time
+		let err = azure_cli_tokens(
+			br#"{"accessToken": "token", "expiresOn": "2000-01-01 00:00:00.000000"}"#,
+			now,
+		)
+		.unwrap_err();
+		assert!(err.to_string().contains("has expired"));
+	}
+
+	#[test]
+	fn certificate_login_sends_signed_client_assertion() {
+		use super::{request_client_credentials_cert, ClientCertificate, Configuration};
+		use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+		use base64::Engine;
+		use oauth2::url::form_urlencoded;
+		use oauth2::{HttpRequest, HttpResponse};
+		use openssl::asn1::Asn1Time;
+		use openssl::hash::MessageDigest;
+		use openssl::pkey::PKey;
+		use openssl::rsa::Rsa;
+		use openssl::sign::Verifier;
+		use openssl::x509::{X509NameBuilder, X509};
+		use std::collections::HashMap;
+
+		// a self-signed certificate and its key, saved in a single PEM file like 'az ad sp create-for-rbac --create-cert'
+		let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+		let mut name = X509NameBuilder::new().unwrap();
+		name.append_entry_by_text("CN", "vminfo").unwrap();
+		let name = name.build();
+		let mut builder = X509::builder().unwrap();
+		builder.set_subject_name(&name).unwrap();
+		builder.set_issuer_name(&name).unwrap();
+		builder.set_pubkey(&key).unwrap();
+		builder
+			.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+			.unwrap();
+		builder
+			.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+			.unwrap();
+		builder.sign(&key, MessageDigest::sha256()).unwrap();
+		let certificate = builder.build();
+
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("sp.pem");
+		let mut pem = key.private_key_to_pem_pkcs8().unwrap();
+		pem.extend(certificate.to_pem().unwrap());
+		std::fs::write(&path, pem).unwrap();
+
+		let mut sent: Option<HttpRequest> = None;
+		let tokens = request_client_credentials_cert(
+			&Configuration::new("tenant", "client", &Some("unused".to_string()))
+				.with_client_certificate(Some(ClientCertificate::new(path.to_str().unwrap(), None))),
+			|req: HttpRequest| {
+				sent = Some(req);
+				Ok::<_, std::io::Error>(HttpResponse {
+					status_code: oauth2::http::StatusCode::OK,
+					headers: oauth2::http::HeaderMap::new(),
+					body: br#"{"token_type": "Bearer", "expires_in": 3599, "access_token": "token"}"#
+						.to_vec(),
+				})
+			},
+		)
+		.unwrap();
+		assert_eq!(tokens.access_token, "token");
+
+		let sent = sent.unwrap();
+		assert!(sent.headers.get("authorization").is_none());
+		let params: HashMap<String, String> = form_urlencoded::parse(&sent.body).into_owned().collect();
+		assert_eq!(params["client_id"], "client");
+		assert!(!params.contains_key("client_secret"));
+		assert_eq!(
+			params["client_assertion_type"],
+			"urn:ietf:params:oauth:client-assertion-type:jwt-bearer"
+		);
+
+		let parts: Vec<&str> = params["client_assertion"].split('.').collect();
+		assert_eq!(parts.len(), 3);
+		let decode = |part: &str| -> serde_json::Value {
+			serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).unwrap()).unwrap()
+		};
+		let header = decode(parts[0]);
+		assert_eq!(header["alg"], "RS256");
+		assert_eq!(
+			header["x5t"],
+			URL_SAFE_NO_PAD.encode(certificate.digest(MessageDigest::sha1()).unwrap())
+		);
+		let claims = decode(parts[1]);
+		assert_eq!(
+			claims["aud"],
+			"https://login.microsoftonline.com/tenant/oauth2/token"
+		);
+		assert_eq!(claims["iss"], "client");
+		assert_eq!(claims["sub"], "client");
+
+		let mut verifier = Verifier::new(MessageDigest::sha256(), &key).unwrap();
+		verifier
+			.update(format!("{}.{}", parts[0], parts[1]).as_bytes())
+			.unwrap();
+		assert!(verifier
+			.verify(&URL_SAFE_NO_PAD.decode(parts[2]).unwrap())
+			.unwrap());
 	}
 }
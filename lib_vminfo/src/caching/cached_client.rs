@@ -0,0 +1,257 @@
+use crate::{
+	auth::AzCredentials,
+	error::{Kind, VMInfoResult},
+	persistance::PersistantStorage,
+	query::QueryResponse,
+	vm::VirtualMachine,
+	Client,
+};
+
+use super::Cache;
+
+///
+/// A read-through wrapper around a vminfo Client
+///
+/// Queries are first resolved from the cache. Any operands that miss the cache are fetched from Resource Graph
+/// and written back to the cache before the cached and remote results are merged into a single response.
+///
+#[derive(Debug)]
+pub struct CachedClient<'c, PS, RC>
+where
+	PS: PersistantStorage<AzCredentials>,
+	RC: Cache<VirtualMachine> + Clone,
+{
+	client: &'c Client<PS, RC>,
+	cache: &'c RC,
+}
+
+impl<'c, PS, RC> CachedClient<'c, PS, RC>
+where
+	PS: PersistantStorage<AzCredentials>,
+	RC: Cache<VirtualMachine> + Clone,
+{
+	///
+	/// creates a new read-through wrapper for a client using the provided cache
+	///
+	pub fn new(client: &'c Client<PS, RC>, cache: &'c RC) -> Self {
+		Self { client, cache }
+	}
+
+	///
+	/// queries for VM info, serving any cached VMs from the cache and fetching the rest from Resource Graph
+	///
+	/// when nocache is set, every operand is fetched from Resource Graph and the cache is neither read nor written
+	///
+	pub fn query_vminfo(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		// partial (or extended) records must never be cached or served from the cache
+		if nocache || self.client.query_options.bypasses_cache() {
+			return self.client.query_remote(
+				query_operand,
+				match_regexp,
+				show_extensions,
+				show_tags,
+				skip,
+				top,
+			);
+		}
+
+		let (mut cached_results, query_ops) = self.lookup(query_operand);
+
+		if query_ops.is_empty() {
+			return Ok(QueryResponse {
+				total_results: cached_results.len() as u64,
+				cache_hits: cached_results.len() as u64,
+				data: cached_results,
+				..Default::default()
+			});
+		}
+
+		match self.client.query_remote(
+			&query_ops,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			skip,
+			top,
+		) {
+			Ok(mut r) => {
+				self.populate(&r.data)?;
+				r.total_results += cached_results.len() as u64;
+				r.cache_hits = cached_results.len() as u64;
+				r.data.append(&mut cached_results);
+				Ok(r)
+			}
+			Err(err) => match err.kind() {
+				Kind::NoneFoundError if !cached_results.is_empty() => Ok(QueryResponse {
+					total_results: cached_results.len() as u64,
+					cache_hits: cached_results.len() as u64,
+					data: cached_results,
+					..Default::default()
+				}),
+				_ => Err(err),
+			},
+		}
+	}
+
+	///
+	/// splits the query operands into VMs found in the cache and operands that must be fetched remotely
+	///
+	pub(crate) fn lookup(&self, query_operand: &Vec<String>) -> (Vec<VirtualMachine>, Vec<String>) {
+		let mut hits: Vec<VirtualMachine> = Vec::new();
+		let mut misses: Vec<String> = Vec::new();
+
+		for q in query_operand {
+			match self.cache.get(q.to_lowercase().as_str()) {
+				Ok(vm) => hits.push(vm),
+				_ => misses.push(q.clone()),
+			}
+		}
+
+		(hits, misses)
+	}
+
+	///
+	/// writes VMs fetched from Resource Graph back into the cache keyed by their lowercased name
+	///
+	pub(crate) fn populate(&self, vms: &[VirtualMachine]) -> VMInfoResult<()> {
+		for vm in vms {
+			if let Some(name) = &vm.vm_name {
+				self.cache.put(name.to_lowercase().as_str(), vm)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod cached_client_tests {
+	use crate::caching::Cache;
+	use crate::testing::{client, query_response, vm, MemoryCache, MockTransport};
+
+	fn names(resp: &crate::query::QueryResponse) -> Vec<String> {
+		resp
+			.data
+			.iter()
+			.map(|v| v.vm_name.clone().unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn cache_hit_makes_no_request() {
+		let cache = MemoryCache::default();
+		cache.put("linux-01", &vm("linux-01")).unwrap();
+		let transport = MockTransport::new(vec![]);
+		let c = client(transport.clone(), Some(cache));
+
+		let resp = c
+			.query_vminfo(
+				&vec!["LINUX-01".to_string()],
+				false,
+				false,
+				false,
+				false,
+				None,
+				None,
+			)
+			.unwrap();
+
+		assert_eq!(names(&resp), vec!["linux-01"]);
+		assert_eq!(transport.requests().len(), 0);
+	}
+
+	#[test]
+	fn cache_miss_fetches_and_populates() {
+		let cache = MemoryCache::default();
+		let transport = MockTransport::new(vec![query_response(&[vm("linux-01")])]);
+		let c = client(transport.clone(), Some(cache.clone()));
+
+		let resp = c
+			.query_vminfo(
+				&vec!["linux-01".to_string()],
+				false,
+				false,
+				false,
+				false,
+				None,
+				None,
+			)
+			.unwrap();
+
+		assert_eq!(names(&resp), vec!["linux-01"]);
+		assert_eq!(transport.requests().len(), 1);
+		assert!(cache.get("linux-01").is_ok());
+	}
+
+	#[test]
+	fn partial_hit_only_fetches_misses() {
+		let cache = MemoryCache::default();
+		cache.put("linux-01", &vm("linux-01")).unwrap();
+		let transport = MockTransport::new(vec![query_response(&[vm("linux-02")])]);
+		let c = client(transport.clone(), Some(cache.clone()));
+
+		let resp = c
+			.query_vminfo(
+				&vec!["linux-01".to_string(), "linux-02".to_string()],
+				false,
+				false,
+				false,
+				false,
+				None,
+				None,
+			)
+			.unwrap();
+
+		let requests = transport.requests();
+		assert_eq!(requests.len(), 1);
+		assert!(requests[0].contains("linux-02"));
+		assert!(!requests[0].contains("linux-01"));
+		assert_eq!(names(&resp), vec!["linux-01", "linux-02"]);
+		assert_eq!((resp.total_results, resp.cache_hits), (2, 1));
+		assert!(cache.get("linux-02").is_ok());
+	}
+
+	#[test]
+	fn results_follow_operand_order() {
+		let cache = MemoryCache::default();
+		cache.put("linux-02", &vm("linux-02")).unwrap();
+		// Resource Graph returns the misses in its own order
+		let transport = MockTransport::new(vec![query_response(&[
+			vm("linux-01"),
+			vm("linux-04"),
+			vm("linux-03"),
+		])]);
+		let c = client(transport.clone(), Some(cache));
+
+		let resp = c
+			.query_vminfo(
+				&vec![
+					"linux-03".to_string(),
+					"LINUX-02".to_string(),
+					"linux-04".to_string(),
+					"linux-01".to_string(),
+				],
+				false,
+				false,
+				false,
+				false,
+				None,
+				None,
+			)
+			.unwrap();
+
+		assert_eq!(
+			names(&resp),
+			vec!["linux-03", "linux-02", "linux-04", "linux-01"]
+		);
+	}
+}
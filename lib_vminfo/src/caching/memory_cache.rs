@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+	error::{self, VMInfoResult},
+	vm::VirtualMachine,
+};
+
+use super::Cache;
+
+///
+/// A results cache held in the memory of the current process, for tests and short-lived programs (ie: a single CLI
+/// run) where caching across processes is not needed
+///
+/// clones share the same entries. entries are kept until they are replaced and never expire
+///
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryCache {
+	entries: Arc<Mutex<HashMap<String, VirtualMachine>>>,
+}
+
+impl InMemoryCache {
+	///
+	/// creates an empty in-memory cache
+	///
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	///
+	/// the number of VMs in the cache
+	///
+	pub fn len(&self) -> usize {
+		self.entries.lock().unwrap().len()
+	}
+
+	///
+	/// whether the cache holds no VMs
+	///
+	pub fn is_empty(&self) -> bool {
+		self.entries.lock().unwrap().is_empty()
+	}
+}
+
+impl Cache<VirtualMachine> for InMemoryCache {
+	fn put(&self, key: &str, data: &VirtualMachine) -> VMInfoResult<()> {
+		self
+			.entries
+			.lock()
+			.unwrap()
+			.insert(key.to_string(), data.clone());
+		Ok(())
+	}
+
+	fn get(&self, key: &str) -> VMInfoResult<VirtualMachine> {
+		match self.entries.lock().unwrap().get(key) {
+			Some(vm) => Ok(vm.clone()),
+			None => Err(error::caching(
+				None::<error::Error>,
+				format!("no cached result for {}", key).as_str(),
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod memory_cache_tests {
+	#[test]
+	fn clones_share_entries() {
+		use super::InMemoryCache;
+		use crate::caching::Cache;
+		use crate::error::Kind;
+		use crate::testing::vm;
+
+		let cache = InMemoryCache::new();
+		let clone = cache.clone();
+		assert!(cache.is_empty());
+
+		clone.put("linux-01", &vm("linux-01")).unwrap();
+
+		assert_eq!(cache.len(), 1);
+		assert_eq!(
+			cache.get("linux-01").unwrap().vm_name.as_deref(),
+			Some("linux-01")
+		);
+		assert_eq!(
+			cache.get("linux-02").unwrap_err().kind(),
+			Kind::ResultCacheError
+		);
+	}
+}
@@ -1,7 +1,22 @@
 ///
+/// provides a read-through cache wrapper around the vminfo Client
+///
+pub mod cached_client;
+///
+/// provides a cache held in process memory
+///
+pub mod memory_cache;
+///
 /// provides a concrete redis cache
 ///
 pub mod redis_cache;
+///
+/// provides a cache of whole query responses keyed by the query they answer
+///
+pub mod response_cache;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::error::VMInfoResult;
@@ -22,4 +37,61 @@ where
 	/// retrieve a stored cached value (if one exists) - Error if None exists
 	///
 	fn get(&self, key: &str) -> VMInfoResult<DT>;
+	///
+	/// store a value which the cache store may evict once `ttl` has passed
+	///
+	/// caches which can not expire values do not need to override this. the value is stored until it is replaced
+	///
+	fn put_with_ttl(&self, key: &str, data: &DT, ttl: Duration) -> VMInfoResult<()> {
+		let _ = ttl;
+		self.put(key, data)
+	}
+	///
+	/// commit any buffered or pipelined writes to the cache store
+	///
+	/// caches which write each value immediately do not need to override this
+	///
+	fn flush(&self) -> VMInfoResult<()> {
+		Ok(())
+	}
+}
+
+///
+/// whether a record fetched (ie: from Resource Graph, before being cached) at `fetched_at` is older than `ttl` and
+/// should be fetched again
+///
+pub fn is_stale(fetched_at: DateTime<Utc>, ttl: Duration) -> bool {
+	is_stale_at(fetched_at, ttl, Utc::now())
+}
+
+///
+/// whether a record fetched at `fetched_at` was older than `ttl` at `now`. a record fetched after `now` is never stale
+///
+pub fn is_stale_at(fetched_at: DateTime<Utc>, ttl: Duration, now: DateTime<Utc>) -> bool {
+	match (now - fetched_at).to_std() {
+		Ok(elapsed) => elapsed > ttl,
+		Err(_) => false,
+	}
+}
+
+#[cfg(test)]
+mod caching_tests {
+	#[test]
+	fn stale_after_ttl() {
+		use super::is_stale_at;
+		use chrono::{Duration as ChronoDuration, TimeZone, Utc};
+		use std::time::Duration;
+
+		let fetched_at = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+		let ttl = Duration::from_secs(60 * 60);
+
+		let after = |secs: i64| fetched_at + ChronoDuration::seconds(secs);
+
+		assert!(!is_stale_at(fetched_at, ttl, after(60 * 60 - 1)));
+		assert!(!is_stale_at(fetched_at, ttl, after(60 * 60)));
+		assert!(is_stale_at(fetched_at, ttl, after(60 * 60 + 1)));
+
+		// fetched after `now` (ie: clock drift between hosts sharing a cache)
+		assert!(!is_stale_at(fetched_at, ttl, after(-1)));
+	}
 }
@@ -1,48 +1,286 @@
 use std::fmt::Debug;
+use std::time::Duration;
 
+use rand::Rng;
 use redis::Commands;
 
 use crate::{
 	error::{self, VMInfoResult},
+	query::QueryResponse,
 	vm::VirtualMachine,
 };
 
 use super::Cache;
 
+///
+/// the default port Redis Sentinel listens on
+///
+const DEFAULT_SENTINEL_PORT: u16 = 26379;
+
+///
+/// the default random variation (in percent) applied to the TTL of each cached entry
+///
+pub const DEFAULT_TTL_JITTER_PERCENT: u8 = 10;
+
 ///
 /// A results caching type that implements fields and methods for handling caching with Redis
 ///
 #[derive(Debug, Clone)]
 pub struct VMResultsCacheRedis {
 	///
-	/// the redis connection to use for caching storage operations
+	/// the redis server (or Sentinel deployment) to use for caching storage operations
+	///
+	target: RedisTarget,
 	///
-	client: redis::Client,
+	/// how long VM results are kept in the cache (None keeps them until they are replaced)
+	///
+	ttl: Option<Duration>,
+	///
+	/// the random variation (±percent) applied to the TTL of each entry, so that entries written together do not all
+	/// expire together
+	///
+	ttl_jitter_percent: u8,
+}
+
+///
+/// describes how the redis server used for caching is located
+///
+#[derive(Debug, Clone)]
+enum RedisTarget {
+	///
+	/// a single, directly addressed redis server
+	///
+	Direct(redis::Client),
+	///
+	/// the current master of a Redis Sentinel deployment, which is resolved from the sentinels for every connection so
+	/// that failovers are followed
+	///
+	Sentinel {
+		sentinels: Vec<redis::Client>,
+		master_name: String,
+		redis_password: Option<String>,
+		use_tls: bool,
+		db: Option<u8>,
+	},
 }
 
 impl VMResultsCacheRedis {
 	///
 	/// constructs a new Results Cache using Redis as the cache store
 	///
+	///
+	/// the logical database index defaults to 0 when db is not provided
+	///
 	pub fn new(
 		host: &str,
 		port: u16,
 		redis_password: Option<String>,
 		use_tls: bool,
+		db: Option<u8>,
 	) -> VMInfoResult<Self> {
-		let uri_scheme = if use_tls { "rediss" } else { "redis" };
-		let password = match redis_password {
-			Some(p) => p,
-			_ => String::from(""),
-		};
+		let redis_connection_url = connection_url(host, port, redis_password, use_tls, db);
+
+		Ok(Self {
+			target: RedisTarget::Direct(
+				redis::Client::open(redis_connection_url)
+					.map_err(|err| error::caching(Some(err), "invalid redis connection URL"))?,
+			),
+			ttl: None,
+			ttl_jitter_percent: DEFAULT_TTL_JITTER_PERCENT,
+		})
+	}
+
+	///
+	/// constructs a new Results Cache using the master of a Redis Sentinel deployment as the cache store
+	///
+	/// sentinels are provided as `host[:port]` (the port defaults to 26379) and are tried in order when resolving the
+	/// address of the named master. no connection is made until the cache is used
+	///
+	pub fn with_sentinel(
+		sentinels: &[String],
+		master_name: &str,
+		redis_password: Option<String>,
+		use_tls: bool,
+		db: Option<u8>,
+	) -> VMInfoResult<Self> {
+		if sentinels.is_empty() {
+			return Err(error::caching(
+				None::<error::Error>,
+				"at least one redis sentinel address is required",
+			));
+		}
+
+		let mut sentinel_clients: Vec<redis::Client> = Vec::new();
+		for address in sentinels {
+			let (host, port) = match address.rsplit_once(':') {
+				Some((host, port)) => (
+					host,
+					port.parse::<u16>().map_err(|err| {
+						error::caching(Some(err), "invalid port in redis sentinel address")
+					})?,
+				),
+				None => (address.as_str(), DEFAULT_SENTINEL_PORT),
+			};
 
-		let redis_connection_url = format!("{}://:{}@{}:{}", uri_scheme, password, host, port);
+			sentinel_clients.push(
+				redis::Client::open(format!("redis://{}:{}", host, port))
+					.map_err(|err| error::caching(Some(err), "invalid redis sentinel address"))?,
+			);
+		}
 
 		Ok(Self {
-			client: redis::Client::open(redis_connection_url)
-				.map_err(|err| error::caching(Some(err), "invalid redis connection URL"))?,
+			target: RedisTarget::Sentinel {
+				sentinels: sentinel_clients,
+				master_name: master_name.to_string(),
+				redis_password,
+				use_tls,
+				db,
+			},
+			ttl: None,
+			ttl_jitter_percent: DEFAULT_TTL_JITTER_PERCENT,
 		})
 	}
+
+	///
+	/// expires cached VM results after ttl (None keeps them until they are replaced)
+	///
+	/// fails if the ttl is shorter than one second, as Redis expires keys in whole seconds and the entry would expire
+	/// (almost) as soon as it was written
+	///
+	pub fn with_ttl(mut self, ttl: Option<Duration>) -> VMInfoResult<Self> {
+		if ttl.is_some_and(|t| t.as_secs() == 0) {
+			return Err(error::caching(
+				None::<error::Error>,
+				"the cache TTL must be at least one second",
+			));
+		}
+
+		self.ttl = ttl;
+		Ok(self)
+	}
+
+	///
+	/// varies the TTL of each cached entry randomly by up to ±percent (capped at 100) so that entries cached together,
+	/// ie: by a bulk query, expire at different times. 0 disables the variation
+	///
+	pub fn with_ttl_jitter(mut self, percent: u8) -> Self {
+		self.ttl_jitter_percent = percent.min(100);
+		self
+	}
+
+	///
+	/// writes a value to the cache, expiring it after the jittered ttl (when provided)
+	///
+	fn set<T: redis::ToRedisArgs>(
+		&self,
+		key: &str,
+		data: &T,
+		ttl: Option<Duration>,
+		message: &str,
+	) -> VMInfoResult<()> {
+		let mut conn = self.connection()?;
+
+		let result: redis::RedisResult<()> = match ttl {
+			Some(ttl) => conn.set_ex(
+				key,
+				data,
+				jittered_ttl(ttl, self.ttl_jitter_percent).as_secs().max(1) as usize,
+			),
+			None => conn.set(key, data),
+		};
+
+		result.map_err(|err| error::caching(Some(err), message))
+	}
+
+	///
+	/// opens a connection to the redis server used for caching
+	///
+	fn connection(&self) -> VMInfoResult<redis::Connection> {
+		match &self.target {
+			RedisTarget::Direct(client) => client
+				.get_connection()
+				.map_err(|err| error::caching(Some(err), "failed to make connection to redis cache")),
+			RedisTarget::Sentinel {
+				sentinels,
+				master_name,
+				redis_password,
+				use_tls,
+				db,
+			} => {
+				let mut last_err: Option<redis::RedisError> = None;
+				for sentinel in sentinels {
+					let master: redis::RedisResult<(String, u16)> =
+						sentinel.get_connection().and_then(|mut conn| {
+							redis::cmd("SENTINEL")
+								.arg("get-master-addr-by-name")
+								.arg(master_name)
+								.query(&mut conn)
+						});
+
+					match master {
+						Ok((host, port)) => {
+							let url = connection_url(&host, port, redis_password.clone(), *use_tls, *db);
+							return redis::Client::open(url)
+								.and_then(|c| c.get_connection())
+								.map_err(|err| {
+									error::caching(Some(err), "failed to make connection to redis cache")
+								});
+						}
+						Err(err) => last_err = Some(err),
+					}
+				}
+
+				Err(error::caching(
+					last_err,
+					format!(
+						"failed to resolve redis master '{}' from any sentinel",
+						master_name
+					)
+					.as_str(),
+				))
+			}
+		}
+	}
+}
+
+///
+/// varies a TTL randomly by up to ±percent
+///
+fn jittered_ttl(ttl: Duration, percent: u8) -> Duration {
+	if percent == 0 {
+		return ttl;
+	}
+
+	let spread = ttl.as_secs_f64() * f64::from(percent) / 100.0;
+	let offset = rand::thread_rng().gen_range(-spread..=spread);
+
+	Duration::from_secs_f64((ttl.as_secs_f64() + offset).max(0.0))
+}
+
+///
+/// builds a redis connection URL in the form `redis[s]://:password@host:port/db`
+///
+fn connection_url(
+	host: &str,
+	port: u16,
+	redis_password: Option<String>,
+	use_tls: bool,
+	db: Option<u8>,
+) -> String {
+	let uri_scheme = if use_tls { "rediss" } else { "redis" };
+	let password = match redis_password {
+		Some(p) => p,
+		_ => String::from(""),
+	};
+
+	format!(
+		"{}://:{}@{}:{}/{}",
+		uri_scheme,
+		password,
+		host,
+		port,
+		db.unwrap_or(0)
+	)
 }
 
 impl AsMut<VMResultsCacheRedis> for VMResultsCacheRedis {
@@ -53,23 +291,16 @@ impl AsMut<VMResultsCacheRedis> for VMResultsCacheRedis {
 
 impl Cache<VirtualMachine> for VMResultsCacheRedis {
 	fn put(&self, key: &str, data: &VirtualMachine) -> VMInfoResult<()> {
-		let mut conn = self
-			.client
-			.get_connection()
-			.map_err(|err| error::caching(Some(err), "failed to make connection to redis cache"))?;
-
-		conn
-			.set(key, &data)
-			.map_err(|err| error::caching(Some(err), "failed to write VM results to redis cache"))?;
-
-		Ok(())
+		self.set(
+			key,
+			data,
+			self.ttl,
+			"failed to write VM results to redis cache",
+		)
 	}
 
 	fn get(&self, key: &str) -> VMInfoResult<VirtualMachine> {
-		let mut conn = self
-			.client
-			.get_connection()
-			.map_err(|err| error::caching(Some(err), "failed to make connection to redis cache"))?;
+		let mut conn = self.connection()?;
 
 		Ok(conn.get(key).map_err(|err| {
 			error::caching(
@@ -79,3 +310,153 @@ impl Cache<VirtualMachine> for VMResultsCacheRedis {
 		})?)
 	}
 }
+
+impl Cache<QueryResponse> for VMResultsCacheRedis {
+	fn put(&self, key: &str, data: &QueryResponse) -> VMInfoResult<()> {
+		self.set(
+			key,
+			data,
+			None,
+			"failed to write query response to redis cache",
+		)
+	}
+
+	fn put_with_ttl(&self, key: &str, data: &QueryResponse, ttl: Duration) -> VMInfoResult<()> {
+		self.set(
+			key,
+			data,
+			Some(ttl),
+			"failed to write query response to redis cache",
+		)
+	}
+
+	fn get(&self, key: &str) -> VMInfoResult<QueryResponse> {
+		let mut conn = self.connection()?;
+
+		conn.get(key).map_err(|err| {
+			error::caching(
+				Some(err),
+				format!("could not find query response with key {} in Redis", key).as_str(),
+			)
+		})
+	}
+}
+
+#[cfg(test)]
+mod redis_cache_tests {
+	#[test]
+	fn db_index_in_connection_url() {
+		use super::{connection_url, VMResultsCacheRedis};
+
+		assert_eq!(
+			connection_url("localhost", 6379, Some("pw".to_string()), false, Some(3)),
+			"redis://:pw@localhost:6379/3"
+		);
+		assert_eq!(
+			connection_url("localhost", 6380, None, true, None),
+			"rediss://:@localhost:6380/0"
+		);
+
+		let cache = VMResultsCacheRedis::new("localhost", 6379, None, false, Some(5)).unwrap();
+		match cache.target {
+			super::RedisTarget::Direct(client) => assert_eq!(client.get_connection_info().redis.db, 5),
+			_ => panic!("expected a direct redis connection"),
+		}
+	}
+
+	#[test]
+	fn ttl_jitter_spreads_expiry() {
+		use super::{jittered_ttl, VMResultsCacheRedis, DEFAULT_TTL_JITTER_PERCENT};
+		use std::time::Duration;
+
+		let ttl = Duration::from_secs(600);
+		let ttls: Vec<f64> = (0..1000)
+			.map(|_| jittered_ttl(ttl, DEFAULT_TTL_JITTER_PERCENT).as_secs_f64())
+			.collect();
+
+		assert!(ttls.iter().all(|t| (540.0..=660.0).contains(t)));
+		// expiries are spread across the range rather than synchronized
+		assert!(ttls.iter().any(|t| *t < 580.0));
+		assert!(ttls.iter().any(|t| *t > 620.0));
+
+		assert_eq!(jittered_ttl(ttl, 0), ttl);
+
+		let cache = VMResultsCacheRedis::new("localhost", 6379, None, false, None)
+			.unwrap()
+			.with_ttl(Some(ttl))
+			.unwrap()
+			.with_ttl_jitter(150);
+		assert_eq!(cache.ttl, Some(ttl));
+		assert_eq!(cache.ttl_jitter_percent, 100);
+	}
+
+	#[test]
+	fn zero_ttl_is_rejected() {
+		use super::VMResultsCacheRedis;
+		use crate::error::Kind;
+		use std::time::Duration;
+
+		let cache = VMResultsCacheRedis::new("localhost", 6379, None, false, None).unwrap();
+
+		assert_eq!(cache.clone().with_ttl(None).unwrap().ttl, None);
+		assert_eq!(
+			cache
+				.with_ttl(Some(Duration::from_secs(0)))
+				.unwrap_err()
+				.kind(),
+			Kind::ResultCacheError
+		);
+	}
+
+	#[test]
+	fn sentinel_config() {
+		use super::{RedisTarget, VMResultsCacheRedis};
+		use crate::caching::Cache;
+		use crate::error::Kind;
+		use crate::vm::VirtualMachine;
+
+		let cache = VMResultsCacheRedis::with_sentinel(
+			&["sentinel-01:26380".to_string(), "sentinel-02".to_string()],
+			"vminfo-master",
+			Some("pw".to_string()),
+			false,
+			Some(2),
+		)
+		.unwrap();
+
+		match &cache.target {
+			RedisTarget::Sentinel {
+				sentinels,
+				master_name,
+				db,
+				..
+			} => {
+				let addresses: Vec<String> = sentinels
+					.iter()
+					.map(|s| s.get_connection_info().addr.to_string())
+					.collect();
+				assert_eq!(addresses, vec!["sentinel-01:26380", "sentinel-02:26379"]);
+				assert_eq!(master_name, "vminfo-master");
+				assert_eq!(*db, Some(2));
+			}
+			_ => panic!("expected a sentinel connection"),
+		}
+
+		assert!(VMResultsCacheRedis::with_sentinel(&[], "vminfo-master", None, false, None).is_err());
+		assert!(
+			VMResultsCacheRedis::with_sentinel(&["sentinel-01:abc".to_string()], "m", None, false, None)
+				.is_err()
+		);
+
+		// an unreachable sentinel surfaces as a caching error
+		let unreachable =
+			VMResultsCacheRedis::with_sentinel(&["127.0.0.1:1".to_string()], "m", None, false, None)
+				.unwrap();
+		assert_eq!(
+			Cache::<VirtualMachine>::get(&unreachable, "linux-01")
+				.unwrap_err()
+				.kind(),
+			Kind::ResultCacheError
+		);
+	}
+}
@@ -0,0 +1,86 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::{
+	error::{self, VMInfoResult},
+	query::{QueryRequest, QueryResponse},
+};
+
+use super::Cache;
+
+///
+/// A cache of whole query responses, keyed by a signature of the tenant, the Resource Graph endpoint and the request
+/// sent to it (the operands, flags, subscriptions and query options), which are served for a short time before the query
+/// is run again
+///
+/// this is separate from the per-VM result cache and is intended for identical queries repeated within a short window
+/// (ie: a dashboard refreshing every 30 seconds)
+///
+#[derive(Clone)]
+pub struct ResponseCache {
+	cache: Arc<dyn Cache<QueryResponse> + Send + Sync>,
+	ttl: Duration,
+}
+
+impl ResponseCache {
+	///
+	/// creates a response cache which stores responses in the provided cache for ttl
+	///
+	pub fn new<C>(cache: C, ttl: Duration) -> Self
+	where
+		C: Cache<QueryResponse> + Send + Sync + 'static,
+	{
+		Self {
+			cache: Arc::new(cache),
+			ttl,
+		}
+	}
+
+	///
+	/// the cache key for the response to a request sent to the Resource Graph endpoint (url) for a tenant. the tenant and
+	/// endpoint are part of the key so that a shared cache never serves one tenant (or a mock endpoint) the response
+	/// queried for another
+	///
+	pub fn key(tenant_id: &str, url: &str, request: &QueryRequest) -> VMInfoResult<String> {
+		let signature = serde_json::to_string(request)
+			.map_err(|err| error::other(Some(err), "failed to generate JSON for query request"))?;
+
+		let mut hasher = DefaultHasher::new();
+		tenant_id.hash(&mut hasher);
+		url.hash(&mut hasher);
+		signature.hash(&mut hasher);
+
+		Ok(format!("vminfo:response:{:016x}", hasher.finish()))
+	}
+
+	///
+	/// gets a cached response which was queried from Resource Graph less than ttl ago
+	///
+	pub fn get(&self, key: &str) -> Option<QueryResponse> {
+		let ttl = chrono::Duration::from_std(self.ttl).unwrap_or_else(|_| chrono::Duration::zero());
+
+		self
+			.cache
+			.get(key)
+			.ok()
+			.filter(|r| matches!(r.queried_at, Some(t) if Utc::now() - t < ttl))
+	}
+
+	///
+	/// stores a response, to be served until ttl has passed
+	///
+	pub fn put(&self, key: &str, response: &QueryResponse) -> VMInfoResult<()> {
+		self.cache.put_with_ttl(key, response, self.ttl)
+	}
+}
+
+impl Debug for ResponseCache {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "ResponseCache {{ ttl: {:?} }}", self.ttl)
+	}
+}
@@ -0,0 +1,161 @@
+//!
+//!
+//! Provides the endpoints of the Azure clouds (the public cloud and the US Government and China sovereign clouds),
+//! which differ in the hosts used for Resource Graph queries and for logging in
+//!
+//!
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+///
+/// the Azure cloud a client authenticates against and queries
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AzureEnvironment {
+	/// the global Azure cloud (AzureCloud)
+	#[default]
+	Public,
+	/// Azure Government (AzureUSGovernment)
+	UsGov,
+	/// Azure operated by 21Vianet (AzureChinaCloud)
+	China,
+}
+
+impl AzureEnvironment {
+	///
+	/// the Azure Resource Manager endpoint, which serves Resource Graph queries
+	///
+	pub fn management_endpoint(&self) -> &'static str {
+		match self {
+			Self::Public => "https://management.azure.com",
+			Self::UsGov => "https://management.usgovcloudapi.net",
+			Self::China => "https://management.chinacloudapi.cn",
+		}
+	}
+
+	///
+	/// the Microsoft Entra ID authority host tokens are requested from
+	///
+	pub fn login_authority(&self) -> &'static str {
+		match self {
+			Self::Public => "https://login.microsoftonline.com",
+			Self::UsGov => "https://login.microsoftonline.us",
+			Self::China => "https://login.chinacloudapi.cn",
+		}
+	}
+
+	///
+	/// the resource v1.0 tokens are requested for
+	///
+	pub fn resource(&self) -> &'static str {
+		match self {
+			Self::Public => "https://management.core.windows.net/",
+			Self::UsGov => "https://management.core.usgovcloudapi.net/",
+			Self::China => "https://management.core.chinacloudapi.cn/",
+		}
+	}
+
+	///
+	/// the v2.0 '.default' scope requested by the client_credentials flow
+	///
+	pub fn default_scope(&self) -> String {
+		format!("{}/.default", self.management_endpoint())
+	}
+
+	///
+	/// the v2.0 scope requested by the device code flow to act as the signed in user
+	///
+	pub fn user_impersonation_scope(&self) -> String {
+		format!("{}user_impersonation", self.resource())
+	}
+
+	///
+	/// the name of the cloud (as used by the Azure CLI)
+	///
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Public => "AzureCloud",
+			Self::UsGov => "AzureUSGovernment",
+			Self::China => "AzureChinaCloud",
+		}
+	}
+}
+
+impl Display for AzureEnvironment {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+impl FromStr for AzureEnvironment {
+	type Err = String;
+
+	///
+	/// parses a cloud from its short name (public, usgov or china) or its Azure CLI name (ie: AzureUSGovernment),
+	/// compared case-insensitively
+	///
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.trim().to_lowercase().as_str() {
+			"public" | "azurecloud" => Ok(Self::Public),
+			"usgov" | "azureusgovernment" => Ok(Self::UsGov),
+			"china" | "azurechinacloud" => Ok(Self::China),
+			_ => Err(format!(
+				"unknown Azure environment '{}'. expected one of public, usgov or china",
+				s
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod cloud_tests {
+	#[test]
+	fn environments_map_to_their_endpoints() {
+		use super::AzureEnvironment;
+
+		assert_eq!(AzureEnvironment::default(), AzureEnvironment::Public);
+		assert_eq!(
+			AzureEnvironment::Public.default_scope(),
+			"https://management.azure.com/.default"
+		);
+		assert_eq!(
+			AzureEnvironment::Public.user_impersonation_scope(),
+			"https://management.core.windows.net/user_impersonation"
+		);
+		assert_eq!(
+			AzureEnvironment::UsGov.login_authority(),
+			"https://login.microsoftonline.us"
+		);
+		assert_eq!(
+			AzureEnvironment::UsGov.default_scope(),
+			"https://management.usgovcloudapi.net/.default"
+		);
+		assert_eq!(
+			AzureEnvironment::China.management_endpoint(),
+			"https://management.chinacloudapi.cn"
+		);
+		assert_eq!(
+			AzureEnvironment::China.resource(),
+			"https://management.core.chinacloudapi.cn/"
+		);
+	}
+
+	#[test]
+	fn environments_are_parsed_from_short_and_cli_names() {
+		use super::AzureEnvironment;
+
+		assert_eq!("public".parse(), Ok(AzureEnvironment::Public));
+		assert_eq!("AzureUSGovernment".parse(), Ok(AzureEnvironment::UsGov));
+		assert_eq!(" USGOV ".parse(), Ok(AzureEnvironment::UsGov));
+		assert_eq!("china".parse(), Ok(AzureEnvironment::China));
+		assert!("germany".parse::<AzureEnvironment>().is_err());
+		assert_eq!(
+			"AzureChinaCloud"
+				.parse::<AzureEnvironment>()
+				.unwrap()
+				.to_string(),
+			"AzureChinaCloud"
+		);
+	}
+}
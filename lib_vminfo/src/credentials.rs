@@ -0,0 +1,309 @@
+//!
+//!
+//! Provides sources of Azure credentials (the token store, environment variables, the OS keyring and the Azure CLI)
+//! which can be chained together and tried in order
+//!
+//!
+
+use std::fmt::Debug;
+
+use crate::{
+	auth::{self, AzCredentials, Method},
+	error::{self, AuthErrorKind, Kind, VMInfoResult},
+	persistance::{KeyringTokenStore, PersistantStorage},
+	AuthTokens,
+};
+
+///
+/// defines a method for acquiring Azure credentials
+///
+/// sources which have no credentials to offer (ie: the environment variables are not set) fail with a MissingToken
+/// authentication error, so that a CredentialChain can move on to the next source
+///
+pub trait CredentialSource: Debug + Send + Sync {
+	///
+	/// acquires the credentials offered by this source
+	///
+	fn credentials(&self) -> VMInfoResult<AzCredentials>;
+}
+
+///
+/// reads credentials from any persistant storage (ie: the FileTokenStore written by a login)
+///
+#[derive(Debug, Clone)]
+pub struct StoreCredentialSource<PS> {
+	store: PS,
+}
+
+impl<PS> StoreCredentialSource<PS>
+where
+	PS: PersistantStorage<AzCredentials> + Debug + Send + Sync,
+{
+	///
+	/// creates a source which reads the credentials held in store
+	///
+	pub fn new(store: PS) -> Self {
+		Self { store }
+	}
+}
+
+impl<PS> CredentialSource for StoreCredentialSource<PS>
+where
+	PS: PersistantStorage<AzCredentials> + Debug + Send + Sync,
+{
+	fn credentials(&self) -> VMInfoResult<AzCredentials> {
+		self.store.read()
+	}
+}
+
+///
+/// reads a service principal from environment variables named <prefix>_TENANT_ID, <prefix>_CLIENT_ID and
+/// <prefix>_CLIENT_SECRET (by default AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET, as used by the Azure SDKs)
+///
+/// the credentials carry no access token, so the client logs in with the client secret before its first query
+///
+#[derive(Debug, Clone)]
+pub struct EnvCredentialSource {
+	prefix: String,
+}
+
+impl EnvCredentialSource {
+	///
+	/// creates a source which reads the AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET variables
+	///
+	pub fn new() -> Self {
+		Self::with_prefix("AZURE")
+	}
+
+	///
+	/// creates a source which reads variables with another prefix (ie: VMINFO reads VMINFO_TENANT_ID)
+	///
+	pub fn with_prefix(prefix: &str) -> Self {
+		Self {
+			prefix: prefix.to_string(),
+		}
+	}
+
+	fn var(&self, name: &str) -> VMInfoResult<String> {
+		let name = format!("{}_{}", self.prefix, name);
+		match std::env::var(&name) {
+			Ok(value) if !value.is_empty() => Ok(value),
+			_ => Err(error::auth(
+				None::<error::Error>,
+				AuthErrorKind::MissingToken,
+				format!("the {} environment variable is not set", name).as_str(),
+			)),
+		}
+	}
+}
+
+impl Default for EnvCredentialSource {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl CredentialSource for EnvCredentialSource {
+	fn credentials(&self) -> VMInfoResult<AzCredentials> {
+		Ok(AzCredentials {
+			tenant_id: self.var("TENANT_ID")?,
+			client_id: self.var("CLIENT_ID")?,
+			client_secret: Some(self.var("CLIENT_SECRET")?),
+			certificate: None,
+			method: None,
+			tokens: AuthTokens {
+				access_token: String::new(),
+				refresh_token: None,
+				expires_at: None,
+			},
+		})
+	}
+}
+
+///
+/// reads the credentials saved in the OS keyring by a KeyringTokenStore (ie: after a login with the keyring store)
+///
+pub type KeyringCredentialSource = StoreCredentialSource<KeyringTokenStore>;
+
+impl KeyringCredentialSource {
+	///
+	/// creates a source which reads the keyring entry of the provided app name (ie: azure-vminfo) and profile (or the
+	/// default profile)
+	///
+	pub fn keyring(app_name: &str, profile: Option<&str>) -> VMInfoResult<Self> {
+		Ok(Self::new(KeyringTokenStore::new(app_name, profile)?))
+	}
+}
+
+///
+/// borrows the access token of the account logged in to the Azure CLI (`az login`)
+///
+/// the Azure CLI refreshes its own tokens, so no refresh token is offered. run `az login` again once it has expired
+///
+#[derive(Debug, Clone, Default)]
+pub struct AzureCliCredentialSource;
+
+impl CredentialSource for AzureCliCredentialSource {
+	fn credentials(&self) -> VMInfoResult<AzCredentials> {
+		let tokens = auth::login_azure_cli(&auth::Configuration::new(
+			"",
+			auth::AZURE_CLI_CLIENT_ID,
+			&None,
+		))
+		.map_err(|err| {
+			error::auth(
+				Some(err),
+				AuthErrorKind::MissingToken,
+				"the Azure CLI is not installed or not logged in",
+			)
+		})?;
+		let tenant_id = auth::decode_token_claims(&tokens.access_token)
+			.ok()
+			.and_then(|claims| claims.tid)
+			.unwrap_or_default();
+
+		Ok(AzCredentials {
+			tenant_id,
+			client_id: auth::AZURE_CLI_CLIENT_ID.to_string(),
+			client_secret: None,
+			certificate: None,
+			method: Some(Method::AzureCli),
+			tokens,
+		})
+	}
+}
+
+///
+/// tries a list of credential sources in order (like the DefaultAzureCredential chain of the Azure SDKs), returning
+/// the credentials of the first source which offers any
+///
+/// a source which has no credentials is skipped, while any other failure (ie: a corrupt token file) ends the chain
+///
+#[derive(Debug, Default)]
+pub struct CredentialChain {
+	sources: Vec<Box<dyn CredentialSource>>,
+}
+
+impl CredentialChain {
+	///
+	/// creates an empty chain
+	///
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	///
+	/// adds a source, tried after every source already in the chain
+	///
+	pub fn with_source<S: CredentialSource + 'static>(mut self, source: S) -> Self {
+		self.sources.push(Box::new(source));
+		self
+	}
+}
+
+impl CredentialSource for CredentialChain {
+	fn credentials(&self) -> VMInfoResult<AzCredentials> {
+		let mut skipped: Vec<String> = Vec::new();
+		for source in &self.sources {
+			match source.credentials() {
+				Err(err) if err.kind() == Kind::AuthenticationError(AuthErrorKind::MissingToken) => {
+					skipped.push(err.to_string());
+				}
+				result => return result,
+			}
+		}
+
+		Err(error::auth(
+			None::<error::Error>,
+			AuthErrorKind::MissingToken,
+			format!(
+				"no credential source offered credentials [{}]",
+				skipped.join("; ")
+			)
+			.as_str(),
+		))
+	}
+}
+
+#[cfg(test)]
+mod credentials_tests {
+	#[test]
+	fn chain_falls_through_missing_env_to_file() {
+		use super::{CredentialChain, CredentialSource, EnvCredentialSource, StoreCredentialSource};
+		use crate::auth::AzCredentials;
+		use crate::persistance::{FileTokenStore, PersistantStorage};
+		use crate::AuthTokens;
+
+		let dir = tempfile::tempdir().unwrap();
+		let store = FileTokenStore::from_path(dir.path().join("tokens.json"), None).unwrap();
+		let stored = AzCredentials {
+			tenant_id: "tenant".to_string(),
+			client_id: "client".to_string(),
+			client_secret: None,
+			certificate: None,
+			method: None,
+			tokens: AuthTokens {
+				access_token: "token".to_string(),
+				refresh_token: Some("refresh".to_string()),
+				expires_at: None,
+			},
+		};
+		store.write(&stored).unwrap();
+
+		let chain = CredentialChain::new()
+			.with_source(EnvCredentialSource::with_prefix("VMINFO_CHAIN_TEST_ABSENT"))
+			.with_source(StoreCredentialSource::new(store.clone()));
+		assert_eq!(chain.credentials().unwrap(), stored);
+
+		store.clear().unwrap();
+		let err = chain.credentials().unwrap_err();
+		assert!(err
+			.to_string()
+			.contains("VMINFO_CHAIN_TEST_ABSENT_TENANT_ID environment variable is not set"));
+		assert!(err
+			.to_string()
+			.contains("no credentials stored for profile"));
+	}
+
+	#[test]
+	fn keyring_source_reads_credentials_saved_by_the_keyring_store() {
+		use super::{CredentialSource, KeyringCredentialSource};
+		use crate::auth::AzCredentials;
+		use crate::error::{AuthErrorKind, Kind};
+		use crate::persistance::{KeyringTokenStore, PersistantStorage};
+		use crate::testing::MemoryKeyring;
+		use crate::AuthTokens;
+
+		MemoryKeyring::install();
+		let stored = AzCredentials {
+			tenant_id: "tenant".to_string(),
+			client_id: "client".to_string(),
+			client_secret: Some("secret".to_string()),
+			certificate: None,
+			method: None,
+			tokens: AuthTokens {
+				access_token: "token".to_string(),
+				refresh_token: None,
+				expires_at: None,
+			},
+		};
+		KeyringTokenStore::new("azure-vminfo-source-test", Some("work"))
+			.unwrap()
+			.write(&stored)
+			.unwrap();
+
+		let source =
+			KeyringCredentialSource::keyring("azure-vminfo-source-test", Some("work")).unwrap();
+		assert_eq!(source.credentials().unwrap(), stored);
+
+		// another profile has no entry, so the source offers no credentials
+		let err = KeyringCredentialSource::keyring("azure-vminfo-source-test", None)
+			.unwrap()
+			.credentials()
+			.unwrap_err();
+		assert_eq!(
+			err.kind(),
+			Kind::AuthenticationError(AuthErrorKind::MissingToken)
+		);
+	}
+}
@@ -39,6 +39,10 @@ pub enum AuthErrorKind {
 	/// Permissions not valid
 	///
 	AccessDenied,
+	///
+	/// Stored credentials exist but could not be parsed (ie: the file was truncated while being written)
+	///
+	CorruptTokenStore,
 }
 
 impl From<AuthErrorKind> for reqwest::StatusCode {
@@ -50,6 +54,7 @@ impl From<AuthErrorKind> for reqwest::StatusCode {
 			AuthErrorKind::MissingToken => reqwest::StatusCode::UNAUTHORIZED,
 			AuthErrorKind::TokenExpired => reqwest::StatusCode::UNAUTHORIZED,
 			AuthErrorKind::BadRequest => reqwest::StatusCode::BAD_REQUEST,
+			AuthErrorKind::CorruptTokenStore => reqwest::StatusCode::UNAUTHORIZED,
 		}
 	}
 }
@@ -63,6 +68,7 @@ impl std::fmt::Display for AuthErrorKind {
 			Self::BadRefresh => write!(f, "Failed to refresh access"),
 			Self::BadRequest => write!(f, "Bad authentication / authorization request"),
 			Self::AccessDenied => write!(f, "Access denied"),
+			Self::CorruptTokenStore => write!(f, "Stored credentials are corrupt"),
 		}
 	}
 }
@@ -89,6 +95,10 @@ pub enum Kind {
 	///
 	RequestError(Option<reqwest::StatusCode>),
 	///
+	/// Error thrown if a query fails pre-flight validation (ie: it is too long or has an unterminated string) and was not sent
+	///
+	InvalidQueryError,
+	///
 	/// Error thrown if there was any issue related to caching VM results
 	///
 	ResultCacheError,
@@ -124,6 +134,7 @@ pub struct Inner {
 	kind: Kind,
 	source: Option<BoxError>,
 	message: String,
+	oauth_error_code: Option<String>,
 }
 
 impl Error {
@@ -139,6 +150,7 @@ impl Error {
 				kind,
 				source: source.map(Into::into),
 				message: message.to_string(),
+				oauth_error_code: None,
 			}),
 		}
 	}
@@ -150,6 +162,33 @@ impl Error {
 		self.inner.kind.clone()
 	}
 
+	///
+	/// used to get the HTTP status associated with the error (for request and authentication errors)
+	///
+	pub fn status(&self) -> Option<reqwest::StatusCode> {
+		match self.inner.kind {
+			Kind::AuthenticationError(aek) => Some(aek.into()),
+			Kind::RequestError(s) => s,
+			_ => None,
+		}
+	}
+
+	///
+	/// used to get the OAuth2 error code returned by the Azure authorization server (ie: invalid_client or
+	/// consent_required), when an authentication request was rejected with one
+	///
+	pub fn oauth_error_code(&self) -> Option<&str> {
+		self.inner.oauth_error_code.as_deref()
+	}
+
+	///
+	/// records the OAuth2 error code the authorization server rejected a request with
+	///
+	pub(crate) fn with_oauth_error_code(mut self, code: &str) -> Self {
+		self.inner.oauth_error_code = Some(code.to_string());
+		self
+	}
+
 	///
 	/// used to get a reference to the wrapped error of the custom vminfo Error type
 	///
@@ -177,6 +216,7 @@ impl std::fmt::Display for Error {
 				)
 				.as_str(),
 			),
+			Kind::InvalidQueryError => f.write_str("invalid query error"),
 			Kind::ResultCacheError => f.write_str("caching error"),
 			Kind::Other => f.write_str("unknown error"),
 		};
@@ -222,6 +262,13 @@ pub fn request<E: Into<BoxError>>(
 	Error::new(Kind::RequestError(req_status), e, message)
 }
 
+///
+/// builds an error for queries that fail pre-flight validation before being sent to Resource Graph
+///
+pub fn invalid_query<E: Into<BoxError>>(e: Option<E>, message: &str) -> Error {
+	Error::new(Kind::InvalidQueryError, e, message)
+}
+
 ///
 /// builds an error for any caching issues that may appear when caching results
 ///
@@ -71,14 +71,30 @@
 //!
 //!
 
+///
+/// defines an opt-in audit log for recording queries made with the client
+///
+pub mod audit;
 ///
 /// defines authentication behaviour and data types for handling Azure authentication
 ///
 pub mod auth;
 ///
+/// defines the endpoints of the public and sovereign Azure clouds
+///
+pub mod cloud;
+///
+/// defines pluggable sources of credentials (token store, environment, keyring, Azure CLI) and a chain to try them in
+///
+pub mod credentials;
+///
 /// defines error and result types used in the client library
 ///
 pub mod error;
+///
+/// maps the OS names reported by VMs to canonical distributions
+///
+pub mod os;
 
 ///
 /// defines data structures for caching API responses for various requests
@@ -94,28 +110,63 @@ pub mod persistance;
 ///
 pub mod query;
 ///
+/// provides the vCPUs and memory of common VM sizes
+///
+pub mod sku;
+///
+/// defines the HTTP transport used to send requests to Azure
+///
+pub mod transport;
+///
 /// Virtual Machine Response Types
 ///
 pub mod vm;
 
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Debug, Display};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use caching::redis_cache::VMResultsCacheRedis;
+use caching::cached_client::CachedClient;
+use caching::response_cache::ResponseCache;
 use caching::Cache;
 
 use crate::query::QueryResponseType;
-use crate::query::{QueryRequest, QueryResponse};
-use auth::{AzCredentials, Method};
+use crate::query::{
+	FanOutResponse, MatchMode, QueryOptions, QueryRequest, QueryResponse, Subscription,
+	SubscriptionsResponse,
+};
+use audit::{AuditLog, AuditRecord};
+use auth::{AzCredentials, ClientCertificate, Method};
+use chrono::{DateTime, Utc};
+use cloud::AzureEnvironment;
+use credentials::CredentialSource;
 use error::{AuthErrorKind, Error, Kind, VMInfoResult};
 use persistance::{FileTokenStore, PersistantStorage};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "async")]
+use transport::{AsyncHttpTransport, AsyncTransport};
+use transport::{HttpTransport, RetryPolicy, Transport, TransportResponse};
 use vm::VirtualMachine;
 
+#[cfg(feature = "async")]
+mod nonblocking;
+#[cfg(test)]
+mod testing;
+
+///
+/// the path of the Resource Graph resources API, relative to the management endpoint of the Azure cloud
+///
+const RESOURCE_GRAPH_PATH: &str =
+	"/providers/Microsoft.ResourceGraph/resources?api-version=2021-03-01";
+
 ///
-/// default management endpoint for querying data from Resource Graph
+/// the default number of VM names matched by each query when querying in batches (see query_vminfo_batch)
 ///
-const MANAGEMENT_API_ENDPOINT: &str =
-	"https://management.azure.com/providers/Microsoft.ResourceGraph/resources?api-version=2021-03-01";
+pub const DEFAULT_BATCH_SIZE: usize = 500;
 
 ///
 /// Defines AuthTokens as a pair of access and refresh tokens
@@ -131,6 +182,11 @@ pub struct AuthTokens {
 	///
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub refresh_token: Option<String>,
+	///
+	/// when the access token expires, if this was reported with the token
+	///
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub expires_at: Option<DateTime<Utc>>,
 }
 
 impl Default for AuthTokens {
@@ -138,6 +194,7 @@ impl Default for AuthTokens {
 		AuthTokens {
 			access_token: "XXX".to_string(),
 			refresh_token: None,
+			expires_at: None,
 		}
 	}
 }
@@ -154,10 +211,38 @@ where
 	tenant_id: String,
 	client_id: String,
 	client_secret: Option<String>,
+	client_certificate: Option<ClientCertificate>,
 	active_tokens: Option<AuthTokens>,
 	token_store: PS,
 	result_cache: Option<RC>,
 	subscriptions: Option<Vec<String>>,
+	audit_log: Option<AuditLog>,
+	clock_skew: Duration,
+	transport: Arc<dyn Transport>,
+	#[cfg(feature = "async")]
+	async_transport: Arc<dyn AsyncTransport>,
+	query_options: QueryOptions,
+	headers: Vec<(String, String)>,
+	min_tls_version: reqwest::tls::Version,
+	poll_interval: Option<Duration>,
+	subscription_names: Arc<Mutex<Option<BTreeMap<String, String>>>>,
+	response_cache: Option<ResponseCache>,
+	last_query_time: Arc<Mutex<Option<DateTime<Utc>>>>,
+	explain_auth: bool,
+	use_v2_endpoint: bool,
+	login_method: Option<Method>,
+	max_buffered_vms: Option<usize>,
+	retry_policy: RetryPolicy,
+	azure_environment: AzureEnvironment,
+	management_endpoint: Option<String>,
+	imds_endpoint: Option<String>,
+}
+
+///
+/// quotes a value for use as a single argument in a POSIX shell
+///
+fn shell_quote(value: &str) -> String {
+	format!("'{}'", value.replace('\'', "'\\''"))
 }
 
 ///
@@ -172,8 +257,12 @@ impl Client<FileTokenStore, VMResultsCacheRedis> {
 	///
 	/// creates a new Client using the 'FileTokenStore' persistence method and 'VMResultsCacheRedis' cache
 	///
+	/// credentials are stored under the provided profile name (or the default profile), and the client authenticates
+	/// against and queries the provided Azure cloud (or the public cloud)
+	///
 	pub fn new(
 		app_name: &str,
+		profile: Option<&str>,
 		tenant_id: &str,
 		client_id: &str,
 		client_secret: Option<String>,
@@ -181,53 +270,102 @@ impl Client<FileTokenStore, VMResultsCacheRedis> {
 		redis_port: Option<u16>,
 		redis_password: Option<String>,
 		redis_use_tls: Option<bool>,
+		redis_db: Option<u8>,
 		subscriptions: Option<Vec<String>>,
+		azure_environment: Option<AzureEnvironment>,
 	) -> VMInfoResult<Self> {
 		Ok(Self {
 			tenant_id: String::from(tenant_id),
 			client_id: String::from(client_id),
 			client_secret,
+			client_certificate: None,
 			active_tokens: None,
-			token_store: FileTokenStore::new(app_name)?,
+			token_store: FileTokenStore::new(app_name, profile)?,
 			result_cache: match redis_host {
 				Some(h) => Some(VMResultsCacheRedis::new(
 					h,
 					redis_port.unwrap_or(6379u16),
 					redis_password,
 					redis_use_tls.unwrap_or(false),
+					redis_db,
 				)?),
 				_ => None,
 			},
 			subscriptions,
+			audit_log: None,
+			clock_skew: auth::DEFAULT_CLOCK_SKEW,
+			transport: Arc::new(HttpTransport::default()),
+			#[cfg(feature = "async")]
+			async_transport: Arc::new(AsyncHttpTransport::default()),
+			query_options: QueryOptions::default(),
+			headers: Vec::new(),
+			min_tls_version: transport::DEFAULT_MIN_TLS_VERSION,
+			poll_interval: None,
+			explain_auth: false,
+			use_v2_endpoint: false,
+			login_method: None,
+			max_buffered_vms: None,
+			retry_policy: RetryPolicy::default(),
+			azure_environment: azure_environment.unwrap_or_default(),
+			management_endpoint: None,
+			imds_endpoint: None,
+			subscription_names: Arc::new(Mutex::new(None)),
+			response_cache: None,
+			last_query_time: Arc::new(Mutex::new(None)),
 		})
 	}
 
 	///
-	/// creates a new vminfo Client from a persistant storage method
+	/// creates a new vminfo Client from a persistant storage method, loading the credentials stored under the provided
+	/// profile name (or the default profile)
 	///
 	pub fn from_store(
 		app_name: &str,
+		profile: Option<&str>,
 		redis_host: Option<&str>,
 		redis_port: Option<u16>,
 		redis_password: Option<String>,
 		redis_use_tls: Option<bool>,
+		redis_db: Option<u8>,
 	) -> VMInfoResult<Self> {
 		let mut c = Self {
 			tenant_id: "".to_string(),
 			client_id: "".to_string(),
 			client_secret: None,
-			token_store: FileTokenStore::new(app_name)?,
+			token_store: FileTokenStore::new(app_name, profile)?,
 			result_cache: match redis_host {
 				Some(h) => Some(VMResultsCacheRedis::new(
 					h,
 					redis_port.unwrap_or(6739u16),
 					redis_password,
 					redis_use_tls.unwrap_or(false),
+					redis_db,
 				)?),
 				_ => None,
 			},
+			client_certificate: None,
 			active_tokens: None,
 			subscriptions: None,
+			audit_log: None,
+			clock_skew: auth::DEFAULT_CLOCK_SKEW,
+			transport: Arc::new(HttpTransport::default()),
+			#[cfg(feature = "async")]
+			async_transport: Arc::new(AsyncHttpTransport::default()),
+			query_options: QueryOptions::default(),
+			headers: Vec::new(),
+			min_tls_version: transport::DEFAULT_MIN_TLS_VERSION,
+			poll_interval: None,
+			explain_auth: false,
+			use_v2_endpoint: false,
+			login_method: None,
+			max_buffered_vms: None,
+			retry_policy: RetryPolicy::default(),
+			azure_environment: AzureEnvironment::Public,
+			management_endpoint: None,
+			imds_endpoint: None,
+			subscription_names: Arc::new(Mutex::new(None)),
+			response_cache: None,
+			last_query_time: Arc::new(Mutex::new(None)),
 		};
 
 		c.load_credentials()
@@ -239,6 +377,160 @@ where
 	PS: PersistantStorage<AzCredentials>,
 	RC: Cache<VirtualMachine> + Clone,
 {
+	///
+	/// creates a new vminfo Client from any combination of persistant storage and result cache implementations
+	///
+	pub fn from_components(
+		tenant_id: &str,
+		client_id: &str,
+		client_secret: Option<String>,
+		token_store: PS,
+		result_cache: Option<RC>,
+		subscriptions: Option<Vec<String>>,
+	) -> Self {
+		Self {
+			tenant_id: String::from(tenant_id),
+			client_id: String::from(client_id),
+			client_secret,
+			client_certificate: None,
+			active_tokens: None,
+			token_store,
+			result_cache,
+			subscriptions,
+			audit_log: None,
+			clock_skew: auth::DEFAULT_CLOCK_SKEW,
+			transport: Arc::new(HttpTransport::default()),
+			#[cfg(feature = "async")]
+			async_transport: Arc::new(AsyncHttpTransport::default()),
+			query_options: QueryOptions::default(),
+			headers: Vec::new(),
+			min_tls_version: transport::DEFAULT_MIN_TLS_VERSION,
+			poll_interval: None,
+			explain_auth: false,
+			use_v2_endpoint: false,
+			login_method: None,
+			max_buffered_vms: None,
+			retry_policy: RetryPolicy::default(),
+			azure_environment: AzureEnvironment::Public,
+			management_endpoint: None,
+			imds_endpoint: None,
+			subscription_names: Arc::new(Mutex::new(None)),
+			response_cache: None,
+			last_query_time: Arc::new(Mutex::new(None)),
+		}
+	}
+
+	///
+	/// replaces the cache used to store VM results (None disables result caching)
+	///
+	pub fn with_result_cache(mut self, result_cache: Option<RC>) -> Self {
+		self.result_cache = result_cache;
+		self
+	}
+
+	///
+	/// serves identical queries from a cache of whole query responses (None disables response caching)
+	///
+	pub fn with_response_cache(mut self, response_cache: Option<ResponseCache>) -> Self {
+		self.response_cache = response_cache;
+		self
+	}
+
+	///
+	/// adds HTTP headers (ie: for an API gateway) to every request sent to Resource Graph. headers are not added to
+	/// authentication requests
+	///
+	/// fails if a header is not a valid HTTP header, or replaces the Authorization or Content-Type headers
+	///
+	pub fn with_headers(mut self, headers: Vec<(String, String)>) -> VMInfoResult<Self> {
+		transport::validate_headers(&headers)?;
+		self.headers = headers;
+		Ok(self)
+	}
+
+	///
+	/// replaces the HTTP transport used to send requests to Resource Graph
+	///
+	pub fn with_transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+		self.transport = Arc::new(transport);
+		self
+	}
+
+	///
+	/// replaces the non-blocking HTTP transport used by query_vminfo_async to send requests to Resource Graph
+	///
+	#[cfg(feature = "async")]
+	pub fn with_async_transport<T: AsyncTransport + 'static>(mut self, transport: T) -> Self {
+		self.async_transport = Arc::new(transport);
+		self
+	}
+
+	///
+	/// sets the minimum TLS version allowed for connections to Resource Graph and the Azure authorization server
+	/// (default: TLS 1.2). this replaces the HTTP transport with a new HttpTransport enforcing the minimum
+	///
+	/// fails if the TLS backend does not support the requested minimum
+	///
+	pub fn with_min_tls_version(
+		mut self,
+		min_tls_version: reqwest::tls::Version,
+	) -> VMInfoResult<Self> {
+		self.transport = Arc::new(HttpTransport::new(min_tls_version)?);
+		#[cfg(feature = "async")]
+		{
+			self.async_transport = Arc::new(AsyncHttpTransport::new(min_tls_version)?);
+		}
+		self.min_tls_version = min_tls_version;
+		Ok(self)
+	}
+
+	///
+	/// sets the Azure cloud the client authenticates against and queries (default: the public cloud)
+	///
+	pub fn with_azure_environment(mut self, azure_environment: AzureEnvironment) -> Self {
+		self.azure_environment = azure_environment;
+		self
+	}
+
+	///
+	/// replaces the management endpoint of the Azure cloud with another base URL (ie: a local mock of Resource Graph
+	/// for testing). Resource Graph requests are sent to its '/providers/Microsoft.ResourceGraph/resources' path, and
+	/// None restores the endpoint of the Azure cloud
+	///
+	/// fails if the endpoint is not an http(s) URL
+	///
+	pub fn with_management_endpoint(
+		mut self,
+		management_endpoint: Option<String>,
+	) -> VMInfoResult<Self> {
+		if let Some(endpoint) = &management_endpoint {
+			let url = reqwest::Url::parse(endpoint).map_err(|err| {
+				error::client_config(
+					Some(err),
+					&format!("management endpoint '{}' is not a valid URL", endpoint),
+				)
+			})?;
+			if !matches!(url.scheme(), "http" | "https") {
+				return Err(error::client_config(
+					None::<Error>,
+					&format!("management endpoint '{}' is not an http(s) URL", endpoint),
+				));
+			}
+		}
+		self.management_endpoint =
+			management_endpoint.map(|endpoint| endpoint.trim_end_matches('/').to_string());
+		Ok(self)
+	}
+
+	///
+	/// replaces the managed identity token endpoint of the Azure Instance Metadata Service with another URL (ie: a local
+	/// mock of IMDS for testing). None restores the IMDS endpoint
+	///
+	pub fn with_imds_endpoint(mut self, imds_endpoint: Option<String>) -> Self {
+		self.imds_endpoint = imds_endpoint;
+		self
+	}
+
 	///
 	/// performs login with Azure authentication server using the client_credentials OAuth2.0 flow described by [RFC6749](https://www.rfc-editor.org/rfc/rfc6749#section-4.4)
 	///
@@ -250,11 +542,16 @@ where
 				if !force {
 					Ok(self)
 				} else {
-					let tokens = auth::login_non_interactive(&auth::Configuration::new(
-						&self.tenant_id.as_str(),
-						&self.client_id.as_str(),
-						&self.client_secret,
-					))?;
+					let tokens = auth::login_non_interactive(
+						&auth::Configuration::new(
+							&self.tenant_id.as_str(),
+							&self.client_id.as_str(),
+							&self.client_secret,
+						)
+						.with_min_tls_version(self.min_tls_version)
+						.with_v2_endpoint(self.use_v2_endpoint)
+						.with_environment(self.azure_environment),
+					)?;
 
 					self.active_tokens = Some(tokens);
 
@@ -264,11 +561,16 @@ where
 				}
 			}
 			_ => {
-				let tokens = auth::login_non_interactive(&auth::Configuration::new(
-					&self.tenant_id.as_str(),
-					&self.client_id.as_str(),
-					&self.client_secret,
-				))?;
+				let tokens = auth::login_non_interactive(
+					&auth::Configuration::new(
+						&self.tenant_id.as_str(),
+						&self.client_id.as_str(),
+						&self.client_secret,
+					)
+					.with_min_tls_version(self.min_tls_version)
+					.with_v2_endpoint(self.use_v2_endpoint)
+					.with_environment(self.azure_environment),
+				)?;
 
 				self.active_tokens = Some(tokens);
 
@@ -278,6 +580,60 @@ where
 			}
 		}
 	}
+	///
+	/// performs login with the client_credentials OAuth2.0 flow using the certificate set with with_client_certificate()
+	/// in place of a client secret. only the path of the certificate is written to persistant storage
+	///
+	pub fn login_client_credentials_cert(mut self, force: bool) -> VMInfoResult<Self> {
+		// the certificate set on the client is preferred over any stored with the credentials
+		let client_certificate = self.client_certificate.take();
+		let _ = self.load_credentials();
+		self.client_certificate = client_certificate.or(self.client_certificate.take());
+
+		if self.access_token().is_some() && !force {
+			return Ok(self);
+		}
+
+		let tokens = auth::login_client_credentials_cert(
+			&auth::Configuration::new(&self.tenant_id, &self.client_id, &None)
+				.with_min_tls_version(self.min_tls_version)
+				.with_v2_endpoint(self.use_v2_endpoint)
+				.with_client_certificate(self.client_certificate.clone())
+				.with_environment(self.azure_environment),
+		)?;
+
+		self.client_secret = None;
+		self.active_tokens = Some(tokens);
+
+		self.save_credentials()?;
+
+		Ok(self)
+	}
+
+	///
+	/// validates a tenant_id, client_id and client_secret combination by performing a client_credentials token request
+	///
+	/// the resulting tokens are discarded and nothing is written to persistant storage. use this to confirm credentials
+	/// work before persisting them with login_client_credentials()
+	///
+	pub fn validate_credentials(
+		tenant_id: &str,
+		client_id: &str,
+		client_secret: &str,
+		min_tls_version: reqwest::tls::Version,
+		use_v2_endpoint: bool,
+		azure_environment: AzureEnvironment,
+	) -> VMInfoResult<()> {
+		let _ = auth::login_non_interactive(
+			&auth::Configuration::new(tenant_id, client_id, &Some(client_secret.to_string()))
+				.with_min_tls_version(min_tls_version)
+				.with_v2_endpoint(use_v2_endpoint)
+				.with_environment(azure_environment),
+		)?;
+
+		Ok(())
+	}
+
 	///
 	/// performs login with Azure authentication server using the devicecode OAuth2.0 flow described by [RFC8628](https://www.rfc-editor.org/rfc/rfc8628#section-3.4)
 	///
@@ -289,11 +645,12 @@ where
 				if !force {
 					Ok(self)
 				} else {
-					let tokens = auth::login_interactive(&auth::Configuration::new(
-						&self.tenant_id.as_str(),
-						&self.client_id.as_str(),
-						&None,
-					))?;
+					let tokens = auth::login_interactive(
+						&auth::Configuration::new(&self.tenant_id.as_str(), &self.client_id.as_str(), &None)
+							.with_min_tls_version(self.min_tls_version)
+							.with_poll_interval(self.poll_interval)
+							.with_environment(self.azure_environment),
+					)?;
 
 					self.active_tokens = Some(tokens);
 
@@ -303,11 +660,12 @@ where
 				}
 			}
 			_ => {
-				let tokens = auth::login_interactive(&auth::Configuration::new(
-					&self.tenant_id.as_str(),
-					&self.client_id.as_str(),
-					&None,
-				))?;
+				let tokens = auth::login_interactive(
+					&auth::Configuration::new(&self.tenant_id.as_str(), &self.client_id.as_str(), &None)
+						.with_min_tls_version(self.min_tls_version)
+						.with_poll_interval(self.poll_interval)
+						.with_environment(self.azure_environment),
+				)?;
 
 				self.active_tokens = Some(tokens);
 
@@ -318,10 +676,82 @@ where
 		}
 	}
 
+	///
+	/// performs login with the managed identity of the Azure host this runs on, using the Azure Instance Metadata
+	/// Service. no client secret is needed, and the client keeps using the managed identity to renew its access token
+	///
+	/// a user-assigned identity is used when the client_id of the client is its client ID, otherwise the system-assigned
+	/// identity is used. the tenant and client IDs of the client are replaced with those the token was issued for
+	///
+	pub fn login_managed_identity(mut self, force: bool) -> VMInfoResult<Self> {
+		self.login_method = Some(Method::ManagedIdentity);
+
+		if self.access_token().is_some() && !force {
+			return Ok(self);
+		}
+
+		let tokens = auth::login_managed_identity(
+			&auth::Configuration::new(&self.tenant_id, &self.client_id, &None)
+				.with_min_tls_version(self.min_tls_version)
+				.with_environment(self.azure_environment)
+				.with_imds_endpoint(self.imds_endpoint.clone()),
+		)?;
+
+		if let Ok(claims) = auth::decode_token_claims(&tokens.access_token) {
+			if let Some(tid) = claims.tid {
+				self.tenant_id = tid;
+			}
+			if let Some(appid) = claims.appid {
+				self.client_id = appid;
+			}
+		}
+		self.client_secret = None;
+		self.active_tokens = Some(tokens);
+
+		self.save_credentials()?;
+
+		Ok(self)
+	}
+
+	///
+	/// performs login by borrowing the access token of the account logged in to the Azure CLI (`az login`). the client
+	/// keeps asking the Azure CLI for a new access token when it expires
+	///
+	/// the Azure CLI keeps its own tokens, so nothing is written to persistant storage. fails with BadCredentials when
+	/// the `az` binary is missing or not logged in
+	///
+	pub fn login_azure_cli(mut self, force: bool) -> VMInfoResult<Self> {
+		self.login_method = Some(Method::AzureCli);
+
+		if self.access_token().is_some() && !force {
+			return Ok(self);
+		}
+
+		let tokens = auth::login_azure_cli(
+			&auth::Configuration::new(&self.tenant_id, auth::AZURE_CLI_CLIENT_ID, &None)
+				.with_environment(self.azure_environment),
+		)?;
+
+		if let Some(tid) = auth::decode_token_claims(&tokens.access_token)
+			.ok()
+			.and_then(|claims| claims.tid)
+		{
+			self.tenant_id = tid;
+		}
+		self.client_id = auth::AZURE_CLI_CLIENT_ID.to_string();
+		self.client_secret = None;
+		self.active_tokens = Some(tokens);
+
+		Ok(self)
+	}
+
 	fn reauth(&self) -> VMInfoResult<Self> {
 		match self.auth_method() {
 			Method::ClientCredentials => self.clone().login_client_credentials(true),
 			Method::DeviceCode => self.clone().login_device_code(true),
+			Method::ManagedIdentity => self.clone().login_managed_identity(true),
+			Method::AzureCli => self.clone().login_azure_cli(true),
+			Method::ClientCertificate => self.clone().login_client_credentials_cert(true),
 		}
 	}
 
@@ -329,9 +759,15 @@ where
 	/// determines which authentication method is being used as primary on the client
 	///
 	pub fn auth_method(&self) -> Method {
-		match self.client_secret {
-			Some(_) => Method::ClientCredentials,
-			None => Method::DeviceCode,
+		match (
+			&self.login_method,
+			&self.client_certificate,
+			&self.client_secret,
+		) {
+			(Some(method), _, _) => method.clone(),
+			(None, Some(_), _) => Method::ClientCertificate,
+			(None, None, Some(_)) => Method::ClientCredentials,
+			(None, None, None) => Method::DeviceCode,
 		}
 	}
 
@@ -349,9 +785,12 @@ where
 				tenant_id: self.tenant_id.clone(),
 				client_id: self.client_id.clone(),
 				client_secret: self.client_secret.clone(),
+				certificate: self.client_certificate.clone(),
+				method: self.login_method.clone(),
 				tokens: AuthTokens {
 					access_token,
 					refresh_token: self.refresh_token(),
+					expires_at: self.active_tokens.as_ref().and_then(|t| t.expires_at),
 				},
 			};
 
@@ -368,211 +807,1177 @@ where
 	}
 
 	///
-	/// public vminfo query request method that wraps request() with special authentication handlers
+	/// enables the audit log for this client. every call to query_vminfo() will append a record to the log
 	///
-	/// ## authentication errors
+	/// writing to the audit log is fail-open: a failure to write a record will never fail the query itself
 	///
-	/// This function will handle regular use authentication errors for you in processing your request,
-	/// but will throw an error back to the client if the authentication error cannot be resolved automatically.
-	/// This includes, but is no limited to:
+	pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+		self.audit_log = Some(audit_log);
+		self
+	}
+
 	///
-	/// - Bad Credentials
-	/// - Invalid request
-	/// - Network timeouts
-	/// - failed token refresh
-	/// - Permissions errors on scope or otherwise
+	/// limits every query made with this client to the provided subscriptions (None queries all accessible subscriptions)
 	///
-	pub fn query_vminfo(
-		&self,
-		query_operand: &Vec<String>,
-		match_regexp: bool,
-		show_extensions: bool,
-		show_tags: bool,
-		nocache: bool,
-		skip: Option<u64>,
-		top: Option<u16>,
-	) -> VMInfoResult<QueryResponse> {
-		let mut query_ops: Vec<String> = query_operand.clone();
-		let mut cached_results: Vec<VirtualMachine> = Vec::new();
-
-		if !nocache {
-			match self.clone().result_cache {
-				Some(cache) => {
-					query_ops = Vec::new();
-					for (_, q) in query_operand.into_iter().enumerate() {
-						match cache.get(q.to_lowercase().as_str()) {
-							Ok(vm) => {
-								cached_results.push(vm);
-							}
-							_ => query_ops.push(q.clone()),
-						}
-					}
-				}
-				_ => (),
-			};
-		}
+	pub fn with_subscriptions(mut self, subscriptions: Option<Vec<String>>) -> Self {
+		self.subscriptions = subscriptions;
+		self
+	}
 
-		if query_ops.len() > 0 {
-			let resp: VMInfoResult<QueryResponse> =
-				self.request(
-					&query_ops,
-					match_regexp,
-					show_extensions,
-					show_tags,
-					skip,
-					top,
-				);
+	///
+	/// sets additional options applied to every query made with this client
+	///
+	pub fn with_query_options(mut self, query_options: QueryOptions) -> Self {
+		self.query_options = query_options;
+		self
+	}
 
-			match resp {
-				Ok(mut r) => {
-					r.data.append(&mut cached_results);
-					Ok(r)
-				}
-				Err(err) => match err.kind() {
-					Kind::AuthenticationError(aek) => match aek {
-						AuthErrorKind::MissingToken => {
-							self
-								.reauth()?
-								.request(
-									&query_ops,
-									match_regexp,
-									show_extensions,
-									show_tags,
-									skip,
-									top,
-								)
-						}
-						AuthErrorKind::TokenExpired => match self.auth_method() {
-							Method::ClientCredentials => {
-								self
-									.reauth()?
-									.request(
-										&query_ops,
-										match_regexp,
-										show_extensions,
-										show_tags,
-										skip,
-										top,
-									)
-							}
-							Method::DeviceCode => self.clone().exchange_refresh_token()?.request(
-								&query_ops,
-								match_regexp,
-								show_extensions,
-								show_tags,
-								skip,
-								top,
-							),
-						},
-						_ => Err(err)?,
-					},
-					Kind::NoneFoundError => {
-						if cached_results.len() > 0 {
-							Ok(QueryResponse {
-								total_results: cached_results.len() as u64,
-								data: cached_results,
-							})
-						} else {
-							Err(err)?
-						}
-					}
-					_ => Err(err)?,
-				},
-			}
-		} else {
-			Ok(QueryResponse {
-				total_results: cached_results.len() as u64,
-				data: cached_results,
-			})
-		}
+	///
+	/// sets the interval between polls for an access token during device code login (default: the interval suggested
+	/// by the authorization server, which is still used when it asks for a longer one)
+	///
+	pub fn with_poll_interval(mut self, poll_interval: Option<Duration>) -> Self {
+		self.poll_interval = poll_interval;
+		self
 	}
 
-	/// creates a request to pull VM meta and instance data from Azure Resource Graph with filters and extra options possible
 	///
-	/// ## Arguments
-	/// - query_operand: specifies either a list of full host names for the VM hosts wishing to get data for XOR a single regular expression to match one or more hosts.
-	/// 							 	 if match_regexp = true, will only use the first query_operand for matching
-	/// - match_regexp: specifies whether to match regular expressions instead of full host names
-	/// - show_extensions: specifies that vminfo should also return a list of VM extensions that are installed for each host matched
-	/// - skip: optionally specifies a number of host results to skip to help while working within the constraints of Resource Graph API's paging responses
-	/// - top: optionally specifies a number of hosts to return for each 'page' (MAXIMUM ALLOWED: 1000)
-	fn request(
-		&self,
-		query_operand: &Vec<String>,
-		match_regexp: bool,
-		show_extensions: bool,
-		show_tags: bool,
-		skip: Option<u64>,
-		top: Option<u16>,
-	) -> VMInfoResult<QueryResponse> {
-		let http_client: reqwest::blocking::Client = reqwest::blocking::Client::new();
+	/// sets whether the client_credentials flow uses the v2.0 token endpoint with the management '.default' scope, like
+	/// the devicecode flow, instead of the v1.0 endpoint with the management resource (default: false)
+	///
+	pub fn with_v2_endpoint(mut self, use_v2_endpoint: bool) -> Self {
+		self.use_v2_endpoint = use_v2_endpoint;
+		self
+	}
 
-		let req_body = QueryRequest::make(
-			query_operand,
-			match_regexp,
-			show_extensions,
-			show_tags,
-			skip,
-			top,
-			&self.subscriptions,
-		);
+	///
+	/// sets the certificate used to authenticate the client in place of a client secret (see
+	/// login_client_credentials_cert)
+	///
+	pub fn with_client_certificate(mut self, client_certificate: Option<ClientCertificate>) -> Self {
+		self.client_certificate = client_certificate;
+		self
+	}
 
-		let access_token_opt = match self.access_token() {
-			Some(t) => t,
-			_ => Err(error::auth(
-				None::<Error>,
-				AuthErrorKind::MissingToken,
-				"no access token provided for request",
-			))?,
-		};
+	///
+	/// limits the number of VMs held in memory while the pages (and batches or subscriptions) of a query are collected
+	/// into a single response (default: no limit)
+	///
+	/// every page is buffered until the last one has been fetched, so a query matching more VMs than the limit fails
+	/// with an error once the limit is exceeded rather than exhausting memory. narrow the query, or page through the
+	/// results with query_vminfo() and QueryOptions::skip_token to handle each page as it arrives
+	///
+	pub fn with_max_buffered_vms(mut self, max_buffered_vms: Option<usize>) -> Self {
+		self.max_buffered_vms = max_buffered_vms;
+		self
+	}
 
-		let resp: QueryResponseType = http_client
-			.post(MANAGEMENT_API_ENDPOINT)
-			.bearer_auth(&access_token_opt)
-			.json(&req_body)
-			.send()
-			.map_err(|err| {
-				let status = err.status();
-				error::request(
-					Some(err),
-					status,
-					"request for vm info from Resource Graph failed",
-				)
-			})?
-			.json()
-			.map_err(|err| {
-				let status = err.status();
-				error::request(
-					Some(err),
-					status,
-					"could not parse vm info into valid response object",
+	///
+	/// retries queries answered with HTTP 429 (Too Many Requests) or a server error according to retry_policy
+	/// (default: up to 3 retries, backing off from 1 second). use RetryPolicy::none() to return such responses at once
+	///
+	pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+		self.retry_policy = retry_policy;
+		self
+	}
+
+	///
+	/// fails once more VMs than the configured limit (see with_max_buffered_vms) are buffered
+	///
+	fn check_buffered_vms(&self, buffered: usize) -> VMInfoResult<()> {
+		match self.max_buffered_vms {
+			Some(max) if buffered > max => Err(error::other(
+				None::<Error>,
+				format!(
+					"the query matched more than the limit of {} VMs buffered in memory. narrow the query or page through the results",
+					max
 				)
-			})?;
+				.as_str(),
+			)),
+			_ => Ok(()),
+		}
+	}
 
-		match resp {
-			QueryResponseType::Ok(r) => {
-				if r.data.len() == 0 {
-					return Err(error::none_found(
-						None::<error::Error>,
-						format!(
-							"no virtual machines were found with the provided query: {:?}",
-							query_operand
-						)
-						.as_str(),
-					));
+	///
+	/// traces each authentication decision (the method in use, the state of the stored tokens, and any refresh or
+	/// re-authentication with its outcome) to stderr. token values are never printed
+	///
+	pub fn with_explain_auth(mut self, explain_auth: bool) -> Self {
+		self.explain_auth = explain_auth;
+		self.explain_token_state();
+		self
+	}
+
+	///
+	/// prints an authentication decision to stderr when explain_auth is enabled
+	///
+	fn explain(&self, message: &str) {
+		if self.explain_auth {
+			eprintln!("[explain-auth] {}", message);
+		}
+	}
+
+	///
+	/// explains which authentication method is in use and the state of the loaded tokens
+	///
+	fn explain_token_state(&self) {
+		if !self.explain_auth {
+			return;
+		}
+
+		self.explain(&self.auth_method_reason());
+		self.explain(&match (self.access_token(), self.token_expires_at()) {
+			(None, _) => "no stored access token was found".to_string(),
+			(Some(_), None) => {
+				"the stored access token expiry can not be read, so it is treated as expired".to_string()
+			}
+			(Some(_), Some(expires_at)) if auth::is_expired(&expires_at, self.clock_skew) => {
+				format!("the stored access token expired at {}", expires_at)
+			}
+			(Some(_), Some(expires_at)) => {
+				format!("the stored access token is valid until {}", expires_at)
+			}
+		});
+		self.explain(&format!(
+			"a refresh token is {}stored",
+			if self.refresh_token().is_some() {
+				""
+			} else {
+				"not "
+			}
+		));
+	}
+
+	///
+	/// explains why auth_method() chose the method in use, from the same inputs
+	///
+	fn auth_method_reason(&self) -> String {
+		let reason = match (
+			&self.login_method,
+			&self.client_certificate,
+			&self.client_secret,
+		) {
+			(Some(_), _, _) => "it was used to log in",
+			(None, Some(_), _) => "a client certificate is set",
+			(None, None, Some(_)) => "a client secret is stored",
+			(None, None, None) => "neither a client secret nor a certificate is stored",
+		};
+
+		format!("using the {} method since {}", self.auth_method(), reason)
+	}
+
+	///
+	/// sets the tolerance for clock drift used when checking token expiry (default: 300 seconds)
+	///
+	pub fn with_clock_skew(mut self, clock_skew: Duration) -> Self {
+		self.clock_skew = clock_skew;
+		self
+	}
+
+	///
+	/// checks the expiry of the active access token against the local clock (allowing for the configured clock skew)
+	///
+	/// returns true if there is no access token, or its expiry cannot be read from the token
+	///
+	pub fn is_token_expired(&self) -> bool {
+		match self.token_expires_at() {
+			Some(expires_at) => auth::is_expired(&expires_at, self.clock_skew),
+			None => true,
+		}
+	}
+
+	///
+	/// the active access token, first obtaining a new one (with the client secret or refresh token) when it has expired
+	/// or its expiry cannot be read. the new tokens are saved to the token store
+	///
+	/// **warning**: the access token grants access to Azure as this client until it expires
+	///
+	pub fn valid_access_token(&self) -> VMInfoResult<String> {
+		self.fresh_access_token(Self::refresh)
+	}
+
+	///
+	/// obtains new tokens for an expired access token, exchanging the refresh token of a device code login or logging in
+	/// again with any other method
+	///
+	fn refresh(&self) -> VMInfoResult<Self> {
+		match self.auth_method() {
+			Method::DeviceCode => self.clone().exchange_refresh_token(),
+			_ => self.reauth(),
+		}
+	}
+
+	///
+	/// the active access token, replacing the client with the result of refresh first when the token has expired
+	///
+	fn fresh_access_token(
+		&self,
+		refresh: impl FnOnce(&Self) -> VMInfoResult<Self>,
+	) -> VMInfoResult<String> {
+		let refreshed: Self;
+		let client = if self.is_token_expired() {
+			self.explain(
+				"the access token has expired (or its expiry can not be read), refreshing it before use",
+			);
+			refreshed = refresh(self)?;
+			&refreshed
+		} else {
+			self
+		};
+
+		client.access_token().ok_or_else(|| {
+			error::auth(
+				None::<Error>,
+				AuthErrorKind::MissingToken,
+				"no access token is available from this Client",
+			)
+		})
+	}
+
+	///
+	/// checks whether the token store holds credentials that can be used without prompting the user. this is the case
+	/// when the stored access token has not expired, when a new one can be obtained from a stored refresh token, client
+	/// secret or certificate, or when the credentials were obtained with a method that logs in again without user input
+	/// (a managed identity or the Azure CLI)
+	///
+	/// no network calls are made, so the stored credentials may still be rejected by Azure
+	///
+	pub fn is_authenticated(&self) -> bool {
+		let credentials = match self.token_store.read() {
+			Ok(c) => c,
+			Err(_) => return false,
+		};
+
+		if credentials.tokens.refresh_token.is_some()
+			|| credentials.client_secret.is_some()
+			|| credentials.certificate.is_some()
+			|| matches!(
+				credentials.method,
+				Some(Method::ManagedIdentity | Method::AzureCli)
+			) {
+			return true;
+		}
+
+		match credentials.tokens.expires_at.or_else(|| {
+			auth::decode_token_claims(&credentials.tokens.access_token)
+				.ok()
+				.and_then(|c| c.expires_at())
+		}) {
+			Some(expires_at) => !auth::is_expired(&expires_at, self.clock_skew),
+			None => false,
+		}
+	}
+
+	///
+	/// when the active access token expires, as reported with the token or otherwise read from its claims
+	///
+	fn token_expires_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+		let tokens = self.active_tokens.as_ref()?;
+
+		tokens.expires_at.or_else(|| {
+			auth::decode_token_claims(&tokens.access_token)
+				.ok()
+				.and_then(|c| c.expires_at())
+		})
+	}
+
+	fn warn_if_clock_skewed(&self) {
+		if let Some(expires_at) = self.token_expires_at() {
+			if auth::is_clock_skewed(&expires_at, self.clock_skew) {
+				eprintln!(
+					"warning: access token was rejected as expired but is valid until {} according to the local clock. the system clock may be out of sync",
+					expires_at
+				);
+			}
+		}
+	}
+
+	///
+	/// queries for VM info in the same way as query_vminfo(), following the skip token (or offset) of each truncated
+	/// response until every matched VM has been returned
+	///
+	/// operands found in the result cache are served from it and only the rest are paged through Resource Graph, so that
+	/// each page follows a skip token (or offset) issued for the same operands. every page fetched from Resource Graph is
+	/// written to the result cache
+	///
+	pub fn query_vminfo_all(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+	) -> VMInfoResult<QueryResponse> {
+		let cached_client = match &self.result_cache {
+			Some(cache) if !nocache && !self.query_options.bypasses_cache() => {
+				Some(CachedClient::new(self, cache))
+			}
+			_ => None,
+		};
+		let (cached_operands, remote_operands): (Vec<String>, Vec<String>) = match &cached_client {
+			Some(c) => {
+				let (_, misses) = c.lookup(query_operand);
+				query_operand
+					.iter()
+					.cloned()
+					.partition(|q| !misses.contains(q))
+			}
+			None => (Vec::new(), query_operand.clone()),
+		};
+
+		// without both cached and remote operands, the first page is requested for every operand like later pages are
+		if cached_operands.is_empty() || remote_operands.is_empty() {
+			return self.query_pages(
+				query_operand,
+				match_regexp,
+				show_extensions,
+				show_tags,
+				nocache,
+				cached_client.as_ref(),
+			);
+		}
+
+		let cached = self.query_vminfo(
+			&cached_operands,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			false,
+			None,
+			None,
+		)?;
+		let mut all = match self.query_pages(
+			&remote_operands,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			true,
+			cached_client.as_ref(),
+		) {
+			Ok(r) => r,
+			Err(err) if err.kind() == Kind::NoneFoundError && !cached.data.is_empty() => {
+				QueryResponse::default()
+			}
+			Err(err) => return Err(err),
+		};
+
+		all.total_results += cached.total_results;
+		all.cache_hits = cached.cache_hits;
+		all.data.extend(cached.data);
+		self.check_buffered_vms(all.data.len())?;
+		self.sort_by_operands(&mut all.data, query_operand, match_regexp);
+
+		Ok(all)
+	}
+
+	///
+	/// requests the first page of a query with query_vminfo(), then follows the skip token (or offset) of each truncated
+	/// response. pages after the first (and the first, when nocache is set) are written to the cache of cached_client
+	///
+	fn query_pages(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+		cached_client: Option<&CachedClient<PS, RC>>,
+	) -> VMInfoResult<QueryResponse> {
+		let mut all = self.query_vminfo(
+			query_operand,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			nocache,
+			None,
+			None,
+		)?;
+		all.pages = 1;
+		if let (Some(c), true) = (cached_client, nocache) {
+			c.populate(&all.data)?;
+		}
+		self.check_buffered_vms(all.data.len())?;
+
+		while all.truncated || all.skip_token.is_some() {
+			let result = match all.skip_token.take() {
+				Some(skip_token) => self
+					.clone()
+					.with_query_options(QueryOptions {
+						skip_token: Some(skip_token),
+						..self.query_options.clone()
+					})
+					.query_vminfo(
+						query_operand,
+						match_regexp,
+						show_extensions,
+						show_tags,
+						true,
+						None,
+						None,
+					),
+				None => self.query_vminfo(
+					query_operand,
+					match_regexp,
+					show_extensions,
+					show_tags,
+					true,
+					Some(all.data.len() as u64 - all.cache_hits),
+					None,
+				),
+			};
+
+			let mut page = match result {
+				Ok(p) => p,
+				Err(err) if err.kind() == Kind::NoneFoundError => break,
+				Err(err) => return Err(err),
+			};
+			if let Some(c) = cached_client {
+				c.populate(&page.data)?;
+			}
+
+			all.pages += 1;
+			all.truncated = page.truncated;
+			all.skip_token = page.skip_token.take();
+			all.data.append(&mut page.data);
+			self.check_buffered_vms(all.data.len())?;
+		}
+
+		all.truncated = false;
+		Ok(all)
+	}
+
+	///
+	/// queries for VM info in the same way as query_vminfo_all(), but with one query per configured subscription. up to
+	/// `concurrency` subscriptions are queried at once, and the results are merged in the order the subscriptions were
+	/// configured
+	///
+	/// a subscription that fails to be queried does not fail the others. it is reported in the failures of the
+	/// response instead, unless every subscription failed (the first error is returned) or no VMs were found
+	///
+	/// cached results are not attributed to a subscription, so they are never read when querying subscriptions separately
+	///
+	pub fn query_vminfo_fan_out(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		concurrency: usize,
+	) -> VMInfoResult<FanOutResponse>
+	where
+		PS: Sync,
+		RC: Sync,
+	{
+		let subscriptions: Vec<String> = match &self.subscriptions {
+			Some(s) if !s.is_empty() => s.clone(),
+			_ => {
+				return Err(error::client_config(
+					None::<Error>,
+					"at least one subscription must be configured to query subscriptions separately",
+				))
+			}
+		};
+
+		let next = AtomicUsize::new(0);
+		let results: Mutex<Vec<Option<VMInfoResult<QueryResponse>>>> =
+			Mutex::new(subscriptions.iter().map(|_| None).collect());
+
+		std::thread::scope(|scope| {
+			for _ in 0..concurrency.max(1).min(subscriptions.len()) {
+				scope.spawn(|| loop {
+					let i = next.fetch_add(1, Ordering::SeqCst);
+					let subscription = match subscriptions.get(i) {
+						Some(s) => s,
+						None => break,
+					};
+
+					let result = self
+						.clone()
+						.with_subscriptions(Some(vec![subscription.clone()]))
+						.query_vminfo_all(
+							query_operand,
+							match_regexp,
+							show_extensions,
+							show_tags,
+							true,
+						);
+					results.lock().unwrap()[i] = Some(result);
+				});
+			}
+		});
+
+		let mut merged = QueryResponse::default();
+		let mut failures: Vec<(String, Error)> = Vec::new();
+		let results = results.into_inner().unwrap_or_else(|p| p.into_inner());
+		for (subscription, result) in subscriptions.into_iter().zip(results) {
+			match result {
+				Some(Ok(mut resp)) => {
+					for vm in resp.data.iter_mut() {
+						vm.set_subscription_id(&subscription);
+					}
+					merged.total_results += resp.total_results;
+					merged.cache_hits += resp.cache_hits;
+					merged.pages += resp.pages;
+					merged.data.append(&mut resp.data);
+					self.check_buffered_vms(merged.data.len())?;
+				}
+				Some(Err(err)) if err.kind() == Kind::NoneFoundError => {}
+				Some(Err(err)) => failures.push((subscription, err)),
+				None => failures.push((
+					subscription,
+					error::other(None::<Error>, "subscription query did not complete"),
+				)),
+			}
+		}
+
+		if merged.data.is_empty() {
+			if let Some((_, err)) = failures.into_iter().next() {
+				return Err(err);
+			}
+			return Err(error::none_found(
+				None::<Error>,
+				"no virtual machines found in any subscription",
+			));
+		}
+
+		Ok(FanOutResponse {
+			response: merged,
+			failures,
+		})
+	}
+
+	///
+	/// queries for VM info in the same way as query_vminfo_all(), splitting a long list of VM names into queries of at
+	/// most `batch_size` names and waiting `delay` between each query, so that large batches stay under the Resource
+	/// Graph request quota instead of being throttled
+	///
+	/// batches that match no VMs are skipped. it is only an error for no batch to match any VMs
+	///
+	pub fn query_vminfo_batch(
+		&self,
+		query_operand: &[String],
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+		batch_size: usize,
+		delay: Duration,
+	) -> VMInfoResult<QueryResponse> {
+		self.query_in_batches(
+			query_operand,
+			show_extensions,
+			show_tags,
+			nocache,
+			batch_size,
+			delay,
+			std::thread::sleep,
+		)
+	}
+
+	///
+	/// queries each batch of VM names with query_vminfo_all(), calling sleep with the delay between batches
+	///
+	fn query_in_batches(
+		&self,
+		query_operand: &[String],
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+		batch_size: usize,
+		delay: Duration,
+		mut sleep: impl FnMut(Duration),
+	) -> VMInfoResult<QueryResponse> {
+		let mut merged = QueryResponse::default();
+
+		for (i, batch) in query_operand.chunks(batch_size.max(1)).enumerate() {
+			if i > 0 && !delay.is_zero() {
+				sleep(delay);
+			}
+
+			match self.query_vminfo_all(&batch.to_vec(), false, show_extensions, show_tags, nocache) {
+				Ok(mut resp) => {
+					merged.total_results += resp.total_results;
+					merged.cache_hits += resp.cache_hits;
+					merged.pages += resp.pages;
+					merged.data.append(&mut resp.data);
+					self.check_buffered_vms(merged.data.len())?;
 				}
+				Err(err) if err.kind() == Kind::NoneFoundError => {}
+				Err(err) => return Err(err),
+			}
+		}
+
+		if merged.data.is_empty() {
+			return Err(error::none_found(
+				None::<Error>,
+				"no virtual machines found in any batch",
+			));
+		}
+
+		Ok(merged)
+	}
+
+	///
+	/// queries for VM info in the same way as query_vminfo_all(), returning the matched VMs keyed by their lowercased
+	/// name for lookups
+	///
+	/// when more than one VM shares a name the last one is kept, unless `strict` is set in which case it is an error
+	///
+	pub fn query_vminfo_map(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+		strict: bool,
+	) -> VMInfoResult<HashMap<String, VirtualMachine>> {
+		let resp = self.query_vminfo_all(
+			query_operand,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			nocache,
+		)?;
+
+		vm::index_by_name(resp.data, strict)
+	}
+
+	///
+	/// public vminfo query request method that wraps request() with special authentication handlers
+	///
+	/// ## authentication errors
+	///
+	/// This function will handle regular use authentication errors for you in processing your request,
+	/// but will throw an error back to the client if the authentication error cannot be resolved automatically.
+	/// This includes, but is no limited to:
+	///
+	/// - Bad Credentials
+	/// - Invalid request
+	/// - Network timeouts
+	/// - failed token refresh
+	/// - Permissions errors on scope or otherwise
+	///
+	pub fn query_vminfo(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		let result = self.query_with_response_cache(
+			query_operand,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			nocache,
+			skip,
+			top,
+		);
+
+		self.complete_query(query_operand, match_regexp, result)
+	}
+
+	///
+	/// builds a curl command which sends the same Resource Graph request as query_vminfo, for reproducing a query outside
+	/// of vminfo. the access token is replaced with a $TOKEN placeholder, to be set in the shell before running it
+	///
+	/// the result cache and response cache are not consulted, so the command always queries Resource Graph
+	///
+	pub fn curl_command(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<String> {
+		let req_body = QueryRequest::make_with_options(
+			query_operand,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			skip,
+			top,
+			&self.subscriptions,
+			&self.query_options,
+		);
+		req_body.validate()?;
+		let body = serde_json::to_string(&req_body)
+			.map_err(|err| error::other(Some(err), "failed to generate JSON for query request"))?;
+
+		// the token placeholder is double quoted so that the shell expands it
+		let mut lines: Vec<String> = vec![
+			format!("curl -X POST {}", shell_quote(&self.resource_graph_url())),
+			"-H \"Authorization: Bearer $TOKEN\"".to_string(),
+			format!("-H {}", shell_quote("Content-Type: application/json")),
+		];
+		lines.extend(
+			self
+				.headers
+				.iter()
+				.map(|(name, value)| format!("-H {}", shell_quote(&format!("{}: {}", name, value)))),
+		);
+		lines.push(format!("--data-raw {}", shell_quote(&body)));
+
+		Ok(lines.join(" \\\n  "))
+	}
+
+	///
+	/// applies the client-side query options to the result of a query, then records when it was fetched and audits it
+	///
+	fn complete_query(
+		&self,
+		query_operand: &[String],
+		match_regexp: bool,
+		result: VMInfoResult<QueryResponse>,
+	) -> VMInfoResult<QueryResponse> {
+		let result = result.map(|mut r| {
+			if self.query_options.show_sku_details {
+				r.data.iter_mut().for_each(VirtualMachine::resolve_sku);
+			}
+			// cached VMs may have been fetched before their subscription was denied (remote results are already filtered)
+			if !self.query_options.subscription_denylist.is_empty() {
+				let before = r.data.len();
+				r.data.retain(|vm| {
+					!vm
+						.subscription_id()
+						.is_some_and(|s| self.query_options.denies_subscription(s))
+				});
+				let denied = (before - r.data.len()) as u64;
+				r.cache_hits = r.cache_hits.saturating_sub(denied);
+				r.total_results = r.total_results.saturating_sub(denied);
+			}
+			self.sort_by_operands(&mut r.data, query_operand, match_regexp);
+			r
+		});
+		if let Ok(r) = &result {
+			*self.last_query_time.lock().unwrap() = r.queried_at;
+		}
+
+		if let Some(audit_log) = &self.audit_log {
+			let identity = self
+				.access_token()
+				.and_then(|t| auth::decode_token_claims(&t).ok())
+				.and_then(|c| c.identity());
+
+			let mut record = AuditRecord::new(query_operand, match_regexp, &self.tenant_id, identity);
+			match &result {
+				Ok(r) => {
+					record.success = true;
+					record.result_count = r.data.len() as u64;
+				}
+				Err(err) => record.error = Some(err.to_string()),
+			}
+
+			let _ = audit_log.append(&record);
+		}
+
+		result
+	}
+
+	///
+	/// returns literal queries in the order of their operands (unless sorted), whether each VM came from cache or remote
+	///
+	fn sort_by_operands(
+		&self,
+		vms: &mut [VirtualMachine],
+		query_operand: &[String],
+		match_regexp: bool,
+	) {
+		if !match_regexp
+			&& self.query_options.match_mode == MatchMode::Exact
+			&& self.query_options.sort.is_none()
+		{
+			vm::sort_by_operands(vms, query_operand);
+		}
+	}
+
+	///
+	/// when this client last fetched the results it returned from Resource Graph (results served from the response cache
+	/// keep the time they were originally fetched)
+	///
+	pub fn last_query_time(&self) -> Option<DateTime<Utc>> {
+		*self.last_query_time.lock().unwrap()
+	}
+
+	///
+	/// serves a query from the response cache (when configured) while it is fresh, otherwise runs the query and caches
+	/// the response. when nocache is set the response cache is neither read nor written
+	///
+	fn query_with_response_cache(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		let key = match (&self.response_cache, nocache) {
+			(Some(_), false) => Some(ResponseCache::key(
+				&self.tenant_id,
+				&self.resource_graph_url(),
+				&QueryRequest::make_with_options(
+					query_operand,
+					match_regexp,
+					show_extensions,
+					show_tags,
+					skip,
+					top,
+					&self.subscriptions,
+					&self.query_options,
+				),
+			)?),
+			_ => None,
+		};
+
+		if let (Some(response_cache), Some(key)) = (&self.response_cache, &key) {
+			if let Some(r) = response_cache.get(key) {
+				return Ok(r);
+			}
+		}
+
+		let mut r = self.query_with_reauth(
+			query_operand,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			nocache,
+			skip,
+			top,
+		)?;
+		r.queried_at = Some(Utc::now());
+
+		if let (Some(response_cache), Some(key)) = (&self.response_cache, &key) {
+			response_cache.put(key, &r)?;
+		}
+
+		Ok(r)
+	}
+
+	fn query_with_reauth(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		nocache: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		match &self.result_cache {
+			Some(cache) => CachedClient::new(self, cache).query_vminfo(
+				query_operand,
+				match_regexp,
+				show_extensions,
+				show_tags,
+				nocache,
+				skip,
+				top,
+			),
+			_ => self.query_remote(
+				query_operand,
+				match_regexp,
+				show_extensions,
+				show_tags,
+				skip,
+				top,
+			),
+		}
+	}
+
+	///
+	/// queries Resource Graph directly (bypassing any result cache), handling re-authentication where possible
+	///
+	pub(crate) fn query_remote(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		self.with_reauth(|client| {
+			client.request(
+				query_operand,
+				match_regexp,
+				show_extensions,
+				show_tags,
+				skip,
+				top,
+			)
+		})
+	}
+
+	///
+	/// runs a request with this client, re-authenticating and running it once more when the access token is missing or
+	/// expired. an access token which is already known to have expired is refreshed before the request is sent
+	///
+	fn with_reauth<T>(&self, request: impl Fn(&Self) -> VMInfoResult<T>) -> VMInfoResult<T> {
+		let refreshed = self.refresh_if_expired(Self::refresh)?;
+		let client = refreshed.as_ref().unwrap_or(self);
+
+		let err = match request(client) {
+			Ok(r) => return Ok(r),
+			Err(err) => err,
+		};
+
+		let result = match err.kind() {
+			Kind::AuthenticationError(AuthErrorKind::MissingToken) => {
+				client.explain(&format!(
+					"the request had no access token, re-authenticating with the {} method",
+					client.auth_method()
+				));
+				client.reauth().and_then(|c| request(&c))
+			}
+			Kind::AuthenticationError(AuthErrorKind::TokenExpired) => {
+				client.warn_if_clock_skewed();
+
+				match client.auth_method() {
+					Method::ClientCredentials => {
+						client.explain("the access token was rejected as expired, requesting a new one with the client secret");
+						client.reauth().and_then(|c| request(&c))
+					}
+					Method::ManagedIdentity => {
+						client.explain("the access token was rejected as expired, requesting a new one for the managed identity");
+						client.reauth().and_then(|c| request(&c))
+					}
+					Method::ClientCertificate => {
+						client.explain(
+							"the access token was rejected as expired, requesting a new one with the client certificate",
+						);
+						client.reauth().and_then(|c| request(&c))
+					}
+					Method::AzureCli => {
+						client.explain(
+							"the access token was rejected as expired, asking the Azure CLI for a new one",
+						);
+						client.reauth().and_then(|c| request(&c))
+					}
+					Method::DeviceCode => {
+						client.explain("the access token was rejected as expired, exchanging the refresh token for a new one");
+						client
+							.clone()
+							.exchange_refresh_token()
+							.and_then(|c| request(&c))
+					}
+				}
+			}
+			_ => {
+				client.explain(&format!(
+					"the request failed without needing re-authentication: {}",
+					err
+				));
+				return Err(err);
+			}
+		};
+
+		client.explain(&match &result {
+			Ok(_) => "re-authenticated and retried the request successfully".to_string(),
+			Err(err) => format!("the request failed after re-authenticating: {}", err),
+		});
+		result
+	}
+
+	///
+	/// refreshes the tokens of this client with refresh when its access token is known to have expired, saving a
+	/// request that Azure would reject. returns None when the access token can still be used
+	///
+	fn refresh_if_expired(
+		&self,
+		refresh: impl FnOnce(&Self) -> VMInfoResult<Self>,
+	) -> VMInfoResult<Option<Self>> {
+		match self.token_expires_at() {
+			Some(expires_at) if auth::is_expired(&expires_at, self.clock_skew) => {
+				self.explain(&format!(
+					"the access token expired at {}, refreshing it before sending the request",
+					expires_at
+				));
+				refresh(self).map(Some)
+			}
+			_ => Ok(None),
+		}
+	}
+
+	///
+	/// the display name of each subscription visible to this client (limited to the configured subscriptions), keyed by
+	/// subscription ID
+	///
+	/// the list is fetched from Resource Graph once and shared by every clone of this client until it is refreshed
+	/// with refresh_subscriptions()
+	///
+	pub fn subscription_names(&self) -> VMInfoResult<BTreeMap<String, String>> {
+		if let Some(names) = self.subscription_names.lock().unwrap().as_ref() {
+			return Ok(names.clone());
+		}
+
+		self.refresh_subscriptions()
+	}
+
+	///
+	/// fetches the subscriptions visible to this client from Resource Graph, replacing the list cached by
+	/// subscription_names()
+	///
+	pub fn refresh_subscriptions(&self) -> VMInfoResult<BTreeMap<String, String>> {
+		let req_body = QueryRequest::subscriptions(&self.subscriptions);
+		let resp: SubscriptionsResponse = self.with_reauth(|client| client.post_query(&req_body))?;
+
+		let names: BTreeMap<String, String> = resp
+			.data
+			.into_iter()
+			.map(|s| (s.subscription_id, s.name))
+			.collect();
+		*self.subscription_names.lock().unwrap() = Some(names.clone());
+
+		Ok(names)
+	}
+
+	///
+	/// lists every subscription the access token of this client can see, ignoring the configured subscriptions. a
+	/// configured subscription missing from this list can not be queried by this identity
+	///
+	pub fn subscriptions_for_identity(&self) -> VMInfoResult<Vec<Subscription>> {
+		let req_body = QueryRequest::subscriptions(&None);
+		let resp: SubscriptionsResponse = self.with_reauth(|client| client.post_query(&req_body))?;
+
+		Ok(resp.data)
+	}
+
+	/// creates a request to pull VM meta and instance data from Azure Resource Graph with filters and extra options possible
+	///
+	/// ## Arguments
+	/// - query_operand: specifies either a list of full host names for the VM hosts wishing to get data for XOR one or more regular expressions to match one or more hosts.
+	/// 							 	 if match_regexp = true, hosts matching any of the query_operands are matched
+	/// - match_regexp: specifies whether to match regular expressions instead of full host names
+	/// - show_extensions: specifies that vminfo should also return a list of VM extensions that are installed for each host matched
+	/// - skip: optionally specifies a number of host results to skip to help while working within the constraints of Resource Graph API's paging responses
+	/// - top: optionally specifies a number of hosts to return for each 'page' (MAXIMUM ALLOWED: 1000)
+	fn request(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		let req_body = QueryRequest::make_with_options(
+			query_operand,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			skip,
+			top,
+			&self.subscriptions,
+			&self.query_options,
+		);
+		let r: QueryResponse = self.post_query(&req_body)?;
+
+		Self::checked_page(r, query_operand)
+	}
+
+	///
+	/// marks a page of results as truncated when more matched than were returned, failing when nothing matched
+	///
+	fn checked_page(
+		mut r: QueryResponse,
+		query_operand: &Vec<String>,
+	) -> VMInfoResult<QueryResponse> {
+		// a full page with more matches than returned is truncated, even when Resource Graph did not say so
+		if r.data.len() >= query::MAX_PAGE_SIZE && r.total_results > r.data.len() as u64 {
+			r.truncated = true;
+		}
+
+		if r.data.len() == 0 {
+			return Err(error::none_found(
+				None::<error::Error>,
+				format!(
+					"no virtual machines were found with the provided query: {:?}",
+					query_operand
+				)
+				.as_str(),
+			));
+		}
+
+		Ok(r)
+	}
+
+	///
+	/// sends a query request to Resource Graph, parsing a successful response as T and mapping any error response from
+	/// Azure to an authentication error
+	///
+	fn post_query<T: DeserializeOwned>(&self, req_body: &QueryRequest) -> VMInfoResult<T> {
+		let (access_token, body) = self.prepare_query(req_body)?;
+
+		let raw = transport::post_json_with_backoff(
+			self.transport.as_ref(),
+			&self.retry_policy,
+			std::thread::sleep,
+			&self.resource_graph_url(),
+			&access_token,
+			&self.headers,
+			&body,
+		)?;
+
+		Self::parse_query_response(raw)
+	}
+
+	///
+	/// the Resource Graph endpoint of the Azure cloud the client queries, or of the management endpoint override
+	///
+	fn resource_graph_url(&self) -> String {
+		format!(
+			"{}{}",
+			self
+				.management_endpoint
+				.as_deref()
+				.unwrap_or(self.azure_environment.management_endpoint()),
+			RESOURCE_GRAPH_PATH
+		)
+	}
+
+	///
+	/// validates a request body and serializes it, returning it with the access token it must be sent with
+	///
+	fn prepare_query(&self, req_body: &QueryRequest) -> VMInfoResult<(String, String)> {
+		req_body.validate()?;
+
+		let access_token_opt = match self.access_token() {
+			Some(t) => t,
+			_ => Err(error::auth(
+				None::<Error>,
+				AuthErrorKind::MissingToken,
+				"no access token provided for request",
+			))?,
+		};
+		auth::verify_token_tenant(&access_token_opt, &self.tenant_id)?;
 
-				match self.clone().result_cache {
-					Some(cache) => {
-						for (_, vm) in r.clone().data.into_iter().enumerate() {
-							cache.put(vm.clone().vm_name.unwrap().to_lowercase().as_str(), &vm)?;
-						}
-					}
-					_ => (),
-				};
+		let body = serde_json::to_string(req_body)
+			.map_err(|err| error::other(Some(err), "failed to generate JSON for query request"))?;
 
-				Ok(r)
-			}
+		Ok((access_token_opt, body))
+	}
+
+	///
+	/// parses a raw Resource Graph response, mapping any error it reports to a vminfo Error
+	///
+	fn parse_query_response<T: DeserializeOwned>(raw: TransportResponse) -> VMInfoResult<T> {
+		let resp: QueryResponseType<T> = serde_json::from_str(&raw.body).map_err(|err| {
+			error::request(
+				Some(err),
+				Some(raw.status),
+				"could not parse vm info into valid response object",
+			)
+		})?;
+
+		match resp {
+			QueryResponseType::Ok(r) => Ok(r),
 			QueryResponseType::Err { error } => {
 				return Err(error::auth(
 					None::<Error>,
@@ -604,14 +2009,43 @@ where
 		self.tenant_id = client_credentials.tenant_id;
 		self.client_id = client_credentials.client_id;
 		self.client_secret = client_credentials.client_secret;
+		self.client_certificate = client_credentials.certificate;
+		if client_credentials.method.is_some() {
+			self.login_method = client_credentials.method;
+		}
 
 		self.active_tokens = Some(AuthTokens {
 			access_token: client_credentials.tokens.access_token,
 			refresh_token: client_credentials.tokens.refresh_token,
+			expires_at: client_credentials.tokens.expires_at,
 		});
 
 		Ok(self.clone())
 	}
+
+	///
+	/// sets the client credentials and tokens from a credential source (ie: a CredentialChain) rather than the token
+	/// store. credentials without an access token (ie: a client secret from the environment) leave the client to log
+	/// in before its first query
+	///
+	pub fn load_credentials_from(&mut self, source: &dyn CredentialSource) -> VMInfoResult<Self> {
+		let client_credentials = source.credentials()?;
+
+		self.tenant_id = client_credentials.tenant_id;
+		self.client_id = client_credentials.client_id;
+		self.client_secret = client_credentials.client_secret;
+		self.client_certificate = client_credentials.certificate;
+		if client_credentials.method.is_some() {
+			self.login_method = client_credentials.method;
+		}
+
+		self.active_tokens = match client_credentials.tokens.access_token.is_empty() {
+			true => None,
+			false => Some(client_credentials.tokens),
+		};
+
+		Ok(self.clone())
+	}
 	///
 	/// get an immutable access token from Client's memory
 	///
@@ -636,17 +2070,66 @@ where
 	///
 	pub fn exchange_refresh_token(&mut self) -> VMInfoResult<Self> {
 		let rt = self.refresh_token();
-		let tokens: AuthTokens = auth::exchange_refresh_tokens(&self.tenant_id, &self.client_id, rt)?;
+		let tokens: AuthTokens = auth::exchange_refresh_tokens(
+			&self.tenant_id,
+			&self.client_id,
+			rt,
+			self.min_tls_version,
+			self.azure_environment,
+		)?;
 
 		self.active_tokens = Some(AuthTokens {
 			access_token: tokens.access_token,
 			refresh_token: tokens.refresh_token,
+			expires_at: tokens.expires_at,
 		});
 
 		self.save_credentials()?;
 
 		Ok(self.clone())
 	}
+	///
+	/// exports the persisted credentials (including any client secret and tokens) as JSON for backup or migration
+	///
+	/// **warning**: the exported data contains live secrets. anyone holding it can authenticate as this client
+	///
+	pub fn export_credentials(&self) -> VMInfoResult<String> {
+		let client_credentials = self.token_store.read()?;
+
+		serde_json::to_string_pretty(&client_credentials)
+			.map_err(|err| error::other(Some(err), "failed to generate JSON for credential export"))
+	}
+
+	///
+	/// imports credentials previously produced by export_credentials(), writing them to persistant storage and
+	/// loading them into the client
+	///
+	pub fn import_credentials(&mut self, data: &str) -> VMInfoResult<Self> {
+		let client_credentials = serde_json::from_str::<AzCredentials>(data).map_err(|err| {
+			error::auth(
+				Some(err),
+				AuthErrorKind::BadCredentials,
+				"could not parse imported credentials",
+			)
+		})?;
+
+		self.token_store.write(&client_credentials)?;
+
+		self.load_credentials()
+	}
+
+	///
+	/// ensures any pending writes to the result cache have been committed. safe to call when no cache is configured
+	///
+	/// call this before the client is dropped (ie: before a process exits) to avoid losing cached results
+	///
+	pub fn flush(&self) -> VMInfoResult<()> {
+		match &self.result_cache {
+			Some(cache) => cache.flush(),
+			None => Ok(()),
+		}
+	}
+
 	///
 	/// clears credentials from token/credential cache
 	///
@@ -683,3 +2166,1044 @@ where
 /// defines a Client which uses local disk storage to persist credential/token data for vminfo
 ///
 pub type LocalClient = Client<FileTokenStore, VMResultsCacheRedis>;
+
+#[cfg(test)]
+mod client_tests {
+	#[test]
+	fn flush_is_safe_with_or_without_cache() {
+		use crate::testing::{client, MemoryCache, MockTransport};
+
+		assert!(client(MockTransport::default(), None).flush().is_ok());
+		assert!(
+			client(MockTransport::default(), Some(MemoryCache::default()))
+				.flush()
+				.is_ok()
+		);
+	}
+
+	#[test]
+	fn is_authenticated_without_network() {
+		use crate::auth::AzCredentials;
+		use crate::persistance::PersistantStorage;
+		use crate::testing::{jwt, MemoryCache, MemoryTokenStore};
+		use crate::{AuthTokens, Client};
+
+		let client_with = |access_token: String, refresh_token: Option<String>| {
+			let store = MemoryTokenStore::default();
+			store
+				.write(&AzCredentials {
+					tenant_id: "tenant".to_string(),
+					client_id: "client".to_string(),
+					client_secret: None,
+					certificate: None,
+					method: None,
+					tokens: AuthTokens {
+						access_token,
+						refresh_token,
+						expires_at: None,
+					},
+				})
+				.unwrap();
+			Client::<MemoryTokenStore, MemoryCache>::from_components("", "", None, store, None, None)
+		};
+		let now = chrono::Utc::now().timestamp();
+
+		// no stored tokens
+		let empty: Client<MemoryTokenStore, MemoryCache> =
+			Client::from_components("", "", None, MemoryTokenStore::default(), None, None);
+		assert!(!empty.is_authenticated());
+
+		// valid access token
+		let valid = jwt(serde_json::json!({ "exp": now + 3600 }));
+		assert!(client_with(valid, None).is_authenticated());
+
+		// expired access token, with and without a refresh token to renew it
+		let expired = jwt(serde_json::json!({ "exp": now - 60 }));
+		assert!(!client_with(expired.clone(), None).is_authenticated());
+		assert!(client_with(expired, Some("refresh".to_string())).is_authenticated());
+	}
+
+	#[test]
+	fn expired_managed_identity_token_is_renewed_through_imds() {
+		use httpmock::{Method::GET, MockServer};
+
+		use crate::auth::{AzCredentials, Method};
+		use crate::persistance::PersistantStorage;
+		use crate::testing::{jwt, MemoryCache, MemoryTokenStore};
+		use crate::{AuthTokens, Client};
+
+		let now = chrono::Utc::now().timestamp();
+		let store = MemoryTokenStore::default();
+		store
+			.write(&AzCredentials {
+				tenant_id: "tenant".to_string(),
+				client_id: "client".to_string(),
+				client_secret: None,
+				certificate: None,
+				method: Some(Method::ManagedIdentity),
+				tokens: AuthTokens {
+					access_token: jwt(serde_json::json!({ "exp": now - 60 })),
+					refresh_token: None,
+					expires_at: None,
+				},
+			})
+			.unwrap();
+
+		// a mock of the IMDS token endpoint
+		let renewed = jwt(serde_json::json!({ "exp": now + 3600 }));
+		let server = MockServer::start();
+		let imds = server.mock(|when, then| {
+			when
+				.method(GET)
+				.path("/metadata/identity/oauth2/token")
+				.header("Metadata", "true");
+			then
+				.status(200)
+				.header("content-type", "application/json")
+				.json_body(serde_json::json!({ "access_token": renewed, "expires_in": "3599" }));
+		});
+
+		let mut c: Client<MemoryTokenStore, MemoryCache> =
+			Client::from_components("", "", None, store, None, None)
+				.with_imds_endpoint(Some(server.url("/metadata/identity/oauth2/token")));
+		assert!(c.is_authenticated());
+
+		let c = c.load_credentials().unwrap();
+		assert_eq!(c.auth_method(), Method::ManagedIdentity);
+		assert_eq!(c.valid_access_token().unwrap(), renewed);
+		imds.assert();
+
+		// the renewed token is saved along with the method, so the next run renews it through IMDS again
+		let stored = c.token_store.read().unwrap();
+		assert_eq!(stored.tokens.access_token, renewed);
+		assert_eq!(stored.method, Some(Method::ManagedIdentity));
+	}
+
+	#[test]
+	fn auth_method_reason_names_the_input_it_was_chosen_from() {
+		use crate::auth::{ClientCertificate, Method};
+		use crate::testing::{client, MockTransport};
+
+		let mut c = client(MockTransport::default(), None);
+		c.client_secret = None;
+		assert_eq!(
+			c.auth_method_reason(),
+			"using the DeviceCode method since neither a client secret nor a certificate is stored"
+		);
+
+		c.client_secret = Some("secret".to_string());
+		assert_eq!(
+			c.auth_method_reason(),
+			"using the ClientCredentials method since a client secret is stored"
+		);
+
+		c.client_certificate = Some(ClientCertificate::new("cert.pem", None));
+		assert_eq!(
+			c.auth_method_reason(),
+			"using the ClientCertificate method since a client certificate is set"
+		);
+
+		c.login_method = Some(Method::AzureCli);
+		assert_eq!(
+			c.auth_method_reason(),
+			"using the AzureCli method since it was used to log in"
+		);
+	}
+
+	#[test]
+	fn certificate_login_reuses_stored_token() {
+		use crate::auth::{AzCredentials, ClientCertificate, Method};
+		use crate::persistance::PersistantStorage;
+		use crate::testing::{jwt, MemoryCache, MemoryTokenStore};
+		use crate::{AuthTokens, Client};
+
+		let valid = jwt(serde_json::json!({ "exp": chrono::Utc::now().timestamp() + 3600 }));
+		let store = MemoryTokenStore::default();
+		store
+			.write(&AzCredentials {
+				tenant_id: "tenant".to_string(),
+				client_id: "client".to_string(),
+				client_secret: None,
+				certificate: Some(ClientCertificate::new("/etc/vminfo/sp.pem", None)),
+				method: None,
+				tokens: AuthTokens {
+					access_token: valid.clone(),
+					refresh_token: None,
+					expires_at: None,
+				},
+			})
+			.unwrap();
+
+		// no certificate is read and no token is requested while the stored token is valid
+		let c =
+			Client::<MemoryTokenStore, MemoryCache>::from_components("", "", None, store, None, None)
+				.with_client_certificate(Some(ClientCertificate::new("/missing/sp.pem", None)))
+				.login_client_credentials_cert(false)
+				.unwrap();
+
+		assert_eq!(c.access_token(), Some(valid));
+		assert_eq!(c.auth_method(), Method::ClientCertificate);
+		assert_eq!(
+			c.client_certificate.unwrap().certificate_path,
+			"/missing/sp.pem"
+		);
+	}
+
+	#[test]
+	fn token_for_wrong_tenant_fails_fast() {
+		use crate::auth::AzCredentials;
+		use crate::error::{AuthErrorKind, Kind};
+		use crate::persistance::PersistantStorage;
+		use crate::testing::{jwt, query_response, vm, MemoryCache, MemoryTokenStore, MockTransport};
+		use crate::{AuthTokens, Client};
+
+		let store = MemoryTokenStore::default();
+		store
+			.write(&AzCredentials {
+				tenant_id: "72f988bf-86f1-41af-91ab-2d7cd011db47".to_string(),
+				client_id: "client".to_string(),
+				client_secret: None,
+				certificate: None,
+				method: None,
+				tokens: AuthTokens {
+					access_token: jwt(serde_json::json!({ "tid": "00000000-0000-0000-0000-000000000001" })),
+					refresh_token: None,
+					expires_at: None,
+				},
+			})
+			.unwrap();
+		let transport = MockTransport::new(vec![query_response(&[vm("linux-01")])]);
+
+		let err =
+			Client::<MemoryTokenStore, MemoryCache>::from_components("", "", None, store, None, None)
+				.with_transport(transport.clone())
+				.load_credentials()
+				.unwrap()
+				.query_vminfo(
+					&vec!["linux-01".to_string()],
+					false,
+					false,
+					false,
+					true,
+					None,
+					None,
+				)
+				.unwrap_err();
+
+		assert_eq!(
+			err.kind(),
+			Kind::AuthenticationError(AuthErrorKind::BadCredentials)
+		);
+		// the mismatch is caught before anything is sent to Resource Graph
+		assert!(transport.requests().is_empty());
+	}
+
+	#[test]
+	fn curl_command_replicates_request() {
+		use crate::query::QueryRequest;
+		use crate::testing::{client, MockTransport};
+
+		let client = client(MockTransport::new(vec![]), None)
+			.with_headers(vec![("X-Gateway-Key".to_string(), "abc".to_string())])
+			.unwrap();
+		let operand = vec!["linux-01".to_string()];
+		let curl = client
+			.curl_command(&operand, false, false, false, None, None)
+			.unwrap();
+
+		let body = serde_json::to_string(&QueryRequest::make(
+			&operand, false, false, false, None, None, &None,
+		))
+		.unwrap();
+		assert!(curl.starts_with(
+			"curl -X POST 'https://management.azure.com/providers/Microsoft.ResourceGraph/resources?api-version=2021-03-01'"
+		));
+		assert!(curl.contains("-H \"Authorization: Bearer $TOKEN\""));
+		assert!(curl.contains("-H 'X-Gateway-Key: abc'"));
+		// single quotes of KQL string literals are escaped for the shell
+		assert!(body.contains('\''));
+		assert!(curl.ends_with(&format!("--data-raw '{}'", body.replace('\'', "'\\''"))));
+		assert!(!curl.contains(&client.access_token().unwrap()));
+	}
+
+	#[test]
+	fn configured_headers_are_sent() {
+		use crate::testing::{client, query_response, vm, MockTransport};
+
+		let transport = MockTransport::new(vec![query_response(&[vm("linux-01")])]);
+		let headers = vec![
+			("X-Gateway-Key".to_string(), "abc123".to_string()),
+			("X-Trace-Id".to_string(), "trace-01".to_string()),
+		];
+
+		client(transport.clone(), None)
+			.with_headers(headers.clone())
+			.unwrap()
+			.query_vminfo(
+				&vec!["linux-01".to_string()],
+				false,
+				false,
+				false,
+				true,
+				None,
+				None,
+			)
+			.unwrap();
+
+		assert_eq!(transport.headers(), vec![headers]);
+		assert!(client(MockTransport::default(), None)
+			.with_headers(vec![("bad header".to_string(), "x".to_string())])
+			.is_err());
+	}
+
+	#[test]
+	fn query_all_follows_skip_token() {
+		use crate::testing::{client, vm, MockTransport};
+		use crate::transport::TransportResponse;
+		use crate::vm::VirtualMachine;
+
+		let page = |vms: Vec<VirtualMachine>, skip_token: Option<&str>| {
+			let mut body = serde_json::json!({ "totalRecords": 1500, "count": vms.len(), "data": vms });
+			if let Some(t) = skip_token {
+				body["$skipToken"] = serde_json::json!(t);
+				body["resultTruncated"] = serde_json::json!("true");
+			}
+			Ok(TransportResponse {
+				status: reqwest::StatusCode::OK,
+				body: body.to_string(),
+				retry_after: None,
+			})
+		};
+		let transport = MockTransport::new(vec![
+			page(
+				(0..1000).map(|i| vm(&format!("linux-{}", i))).collect(),
+				Some("page-2"),
+			),
+			page(
+				(1000..1500).map(|i| vm(&format!("linux-{}", i))).collect(),
+				None,
+			),
+		]);
+
+		let resp = client(transport.clone(), None)
+			.query_vminfo_all(&vec!["linux-.*".to_string()], true, false, false, true)
+			.unwrap();
+
+		assert_eq!(resp.data.len(), 1500);
+		assert_eq!(resp.pages, 2);
+		assert!(!resp.truncated);
+
+		let requests = transport.requests();
+		assert_eq!(requests.len(), 2);
+		assert!(requests[0].contains("\"$skipToken\":null"));
+		assert!(requests[1].contains("\"$skipToken\":\"page-2\""));
+	}
+
+	#[test]
+	fn truncated_query_with_cache_hits_pages_through_misses() {
+		use crate::caching::Cache;
+		use crate::testing::{client, vm, MemoryCache, MockTransport};
+		use crate::transport::TransportResponse;
+
+		let page = |names: &[&str], skip_token: Option<&str>| {
+			let vms: Vec<_> = names.iter().map(|n| vm(n)).collect();
+			let mut body = serde_json::json!({ "totalRecords": 5, "count": vms.len(), "data": vms });
+			if let Some(t) = skip_token {
+				body["$skipToken"] = serde_json::json!(t);
+				body["resultTruncated"] = serde_json::json!("true");
+			}
+			Ok(TransportResponse {
+				status: reqwest::StatusCode::OK,
+				body: body.to_string(),
+				retry_after: None,
+			})
+		};
+		let cache = MemoryCache::default();
+		cache.put("web-00", &vm("web-00")).unwrap();
+		let transport = MockTransport::new(vec![
+			page(&["web-01", "web-02", "web-03"], Some("page-2")),
+			page(&["web-04", "web-05"], None),
+		]);
+
+		let operands: Vec<String> = (0..6).map(|i| format!("web-0{}", i)).collect();
+		let resp = client(transport.clone(), Some(cache.clone()))
+			.query_vminfo_all(&operands, false, false, false, false)
+			.unwrap();
+
+		// the skip token is followed with the same (missed) operands it was issued for
+		let requests = transport.requests();
+		assert_eq!(requests.len(), 2);
+		assert!(requests[1].contains("\"$skipToken\":\"page-2\""));
+		for request in &requests {
+			assert!(!request.contains("web-00"));
+			assert!(request.contains("web-05"));
+		}
+
+		let names: Vec<&str> = resp
+			.data
+			.iter()
+			.filter_map(|v| v.vm_name.as_deref())
+			.collect();
+		assert_eq!(
+			names,
+			operands.iter().map(String::as_str).collect::<Vec<_>>()
+		);
+		assert_eq!((resp.total_results, resp.cache_hits, resp.pages), (6, 1, 2));
+		// every fetched page is written to the result cache
+		assert!(cache.get("web-05").is_ok());
+	}
+
+	#[test]
+	fn buffered_vm_limit_stops_paging() {
+		use crate::error::Kind;
+		use crate::testing::{client, vm, MockTransport};
+		use crate::transport::TransportResponse;
+
+		let page = |first: usize, skip_token: Option<&str>| {
+			let vms: Vec<_> = (first..first + 3)
+				.map(|i| vm(&format!("linux-{}", i)))
+				.collect();
+			let mut body = serde_json::json!({ "totalRecords": 9, "count": 3, "data": vms });
+			if let Some(t) = skip_token {
+				body["$skipToken"] = serde_json::json!(t);
+			}
+			Ok(TransportResponse {
+				status: reqwest::StatusCode::OK,
+				body: body.to_string(),
+				retry_after: None,
+			})
+		};
+		let pages = || {
+			vec![
+				page(0, Some("page-2")),
+				page(3, Some("page-3")),
+				page(6, None),
+			]
+		};
+
+		// the second page takes the buffer over the limit, so the last page is never requested
+		let transport = MockTransport::new(pages());
+		let err = client(transport.clone(), None)
+			.with_max_buffered_vms(Some(5))
+			.query_vminfo_all(&vec!["linux-.*".to_string()], true, false, false, true)
+			.unwrap_err();
+		assert_eq!(err.kind(), Kind::Other);
+		assert!(err.to_string().contains("limit of 5 VMs"));
+		assert_eq!(transport.requests().len(), 2);
+
+		let resp = client(MockTransport::new(pages()), None)
+			.with_max_buffered_vms(Some(9))
+			.query_vminfo_all(&vec!["linux-.*".to_string()], true, false, false, true)
+			.unwrap();
+		assert_eq!(resp.data.len(), 9);
+	}
+
+	#[test]
+	fn query_map_keyed_by_name() {
+		use crate::error::Kind;
+		use crate::testing::{client, query_response, vm, MockTransport};
+
+		let vms = vec![vm("Web-01"), vm("web-02")];
+		let transport = MockTransport::new(vec![query_response(&vms)]);
+		let map = client(transport, None)
+			.query_vminfo_map(&vec!["web-.*".to_string()], true, false, false, true, false)
+			.unwrap();
+
+		let mut keys: Vec<&String> = map.keys().collect();
+		keys.sort();
+		assert_eq!(keys, vec!["web-01", "web-02"]);
+		assert_eq!(map["web-01"].vm_name.as_deref(), Some("Web-01"));
+
+		let duplicates = vec![vm("web-01"), vm("WEB-01")];
+		let transport = MockTransport::new(vec![query_response(&duplicates)]);
+		let err = client(transport, None)
+			.query_vminfo_map(&vec!["web-.*".to_string()], true, false, false, true, true)
+			.unwrap_err();
+		assert_eq!(err.kind(), Kind::Other);
+	}
+
+	#[test]
+	fn fan_out_attributes_results_to_subscriptions() {
+		use crate::error::{self, Error, Kind, VMInfoResult};
+		use crate::testing::{client, vm};
+		use crate::transport::{Transport, TransportResponse};
+
+		/// responds to each query with VMs named after the (single) subscription it was sent to
+		#[derive(Debug)]
+		struct PerSubscriptionTransport;
+
+		impl Transport for PerSubscriptionTransport {
+			fn post_json(
+				&self,
+				_url: &str,
+				_access_token: &str,
+				_headers: &[(String, String)],
+				body: &str,
+			) -> VMInfoResult<TransportResponse> {
+				let req: serde_json::Value = serde_json::from_str(body).unwrap();
+				let subscription = req["subscriptions"][0].as_str().unwrap().to_string();
+				if subscription == "sub-broken" {
+					return Err(error::request(
+						None::<Error>,
+						Some(reqwest::StatusCode::FORBIDDEN),
+						"not authorized for subscription",
+					));
+				}
+
+				let vms = vec![
+					vm(&format!("{}-web-01", subscription)),
+					vm(&format!("{}-web-02", subscription)),
+				];
+				Ok(TransportResponse {
+					status: reqwest::StatusCode::OK,
+					body: serde_json::json!({ "totalRecords": 2, "count": 2, "data": vms }).to_string(),
+					retry_after: None,
+				})
+			}
+		}
+
+		let subscriptions = vec![
+			"sub-a".to_string(),
+			"sub-broken".to_string(),
+			"sub-b".to_string(),
+		];
+		let resp = client(Default::default(), None)
+			.with_transport(PerSubscriptionTransport)
+			.with_subscriptions(Some(subscriptions))
+			.query_vminfo_fan_out(&vec![".*web.*".to_string()], true, false, false, 2)
+			.unwrap();
+
+		let attributed: Vec<(String, String)> = resp
+			.response
+			.data
+			.iter()
+			.map(|vm| {
+				(
+					vm.subscription_id().unwrap().to_string(),
+					vm.vm_name.clone().unwrap(),
+				)
+			})
+			.collect();
+		assert_eq!(
+			attributed,
+			vec![
+				("sub-a".to_string(), "sub-a-web-01".to_string()),
+				("sub-a".to_string(), "sub-a-web-02".to_string()),
+				("sub-b".to_string(), "sub-b-web-01".to_string()),
+				("sub-b".to_string(), "sub-b-web-02".to_string()),
+			]
+		);
+		assert_eq!(resp.response.total_results, 4);
+		assert_eq!(resp.failures.len(), 1);
+		assert_eq!(resp.failures[0].0, "sub-broken");
+		assert_eq!(
+			resp.failures[0].1.kind(),
+			Kind::RequestError(Some(reqwest::StatusCode::FORBIDDEN))
+		);
+	}
+
+	#[test]
+	fn subscription_list_is_cached_until_refreshed() {
+		use crate::testing::{client, MockTransport};
+		use crate::transport::TransportResponse;
+
+		let subscriptions = |names: &[(&str, &str)]| {
+			let data: Vec<serde_json::Value> = names
+				.iter()
+				.map(|(id, name)| serde_json::json!({ "subscriptionId": id, "name": name }))
+				.collect();
+			Ok(TransportResponse {
+				status: reqwest::StatusCode::OK,
+				body: serde_json::json!({ "totalRecords": data.len(), "count": data.len(), "data": data })
+					.to_string(),
+				retry_after: None,
+			})
+		};
+		let transport = MockTransport::new(vec![
+			subscriptions(&[("0000-1111", "prod")]),
+			subscriptions(&[("0000-1111", "prod"), ("2222-3333", "dev")]),
+		]);
+		let client = client(transport.clone(), None);
+
+		let first = client.subscription_names().unwrap();
+		assert_eq!(first.get("0000-1111").map(|n| n.as_str()), Some("prod"));
+		// clones share the cached list, so no further requests are made
+		assert_eq!(client.clone().subscription_names().unwrap(), first);
+		assert_eq!(transport.requests().len(), 1);
+		assert!(transport.requests()[0].contains("microsoft.resources/subscriptions"));
+
+		let refreshed = client.refresh_subscriptions().unwrap();
+		assert_eq!(refreshed.len(), 2);
+		assert_eq!(client.subscription_names().unwrap(), refreshed);
+		assert_eq!(transport.requests().len(), 2);
+	}
+
+	#[test]
+	fn subscriptions_for_identity_ignores_configured_subscriptions() {
+		use crate::testing::{client, MockTransport};
+		use crate::transport::TransportResponse;
+
+		let transport = MockTransport::new(vec![Ok(TransportResponse {
+			status: reqwest::StatusCode::OK,
+			body: serde_json::json!({
+				"totalRecords": 2,
+				"count": 2,
+				"data": [
+					{ "subscriptionId": "0000-1111", "name": "prod" },
+					{ "subscriptionId": "2222-3333", "name": "dev" }
+				]
+			})
+			.to_string(),
+			retry_after: None,
+		})]);
+		let client =
+			client(transport.clone(), None).with_subscriptions(Some(vec!["4444-5555".to_string()]));
+
+		let visible = client.subscriptions_for_identity().unwrap();
+		let ids: Vec<&str> = visible.iter().map(|s| s.subscription_id.as_str()).collect();
+		assert_eq!(ids, ["0000-1111", "2222-3333"]);
+		assert_eq!(visible[1].name, "dev");
+
+		let request: serde_json::Value = serde_json::from_str(&transport.requests()[0]).unwrap();
+		assert!(request["query"]
+			.as_str()
+			.unwrap()
+			.contains("microsoft.resources/subscriptions"));
+		assert!(request.get("subscriptions").is_none_or(|s| s.is_null()));
+	}
+
+	#[test]
+	fn identical_query_within_ttl_is_served_from_response_cache() {
+		use std::time::Duration;
+
+		use crate::caching::response_cache::ResponseCache;
+		use crate::testing::{client, query_response, vm, MemoryResponseCache, MockTransport};
+
+		let query = |client: &crate::Client<_, _>, name: &str| {
+			client.query_vminfo(
+				&vec![name.to_string()],
+				false,
+				false,
+				false,
+				false,
+				None,
+				None,
+			)
+		};
+
+		let transport = MockTransport::new(vec![
+			query_response(&[vm("linux-01")]),
+			query_response(&[vm("linux-02")]),
+		]);
+		let cache = MemoryResponseCache::default();
+		let client = client(transport.clone(), None).with_response_cache(Some(ResponseCache::new(
+			cache.clone(),
+			Duration::from_secs(30),
+		)));
+		assert_eq!(client.last_query_time(), None);
+
+		let first = query(&client, "linux-01").unwrap();
+		let queried_at = client.last_query_time();
+		assert!(queried_at.is_some());
+
+		let second = query(&client, "linux-01").unwrap();
+		assert_eq!(second.data[0].vm_name.as_deref(), Some("linux-01"));
+		assert_eq!(second.total_results, first.total_results);
+		// no HTTP call is made, and the response keeps the time it was fetched
+		assert_eq!(transport.requests().len(), 1);
+		assert_eq!(client.last_query_time(), queried_at);
+
+		// a different query has a different signature
+		query(&client, "linux-02").unwrap();
+		assert_eq!(transport.requests().len(), 2);
+		assert_eq!(cache.len(), 2);
+	}
+
+	#[test]
+	fn cached_responses_are_not_shared_between_tenants() {
+		use std::time::Duration;
+
+		use crate::caching::response_cache::ResponseCache;
+		use crate::testing::{client, query_response, vm, MemoryResponseCache, MockTransport};
+
+		let cache = MemoryResponseCache::default();
+		let tenant_client = |tenant_id: &str, transport: &MockTransport| {
+			let mut c = client(transport.clone(), None).with_response_cache(Some(ResponseCache::new(
+				cache.clone(),
+				Duration::from_secs(30),
+			)));
+			c.tenant_id = tenant_id.to_string();
+			c
+		};
+		let query = |client: &crate::Client<_, _>| {
+			client
+				.query_vminfo(
+					&vec!["linux-01".to_string()],
+					false,
+					false,
+					false,
+					false,
+					None,
+					None,
+				)
+				.unwrap()
+		};
+
+		let first_transport = MockTransport::new(vec![query_response(&[vm("linux-01")])]);
+		let second_transport = MockTransport::new(vec![query_response(&[vm("linux-01")])]);
+		query(&tenant_client("first-tenant", &first_transport));
+		query(&tenant_client("second-tenant", &second_transport));
+
+		// the same query in another tenant is sent to Resource Graph rather than served the first tenant's response
+		assert_eq!(first_transport.requests().len(), 1);
+		assert_eq!(second_transport.requests().len(), 1);
+		assert_eq!(cache.len(), 2);
+
+		// nor is a response from another management endpoint served
+		let mock_transport = MockTransport::new(vec![query_response(&[vm("linux-01")])]);
+		query(
+			&tenant_client("first-tenant", &mock_transport)
+				.with_management_endpoint(Some("http://127.0.0.1:8080".to_string()))
+				.unwrap(),
+		);
+		assert_eq!(mock_transport.requests().len(), 1);
+		assert_eq!(cache.len(), 3);
+	}
+
+	#[test]
+	fn expired_response_is_queried_again() {
+		use std::time::Duration;
+
+		use crate::caching::response_cache::ResponseCache;
+		use crate::testing::{client, query_response, vm, MemoryResponseCache, MockTransport};
+
+		let transport = MockTransport::new(vec![
+			query_response(&[vm("linux-01")]),
+			query_response(&[vm("linux-01")]),
+		]);
+		let client = client(transport.clone(), None).with_response_cache(Some(ResponseCache::new(
+			MemoryResponseCache::default(),
+			Duration::ZERO,
+		)));
+
+		for _ in 0..2 {
+			client
+				.query_vminfo(
+					&vec!["linux-01".to_string()],
+					false,
+					false,
+					false,
+					false,
+					None,
+					None,
+				)
+				.unwrap();
+		}
+
+		assert_eq!(transport.requests().len(), 2);
+	}
+
+	#[test]
+	fn expired_token_is_refreshed_before_use() {
+		use crate::testing::{client, jwt, MockTransport};
+		use crate::AuthTokens;
+
+		let valid = jwt(serde_json::json!({ "exp": chrono::Utc::now().timestamp() + 3600 }));
+		let expired = jwt(serde_json::json!({ "exp": chrono::Utc::now().timestamp() - 60 }));
+
+		let mut c = client(MockTransport::default(), None);
+		c.active_tokens = Some(AuthTokens {
+			access_token: valid.clone(),
+			refresh_token: None,
+			expires_at: None,
+		});
+		let token = c
+			.fresh_access_token(|_| panic!("a valid token must not be refreshed"))
+			.unwrap();
+		assert_eq!(token, valid);
+
+		c.active_tokens = Some(AuthTokens {
+			access_token: expired,
+			refresh_token: Some("refresh".to_string()),
+			expires_at: None,
+		});
+		let mut refreshed = false;
+		let token = c
+			.fresh_access_token(|c| {
+				refreshed = true;
+				let mut c = c.clone();
+				c.active_tokens = Some(AuthTokens {
+					access_token: valid.clone(),
+					refresh_token: Some("refresh".to_string()),
+					expires_at: None,
+				});
+				Ok(c)
+			})
+			.unwrap();
+		assert!(refreshed);
+		assert_eq!(token, valid);
+	}
+
+	#[test]
+	fn known_expiry_is_refreshed_before_sending() {
+		use crate::testing::{client, MockTransport};
+		use crate::AuthTokens;
+
+		let mut c = client(MockTransport::default(), None);
+		c.active_tokens = Some(AuthTokens {
+			access_token: "token".to_string(),
+			refresh_token: Some("refresh".to_string()),
+			expires_at: Some(chrono::Utc::now() + chrono::Duration::hours(1)),
+		});
+		assert!(c
+			.refresh_if_expired(|_| panic!("a valid token must not be refreshed"))
+			.unwrap()
+			.is_none());
+
+		// the expiry is saved with the tokens, so it is still known after loading them again
+		let expired_at = chrono::Utc::now() - chrono::Duration::minutes(1);
+		c.active_tokens = Some(AuthTokens {
+			expires_at: Some(expired_at),
+			..c.active_tokens.clone().unwrap()
+		});
+		c.save_credentials().unwrap();
+		let c = c.load_credentials().unwrap();
+		assert_eq!(
+			c.active_tokens.as_ref().unwrap().expires_at,
+			Some(expired_at)
+		);
+
+		let refreshed = c
+			.refresh_if_expired(|c| {
+				let mut c = c.clone();
+				c.active_tokens = Some(AuthTokens {
+					access_token: "new-token".to_string(),
+					refresh_token: Some("refresh".to_string()),
+					expires_at: Some(chrono::Utc::now() + chrono::Duration::hours(1)),
+				});
+				Ok(c)
+			})
+			.unwrap()
+			.unwrap();
+		assert_eq!(refreshed.access_token().unwrap(), "new-token");
+	}
+
+	#[test]
+	fn rate_limited_query_is_retried() {
+		use std::time::Duration;
+
+		use crate::testing::{client, query_response, vm, MockTransport};
+		use crate::transport::{RetryPolicy, TransportResponse};
+
+		let throttled = || {
+			Ok(TransportResponse {
+				status: reqwest::StatusCode::TOO_MANY_REQUESTS,
+				body: r#"{"error": {"code": "RateLimiting", "message": "Please provide below info when asking for support"}}"#
+					.to_string(),
+				retry_after: Some(Duration::ZERO),
+			})
+		};
+		let transport = MockTransport::new(vec![
+			throttled(),
+			throttled(),
+			query_response(&[vm("linux-01")]),
+		]);
+
+		let resp = client(transport.clone(), None)
+			.with_retry_policy(RetryPolicy {
+				base_delay: Duration::ZERO,
+				..RetryPolicy::default()
+			})
+			.query_vminfo(
+				&vec!["linux-01".to_string()],
+				false,
+				false,
+				false,
+				true,
+				None,
+				None,
+			)
+			.unwrap();
+		assert_eq!(resp.total_results, 1);
+		assert_eq!(transport.requests().len(), 3);
+	}
+
+	#[test]
+	fn queries_are_sent_to_the_azure_environment() {
+		use crate::cloud::AzureEnvironment;
+		use crate::testing::{client, query_response, vm, MockTransport};
+
+		let query = |azure_environment: Option<AzureEnvironment>| {
+			let transport = MockTransport::new(vec![query_response(&[vm("linux-01")])]);
+			let mut client = client(transport.clone(), None);
+			if let Some(azure_environment) = azure_environment {
+				client = client.with_azure_environment(azure_environment);
+			}
+			client
+				.query_vminfo(
+					&vec!["linux-01".to_string()],
+					false,
+					false,
+					false,
+					true,
+					None,
+					None,
+				)
+				.unwrap();
+			transport.urls()
+		};
+
+		// the public cloud is used by default
+		assert_eq!(
+			query(None),
+			vec!["https://management.azure.com/providers/Microsoft.ResourceGraph/resources?api-version=2021-03-01"]
+		);
+		assert_eq!(
+			query(Some(AzureEnvironment::UsGov)),
+			vec!["https://management.usgovcloudapi.net/providers/Microsoft.ResourceGraph/resources?api-version=2021-03-01"]
+		);
+	}
+
+	#[test]
+	fn query_round_trips_through_management_endpoint_override() {
+		use httpmock::{Method::POST, MockServer};
+
+		use crate::testing::{client, query_response, vm, MockTransport};
+		use crate::transport::HttpTransport;
+
+		// a mock of Resource Graph which answers the query
+		let server = MockServer::start();
+		let endpoint = server.url("/");
+		let resource_graph = server.mock(|when, then| {
+			when
+				.method(POST)
+				.path("/providers/Microsoft.ResourceGraph/resources")
+				.query_param("api-version", "2021-03-01")
+				.body_contains("linux-0[12]");
+			then
+				.status(200)
+				.header("content-type", "application/json")
+				.body(
+					query_response(&[vm("linux-01"), vm("linux-02")])
+						.unwrap()
+						.body,
+				);
+		});
+
+		let resp = client(MockTransport::new(vec![]), None)
+			.with_transport(HttpTransport::default())
+			.with_management_endpoint(Some(endpoint))
+			.unwrap()
+			.query_vminfo(
+				&vec!["linux-0[12]".to_string()],
+				true,
+				false,
+				false,
+				true,
+				None,
+				None,
+			)
+			.unwrap();
+
+		resource_graph.assert();
+		assert_eq!(resp.total_results, 2);
+		assert_eq!(resp.data[1].vm_name.as_deref(), Some("linux-02"));
+
+		assert!(client(MockTransport::new(vec![]), None)
+			.with_management_endpoint(Some("ftp://localhost".to_string()))
+			.is_err());
+	}
+
+	#[test]
+	fn batches_are_delayed() {
+		use std::time::Duration;
+
+		use crate::testing::{client, query_response, vm, MockTransport};
+
+		let transport = MockTransport::new(vec![
+			query_response(&[vm("linux-01"), vm("linux-02")]),
+			query_response(&[vm("linux-03"), vm("linux-04")]),
+			query_response(&[vm("linux-05")]),
+		]);
+		let client = client(transport.clone(), None);
+		let names: Vec<String> = (1..=5).map(|i| format!("linux-0{}", i)).collect();
+
+		let mut sleeps: Vec<Duration> = Vec::new();
+		let resp = client
+			.query_in_batches(
+				&names,
+				false,
+				false,
+				false,
+				2,
+				Duration::from_millis(250),
+				|d| sleeps.push(d),
+			)
+			.unwrap();
+
+		let requests = transport.requests();
+		assert_eq!(requests.len(), 3);
+		assert!(requests[1].contains("('linux-03', 'linux-04')"));
+		assert_eq!(resp.data.len(), 5);
+		// the delay is only applied between batches
+		assert_eq!(sleeps, vec![Duration::from_millis(250); 2]);
+	}
+
+	#[test]
+	fn nocache_neither_reads_nor_writes_caches() {
+		use std::time::Duration;
+
+		use crate::caching::response_cache::ResponseCache;
+		use crate::caching::Cache;
+		use crate::testing::{
+			client, query_response, vm, MemoryCache, MemoryResponseCache, MockTransport,
+		};
+
+		let transport = MockTransport::new(vec![query_response(&[vm("linux-02")])]);
+		let cache = MemoryCache::default();
+		cache.put("linux-01", &vm("linux-01")).unwrap();
+		let response_cache = MemoryResponseCache::default();
+		let client = client(transport.clone(), Some(cache.clone())).with_response_cache(Some(
+			ResponseCache::new(response_cache.clone(), Duration::from_secs(30)),
+		));
+
+		client
+			.query_vminfo(
+				&vec!["linux-01".to_string(), "linux-02".to_string()],
+				false,
+				false,
+				false,
+				true,
+				None,
+				None,
+			)
+			.unwrap();
+
+		// every operand is fetched (including the cached one) and nothing is written back
+		assert_eq!(transport.requests().len(), 1);
+		assert!(transport.requests()[0].contains("linux-01"));
+		assert_eq!(cache.len(), 1);
+		assert!(cache.get("linux-02").is_err());
+		assert_eq!(response_cache.len(), 0);
+	}
+
+	#[test]
+	fn export_import_credentials_round_trip() {
+		use crate::persistance::PersistantStorage;
+		use crate::testing::{client, MemoryCache, MemoryTokenStore, MockTransport};
+		use crate::Client;
+
+		let source = client(MockTransport::default(), None);
+		let exported = source.export_credentials().unwrap();
+
+		let store = MemoryTokenStore::default();
+		let mut target: Client<MemoryTokenStore, MemoryCache> =
+			Client::from_components("", "", None, store.clone(), None, None);
+		let target = target.import_credentials(&exported).unwrap();
+
+		assert_eq!(target.access_token(), source.access_token());
+		assert_eq!(target.auth_method(), source.auth_method());
+		assert_eq!(store.read().unwrap().tenant_id, "tenant");
+		assert_eq!(target.export_credentials().unwrap(), exported);
+	}
+}
@@ -0,0 +1,276 @@
+//!
+//!
+//! Provides the non-blocking (async) query and authorization methods of the vminfo Client
+//!
+//! The result cache and response cache are blocking, so async queries are always sent to Resource Graph
+//!
+//!
+
+use chrono::Utc;
+
+use crate::auth::{self, AzCredentials, Method};
+use crate::caching::Cache;
+use crate::error::{AuthErrorKind, Kind, VMInfoResult};
+use crate::persistance::PersistantStorage;
+use crate::query::{QueryRequest, QueryResponse};
+use crate::transport;
+use crate::vm::VirtualMachine;
+use crate::{AuthTokens, Client};
+
+impl<PS, RC> Client<PS, RC>
+where
+	PS: PersistantStorage<AzCredentials>,
+	RC: Cache<VirtualMachine> + Clone,
+{
+	///
+	/// pulls VM meta and instance data from Azure Resource Graph like query_vminfo, without blocking the calling thread
+	///
+	/// the result and response caches are not used, so every query is sent to Resource Graph. a missing or expired access
+	/// token is renewed with the client secret (or refresh token), but a device code login must already have been made
+	/// with login_device_code()
+	///
+	pub async fn query_vminfo_async(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		let result = self
+			.query_remote_async(
+				query_operand,
+				match_regexp,
+				show_extensions,
+				show_tags,
+				skip,
+				top,
+			)
+			.await
+			.map(|mut r| {
+				r.queried_at = Some(Utc::now());
+				r
+			});
+
+		self.complete_query(query_operand, match_regexp, result)
+	}
+
+	///
+	/// performs login using the client_credentials flow like login_client_credentials, without blocking the calling thread
+	///
+	pub async fn login_client_credentials_async(mut self, force: bool) -> VMInfoResult<Self> {
+		let _ = self.load_credentials();
+
+		if self.access_token().is_some() && !force {
+			return Ok(self);
+		}
+
+		let tokens = auth::login_non_interactive_async(
+			&auth::Configuration::new(&self.tenant_id, &self.client_id, &self.client_secret)
+				.with_min_tls_version(self.min_tls_version)
+				.with_v2_endpoint(self.use_v2_endpoint)
+				.with_environment(self.azure_environment),
+		)
+		.await?;
+
+		self.active_tokens = Some(tokens);
+
+		self.save_credentials()?;
+
+		Ok(self)
+	}
+
+	///
+	/// exchanges a refresh token for new access and refresh tokens like exchange_refresh_token, without blocking the
+	/// calling thread
+	///
+	pub async fn exchange_refresh_token_async(mut self) -> VMInfoResult<Self> {
+		let tokens: AuthTokens = auth::exchange_refresh_tokens_async(
+			&self.tenant_id,
+			&self.client_id,
+			self.refresh_token(),
+			self.min_tls_version,
+			self.azure_environment,
+		)
+		.await?;
+
+		self.active_tokens = Some(tokens);
+
+		self.save_credentials()?;
+
+		Ok(self)
+	}
+
+	///
+	/// queries Resource Graph, re-authenticating and querying once more when the access token is missing or expired
+	///
+	async fn query_remote_async(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		let err = match self
+			.request_async(
+				query_operand,
+				match_regexp,
+				show_extensions,
+				show_tags,
+				skip,
+				top,
+			)
+			.await
+		{
+			Ok(r) => return Ok(r),
+			Err(err) => err,
+		};
+
+		let client = match (err.kind(), self.auth_method()) {
+			(Kind::AuthenticationError(AuthErrorKind::MissingToken), Method::ClientCredentials) => {
+				self.explain("the request had no access token, requesting one with the client secret");
+				self.clone().login_client_credentials_async(true).await?
+			}
+			(Kind::AuthenticationError(AuthErrorKind::TokenExpired), Method::ClientCredentials) => {
+				self.warn_if_clock_skewed();
+				self.explain(
+					"the access token was rejected as expired, requesting a new one with the client secret",
+				);
+				self.clone().login_client_credentials_async(true).await?
+			}
+			(Kind::AuthenticationError(AuthErrorKind::TokenExpired), Method::DeviceCode) => {
+				self.warn_if_clock_skewed();
+				self.explain(
+					"the access token was rejected as expired, exchanging the refresh token for a new one",
+				);
+				self.clone().exchange_refresh_token_async().await?
+			}
+			_ => {
+				self.explain(&format!(
+					"the request failed without a non-interactive way to re-authenticate: {}",
+					err
+				));
+				return Err(err);
+			}
+		};
+
+		client
+			.request_async(
+				query_operand,
+				match_regexp,
+				show_extensions,
+				show_tags,
+				skip,
+				top,
+			)
+			.await
+	}
+
+	///
+	/// sends a single page request to Resource Graph with the async transport
+	///
+	async fn request_async(
+		&self,
+		query_operand: &Vec<String>,
+		match_regexp: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+	) -> VMInfoResult<QueryResponse> {
+		let req_body = QueryRequest::make_with_options(
+			query_operand,
+			match_regexp,
+			show_extensions,
+			show_tags,
+			skip,
+			top,
+			&self.subscriptions,
+			&self.query_options,
+		);
+		let (access_token, body) = self.prepare_query(&req_body)?;
+
+		let raw = transport::post_json_with_retry_async(
+			self.async_transport.as_ref(),
+			&self.resource_graph_url(),
+			&access_token,
+			&self.headers,
+			&body,
+		)
+		.await?;
+
+		Self::checked_page(Self::parse_query_response(raw)?, query_operand)
+	}
+}
+
+#[cfg(test)]
+mod nonblocking_tests {
+	use std::future::Future;
+
+	fn block_on<F: Future>(future: F) -> F::Output {
+		tokio::runtime::Builder::new_current_thread()
+			.build()
+			.unwrap()
+			.block_on(future)
+	}
+
+	#[test]
+	fn async_query_returns_results_in_operand_order() {
+		use crate::testing::{client, query_response, vm, MockTransport};
+
+		let transport = MockTransport::new(vec![query_response(&[vm("linux-01"), vm("linux-02")])]);
+		let client = client(MockTransport::new(vec![]), None).with_async_transport(transport.clone());
+
+		let resp = block_on(client.query_vminfo_async(
+			&vec!["linux-02".to_string(), "linux-01".to_string()],
+			false,
+			false,
+			false,
+			None,
+			None,
+		))
+		.unwrap();
+
+		let names: Vec<String> = resp
+			.data
+			.iter()
+			.map(|v| v.vm_name.clone().unwrap())
+			.collect();
+		assert_eq!(names, vec!["linux-02", "linux-01"]);
+		assert!(resp.queried_at.is_some());
+		assert_eq!(client.last_query_time(), resp.queried_at);
+		assert_eq!(transport.requests().len(), 1);
+	}
+
+	#[test]
+	fn async_query_retries_after_connection_reset() {
+		use crate::error::{self, Error};
+		use crate::testing::{client, query_response, vm, MockTransport};
+
+		let transport = MockTransport::new(vec![
+			Err(error::request(
+				None::<Error>,
+				None,
+				"connection reset by peer",
+			)),
+			query_response(&[vm("linux-01")]),
+		]);
+		let client = client(MockTransport::new(vec![]), None).with_async_transport(transport.clone());
+
+		let resp = block_on(client.query_vminfo_async(
+			&vec!["linux-01".to_string()],
+			false,
+			false,
+			false,
+			None,
+			None,
+		))
+		.unwrap();
+
+		assert_eq!(resp.total_results, 1);
+		assert_eq!(transport.requests().len(), 2);
+	}
+}
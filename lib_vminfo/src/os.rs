@@ -0,0 +1,166 @@
+//!
+//!
+//! Provides a bundled table of the OS names reported by Azure Virtual Machines (ie: `ubuntu`, `Red Hat Enterprise
+//! Linux`, `WindowsServer`) and the distributions they belong to, so VMs can be grouped by OS without the variations in
+//! how the same OS is named
+//!
+//!
+
+use serde::Serialize;
+
+///
+/// The canonical OS of a Virtual Machine
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CanonicalOs {
+	/// the canonical name of the distribution (ie: ubuntu, rhel or windows-server)
+	pub distro: &'static str,
+	/// the major version (ie: 22 for Ubuntu 22.04, or 2019 for Windows Server 2019), if one could be read
+	pub major_version: Option<u32>,
+}
+
+///
+/// the distribution of each OS name, matched against the start of the OS name once it is lowercased and stripped of
+/// anything but letters and digits. more specific names are listed before the names they start with
+///
+const DISTROS: [(&str, &str); 24] = [
+	("ubuntu", "ubuntu"),
+	("canonicalubuntu", "ubuntu"),
+	("redhatenterpriselinux", "rhel"),
+	("redhat", "rhel"),
+	("rhel", "rhel"),
+	("centos", "centos"),
+	("almalinux", "almalinux"),
+	("rockylinux", "rocky"),
+	("rocky", "rocky"),
+	("oraclelinux", "oracle-linux"),
+	("debian", "debian"),
+	("suselinuxenterpriseserver", "sles"),
+	("sles", "sles"),
+	("opensuse", "opensuse"),
+	("cblmariner", "azure-linux"),
+	("commonbaselinuxmariner", "azure-linux"),
+	("mariner", "azure-linux"),
+	("azurelinux", "azure-linux"),
+	("flatcar", "flatcar"),
+	("freebsd", "freebsd"),
+	("microsoftwindowsserver", "windows-server"),
+	("windowsserver", "windows-server"),
+	("microsoftwindows", "windows"),
+	("windows", "windows"),
+];
+
+///
+/// the Windows Server release of each build number (the third part of the OS version, ie: 10.0.17763), used when the
+/// OS name does not include the release year
+///
+const WINDOWS_SERVER_BUILDS: [(u32, u32); 5] = [
+	(9600, 2012),
+	(14393, 2016),
+	(17763, 2019),
+	(20348, 2022),
+	(26100, 2025),
+];
+
+///
+/// maps an OS name (and version) reported by Azure to its canonical distribution and major version. the major version
+/// is read from the OS name when it includes one (ie: Windows Server 2022 Datacenter), otherwise from the OS version
+///
+/// returns None for OS names which are not in the bundled table
+///
+pub fn canonical_os(os_name: &str, os_version: Option<&str>) -> Option<CanonicalOs> {
+	let key: String = os_name
+		.chars()
+		.filter(|c| c.is_ascii_alphanumeric())
+		.collect::<String>()
+		.to_ascii_lowercase();
+	let (_, distro) = DISTROS.iter().find(|(name, _)| key.starts_with(name))?;
+
+	let major_version = match (first_number(os_name), os_version) {
+		(Some(major), _) => Some(major),
+		(None, Some(version)) if *distro == "windows-server" => windows_server_release(version),
+		(None, Some(version)) => first_number(version),
+		(None, None) => None,
+	};
+
+	Some(CanonicalOs {
+		distro,
+		major_version,
+	})
+}
+
+///
+/// the first run of digits in s (ie: 22 in 22.04)
+///
+fn first_number(s: &str) -> Option<u32> {
+	s.split(|c: char| !c.is_ascii_digit())
+		.find(|part| !part.is_empty())
+		.and_then(|part| part.parse().ok())
+}
+
+///
+/// the Windows Server release year of an OS version (ie: 2019 for 10.0.17763.5329)
+///
+fn windows_server_release(os_version: &str) -> Option<u32> {
+	let build: u32 = os_version.split('.').nth(2)?.parse().ok()?;
+
+	WINDOWS_SERVER_BUILDS
+		.iter()
+		.find(|(known, _)| *known == build)
+		.map(|(_, release)| *release)
+}
+
+#[cfg(test)]
+mod os_tests {
+	#[test]
+	fn reported_os_names_are_normalized() {
+		use super::{canonical_os, CanonicalOs};
+
+		let os = |distro: &'static str, major_version: Option<u32>| {
+			Some(CanonicalOs {
+				distro,
+				major_version,
+			})
+		};
+
+		assert_eq!(
+			canonical_os("ubuntu", Some("22.04")),
+			os("ubuntu", Some(22))
+		);
+		assert_eq!(
+			canonical_os("Ubuntu", Some("20.04")),
+			os("ubuntu", Some(20))
+		);
+		assert_eq!(
+			canonical_os("Red Hat Enterprise Linux", Some("8.6")),
+			os("rhel", Some(8))
+		);
+		assert_eq!(canonical_os("RHEL", Some("9.2")), os("rhel", Some(9)));
+		assert_eq!(
+			canonical_os("centos", Some("7.9.2009")),
+			os("centos", Some(7))
+		);
+		assert_eq!(canonical_os("debian", Some("11")), os("debian", Some(11)));
+		assert_eq!(
+			canonical_os("SUSE Linux Enterprise Server 15 SP4", None),
+			os("sles", Some(15))
+		);
+		assert_eq!(
+			canonical_os("CBL-Mariner", Some("2.0.20231004")),
+			os("azure-linux", Some(2))
+		);
+		assert_eq!(
+			canonical_os("Windows Server 2022 Datacenter", Some("10.0.20348.2113")),
+			os("windows-server", Some(2022))
+		);
+		assert_eq!(
+			canonical_os("WindowsServer", Some("10.0.17763.5329")),
+			os("windows-server", Some(2019))
+		);
+		assert_eq!(
+			canonical_os("Windows 10 Enterprise", None),
+			os("windows", Some(10))
+		);
+		assert_eq!(canonical_os("MyCustomOS", Some("1.0")), None);
+	}
+}
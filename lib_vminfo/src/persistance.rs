@@ -7,10 +7,12 @@
 //!
 
 use std::{
+	collections::BTreeMap,
 	fmt::Display,
-	fs::{self, File},
+	fs::{self, File, OpenOptions},
 	io::Write,
-	path::PathBuf,
+	path::{Path, PathBuf},
+	sync::Arc,
 };
 
 use serde::{de::DeserializeOwned, Serialize};
@@ -44,12 +46,20 @@ where
 	fn clear(&self) -> VMInfoResult<()>;
 }
 
+///
+/// the name of the credential profile used when none is specified
+///
+pub const DEFAULT_PROFILE: &str = "default";
+
 ///
 /// A Persistence Method for storage of Access and Refresh token pairs
 ///
+/// credentials are stored under a named profile so that multiple sets of credentials can coexist in the same file
+///
 #[derive(Debug, Clone)]
 pub struct FileTokenStore {
 	file_path: PathBuf,
+	profile: String,
 }
 
 impl FileTokenStore {
@@ -57,7 +67,7 @@ impl FileTokenStore {
 	/// creates a new FileTokenStore
 	///
 	#[cfg(target_os = "macos")]
-	pub fn new(app_name: &str) -> VMInfoResult<FileTokenStore> {
+	pub fn new(app_name: &str, profile: Option<&str>) -> VMInfoResult<FileTokenStore> {
 		let username: String = String::from(users::get_current_username().unwrap().to_str().unwrap());
 
 		#[cfg(target_os = "macos")]
@@ -72,7 +82,10 @@ impl FileTokenStore {
 			error::client_config(Some(err), "failed to generate path for token persistence")
 		})?;
 
-		let store = Self { file_path: path };
+		let store = Self {
+			file_path: path,
+			profile: profile.unwrap_or(DEFAULT_PROFILE).to_string(),
+		};
 		store.create_config()?;
 
 		if !store.file_path.exists() {
@@ -87,7 +100,7 @@ impl FileTokenStore {
 	/// creates a new FileTokenStore
 	///
 	#[cfg(target_os = "linux")]
-	pub fn new(app_name: &str) -> VMInfoResult<FileTokenStore> {
+	pub fn new(app_name: &str, profile: Option<&str>) -> VMInfoResult<FileTokenStore> {
 		let username: String = String::from(users::get_current_username().unwrap().to_str().unwrap());
 
 		let path = match username.as_str() {
@@ -105,7 +118,23 @@ impl FileTokenStore {
 			}
 		};
 
-		let store = Self { file_path: path };
+		let store = Self {
+			file_path: path,
+			profile: profile.unwrap_or(DEFAULT_PROFILE).to_string(),
+		};
+		store.create_config()?;
+
+		Ok(store)
+	}
+
+	///
+	/// creates a FileTokenStore which keeps its credentials in the provided file rather than the app config directory
+	///
+	pub fn from_path(file_path: PathBuf, profile: Option<&str>) -> VMInfoResult<FileTokenStore> {
+		let store = Self {
+			file_path,
+			profile: profile.unwrap_or(DEFAULT_PROFILE).to_string(),
+		};
 		store.create_config()?;
 
 		Ok(store)
@@ -117,73 +146,455 @@ impl FileTokenStore {
 
 		Ok(())
 	}
-}
 
-impl PersistantStorage<AzCredentials> for FileTokenStore {
-	fn write(&self, data: &AzCredentials) -> VMInfoResult<()> {
+	///
+	/// reads every stored credential profile. files written before profiles were supported hold a single set of
+	/// credentials, which are read as the default profile
+	///
+	/// a file which can not be parsed is reported as a CorruptTokenStore error rather than as missing credentials.
+	/// writing new credentials or clearing the profile replaces it
+	///
+	fn read_profiles(&self) -> VMInfoResult<BTreeMap<String, AzCredentials>> {
+		let contents = fs::read_to_string(&self.file_path).map_err(|err| {
+			error::auth(
+				Some(err),
+				AuthErrorKind::MissingToken,
+				"could not read credentials from file.",
+			)
+		})?;
+
+		if contents.trim().is_empty() {
+			return Ok(BTreeMap::new());
+		}
+
+		if let Ok(profiles) = serde_json::from_str::<BTreeMap<String, AzCredentials>>(&contents) {
+			return Ok(profiles);
+		}
+
+		// the file exists but holds neither format, so it was likely truncated or corrupted while being written
+		let legacy = serde_json::from_str::<AzCredentials>(&contents).map_err(|err| {
+			error::auth(
+				Some(err),
+				AuthErrorKind::CorruptTokenStore,
+				format!(
+					"token file '{}' is corrupt and could not be parsed",
+					self.file_path.display()
+				)
+				.as_str(),
+			)
+		})?;
+
+		Ok(BTreeMap::from([(DEFAULT_PROFILE.to_string(), legacy)]))
+	}
+
+	///
+	/// replaces the stored credential profiles
+	///
+	/// the profiles are written to a temporary file alongside the token file, which is renamed over it once complete.
+	/// a write which fails before the rename (ie: the disk is full) leaves the previous token file untouched
+	///
+	fn write_profiles(&self, profiles: &BTreeMap<String, AzCredentials>) -> VMInfoResult<()> {
 		if !self.file_path.parent().unwrap().exists() {
 			self.create_config()?
 		}
 
-		let mut tokens_file: File = File::create(&self.file_path)
-			.map_err(|err| error::other(Some(err), "failed to create token storage file"))?;
-		tokens_file
-			.write(
-				serde_json::to_string_pretty(&data)
-					.map_err(|err| {
-						error::other(
-							Some(err),
-							"failed to generate JSON for auth tokens persistence",
-						)
-					})?
-					.as_bytes(),
+		let contents = serde_json::to_string_pretty(profiles).map_err(|err| {
+			error::other(
+				Some(err),
+				"failed to generate JSON for auth tokens persistence",
 			)
-			.map_err(|err| error::other(Some(err), "failed to write auth tokens to file"))?;
+		})?;
+
+		let temp_path = self.temp_path();
+		let written = self
+			.create_temp_file(&temp_path)
+			.and_then(|mut temp_file| {
+				temp_file.write_all(contents.as_bytes())?;
+				temp_file.sync_all()
+			})
+			.and_then(|_| fs::rename(&temp_path, &self.file_path));
+
+		if let Err(err) = written {
+			let _ = fs::remove_file(&temp_path);
+			return Err(error::other(
+				Some(err),
+				"failed to write auth tokens to file",
+			));
+		}
 
 		Ok(())
 	}
 
+	///
+	/// creates the temporary file new credentials are written to. the file is only readable by its owner, and it is
+	/// given the permissions of the token file it replaces (if one exists) so that permissions set by the user are kept
+	///
+	fn create_temp_file(&self, temp_path: &Path) -> std::io::Result<File> {
+		let mut options = OpenOptions::new();
+		options.write(true).create(true).truncate(true);
+		#[cfg(unix)]
+		std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+		let temp_file = options.open(temp_path)?;
+		if let Ok(metadata) = fs::metadata(&self.file_path) {
+			temp_file.set_permissions(metadata.permissions())?;
+		}
+
+		Ok(temp_file)
+	}
+
+	///
+	/// the temporary file new credentials are written to before replacing the token file
+	///
+	fn temp_path(&self) -> PathBuf {
+		let mut temp_path = self.file_path.clone().into_os_string();
+		temp_path.push(".tmp");
+		PathBuf::from(temp_path)
+	}
+}
+
+impl PersistantStorage<AzCredentials> for FileTokenStore {
+	fn write(&self, data: &AzCredentials) -> VMInfoResult<()> {
+		// a missing or unreadable store is replaced rather than preventing a new login
+		let mut profiles = self.read_profiles().unwrap_or_default();
+		profiles.insert(self.profile.clone(), data.clone());
+
+		self.write_profiles(&profiles)
+	}
+
 	fn read(&self) -> VMInfoResult<AzCredentials> {
-		let contents = fs::read_to_string(&self.file_path).map_err(|err| {
-			error::auth(
-				Some(err),
+		match self.read_profiles()?.remove(&self.profile) {
+			Some(credentials) => Ok(credentials),
+			None => Err(error::auth(
+				None::<error::Error>,
 				AuthErrorKind::MissingToken,
-				"could not read credentials from file.",
+				format!("no credentials stored for profile '{}'", self.profile).as_str(),
+			)),
+		}
+	}
+
+	fn clear(&self) -> VMInfoResult<()> {
+		if !self.file_path.parent().unwrap().exists() || !self.file_path.exists() {
+			Ok(())
+		} else {
+			let mut profiles = self.read_profiles().unwrap_or_default();
+			profiles.remove(&self.profile);
+
+			if profiles.is_empty() {
+				let _ = File::create(&self.file_path).map_err(|err| {
+					error::other(
+						Some(err),
+						"could not truncate local token/credential cache file",
+					)
+				})?;
+				Ok(())
+			} else {
+				self.write_profiles(&profiles)
+			}
+		}
+	}
+}
+
+impl Display for FileTokenStore {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"Token Secret File Located at: {} (profile: {})",
+			self.file_path.as_path().to_str().unwrap_or("unknown"),
+			self.profile
+		)
+	}
+}
+
+///
+/// A Persistence Method which keeps credentials in the OS secret store (the macOS Keychain, the Secret Service on
+/// Linux or the Windows Credential Manager) rather than in a plaintext file
+///
+/// the credentials of each profile are stored as a JSON entry under the app name (service) and profile (account)
+///
+#[derive(Debug, Clone)]
+pub struct KeyringTokenStore {
+	service: String,
+	account: String,
+	entry: Arc<keyring::Entry>,
+}
+
+impl KeyringTokenStore {
+	///
+	/// creates a new KeyringTokenStore
+	///
+	pub fn new(app_name: &str, profile: Option<&str>) -> VMInfoResult<KeyringTokenStore> {
+		let service = app_name.to_string();
+		let account = profile.unwrap_or(DEFAULT_PROFILE).to_string();
+		let entry = keyring::Entry::new(&service, &account)
+			.map_err(|err| error::client_config(Some(err), "failed to open the OS keyring entry"))?;
+
+		Ok(Self {
+			service,
+			account,
+			entry: Arc::new(entry),
+		})
+	}
+
+	fn missing(&self, err: Option<keyring::Error>) -> error::Error {
+		error::auth(
+			err,
+			AuthErrorKind::MissingToken,
+			format!(
+				"no credentials stored in the OS keyring for profile '{}'",
+				self.account
+			)
+			.as_str(),
+		)
+	}
+}
+
+impl PersistantStorage<AzCredentials> for KeyringTokenStore {
+	fn write(&self, data: &AzCredentials) -> VMInfoResult<()> {
+		let contents = serde_json::to_string(data).map_err(|err| {
+			error::other(
+				Some(err),
+				"failed to generate JSON for auth tokens persistence",
 			)
 		})?;
 
-		Ok(
-			serde_json::from_str::<AzCredentials>(&contents.as_str()).map_err(|err| {
-				error::auth(
-					Some(err),
-					AuthErrorKind::BadCredentials,
-					"could not parse credential contents to struct",
+		self
+			.entry
+			.set_password(&contents)
+			.map_err(|err| error::other(Some(err), "failed to write auth tokens to the OS keyring"))
+	}
+
+	fn read(&self) -> VMInfoResult<AzCredentials> {
+		let contents = match self.entry.get_password() {
+			Ok(contents) => contents,
+			Err(keyring::Error::NoEntry) => return Err(self.missing(None)),
+			Err(err) => return Err(self.missing(Some(err))),
+		};
+
+		serde_json::from_str(&contents).map_err(|err| {
+			error::auth(
+				Some(err),
+				AuthErrorKind::CorruptTokenStore,
+				format!(
+					"credentials in the OS keyring for profile '{}' are corrupt and could not be parsed",
+					self.account
 				)
-			})?,
-		)
+				.as_str(),
+			)
+		})
 	}
 
 	fn clear(&self) -> VMInfoResult<()> {
-		if !self.file_path.parent().unwrap().exists() {
-			Ok(())
-		} else {
-			let _ = File::create(&self.file_path).map_err(|err| {
-				error::other(
-					Some(err),
-					"could not truncate local token/credential cache file",
-				)
-			})?;
-			Ok(())
+		match self.entry.delete_password() {
+			Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+			Err(err) => Err(error::other(
+				Some(err),
+				"could not remove credentials from the OS keyring",
+			)),
 		}
 	}
 }
 
-impl Display for FileTokenStore {
+impl Display for KeyringTokenStore {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(
 			f,
-			"Token Secret File Located at: {}",
-			self.file_path.as_path().to_str().unwrap_or("unknown")
+			"Token Secret stored in the OS keyring (service: {}, profile: {})",
+			self.service, self.account
+		)
+	}
+}
+
+#[cfg(test)]
+mod file_token_store_tests {
+	use std::path::Path;
+
+	use super::{FileTokenStore, PersistantStorage};
+	use crate::{auth::AzCredentials, AuthTokens};
+
+	fn store(path: &Path, profile: &str) -> FileTokenStore {
+		FileTokenStore {
+			file_path: path.to_path_buf(),
+			profile: profile.to_string(),
+		}
+	}
+
+	fn credentials(client_id: &str) -> AzCredentials {
+		AzCredentials {
+			tenant_id: "tenant".to_string(),
+			client_id: client_id.to_string(),
+			client_secret: Some(format!("{}-secret", client_id)),
+			certificate: None,
+			method: None,
+			tokens: AuthTokens {
+				access_token: format!("{}-token", client_id),
+				refresh_token: None,
+				expires_at: None,
+			},
+		}
+	}
+
+	#[test]
+	fn profiles_are_stored_independently() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("tokens.json");
+		let old = store(&path, "old");
+		let new = store(&path, "new");
+
+		old.write(&credentials("old-app")).unwrap();
+		new.write(&credentials("new-app")).unwrap();
+
+		assert_eq!(old.read().unwrap(), credentials("old-app"));
+		assert_eq!(new.read().unwrap(), credentials("new-app"));
+		assert!(store(&path, "other").read().is_err());
+	}
+
+	#[test]
+	fn clearing_a_profile_keeps_the_others() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("tokens.json");
+		let old = store(&path, "old");
+		let new = store(&path, "new");
+
+		old.write(&credentials("old-app")).unwrap();
+		new.write(&credentials("new-app")).unwrap();
+		old.clear().unwrap();
+
+		assert!(old.read().is_err());
+		assert_eq!(new.read().unwrap(), credentials("new-app"));
+
+		new.clear().unwrap();
+		assert!(new.read().is_err());
+	}
+
+	#[test]
+	fn legacy_credentials_are_read_as_default_profile() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("tokens.json");
+		std::fs::write(
+			&path,
+			serde_json::to_string_pretty(&credentials("legacy-app")).unwrap(),
 		)
+		.unwrap();
+
+		assert_eq!(
+			store(&path, super::DEFAULT_PROFILE).read().unwrap(),
+			credentials("legacy-app")
+		);
+		assert!(store(&path, "other").read().is_err());
+	}
+
+	#[test]
+	fn corrupt_file_is_distinguished_from_missing() {
+		use crate::error::{AuthErrorKind, Kind};
+
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("tokens.json");
+		let store = store(&path, super::DEFAULT_PROFILE);
+
+		assert_eq!(
+			store.read().unwrap_err().kind(),
+			Kind::AuthenticationError(AuthErrorKind::MissingToken)
+		);
+
+		// a write cut short by a power loss
+		store.write(&credentials("app")).unwrap();
+		let contents = std::fs::read_to_string(&path).unwrap();
+		std::fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+
+		let err = store.read().unwrap_err();
+		assert_eq!(
+			err.kind(),
+			Kind::AuthenticationError(AuthErrorKind::CorruptTokenStore)
+		);
+		assert!(err.to_string().contains("is corrupt"));
+
+		// logging in again replaces the corrupt file
+		store.write(&credentials("app")).unwrap();
+		assert_eq!(store.read().unwrap(), credentials("app"));
+	}
+
+	#[test]
+	fn failed_write_keeps_the_previous_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("tokens.json");
+		let store = store(&path, super::DEFAULT_PROFILE);
+
+		store.write(&credentials("old-app")).unwrap();
+		let before = std::fs::read_to_string(&path).unwrap();
+
+		// the temporary file can not be created while a directory holds its name
+		let temp_path = dir.path().join("tokens.json.tmp");
+		std::fs::create_dir(&temp_path).unwrap();
+		assert!(store.write(&credentials("new-app")).is_err());
+
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+		assert_eq!(store.read().unwrap(), credentials("old-app"));
+
+		std::fs::remove_dir(&temp_path).unwrap();
+		store.write(&credentials("new-app")).unwrap();
+		assert_eq!(store.read().unwrap(), credentials("new-app"));
+		assert!(!temp_path.exists());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn writes_keep_the_token_file_private() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("tokens.json");
+		let store = store(&path, super::DEFAULT_PROFILE);
+		let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+		// a new token file is only readable by its owner
+		store.write(&credentials("old-app")).unwrap();
+		assert_eq!(mode(&path), 0o600);
+
+		// permissions set by the user survive later writes
+		std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o400)).unwrap();
+		store.write(&credentials("new-app")).unwrap();
+		assert_eq!(mode(&path), 0o400);
+		assert_eq!(store.read().unwrap(), credentials("new-app"));
+	}
+}
+
+#[cfg(test)]
+mod keyring_token_store_tests {
+	#[test]
+	fn credentials_round_trip_through_the_keyring() {
+		use super::{KeyringTokenStore, PersistantStorage};
+		use crate::error::{AuthErrorKind, Kind};
+		use crate::{auth::AzCredentials, AuthTokens};
+
+		// the keyring is kept in memory, so no OS secret store is touched
+		crate::testing::MemoryKeyring::install();
+		let store = KeyringTokenStore::new("azure-vminfo-test", Some("work")).unwrap();
+		let credentials = AzCredentials {
+			tenant_id: "tenant".to_string(),
+			client_id: "client".to_string(),
+			client_secret: Some("super-secret".to_string()),
+			certificate: None,
+			method: None,
+			tokens: AuthTokens {
+				access_token: "token".to_string(),
+				refresh_token: None,
+				expires_at: None,
+			},
+		};
+
+		assert_eq!(
+			store.read().unwrap_err().kind(),
+			Kind::AuthenticationError(AuthErrorKind::MissingToken)
+		);
+
+		store.write(&credentials).unwrap();
+		assert_eq!(store.clone().read().unwrap(), credentials);
+		assert!(!store.to_string().contains("super-secret"));
+		assert!(store.to_string().contains("work"));
+
+		store.clear().unwrap();
+		assert!(store.read().is_err());
+		store.clear().unwrap();
 	}
 }
@@ -4,8 +4,14 @@
 //!
 //!
 
-use super::vm::VirtualMachine;
-use redis::ToRedisArgs;
+use std::io;
+use std::str::FromStr;
+
+use super::error::{self, VMInfoResult};
+use super::vm::{PowerState, VirtualMachine};
+use chrono::{DateTime, Utc};
+use redis::{FromRedisValue, ToRedisArgs};
+use serde::de::Deserializer;
 use serde::{Deserialize, Serialize};
 
 /// specifies and acceptable request body format for Resource Graph to understand
@@ -15,6 +21,548 @@ pub struct QueryRequest {
 	query: String,
 	options: QueryRequestOptions,
 	subscriptions: Option<Vec<String>>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	facets: Vec<FacetRequest>,
+}
+
+///
+/// requests the distribution of values of a column across every VM matched by a query
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FacetRequest {
+	/// the column to summarize
+	expression: String,
+	options: FacetRequestOptions,
+}
+
+///
+/// defines how the values of a facet are sorted and limited
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FacetRequestOptions {
+	#[serde(alias = "sortBy", rename(serialize = "sortBy"))]
+	sort_by: String,
+	#[serde(alias = "sortOrder", rename(serialize = "sortOrder"))]
+	sort_order: String,
+	#[serde(alias = "$top", rename(serialize = "$top"))]
+	top: u16,
+}
+
+impl FacetRequest {
+	///
+	/// requests the distribution of values of a column, ordered from most to least common
+	///
+	pub fn new(expression: &str) -> Self {
+		Self {
+			expression: expression.to_string(),
+			options: FacetRequestOptions {
+				sort_by: "count".to_string(),
+				sort_order: "desc".to_string(),
+				top: 1000,
+			},
+		}
+	}
+}
+
+///
+/// defines how the query operand is matched against VM names
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+	/// match VMs named exactly as one of the query operands
+	#[default]
+	Exact,
+	/// match VMs whose name matches the first query operand as a regular expression
+	Regex,
+	/// match VMs whose name contains the first query operand (case-insensitive)
+	Contains,
+}
+
+///
+/// defines the columns query results can be sorted by
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+	/// sort by the name of each VM
+	VmName,
+	/// sort by the power state code of each VM (ie: PowerState/running)
+	PowerState,
+	/// sort by when each VM was created
+	Created,
+	/// sort by the private IP address of each VM, compared numerically
+	PrivateIp,
+}
+
+impl SortBy {
+	///
+	/// the name of every sort column, as accepted when parsing a SortBy
+	///
+	pub const NAMES: [&'static str; 4] = ["vmName", "powerstate", "created", "privateIp"];
+
+	///
+	/// the output column sorted by (ie: `vmName`)
+	///
+	pub fn column(&self) -> &'static str {
+		match self {
+			Self::VmName => "vmName",
+			Self::PowerState => "powerstate",
+			Self::Created => "created",
+			Self::PrivateIp => "privateIp",
+		}
+	}
+
+	///
+	/// the KQL expression sorted by. private IPs are compared as numbers, so that 10.0.0.9 comes before 10.0.0.10
+	///
+	fn expression(&self) -> &'static str {
+		match self {
+			Self::PrivateIp => "parse_ipv4(privateIp)",
+			_ => self.column(),
+		}
+	}
+}
+
+impl FromStr for SortBy {
+	type Err = String;
+
+	///
+	/// parses a sort column from its output column name (compared case-insensitively)
+	///
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		[
+			Self::VmName,
+			Self::PowerState,
+			Self::Created,
+			Self::PrivateIp,
+		]
+		.into_iter()
+		.find(|by| by.column().eq_ignore_ascii_case(s.trim()))
+		.ok_or_else(|| format!("unknown sort column '{}'", s))
+	}
+}
+
+///
+/// defines the direction query results are sorted in
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+	/// smallest (or earliest) first
+	#[default]
+	Asc,
+	/// largest (or latest) first
+	Desc,
+}
+
+impl SortOrder {
+	///
+	/// the KQL keyword for this direction
+	///
+	pub fn keyword(&self) -> &'static str {
+		match self {
+			Self::Asc => "asc",
+			Self::Desc => "desc",
+		}
+	}
+}
+
+///
+/// defines additional options used when templating a vminfo query
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryOptions {
+	///
+	/// skips lowercasing of the query operands. matching in Resource Graph remains case-insensitive
+	///
+	pub preserve_case: bool,
+	///
+	/// only match VMs whose OS name (ie: ubuntu, RedHat, Windows Server) matches this case-insensitive regular expression
+	///
+	/// **note**: the OS name is reported by the VM agent through the instance view, so it is empty for stopped or
+	/// deallocated VMs and they will never match this filter
+	///
+	pub os_filter: Option<String>,
+	///
+	/// limits the columns requested from Resource Graph to these (see OUTPUT_COLUMNS). vmId is always included
+	///
+	/// results queried with a limited set of fields are never read from or written to the result cache
+	///
+	pub fields: Option<Vec<String>>,
+	///
+	/// include whether the OS disk is encrypted (osDiskEncrypted). this joins against the managed disk resource to
+	/// determine encryption-at-rest status
+	///
+	/// results queried with encryption status are never read from or written to the result cache
+	///
+	pub show_encryption: bool,
+	///
+	/// include the optional capabilities of each VM (hibernationEnabled). capabilities that are not set are null
+	///
+	/// results queried with capabilities are never read from or written to the result cache
+	///
+	pub show_capabilities: bool,
+	///
+	/// include details of each VM's disks (ephemeralOsDisk)
+	///
+	/// results queried with disk details are never read from or written to the result cache
+	///
+	pub show_disks: bool,
+	///
+	/// include any pending platform maintenance for each VM (maintenanceStatus)
+	///
+	/// results queried with maintenance details are never read from or written to the result cache
+	///
+	pub show_maintenance: bool,
+	///
+	/// include the proximity placement group, dedicated host and dedicated host group each VM is assigned to (see
+	/// PLACEMENT_COLUMNS). assignments a VM does not have are null
+	///
+	/// results queried with placement details are never read from or written to the result cache
+	///
+	pub show_placement: bool,
+	///
+	/// include the IOPS and throughput limits of each VM's managed OS disk and the totals across its managed data disks
+	/// (see DISK_PERF_COLUMNS). this joins against the managed disk resource. unmanaged disks have no limits (null)
+	///
+	/// results queried with disk performance limits are never read from or written to the result cache
+	///
+	pub show_disk_perf: bool,
+	///
+	/// include the vCPUs and memory of each VM's size (vcpus, memoryGb), resolved from a bundled table of VM sizes
+	///
+	pub show_sku_details: bool,
+	///
+	/// additional columns (output name, KQL expression) to project from each VM resource (see tag_projection). they
+	/// are returned in the extra columns of each VM, even when the fields are limited
+	///
+	/// results queried with extra projections are never read from or written to the result cache
+	///
+	pub extra_projections: Vec<(String, String)>,
+	///
+	/// only match VMs in exactly this power state. `Stopped` does not match deallocated VMs
+	///
+	pub power_state: Option<PowerState>,
+	///
+	/// how the query operand is matched against VM names. when `match_regex` is set on the query it takes precedence
+	///
+	/// results matched with `Contains` are never read from or written to the result cache
+	///
+	pub match_mode: MatchMode,
+	///
+	/// columns (see OUTPUT_COLUMNS) to return the distribution of values for, across every matched VM
+	///
+	/// results queried with facets are never read from or written to the result cache
+	///
+	pub facets: Vec<String>,
+	///
+	/// continue a previous query from the page identified by this token (see QueryResponse::skip_token)
+	///
+	/// pages requested with a skip token are never read from the result cache
+	///
+	pub skip_token: Option<String>,
+	///
+	/// subscriptions which are never queried, even when requested. they are removed from the requested subscriptions
+	/// and VMs in them are filtered from every query (including when no subscriptions are requested)
+	///
+	pub subscription_denylist: Vec<String>,
+	///
+	/// sort the results by this column and direction instead of by subnet (or by VM ID when no network details are
+	/// requested). the sort column is always projected
+	///
+	/// results queried with a sort are never read from or written to the result cache, and literal VM names are no
+	/// longer returned in the order they were provided
+	///
+	pub sort: Option<(SortBy, SortOrder)>,
+}
+
+impl QueryOptions {
+	///
+	/// whether the subscription (compared case-insensitively) is on the subscription denylist
+	///
+	pub fn denies_subscription(&self, subscription: &str) -> bool {
+		self
+			.subscription_denylist
+			.iter()
+			.any(|d| d.eq_ignore_ascii_case(subscription))
+	}
+
+	///
+	/// whether results for these options are incomplete, include extra data or are not matched by VM name alone, and
+	/// so must skip the result cache
+	///
+	pub(crate) fn bypasses_cache(&self) -> bool {
+		self.match_mode == MatchMode::Contains
+			|| !self.facets.is_empty()
+			|| self.skip_token.is_some()
+			|| self.fields.is_some()
+			|| self.show_encryption
+			|| self.show_capabilities
+			|| self.show_disks
+			|| self.show_maintenance
+			|| self.show_placement
+			|| self.show_disk_perf
+			|| !self.extra_projections.is_empty()
+			|| self.os_filter.is_some()
+			|| self.power_state.is_some()
+			|| self.sort.is_some()
+	}
+}
+
+///
+/// the columns (and their KQL expressions) projected from each Virtual Machine resource
+///
+const VM_COLUMNS: [(&str, &str); 12] = [
+	("subscriptionId", "subscriptionId"),
+	("rg", "rg=resourceGroup"),
+	("vmId", "vmId = id"),
+	("vmName", "vmName = name"),
+	("location", "location = tostring(location)"),
+	("created", "created = tostring(properties.timeCreated)"),
+	("vmSize", "vmSize=tostring(properties.hardwareProfile.vmSize)"),
+	("nicId", "nicId = tostring(nic.id)"),
+	(
+		"osType",
+		"osType = tostring(properties.storageProfile.osDisk.osType)",
+	),
+	(
+		"osName",
+		"osName = tostring(properties.extended.instanceView.osName)",
+	),
+	(
+		"osVersion",
+		"osVersion = tostring(properties.extended.instanceView.osVersion)",
+	),
+	(
+		"powerstate",
+		"powerstate = tostring(properties.extended.instanceView.powerState.code)",
+	),
+];
+
+///
+/// the columns provided by the join against the primary network interface of each Virtual Machine
+///
+/// virtualNetwork and subnet are not projected directly, they are parsed from the projected subnetId
+///
+const NIC_COLUMNS: [&str; 4] = ["privateIp", "virtualNetwork", "subnet", "subnetId"];
+
+///
+/// every column that can appear in a query result
+///
+pub const OUTPUT_COLUMNS: [&str; 28] = [
+	"vmId",
+	"vmName",
+	"created",
+	"sub",
+	"location",
+	"rg",
+	"privateIp",
+	"osType",
+	"osName",
+	"osVersion",
+	"powerstate",
+	"vmSize",
+	"virtualNetwork",
+	"subnet",
+	"subnetId",
+	"extensions",
+	"tags",
+	"osDiskEncrypted",
+	"hibernationEnabled",
+	"ephemeralOsDisk",
+	"maintenanceStatus",
+	"proximityPlacementGroupId",
+	"hostId",
+	"hostGroupId",
+	"osDiskIOPSReadWrite",
+	"osDiskMBpsReadWrite",
+	"dataDiskIOPSReadWrite",
+	"dataDiskMBpsReadWrite",
+];
+
+///
+/// the output columns which hold the IOPS and throughput (MBps) limits of each VM's disks
+///
+pub const DISK_PERF_COLUMNS: [&str; 4] = [
+	"osDiskIOPSReadWrite",
+	"osDiskMBpsReadWrite",
+	"dataDiskIOPSReadWrite",
+	"dataDiskMBpsReadWrite",
+];
+
+///
+/// the output columns which hold the placement (proximity placement group and dedicated host) of each VM
+///
+pub const PLACEMENT_COLUMNS: [&str; 3] = ["proximityPlacementGroupId", "hostId", "hostGroupId"];
+
+///
+/// the columns projected from each Virtual Machine resource which describe its placement. a VM is either placed on a
+/// specific dedicated host, or on any host of a dedicated host group (automatic placement)
+///
+const PLACEMENT_PROJECTION: &str = "proximityPlacementGroupId = tostring(properties.proximityPlacementGroup.id), hostId = tostring(properties.host.id), hostGroupId = tostring(properties.hostGroup.id)";
+
+///
+/// the columns projected from each Virtual Machine resource which describe its optional capabilities
+///
+const CAPABILITY_COLUMNS: &str =
+	"hibernationEnabled = tobool(properties.additionalCapabilities.hibernationEnabled)";
+
+///
+/// the columns projected from each Virtual Machine resource which describe its disks. an OS disk is ephemeral when it
+/// has diff disk settings (ie: placed on the local cache or resource disk)
+///
+const DISK_COLUMNS: &str =
+	"ephemeralOsDisk = isnotempty(tostring(properties.storageProfile.osDisk.diffDiskSettings.option))";
+
+///
+/// the columns projected from each Virtual Machine resource which describe its platform maintenance. maintenance is
+/// pending while the customer initiated (self-service) maintenance window is open, otherwise the result of the last
+/// maintenance redeploy is reported (empty when the VM has never been under maintenance)
+///
+const MAINTENANCE_COLUMNS: &str = "maintenanceStatus = case(tobool(properties.extended.instanceView.maintenanceRedeployStatus.isCustomerInitiatedMaintenanceAllowed), strcat('Pending (window ends ', tostring(properties.extended.instanceView.maintenanceRedeployStatus.preMaintenanceWindowEndTime), ')'), tostring(properties.extended.instanceView.maintenanceRedeployStatus.lastOperationResultCode))";
+
+///
+/// the columns projected from each Virtual Machine resource which are used to determine OS disk encryption status
+///
+const ENCRYPTION_COLUMNS: &str = "osDiskId = tolower(tostring(properties.storageProfile.osDisk.managedDisk.id)), osDiskAde = tobool(properties.storageProfile.osDisk.encryptionSettings.enabled), encryptionAtHost = tobool(properties.securityProfile.encryptionAtHost)";
+
+///
+/// an extra projection (see QueryOptions::extra_projections) of the value of the named tag of each VM, output under
+/// the tag name. VMs without the tag have an empty value
+///
+pub fn tag_projection(tag: &str) -> (String, String) {
+	(
+		tag.to_string(),
+		format!("tostring(tags['{}'])", escape_kql(tag)),
+	)
+}
+
+///
+/// whether a query operand is the full resource ID of a VM (ie: /subscriptions/.../virtualMachines/web-01) rather
+/// than a VM name
+///
+pub fn is_resource_id(operand: &str) -> bool {
+	operand.to_lowercase().starts_with("/subscriptions/")
+}
+
+///
+/// escapes a value for use inside a single-quoted KQL string literal
+///
+pub fn escape_kql(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+///
+/// the longest query (in characters) that will be sent to Resource Graph. anything longer is almost certainly a mistake
+/// (ie: a huge list of VM names) and would only be throttled or rejected
+///
+pub const MAX_QUERY_LENGTH: usize = 100_000;
+
+///
+/// cheap pre-flight checks of a KQL query before it is sent to Resource Graph. rejects queries that are empty, longer
+/// than MAX_QUERY_LENGTH, or that leave a string literal unterminated (unbalanced quotes)
+///
+pub fn validate_query(query: &str) -> VMInfoResult<()> {
+	if query.trim().is_empty() {
+		return Err(error::invalid_query(None::<error::Error>, "query is empty"));
+	}
+
+	let length = query.chars().count();
+	if length > MAX_QUERY_LENGTH {
+		return Err(error::invalid_query(
+			None::<error::Error>,
+			format!(
+				"query is {} characters long, which is more than the maximum of {}",
+				length, MAX_QUERY_LENGTH
+			)
+			.as_str(),
+		));
+	}
+
+	// walk the query tracking the open string literal (if any). escaped characters never close a string
+	let mut open_quote: Option<char> = None;
+	let mut chars = query.chars();
+	while let Some(c) = chars.next() {
+		match (open_quote, c) {
+			(Some(_), '\\') => {
+				chars.next();
+			}
+			(Some(q), c) if c == q => open_quote = None,
+			(None, '\'') | (None, '"') => open_quote = Some(c),
+			_ => {}
+		}
+	}
+
+	match open_quote {
+		Some(q) => Err(error::invalid_query(
+			None::<error::Error>,
+			format!("query has an unterminated string literal (unbalanced {} quote)", q).as_str(),
+		)),
+		None => Ok(()),
+	}
+}
+
+///
+/// cheap guardrail for a regular expression used to match VM names. returns a warning when the pattern is trivially
+/// broad (ie: '.*', '.+' or empty, which match every VM) or nests quantifiers (ie: '(a+)+'), which can make matching
+/// very expensive for Resource Graph. this is not a full analysis of how costly a pattern is
+///
+pub fn regex_warning(pattern: &str) -> Option<String> {
+	let unanchored = pattern.trim_start_matches('^').trim_end_matches('$');
+	if matches!(unanchored, "" | ".*" | ".+" | "(.*)" | "(.+)") {
+		return Some(format!(
+			"regular expression '{}' matches every VM name",
+			pattern
+		));
+	}
+
+	if has_nested_quantifier(pattern) {
+		return Some(format!(
+			"regular expression '{}' repeats a group which is itself repeated (a nested quantifier), which can be very slow to match",
+			pattern
+		));
+	}
+
+	None
+}
+
+///
+/// whether a quantified group (ie: '(...)+') contains another quantifier. escaped characters and character classes are
+/// skipped
+///
+fn has_nested_quantifier(pattern: &str) -> bool {
+	let chars: Vec<char> = pattern.chars().collect();
+	// for each open group, whether a quantifier has been seen inside it
+	let mut groups: Vec<bool> = Vec::new();
+	let mut in_class = false;
+	let mut i = 0;
+
+	while i < chars.len() {
+		match chars[i] {
+			'\\' => i += 1,
+			']' if in_class => in_class = false,
+			_ if in_class => {}
+			'[' => in_class = true,
+			'(' => groups.push(false),
+			')' => {
+				let quantified = groups.pop().unwrap_or(false);
+				let repeated = matches!(chars.get(i + 1), Some('+') | Some('*') | Some('{'));
+				if quantified && repeated {
+					return true;
+				}
+				if let Some(outer) = groups.last_mut() {
+					*outer |= quantified;
+				}
+			}
+			'+' | '*' | '{' => {
+				if let Some(group) = groups.last_mut() {
+					*group = true;
+				}
+			}
+			_ => {}
+		}
+		i += 1;
+	}
+
+	false
 }
 
 impl QueryRequest {
@@ -46,26 +594,86 @@ impl QueryRequest {
 		skip: Option<u64>,
 		top: Option<u16>,
 		subscriptions: &Option<Vec<String>>,
+	) -> Self {
+		Self::make_with_options(
+			query_items,
+			match_regex,
+			show_extensions,
+			show_tags,
+			skip,
+			top,
+			subscriptions,
+			&QueryOptions::default(),
+		)
+	}
+
+	/// builds a request body in the same way as make() while applying any additional query options
+	pub fn make_with_options(
+		query_items: &Vec<String>,
+		match_regex: bool,
+		show_extensions: bool,
+		show_tags: bool,
+		skip: Option<u64>,
+		top: Option<u16>,
+		subscriptions: &Option<Vec<String>>,
+		options: &QueryOptions,
 	) -> Self {
 		let mut search_query: String = String::new();
 		let mut comparison_operator: &str = "in";
+		let mut resource_ids: Vec<String> = Vec::new();
+		let mut match_names: bool = true;
+		let mut regex_patterns: Vec<String> = Vec::new();
 		let mut extensions_join: &str = "";
 		let mut tags_join: &str = "";
 		let skip_param: u64 = skip.unwrap_or(0);
 		let top_param: u16 = top.unwrap_or(1000);
 
-		// ensure all vm names are lowercased
+		// ensure all vm names are lowercased (unless case should be preserved)
 		let vm_list: Vec<String> = query_items
 			.into_iter()
-			.map(|vm| vm.to_lowercase())
+			.map(|vm| {
+				if options.preserve_case {
+					vm.clone()
+				} else {
+					vm.to_lowercase()
+				}
+			})
 			.collect::<Vec<String>>();
 
-		// either interpret the query operand as a regular expression or as a list of hostname literals
-		if match_regex {
+		let match_mode: MatchMode = if match_regex {
+			MatchMode::Regex
+		} else {
+			options.match_mode
+		};
+
+		// either interpret the query operand as a regular expression, a substring or as a list of hostname literals
+		if match_mode == MatchMode::Contains {
+			comparison_operator = "contains";
+			search_query = format!("'{}'", escape_kql(&vm_list[0]));
+		} else if match_mode == MatchMode::Regex {
 			comparison_operator = "matches regex";
-			search_query = format!("'{}'", vm_list[0].clone());
+			regex_patterns = vm_list
+				.iter()
+				.map(|pattern| {
+					if options.preserve_case {
+						format!("'(?i){}'", pattern)
+					} else {
+						format!("'{}'", pattern)
+					}
+				})
+				.collect();
+			search_query = regex_patterns[0].clone();
 		} else {
-			let mut query_list_iterator = vm_list.into_iter();
+			if options.preserve_case {
+				comparison_operator = "in~";
+			}
+			// full resource IDs are matched against the VM ID rather than its name
+			let (ids, names): (Vec<String>, Vec<String>) = vm_list
+				.into_iter()
+				.partition(|vm| is_resource_id(vm));
+			resource_ids = ids;
+			match_names = !names.is_empty() || resource_ids.is_empty();
+			let mut query_list_iterator = names.into_iter();
 			search_query.push_str("(");
 			search_query.push_str(
 				format!(
@@ -75,11 +683,45 @@ impl QueryRequest {
 				.as_str(),
 			); // push the first one in without the preceding ', '
 			while let Some(vm) = query_list_iterator.next() {
-				search_query.push_str(format!(", '{}'", vm.to_lowercase()).as_str());
+				search_query.push_str(format!(", '{}'", vm).as_str());
 			}
 			search_query.push_str(")");
 		}
 
+		// a VM matching any one of several regular expressions is matched
+		let name_clause: String = if regex_patterns.len() > 1 {
+			format!(
+				"({})",
+				regex_patterns
+					.iter()
+					.map(|pattern| format!("tolower(tostring(name)) matches regex {}", pattern))
+					.collect::<Vec<String>>()
+					.join(" or ")
+			)
+		} else {
+			format!(
+				"tolower(tostring(name)) {} {}",
+				comparison_operator, search_query
+			)
+		};
+		let match_clause: String = if resource_ids.is_empty() {
+			name_clause
+		} else {
+			let id_clause: String = format!(
+				"id in~ ({})",
+				resource_ids
+					.iter()
+					.map(|id| format!("'{}'", escape_kql(id)))
+					.collect::<Vec<String>>()
+					.join(", ")
+			);
+			if match_names {
+				format!("({} or {})", name_clause, id_clause)
+			} else {
+				id_clause
+			}
+		};
+
 		// optionally inject join query for extension data
 		if show_extensions {
 			extensions_join = "| join kind=leftouter(Resources | where type =~ 'microsoft.compute/virtualmachines/extensions' | extend vmId = substring(id, 0, indexof(id, '/extensions')) | extend d = pack('name', name, 'version', properties.typeHandlerVersion) | summarize extensions = make_list(d) by vmId) on vmId";
@@ -90,15 +732,229 @@ impl QueryRequest {
 			tags_join = ", tags=tags"
 		}
 
+		// limit the projected columns to those requested (vmId, any faceted columns and the sort column are always
+		// projected)
+		let requested = |column: &str| -> bool {
+			match &options.fields {
+				Some(fields) => {
+					column == "vmId"
+						|| fields.iter().any(|f| f == column)
+						|| options.facets.iter().any(|f| f == column)
+						|| options.sort.is_some_and(|(by, _)| by.column() == column)
+				}
+				None => true,
+			}
+		};
+		let include_sub = requested("sub");
+		let include_encryption = options.show_encryption && requested("osDiskEncrypted");
+		let include_capabilities = options.show_capabilities && requested("hibernationEnabled");
+		let include_disks = options.show_disks && requested("ephemeralOsDisk");
+		let include_maintenance = options.show_maintenance && requested("maintenanceStatus");
+		let include_placement =
+			options.show_placement && PLACEMENT_COLUMNS.iter().any(|c| requested(c));
+		let include_disk_perf =
+			options.show_disk_perf && DISK_PERF_COLUMNS.iter().any(|c| requested(c));
+		let include_nic = NIC_COLUMNS.iter().any(|c| requested(c));
+
+		if show_tags && !requested("tags") {
+			tags_join = "";
+		}
+		if show_extensions && !requested("extensions") {
+			extensions_join = "";
+		}
+
+		let mut projection: Vec<&str> = Vec::new();
+		for (column, expression) in VM_COLUMNS {
+			let needed = match column {
+				"subscriptionId" => include_sub,
+				"nicId" => include_nic,
+				"osName" => requested(column) || options.os_filter.is_some(),
+				"powerstate" => requested(column) || options.power_state.is_some(),
+				_ => requested(column),
+			};
+
+			if needed {
+				projection.push(expression);
+			}
+		}
+		if include_encryption {
+			projection.push(ENCRYPTION_COLUMNS);
+		}
+		if include_capabilities {
+			projection.push(CAPABILITY_COLUMNS);
+		}
+		if include_disks {
+			projection.push(DISK_COLUMNS);
+		}
+		if include_maintenance {
+			projection.push(MAINTENANCE_COLUMNS);
+		}
+		if include_placement {
+			projection.push(PLACEMENT_PROJECTION);
+		}
+
+		// extra projections are packed into a single column, since their names may not be valid KQL column names
+		let extra_columns: String = if options.extra_projections.is_empty() {
+			String::new()
+		} else {
+			let pairs: Vec<String> = options
+				.extra_projections
+				.iter()
+				.map(|(name, expression)| format!("'{}', {}", escape_kql(name), expression))
+				.collect();
+			format!("extraColumns = pack({})", pairs.join(", "))
+		};
+		if !extra_columns.is_empty() {
+			projection.push(&extra_columns);
+		}
+
+		// optionally filter on the projected columns
+		let mut filters: String = String::new();
+		if let Some(os) = &options.os_filter {
+			filters.push_str(format!(" | where osName matches regex '(?i){}'", escape_kql(os)).as_str());
+		}
+		if let Some(power_state) = &options.power_state {
+			filters.push_str(format!(" | where powerstate =~ '{}'", power_state.code()).as_str());
+		}
+
+		// unmanaged OS disks only report Azure Disk Encryption settings on the VM itself. managed disks are considered
+		// encrypted when they report an encryption-at-rest type or Azure Disk Encryption, or when encryption at host is enabled
+		let encryption_join: &str = if include_encryption {
+			" | join kind=leftouter (Resources | where type =~ 'microsoft.compute/disks' | project diskId = tolower(id), diskEncryptionType = tostring(properties.encryption.type), diskAde = tobool(properties.encryptionSettingsCollection.enabled)) on $left.osDiskId == $right.diskId | extend osDiskEncrypted = iff(isempty(osDiskId), coalesce(osDiskAde, false), coalesce(diskAde, false) or coalesce(encryptionAtHost, false) or isnotempty(diskEncryptionType)) | project-away osDiskId, osDiskAde, encryptionAtHost, diskId, diskEncryptionType, diskAde"
+		} else {
+			""
+		};
+
+		let sub_join: &str = if include_sub {
+			" | join kind=leftouter (ResourceContainers | where type=='microsoft.resources/subscriptions'| project sub=name, subscriptionId) on subscriptionId"
+		} else {
+			""
+		};
+
+		let nic_join: &str = if include_nic {
+			" | join kind=leftouter (Resources| where type =~ 'microsoft.network/networkinterfaces'| extend ipConfigsCount=array_length(properties.ipConfigurations)| mv-expand ipconfig=properties.ipConfigurations| where ipConfigsCount == 1 or ipconfig.properties.primary =~ 'true'| project nicId = id, subnetId = tostring(ipconfig.properties.subnet.id), privateIp = tostring(ipconfig.properties.privateIPAddress))on nicId"
+		} else {
+			""
+		};
+
+		// managed disks are matched to the VM which manages them. an OS disk is identified by its OS type, and the limits
+		// of every other (data) disk are totalled
+		let disk_perf_join: &str = if include_disk_perf {
+			" | extend diskPerfVmId = tolower(vmId) | join kind=leftouter (Resources | where type =~ 'microsoft.compute/disks' and isnotempty(managedBy) | extend osDisk = isnotempty(tostring(properties.osType)), iops = tolong(properties.diskIOPSReadWrite), mbps = tolong(properties.diskMBpsReadWrite) | summarize osDiskIOPSReadWrite = max(iff(osDisk, iops, long(null))), osDiskMBpsReadWrite = max(iff(osDisk, mbps, long(null))), dataDiskIOPSReadWrite = sum(iff(osDisk, long(null), iops)), dataDiskMBpsReadWrite = sum(iff(osDisk, long(null), mbps)) by diskPerfVmId = tolower(managedBy)) on diskPerfVmId | project-away diskPerfVmId, diskPerfVmId1"
+		} else {
+			""
+		};
+
+		let order: String = match options.sort {
+			Some((by, direction)) => format!("| order by {} {}", by.expression(), direction.keyword()),
+			// results are ordered by subnet name. the name is parsed from the subnet resource ID just for sorting, since
+			// the virtualNetwork and subnet columns are parsed from subnetId once the response is received
+			None if include_nic => "| extend subnet = tostring(split(subnetId, '/subnets/')[1]) | order by subnet asc | project-away subnet".to_string(),
+			None => "| order by vmId asc".to_string(),
+		};
+
+		// trim any columns only needed for joins and filters from the final result
+		let final_projection: String = match &options.fields {
+			Some(_) => {
+				let mut columns: Vec<&str> = OUTPUT_COLUMNS
+					.iter()
+					.copied()
+					.filter(|c| requested(c))
+					.collect();
+				if tags_join.is_empty() {
+					columns.retain(|c| *c != "tags");
+				}
+				if extensions_join.is_empty() {
+					columns.retain(|c| *c != "extensions");
+				}
+				if encryption_join.is_empty() {
+					columns.retain(|c| *c != "osDiskEncrypted");
+				}
+				if !include_capabilities {
+					columns.retain(|c| *c != "hibernationEnabled");
+				}
+				if !include_disks {
+					columns.retain(|c| *c != "ephemeralOsDisk");
+				}
+				if !include_maintenance {
+					columns.retain(|c| *c != "maintenanceStatus");
+				}
+				if !include_placement {
+					columns.retain(|c| !PLACEMENT_COLUMNS.contains(c));
+				}
+				if !include_disk_perf {
+					columns.retain(|c| !DISK_PERF_COLUMNS.contains(c));
+				}
+				if !extra_columns.is_empty() {
+					columns.push("extraColumns");
+				}
+
+				// virtualNetwork and subnet are parsed from the subnet resource ID once the response is received
+				if columns.iter().any(|c| *c == "virtualNetwork" || *c == "subnet") {
+					columns.retain(|c| *c != "virtualNetwork" && *c != "subnet");
+					if !columns.contains(&"subnetId") {
+						columns.push("subnetId");
+					}
+				}
+
+				format!(" | project {}", columns.join(", "))
+			}
+			None => String::new(),
+		};
+
+		// VMs in denied subscriptions are never returned, even when every accessible subscription is queried
+		let match_clause: String = if options.subscription_denylist.is_empty() {
+			match_clause
+		} else {
+			format!(
+				"{} and subscriptionId !in~ ({})",
+				match_clause,
+				options
+					.subscription_denylist
+					.iter()
+					.map(|s| format!("'{}'", escape_kql(s)))
+					.collect::<Vec<String>>()
+					.join(", ")
+			)
+		};
+		let subscriptions: Option<Vec<String>> = subscriptions.as_ref().map(|subscriptions| {
+			subscriptions
+				.iter()
+				.filter(|s| !options.denies_subscription(s))
+				.cloned()
+				.collect()
+		});
+
 		// template out the query
-		let query = format!("Resources | where type =~ 'microsoft.compute/virtualmachines' | where tolower(tostring(name)) {} {} | extend nics=array_length(properties.networkProfile.networkInterfaces) | mv-expand nic=properties.networkProfile.networkInterfaces | where nics == 1 or nic.properties.primary =~ 'true' or isempty(nic) | project subscriptionId, rg=resourceGroup, vmId = id, vmName = name, location = tostring(location), created = tostring(properties.timeCreated), vmSize=tostring(properties.hardwareProfile.vmSize), nicId = tostring(nic.id), osType = tostring(properties.storageProfile.osDisk.osType), osName = tostring(properties.extended.instanceView.osName), osVersion = tostring(properties.extended.instanceView.osVersion), powerstate = tostring(properties.extended.instanceView.powerState.code){} {} | join kind=leftouter (ResourceContainers | where type=='microsoft.resources/subscriptions'| project sub=name, subscriptionId) on subscriptionId | join kind=leftouter (Resources| where type =~ 'microsoft.network/networkinterfaces'| extend ipConfigsCount=array_length(properties.ipConfigurations)| extend subnetId = tostring(properties.ipConfigurations[0].properties.subnet.id)| extend virtualNetwork = split(substring(subnetId, indexof(subnetId, '/virtualNetworks/') + strlen('/virtualNetworks/')), '/')[0]| extend subnet = substring(subnetId, indexof(subnetId, '/subnets/') + strlen('/subnets/'))| mv-expand ipconfig=properties.ipConfigurations| where ipConfigsCount == 1 or ipconfig.properties.primary =~ 'true'| project nicId = id, subnet, virtualNetwork, privateIp = tostring(ipconfig.properties.privateIPAddress))on nicId| order by subnet asc", comparison_operator, search_query, tags_join,extensions_join);
+		let query = format!("Resources | where type =~ 'microsoft.compute/virtualmachines' | where {} | extend nics=array_length(properties.networkProfile.networkInterfaces) | mv-expand nic=properties.networkProfile.networkInterfaces | where nics == 1 or nic.properties.primary =~ 'true' or isempty(nic) | project {}{}{} {}{}{}{}{}{}{}", match_clause, projection.join(", "), tags_join, filters, extensions_join, encryption_join, sub_join, nic_join, disk_perf_join, order, final_projection);
 
 		Self {
 			query,
-			options: QueryRequestOptions::new(skip_param, None, top_param),
+			options: QueryRequestOptions::new(skip_param, options.skip_token.clone(), top_param),
+			subscriptions,
+			facets: options.facets.iter().map(|f| FacetRequest::new(f)).collect(),
+		}
+	}
+
+	///
+	/// builds a request body listing the ID and display name of each subscription (limited to the provided
+	/// subscriptions, or every subscription the client can access)
+	///
+	pub fn subscriptions(subscriptions: &Option<Vec<String>>) -> Self {
+		Self {
+			query: "ResourceContainers | where type =~ 'microsoft.resources/subscriptions' | project subscriptionId, name | order by subscriptionId asc".to_string(),
+			options: QueryRequestOptions::default(),
 			subscriptions: subscriptions.to_owned(),
+			facets: vec![],
 		}
 	}
+
+	///
+	/// runs the pre-flight checks of validate_query() against the KQL query of this request
+	///
+	pub fn validate(&self) -> VMInfoResult<()> {
+		validate_query(&self.query)
+	}
 }
 
 ///
@@ -134,14 +990,19 @@ impl Default for QueryRequestOptions {
 	}
 }
 
+///
+/// the maximum number of records Resource Graph will return in a single response, regardless of the requested page size
+///
+pub const MAX_PAGE_SIZE: usize = 1000;
+
 ///
 /// special query response type for vminfo responses which can have a special format for errors thrown by Azure
 ///
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
-pub enum QueryResponseType {
+pub enum QueryResponseType<T = QueryResponse> {
 	/// defines the 200 response
-	Ok(QueryResponse),
+	Ok(T),
 	/// defines any custom error response from Azure
 	Err {
 		/// entrypoint for any non-200 error body responses from Azure
@@ -160,6 +1021,43 @@ pub struct AzureError {
 	pub message: String,
 }
 
+///
+/// a subscription visible to Resource Graph
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Subscription {
+	/// the ID (GUID) of the subscription
+	#[serde(alias = "subscriptionId", rename(serialize = "subscriptionId"))]
+	pub subscription_id: String,
+	/// the display name of the subscription
+	pub name: String,
+}
+
+///
+/// the response to a subscription listing request (see QueryRequest::subscriptions)
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscriptionsResponse {
+	/// the subscriptions that were found
+	pub data: Vec<Subscription>,
+}
+
+///
+/// the results of querying each subscription separately (see Client::query_vminfo_fan_out)
+///
+#[derive(Debug)]
+pub struct FanOutResponse {
+	///
+	/// the merged results of every subscription that was queried successfully. each VM is attributed to the
+	/// subscription it was found in
+	///
+	pub response: QueryResponse,
+	///
+	/// the subscriptions that could not be queried, and why
+	///
+	pub failures: Vec<(String, error::Error)>,
+}
+
 ///
 ///  Defines a format for an acceptable response from the Resource Graph API
 ///
@@ -169,7 +1067,106 @@ pub struct QueryResponse {
 	#[serde(alias = "totalRecords")]
 	pub total_results: u64,
 	/// list of Virtual Machines returned from the Graph API
+	#[serde(deserialize_with = "parse_virtual_machines")]
 	pub data: Vec<VirtualMachine>,
+	/// number of the returned Virtual Machines which were served from the result cache (included in total_results)
+	#[serde(skip)]
+	pub cache_hits: u64,
+	/// the distributions of values for any requested facets
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub facets: Vec<Facet>,
+	/// identifies the next page of results when more VMs matched than were returned
+	#[serde(
+		alias = "$skipToken",
+		rename(serialize = "$skipToken"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub skip_token: Option<String>,
+	/// whether more VMs matched than were returned in this response
+	#[serde(
+		alias = "resultTruncated",
+		rename(serialize = "resultTruncated"),
+		default,
+		deserialize_with = "parse_truncated"
+	)]
+	pub truncated: bool,
+	/// number of pages requested from Resource Graph to assemble this response (set by Client::query_vminfo_all)
+	#[serde(skip)]
+	pub pages: u64,
+	/// when this response was fetched from Resource Graph (kept when the response is served from a response cache)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub queried_at: Option<DateTime<Utc>>,
+}
+
+///
+/// deserializer for the resultTruncated flag, which Resource Graph reports as a string ("true" / "false")
+///
+fn parse_truncated<'de, D>(d: D) -> Result<bool, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	match Deserialize::deserialize(d)? {
+		serde_json::Value::Bool(b) => Ok(b),
+		serde_json::Value::String(s) => Ok(s.eq_ignore_ascii_case("true")),
+		_ => Ok(false),
+	}
+}
+
+///
+/// the distribution of values for a requested facet
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Facet {
+	/// the column the facet summarizes
+	pub expression: String,
+	/// either `FacetResult` or `FacetError`
+	#[serde(alias = "resultType", rename(serialize = "resultType"))]
+	pub result_type: String,
+	/// each distinct value and the number of VMs with that value (ie: `{"location": "eastus", "count": 3}`)
+	#[serde(default)]
+	pub data: Vec<serde_json::Map<String, serde_json::Value>>,
+	/// the reasons the facet could not be computed (for a `FacetError`)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub errors: Option<serde_json::Value>,
+}
+
+impl Facet {
+	///
+	/// lists each distinct value of the facet with its count
+	///
+	pub fn counts(&self) -> Vec<(String, u64)> {
+		self
+			.data
+			.iter()
+			.map(|row| {
+				let value = match row.get(&self.expression) {
+					Some(serde_json::Value::String(v)) => v.clone(),
+					Some(v) => v.to_string(),
+					None => String::new(),
+				};
+				let count = row.get("count").and_then(|c| c.as_u64()).unwrap_or(0);
+
+				(value, count)
+			})
+			.collect()
+	}
+}
+
+///
+/// deserializer for the list of Virtual Machines in a Resource Graph response which resolves the network information
+/// of each VM from its projected subnet resource ID
+///
+fn parse_virtual_machines<'de, D>(d: D) -> Result<Vec<VirtualMachine>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	let mut vms: Vec<VirtualMachine> = Deserialize::deserialize(d)?;
+	for vm in vms.iter_mut() {
+		vm.resolve_network();
+	}
+
+	Ok(vms)
 }
 
 impl Default for QueryResponse {
@@ -177,6 +1174,12 @@ impl Default for QueryResponse {
 		Self {
 			total_results: 0,
 			data: vec![],
+			cache_hits: 0,
+			facets: vec![],
+			skip_token: None,
+			truncated: false,
+			pages: 0,
+			queried_at: None,
 		}
 	}
 }
@@ -207,6 +1210,20 @@ impl ToRedisArgs for QueryResponse {
 	}
 }
 
+impl FromRedisValue for QueryResponse {
+	fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {
+		match v {
+			redis::Value::Data(d) => {
+				serde_json::from_slice::<QueryResponse>(d).map_err(redis::RedisError::from)
+			}
+			_ => Err(redis::RedisError::from(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Cannot read data into QueryResponse type",
+			))),
+		}
+	}
+}
+
 #[cfg(test)]
 mod query_request_tests {
 	#[test]
@@ -254,6 +1271,18 @@ mod query_request_tests {
 		assert_eq!(req_body.query.contains("matches regex"), true);
 	}
 
+	#[test]
+	fn multiple_regular_expressions_are_ored() {
+		use super::QueryRequest;
+		let patterns: Vec<String> = vec!["^web-[0-9]+$".to_string(), "^db-.*".to_string()];
+
+		let req_body = QueryRequest::make(&patterns, true, false, false, None, None, &None);
+
+		assert!(req_body.query.contains(
+			"(tolower(tostring(name)) matches regex '^web-[0-9]+$' or tolower(tostring(name)) matches regex '^db-.*')"
+		));
+	}
+
 	#[test]
 	fn query_extensions() {
 		use super::QueryRequest;
@@ -309,6 +1338,561 @@ mod query_request_tests {
 		assert_eq!(req_body.options.top, 150);
 	}
 
+	#[test]
+	fn preserve_case_keeps_operand_case() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["Linux-01".to_string(), "WINDOWS-98".to_string()];
+
+		let lowered = QueryRequest::make(&hostnames, false, false, false, None, None, &None);
+		assert!(lowered.query.contains("in ('linux-01', 'windows-98')"));
+
+		let options = QueryOptions {
+			preserve_case: true,
+			..Default::default()
+		};
+		let req_body = QueryRequest::make_with_options(
+			&hostnames, false, false, false, None, None, &None, &options,
+		);
+
+		assert!(req_body.query.contains("in~ ('Linux-01', 'WINDOWS-98')"));
+	}
+
+	#[test]
+	fn os_name_filter() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["web-[0-9]+".to_string()];
+
+		let options = QueryOptions {
+			os_filter: Some("ubuntu".to_string()),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, true, true, false, None, None, &None, &options);
+
+		assert!(req_body
+			.query
+			.contains("| where osName matches regex '(?i)ubuntu' | join kind=leftouter(Resources"));
+
+		let unfiltered = QueryRequest::make(&hostnames, true, false, false, None, None, &None);
+		assert!(!unfiltered.query.contains("where osName"));
+	}
+
+	#[test]
+	fn facets_are_serialized() {
+		use super::{QueryOptions, QueryRequest, QueryResponse};
+		let hostnames: Vec<String> = vec!["linux-.*".to_string()];
+
+		let options = QueryOptions {
+			facets: vec!["location".to_string()],
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, true, false, false, None, None, &None, &options);
+		let body = serde_json::to_value(&req_body).unwrap();
+
+		assert_eq!(
+			body["facets"],
+			serde_json::json!([{ "expression": "location", "options": { "sortBy": "count", "sortOrder": "desc", "$top": 1000 } }])
+		);
+
+		let unfaceted = QueryRequest::make(&hostnames, true, false, false, None, None, &None);
+		assert!(serde_json::to_value(&unfaceted).unwrap().get("facets").is_none());
+
+		let resp: QueryResponse = serde_json::from_value(serde_json::json!({
+			"totalRecords": 0,
+			"data": [],
+			"facets": [{
+				"expression": "location",
+				"resultType": "FacetResult",
+				"totalRecords": 2,
+				"count": 2,
+				"data": [{ "location": "canadacentral", "count": 5 }, { "location": "canadaeast", "count": 2 }]
+			}]
+		}))
+		.unwrap();
+		assert_eq!(
+			resp.facets[0].counts(),
+			vec![("canadacentral".to_string(), 5), ("canadaeast".to_string(), 2)]
+		);
+	}
+
+	#[test]
+	fn contains_match_mode() {
+		use super::{MatchMode, QueryOptions, QueryRequest};
+		let operand: Vec<String> = vec!["SQL'".to_string()];
+
+		let options = QueryOptions {
+			match_mode: MatchMode::Contains,
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&operand, false, false, false, None, None, &None, &options);
+
+		assert!(req_body
+			.query
+			.contains("| where tolower(tostring(name)) contains 'sql\\'' |"));
+		assert!(!req_body.query.contains("matches regex"));
+
+		// an explicit regex match takes precedence
+		let regex =
+			QueryRequest::make_with_options(&operand, true, false, false, None, None, &None, &options);
+		assert!(regex.query.contains("matches regex"));
+	}
+
+	#[test]
+	fn power_state_filter() {
+		use super::{QueryOptions, QueryRequest};
+		use crate::vm::PowerState;
+		let hostnames: Vec<String> = vec!["linux-.*".to_string()];
+
+		let options = QueryOptions {
+			power_state: Some(PowerState::Stopped),
+			fields: Some(vec!["vmName".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, true, false, false, None, None, &None, &options);
+
+		assert!(req_body
+			.query
+			.contains("powerstate = tostring(properties.extended.instanceView.powerState.code) | where powerstate =~ 'PowerState/stopped'"));
+		assert!(!req_body.query.contains("deallocated"));
+		assert!(req_body.query.ends_with("| project vmId, vmName"));
+	}
+
+	#[test]
+	fn power_state_filter_with_tags_and_extensions() {
+		use super::{QueryOptions, QueryRequest};
+		use crate::vm::PowerState;
+		let hostnames: Vec<String> = vec!["linux-.*".to_string()];
+
+		let options = QueryOptions {
+			power_state: Some(PowerState::Running),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, true, true, true, None, None, &None, &options);
+
+		// the filter follows the projection (and tags) which define powerstate, and precedes the extensions join
+		let project = req_body.query.find("| project ").unwrap();
+		let tags = req_body.query.find(", tags=tags").unwrap();
+		let filter = req_body
+			.query
+			.find(" | where powerstate =~ 'PowerState/running'")
+			.unwrap();
+		let extensions = req_body
+			.query
+			.find("| join kind=leftouter(Resources")
+			.unwrap();
+		assert!(project < tags && tags < filter && filter < extensions);
+		assert!(req_body
+			.query
+			.contains("tolower(tostring(name)) matches regex"));
+	}
+
+	#[test]
+	fn limited_fields_projection() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			fields: Some(vec!["vmName".to_string(), "powerstate".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, true, None, None, &None, &options);
+
+		assert!(req_body.query.ends_with("| project vmId, vmName, powerstate"));
+		assert!(req_body.query.contains("| project vmId = id, vmName = name, powerstate = tostring("));
+		assert!(!req_body.query.contains("osType"));
+		assert!(!req_body.query.contains("tags=tags"));
+		assert!(!req_body.query.contains("ResourceContainers"));
+		assert!(!req_body.query.contains("microsoft.network/networkinterfaces"));
+	}
+
+	#[test]
+	fn os_disk_encryption_join() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			show_encryption: true,
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+
+		assert!(req_body
+			.query
+			.contains("osDiskId = tolower(tostring(properties.storageProfile.osDisk.managedDisk.id))"));
+		assert!(req_body
+			.query
+			.contains("| join kind=leftouter (Resources | where type =~ 'microsoft.compute/disks'"));
+		assert!(req_body.query.contains("| extend osDiskEncrypted = "));
+
+		let unencrypted = QueryRequest::make(&hostnames, false, false, false, None, None, &None);
+		assert!(!unencrypted.query.contains("microsoft.compute/disks"));
+		assert!(!unencrypted.query.contains("osDiskEncrypted"));
+	}
+
+	#[test]
+	fn hibernation_capability_projection() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			show_capabilities: true,
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body
+			.query
+			.contains("hibernationEnabled = tobool(properties.additionalCapabilities.hibernationEnabled)"));
+		assert!(options.bypasses_cache());
+
+		let limited = QueryOptions {
+			show_capabilities: true,
+			fields: Some(vec!["vmName".to_string(), "hibernationEnabled".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &limited);
+		assert!(req_body.query.ends_with("| project vmId, vmName, hibernationEnabled"));
+
+		let without = QueryRequest::make(&hostnames, false, false, false, None, None, &None);
+		assert!(!without.query.contains("hibernationEnabled"));
+	}
+
+	#[test]
+	fn oversized_query_is_rejected() {
+		use super::{validate_query, MAX_QUERY_LENGTH};
+		use crate::error::Kind;
+
+		let query = format!(
+			"Resources | where name in~ ('{}')",
+			"a".repeat(MAX_QUERY_LENGTH)
+		);
+
+		assert_eq!(
+			validate_query(&query).unwrap_err().kind(),
+			Kind::InvalidQueryError
+		);
+	}
+
+	#[test]
+	fn unbalanced_quotes_are_rejected() {
+		use super::{validate_query, QueryRequest};
+		use crate::error::Kind;
+
+		assert_eq!(
+			validate_query("Resources | where name =~ 'linux-01").unwrap_err().kind(),
+			Kind::InvalidQueryError
+		);
+		assert!(validate_query("Resources | where name =~ \"linux-01'").is_err());
+
+		// escaped and nested quotes are balanced
+		assert!(validate_query(r#"Resources | where name =~ 'it\'s \\' | where a == "'""#).is_ok());
+		let templated = |names: &[&str]| {
+			QueryRequest::make(
+				&names.iter().map(|n| n.to_string()).collect(),
+				false,
+				true,
+				true,
+				None,
+				None,
+				&None,
+			)
+		};
+		assert!(templated(&["linux-01", "linux-02"]).validate().is_ok());
+		// VM names are not escaped, so a stray quote would otherwise be sent as malformed KQL
+		assert!(templated(&["linux-'01"]).validate().is_err());
+	}
+
+	#[test]
+	fn broad_regex_warns() {
+		use super::regex_warning;
+
+		for pattern in [".*", "^.+$", ""] {
+			assert!(regex_warning(pattern)
+				.unwrap()
+				.contains("matches every VM name"));
+		}
+	}
+
+	#[test]
+	fn nested_quantifier_regex_warns() {
+		use super::regex_warning;
+
+		assert!(regex_warning("(a+)+$").unwrap().contains("nested quantifier"));
+		assert!(regex_warning(r"^(web-(\d+)*)$").is_some());
+		// quantifiers inside character classes or escaped parentheses are not nested
+		assert!(regex_warning(r"^web([+]\d)+$").is_none());
+		assert!(regex_warning(r"^web\(\d+\)+$").is_none());
+	}
+
+	#[test]
+	fn normal_regex_passes() {
+		use super::regex_warning;
+
+		assert_eq!(regex_warning("^web-[0-9]+$"), None);
+		assert_eq!(regex_warning("linux-.*"), None);
+		assert_eq!(regex_warning("^(web|db)-0[1-9]$"), None);
+	}
+
+	#[test]
+	fn ephemeral_os_disk_projection() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			show_disks: true,
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body.query.contains(
+			"ephemeralOsDisk = isnotempty(tostring(properties.storageProfile.osDisk.diffDiskSettings.option))"
+		));
+		assert!(options.bypasses_cache());
+
+		let without = QueryRequest::make(&hostnames, false, false, false, None, None, &None);
+		assert!(!without.query.contains("ephemeralOsDisk"));
+	}
+
+	#[test]
+	fn disk_perf_join_only_when_requested() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+		let disk_perf_join = "| join kind=leftouter (Resources | where type =~ 'microsoft.compute/disks' and isnotempty(managedBy)";
+
+		let options = QueryOptions {
+			show_disk_perf: true,
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body.query.contains(disk_perf_join));
+		assert!(req_body.query.contains("osDiskIOPSReadWrite = max("));
+		assert!(req_body.query.contains("dataDiskMBpsReadWrite = sum("));
+		assert!(options.bypasses_cache());
+
+		let without = QueryRequest::make(&hostnames, false, false, false, None, None, &None);
+		assert!(!without.query.contains("microsoft.compute/disks"));
+
+		// the join is skipped when none of the disk performance fields are requested
+		let unrequested = QueryOptions {
+			show_disk_perf: true,
+			fields: Some(vec!["vmName".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &unrequested);
+		assert!(!req_body.query.contains("microsoft.compute/disks"));
+	}
+
+	#[test]
+	fn maintenance_status_projection() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			show_maintenance: true,
+			fields: Some(vec!["vmName".to_string(), "maintenanceStatus".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body.query.contains("maintenanceStatus = case(tobool(properties.extended.instanceView.maintenanceRedeployStatus.isCustomerInitiatedMaintenanceAllowed)"));
+		assert!(req_body.query.ends_with("| project vmId, vmName, maintenanceStatus"));
+		assert!(options.bypasses_cache());
+
+		// the column is only returned when maintenance details are requested
+		let options = QueryOptions {
+			fields: Some(vec!["vmName".to_string(), "maintenanceStatus".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(!req_body.query.contains("maintenanceStatus"));
+	}
+
+	#[test]
+	fn placement_projection() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			show_placement: true,
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body.query.contains("proximityPlacementGroupId = tostring(properties.proximityPlacementGroup.id), hostId = tostring(properties.host.id), hostGroupId = tostring(properties.hostGroup.id)"));
+		assert!(options.bypasses_cache());
+
+		// only the requested placement columns are returned
+		let options = QueryOptions {
+			show_placement: true,
+			fields: Some(vec!["vmName".to_string(), "hostGroupId".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body.query.ends_with("| project vmId, vmName, hostGroupId"));
+
+		let options = QueryOptions {
+			fields: Some(vec!["vmName".to_string(), "hostGroupId".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(!req_body.query.contains("hostGroupId"));
+	}
+
+	#[test]
+	fn sort_clause() {
+		use super::{QueryOptions, QueryRequest, SortBy, SortOrder};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let req_body = QueryRequest::make(&hostnames, false, false, false, None, None, &None);
+		// without a sort, results are ordered by subnet name as they always have been (not by subnet resource ID)
+		assert!(req_body
+			.query
+			.ends_with("| order by subnet asc | project-away subnet"));
+		assert!(!req_body.query.contains("order by subnetId"));
+
+		let sorted = |sort: (SortBy, SortOrder)| {
+			let options = QueryOptions {
+				sort: Some(sort),
+				..Default::default()
+			};
+			assert!(options.bypasses_cache());
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options)
+				.query
+		};
+		assert!(sorted((SortBy::VmName, SortOrder::Asc)).ends_with("| order by vmName asc"));
+		assert!(!sorted((SortBy::VmName, SortOrder::Asc)).contains("order by subnet"));
+		assert!(sorted((SortBy::PowerState, SortOrder::Desc)).ends_with("| order by powerstate desc"));
+		assert!(sorted((SortBy::Created, SortOrder::Desc)).ends_with("| order by created desc"));
+		assert!(
+			sorted((SortBy::PrivateIp, SortOrder::Asc)).ends_with("| order by parse_ipv4(privateIp) asc")
+		);
+
+		// the sort column is projected even when the fields are limited
+		let options = QueryOptions {
+			fields: Some(vec!["vmName".to_string()]),
+			sort: Some((SortBy::Created, SortOrder::Asc)),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body
+			.query
+			.ends_with("| order by created asc | project vmId, vmName, created"));
+
+		assert_eq!("PRIVATEIP".parse::<SortBy>(), Ok(SortBy::PrivateIp));
+		assert!("subnet".parse::<SortBy>().is_err());
+	}
+
+	#[test]
+	fn subnet_id_projection() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			fields: Some(vec!["subnetId".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body.query.contains("subnetId = tostring(ipconfig.properties.subnet.id)"));
+		assert!(req_body.query.ends_with("| project vmId, subnetId"));
+
+		// the subnet ID is only projected once when the names parsed from it are also requested
+		let options = QueryOptions {
+			fields: Some(vec!["subnet".to_string(), "subnetId".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body.query.ends_with("| project vmId, subnetId"));
+	}
+
+	#[test]
+	fn tag_column_projection() {
+		use super::{tag_projection, QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			extra_projections: vec![tag_projection("env"), tag_projection("cost centre")],
+			fields: Some(vec!["vmName".to_string()]),
+			..Default::default()
+		};
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert!(req_body.query.contains(
+			"extraColumns = pack('env', tostring(tags['env']), 'cost centre', tostring(tags['cost centre']))"
+		));
+		assert!(req_body.query.ends_with("| project vmId, vmName, extraColumns"));
+		assert!(options.bypasses_cache());
+
+		let without = QueryRequest::make(&hostnames, false, false, false, None, None, &None);
+		assert!(!without.query.contains("extraColumns"));
+	}
+
+	#[test]
+	fn mixed_resource_ids_and_names() {
+		use super::QueryRequest;
+		let operands: Vec<String> = vec![
+			"Linux-01".to_string(),
+			"/subscriptions/0000/resourceGroups/prod-rg/providers/Microsoft.Compute/virtualMachines/web-01"
+				.to_string(),
+			"linux-02".to_string(),
+		];
+
+		let req_body = QueryRequest::make(&operands, false, false, false, None, None, &None);
+		assert!(req_body.query.contains("| where (tolower(tostring(name)) in ('linux-01', 'linux-02') or id in~ ('/subscriptions/0000/resourcegroups/prod-rg/providers/microsoft.compute/virtualmachines/web-01')) |"));
+
+		// only resource IDs
+		let req_body = QueryRequest::make(&operands[1..2].to_vec(), false, false, false, None, None, &None);
+		assert!(req_body.query.contains("| where id in~ ('/subscriptions/0000/"));
+		assert!(!req_body.query.contains("tolower(tostring(name))"));
+	}
+
+	#[test]
+	fn denied_subscriptions_are_excluded() {
+		use super::{QueryOptions, QueryRequest};
+		let hostnames: Vec<String> = vec!["linux-01".to_string()];
+
+		let options = QueryOptions {
+			subscription_denylist: vec!["2222-3333".to_string()],
+			..Default::default()
+		};
+		let req_body = QueryRequest::make_with_options(
+			&hostnames,
+			false,
+			false,
+			false,
+			None,
+			None,
+			&Some(vec!["0000-1111".to_string(), "2222-3333".to_string()]),
+			&options,
+		);
+		assert_eq!(req_body.subscriptions, Some(vec!["0000-1111".to_string()]));
+		assert!(req_body
+			.query
+			.contains("and subscriptionId !in~ ('2222-3333') |"));
+
+		// VMs in a denied subscription are filtered even when every subscription is queried
+		let req_body =
+			QueryRequest::make_with_options(&hostnames, false, false, false, None, None, &None, &options);
+		assert_eq!(req_body.subscriptions, None);
+		assert!(req_body.query.contains("subscriptionId !in~ ('2222-3333')"));
+		assert!(options.denies_subscription("2222-3333"));
+		assert!(!options.denies_subscription("0000-1111"));
+	}
+
 	#[test]
 	fn query_a_page() {
 		use super::QueryRequest;
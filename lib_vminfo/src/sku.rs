@@ -0,0 +1,239 @@
+//!
+//!
+//! Provides a bundled table of the vCPU and memory of common Azure Virtual Machine sizes, so VM sizes can be resolved
+//! without querying the Azure SKU API
+//!
+//!
+
+///
+/// The capacity of a Virtual Machine size
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkuDetails {
+	/// the number of vCPUs
+	pub vcpus: u32,
+	/// the memory in GiB
+	pub memory_gb: f64,
+}
+
+///
+/// the vCPUs and memory (GiB) of common VM sizes, as published by Azure
+///
+const SKUS: [(&str, u32, f64); 176] = [
+	("Standard_B1ls", 1, 0.5),
+	("Standard_B1s", 1, 1.0),
+	("Standard_B1ms", 1, 2.0),
+	("Standard_B2s", 2, 4.0),
+	("Standard_B2ms", 2, 8.0),
+	("Standard_B4ms", 4, 16.0),
+	("Standard_B8ms", 8, 32.0),
+	("Standard_B12ms", 12, 48.0),
+	("Standard_B16ms", 16, 64.0),
+	("Standard_B20ms", 20, 80.0),
+	("Standard_A1_v2", 1, 2.0),
+	("Standard_A2_v2", 2, 4.0),
+	("Standard_A4_v2", 4, 8.0),
+	("Standard_A8_v2", 8, 16.0),
+	("Standard_A2m_v2", 2, 16.0),
+	("Standard_A4m_v2", 4, 32.0),
+	("Standard_A8m_v2", 8, 64.0),
+	("Standard_D1_v2", 1, 3.5),
+	("Standard_DS1_v2", 1, 3.5),
+	("Standard_D2_v2", 2, 7.0),
+	("Standard_DS2_v2", 2, 7.0),
+	("Standard_D3_v2", 4, 14.0),
+	("Standard_DS3_v2", 4, 14.0),
+	("Standard_D4_v2", 8, 28.0),
+	("Standard_DS4_v2", 8, 28.0),
+	("Standard_D5_v2", 16, 56.0),
+	("Standard_DS5_v2", 16, 56.0),
+	("Standard_D2_v3", 2, 8.0),
+	("Standard_D2s_v3", 2, 8.0),
+	("Standard_D4_v3", 4, 16.0),
+	("Standard_D4s_v3", 4, 16.0),
+	("Standard_D8_v3", 8, 32.0),
+	("Standard_D8s_v3", 8, 32.0),
+	("Standard_D16_v3", 16, 64.0),
+	("Standard_D16s_v3", 16, 64.0),
+	("Standard_D32_v3", 32, 128.0),
+	("Standard_D32s_v3", 32, 128.0),
+	("Standard_D48_v3", 48, 192.0),
+	("Standard_D48s_v3", 48, 192.0),
+	("Standard_D64_v3", 64, 256.0),
+	("Standard_D64s_v3", 64, 256.0),
+	("Standard_D2_v4", 2, 8.0),
+	("Standard_D2s_v4", 2, 8.0),
+	("Standard_D2ds_v4", 2, 8.0),
+	("Standard_D2as_v4", 2, 8.0),
+	("Standard_D4_v4", 4, 16.0),
+	("Standard_D4s_v4", 4, 16.0),
+	("Standard_D4ds_v4", 4, 16.0),
+	("Standard_D4as_v4", 4, 16.0),
+	("Standard_D8_v4", 8, 32.0),
+	("Standard_D8s_v4", 8, 32.0),
+	("Standard_D8ds_v4", 8, 32.0),
+	("Standard_D8as_v4", 8, 32.0),
+	("Standard_D16_v4", 16, 64.0),
+	("Standard_D16s_v4", 16, 64.0),
+	("Standard_D16ds_v4", 16, 64.0),
+	("Standard_D16as_v4", 16, 64.0),
+	("Standard_D32_v4", 32, 128.0),
+	("Standard_D32s_v4", 32, 128.0),
+	("Standard_D32ds_v4", 32, 128.0),
+	("Standard_D32as_v4", 32, 128.0),
+	("Standard_D48_v4", 48, 192.0),
+	("Standard_D48s_v4", 48, 192.0),
+	("Standard_D48ds_v4", 48, 192.0),
+	("Standard_D48as_v4", 48, 192.0),
+	("Standard_D64_v4", 64, 256.0),
+	("Standard_D64s_v4", 64, 256.0),
+	("Standard_D64ds_v4", 64, 256.0),
+	("Standard_D64as_v4", 64, 256.0),
+	("Standard_D2_v5", 2, 8.0),
+	("Standard_D2s_v5", 2, 8.0),
+	("Standard_D2ds_v5", 2, 8.0),
+	("Standard_D2as_v5", 2, 8.0),
+	("Standard_D4_v5", 4, 16.0),
+	("Standard_D4s_v5", 4, 16.0),
+	("Standard_D4ds_v5", 4, 16.0),
+	("Standard_D4as_v5", 4, 16.0),
+	("Standard_D8_v5", 8, 32.0),
+	("Standard_D8s_v5", 8, 32.0),
+	("Standard_D8ds_v5", 8, 32.0),
+	("Standard_D8as_v5", 8, 32.0),
+	("Standard_D16_v5", 16, 64.0),
+	("Standard_D16s_v5", 16, 64.0),
+	("Standard_D16ds_v5", 16, 64.0),
+	("Standard_D16as_v5", 16, 64.0),
+	("Standard_D32_v5", 32, 128.0),
+	("Standard_D32s_v5", 32, 128.0),
+	("Standard_D32ds_v5", 32, 128.0),
+	("Standard_D32as_v5", 32, 128.0),
+	("Standard_D48_v5", 48, 192.0),
+	("Standard_D48s_v5", 48, 192.0),
+	("Standard_D48ds_v5", 48, 192.0),
+	("Standard_D48as_v5", 48, 192.0),
+	("Standard_D64_v5", 64, 256.0),
+	("Standard_D64s_v5", 64, 256.0),
+	("Standard_D64ds_v5", 64, 256.0),
+	("Standard_D64as_v5", 64, 256.0),
+	("Standard_D96_v5", 96, 384.0),
+	("Standard_D96s_v5", 96, 384.0),
+	("Standard_D96ds_v5", 96, 384.0),
+	("Standard_D96as_v5", 96, 384.0),
+	("Standard_E2_v3", 2, 16.0),
+	("Standard_E2s_v3", 2, 16.0),
+	("Standard_E4_v3", 4, 32.0),
+	("Standard_E4s_v3", 4, 32.0),
+	("Standard_E8_v3", 8, 64.0),
+	("Standard_E8s_v3", 8, 64.0),
+	("Standard_E16_v3", 16, 128.0),
+	("Standard_E16s_v3", 16, 128.0),
+	("Standard_E20_v3", 20, 160.0),
+	("Standard_E20s_v3", 20, 160.0),
+	("Standard_E32_v3", 32, 256.0),
+	("Standard_E32s_v3", 32, 256.0),
+	("Standard_E48_v3", 48, 384.0),
+	("Standard_E48s_v3", 48, 384.0),
+	("Standard_E64_v3", 64, 432.0),
+	("Standard_E64s_v3", 64, 432.0),
+	("Standard_E2s_v4", 2, 16.0),
+	("Standard_E2ds_v4", 2, 16.0),
+	("Standard_E2as_v4", 2, 16.0),
+	("Standard_E4s_v4", 4, 32.0),
+	("Standard_E4ds_v4", 4, 32.0),
+	("Standard_E4as_v4", 4, 32.0),
+	("Standard_E8s_v4", 8, 64.0),
+	("Standard_E8ds_v4", 8, 64.0),
+	("Standard_E8as_v4", 8, 64.0),
+	("Standard_E16s_v4", 16, 128.0),
+	("Standard_E16ds_v4", 16, 128.0),
+	("Standard_E16as_v4", 16, 128.0),
+	("Standard_E20s_v4", 20, 160.0),
+	("Standard_E20ds_v4", 20, 160.0),
+	("Standard_E20as_v4", 20, 160.0),
+	("Standard_E32s_v4", 32, 256.0),
+	("Standard_E32ds_v4", 32, 256.0),
+	("Standard_E32as_v4", 32, 256.0),
+	("Standard_E48s_v4", 48, 384.0),
+	("Standard_E48ds_v4", 48, 384.0),
+	("Standard_E48as_v4", 48, 384.0),
+	("Standard_E64s_v4", 64, 504.0),
+	("Standard_E64ds_v4", 64, 504.0),
+	("Standard_E64as_v4", 64, 512.0),
+	("Standard_E2s_v5", 2, 16.0),
+	("Standard_E2ds_v5", 2, 16.0),
+	("Standard_E2as_v5", 2, 16.0),
+	("Standard_E4s_v5", 4, 32.0),
+	("Standard_E4ds_v5", 4, 32.0),
+	("Standard_E4as_v5", 4, 32.0),
+	("Standard_E8s_v5", 8, 64.0),
+	("Standard_E8ds_v5", 8, 64.0),
+	("Standard_E8as_v5", 8, 64.0),
+	("Standard_E16s_v5", 16, 128.0),
+	("Standard_E16ds_v5", 16, 128.0),
+	("Standard_E16as_v5", 16, 128.0),
+	("Standard_E20s_v5", 20, 160.0),
+	("Standard_E20ds_v5", 20, 160.0),
+	("Standard_E20as_v5", 20, 160.0),
+	("Standard_E32s_v5", 32, 256.0),
+	("Standard_E32ds_v5", 32, 256.0),
+	("Standard_E32as_v5", 32, 256.0),
+	("Standard_E48s_v5", 48, 384.0),
+	("Standard_E48ds_v5", 48, 384.0),
+	("Standard_E48as_v5", 48, 384.0),
+	("Standard_E64s_v5", 64, 512.0),
+	("Standard_E64ds_v5", 64, 512.0),
+	("Standard_E64as_v5", 64, 512.0),
+	("Standard_E96s_v5", 96, 672.0),
+	("Standard_E96ds_v5", 96, 672.0),
+	("Standard_E96as_v5", 96, 672.0),
+	("Standard_F2s_v2", 2, 4.0),
+	("Standard_F4s_v2", 4, 8.0),
+	("Standard_F8s_v2", 8, 16.0),
+	("Standard_F16s_v2", 16, 32.0),
+	("Standard_F32s_v2", 32, 64.0),
+	("Standard_F48s_v2", 48, 96.0),
+	("Standard_F64s_v2", 64, 128.0),
+	("Standard_F72s_v2", 72, 144.0),
+];
+
+///
+/// looks up the vCPUs and memory of a VM size (ie: Standard_D2s_v3). sizes are matched case-insensitively
+///
+/// returns None for sizes which are not in the bundled table
+///
+pub fn sku_details(vm_size: &str) -> Option<SkuDetails> {
+	SKUS
+		.iter()
+		.find(|(name, _, _)| name.eq_ignore_ascii_case(vm_size))
+		.map(|(_, vcpus, memory_gb)| SkuDetails {
+			vcpus: *vcpus,
+			memory_gb: *memory_gb,
+		})
+}
+
+#[cfg(test)]
+mod sku_tests {
+	#[test]
+	fn known_sizes_are_resolved() {
+		use super::{sku_details, SkuDetails};
+
+		assert_eq!(
+			sku_details("Standard_D2s_v3"),
+			Some(SkuDetails {
+				vcpus: 2,
+				memory_gb: 8.0
+			})
+		);
+		assert_eq!(
+			sku_details("standard_e16s_v5"),
+			Some(SkuDetails {
+				vcpus: 16,
+				memory_gb: 128.0
+			})
+		);
+		assert_eq!(sku_details("Standard_DS2_v2").unwrap().memory_gb, 7.0);
+		assert_eq!(sku_details("Standard_Unknown_v9"), None);
+	}
+}
@@ -0,0 +1,319 @@
+//!
+//!
+//! Test doubles shared by the unit tests in this crate
+//!
+//!
+
+use std::{
+	collections::{HashMap, VecDeque},
+	fmt::Display,
+	sync::{Arc, Mutex},
+};
+
+use crate::{
+	auth::AzCredentials,
+	caching::Cache,
+	error::{self, AuthErrorKind, Error, VMInfoResult},
+	persistance::PersistantStorage,
+	query::QueryResponse,
+	transport::{Transport, TransportResponse},
+	vm::VirtualMachine,
+	AuthTokens, Client,
+};
+
+///
+/// a Transport which records request URLs and bodies and replays queued responses in order
+///
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+	responses: Arc<Mutex<VecDeque<VMInfoResult<TransportResponse>>>>,
+	urls: Arc<Mutex<Vec<String>>>,
+	requests: Arc<Mutex<Vec<String>>>,
+	headers: Arc<Mutex<Vec<Vec<(String, String)>>>>,
+}
+
+impl MockTransport {
+	pub fn new(responses: Vec<VMInfoResult<TransportResponse>>) -> Self {
+		Self {
+			responses: Arc::new(Mutex::new(responses.into_iter().collect())),
+			urls: Arc::new(Mutex::new(Vec::new())),
+			requests: Arc::new(Mutex::new(Vec::new())),
+			headers: Arc::new(Mutex::new(Vec::new())),
+		}
+	}
+
+	pub fn urls(&self) -> Vec<String> {
+		self.urls.lock().unwrap().clone()
+	}
+
+	pub fn requests(&self) -> Vec<String> {
+		self.requests.lock().unwrap().clone()
+	}
+
+	pub fn headers(&self) -> Vec<Vec<(String, String)>> {
+		self.headers.lock().unwrap().clone()
+	}
+}
+
+impl Transport for MockTransport {
+	fn post_json(
+		&self,
+		url: &str,
+		_access_token: &str,
+		headers: &[(String, String)],
+		body: &str,
+	) -> VMInfoResult<TransportResponse> {
+		self.urls.lock().unwrap().push(url.to_string());
+		self.requests.lock().unwrap().push(body.to_string());
+		self.headers.lock().unwrap().push(headers.to_vec());
+
+		match self.responses.lock().unwrap().pop_front() {
+			Some(r) => r,
+			None => Err(error::other(
+				None::<Error>,
+				"mock transport has no responses left",
+			)),
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl crate::transport::AsyncTransport for MockTransport {
+	fn post_json<'a>(
+		&'a self,
+		url: &'a str,
+		access_token: &'a str,
+		headers: &'a [(String, String)],
+		body: &'a str,
+	) -> crate::transport::TransportFuture<'a> {
+		let result = Transport::post_json(self, url, access_token, headers, body);
+		Box::pin(async move { result })
+	}
+}
+
+///
+/// an in-memory token store
+///
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTokenStore {
+	credentials: Arc<Mutex<Option<AzCredentials>>>,
+}
+
+impl PersistantStorage<AzCredentials> for MemoryTokenStore {
+	fn write(&self, data: &AzCredentials) -> VMInfoResult<()> {
+		*self.credentials.lock().unwrap() = Some(data.clone());
+		Ok(())
+	}
+
+	fn read(&self) -> VMInfoResult<AzCredentials> {
+		match self.credentials.lock().unwrap().clone() {
+			Some(c) => Ok(c),
+			None => Err(error::auth(
+				None::<Error>,
+				AuthErrorKind::MissingToken,
+				"no credentials in memory",
+			)),
+		}
+	}
+
+	fn clear(&self) -> VMInfoResult<()> {
+		*self.credentials.lock().unwrap() = None;
+		Ok(())
+	}
+}
+
+impl Display for MemoryTokenStore {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "in-memory token store")
+	}
+}
+
+///
+/// an in-memory VM cache
+///
+pub type MemoryCache = crate::caching::memory_cache::InMemoryCache;
+
+///
+/// an in-memory query response cache, counting the responses written to it
+///
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResponseCache {
+	entries: Arc<Mutex<HashMap<String, QueryResponse>>>,
+}
+
+impl MemoryResponseCache {
+	pub fn len(&self) -> usize {
+		self.entries.lock().unwrap().len()
+	}
+}
+
+impl Cache<QueryResponse> for MemoryResponseCache {
+	fn put(&self, key: &str, data: &QueryResponse) -> VMInfoResult<()> {
+		self
+			.entries
+			.lock()
+			.unwrap()
+			.insert(key.to_string(), data.clone());
+		Ok(())
+	}
+
+	fn get(&self, key: &str) -> VMInfoResult<QueryResponse> {
+		match self.entries.lock().unwrap().get(key) {
+			Some(r) => Ok(r.clone()),
+			None => Err(error::caching(None::<Error>, "cache miss")),
+		}
+	}
+}
+
+///
+/// builds a VM with the provided name
+///
+pub fn vm(name: &str) -> VirtualMachine {
+	serde_json::from_value(serde_json::json!({
+		"vmId": format!("/subscriptions/0000/resourceGroups/rg/providers/Microsoft.Compute/virtualMachines/{}", name),
+		"vmName": name,
+		"privateIp": "10.0.0.4",
+	}))
+	.unwrap()
+}
+
+///
+/// builds a successful Resource Graph response containing the provided VMs
+///
+pub fn query_response(vms: &[VirtualMachine]) -> VMInfoResult<TransportResponse> {
+	Ok(TransportResponse {
+		status: reqwest::StatusCode::OK,
+		body: serde_json::json!({ "totalRecords": vms.len(), "count": vms.len(), "data": vms })
+			.to_string(),
+		retry_after: None,
+	})
+}
+
+///
+/// builds an unsigned JWT carrying the provided claims
+///
+pub fn jwt(claims: serde_json::Value) -> String {
+	use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+	use base64::Engine;
+
+	format!(
+		"{}.{}.sig",
+		URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#),
+		URL_SAFE_NO_PAD.encode(claims.to_string())
+	)
+}
+
+///
+/// builds an authenticated client using the provided transport and cache
+///
+pub fn client(
+	transport: MockTransport,
+	cache: Option<MemoryCache>,
+) -> Client<MemoryTokenStore, MemoryCache> {
+	let store = MemoryTokenStore::default();
+	store
+		.write(&AzCredentials {
+			tenant_id: "tenant".to_string(),
+			client_id: "client".to_string(),
+			client_secret: None,
+			certificate: None,
+			method: None,
+			tokens: AuthTokens {
+				access_token: "token".to_string(),
+				refresh_token: None,
+				expires_at: None,
+			},
+		})
+		.unwrap();
+
+	Client::from_components("tenant", "client", None, store, cache, None)
+		.with_transport(transport)
+		.load_credentials()
+		.unwrap()
+}
+
+///
+/// an in-memory OS keyring. unlike the mock of the keyring crate (which keeps each password in its own Entry), every
+/// Entry for the same service and user shares a password, as they do in a real keyring
+///
+#[derive(Debug, Default)]
+pub struct MemoryKeyring {
+	entries: KeyringEntries,
+}
+
+///
+/// the passwords of an in-memory keyring, keyed by service and user
+///
+type KeyringEntries = Arc<Mutex<HashMap<(String, String), String>>>;
+
+impl MemoryKeyring {
+	///
+	/// makes keyring::Entry use a single in-memory keyring (shared by every test) instead of the OS keyring
+	///
+	pub fn install() {
+		static KEYRING: std::sync::OnceLock<KeyringEntries> = std::sync::OnceLock::new();
+
+		keyring::set_default_credential_builder(Box::new(MemoryKeyring {
+			entries: KEYRING.get_or_init(Default::default).clone(),
+		}));
+	}
+}
+
+impl keyring::credential::CredentialBuilderApi for MemoryKeyring {
+	fn build(
+		&self,
+		_target: Option<&str>,
+		service: &str,
+		user: &str,
+	) -> keyring::Result<Box<keyring::Credential>> {
+		Ok(Box::new(MemoryKeyringEntry {
+			entries: self.entries.clone(),
+			key: (service.to_string(), user.to_string()),
+		}))
+	}
+
+	fn as_any(&self) -> &dyn std::any::Any {
+		self
+	}
+}
+
+#[derive(Debug)]
+struct MemoryKeyringEntry {
+	entries: KeyringEntries,
+	key: (String, String),
+}
+
+impl keyring::credential::CredentialApi for MemoryKeyringEntry {
+	fn set_password(&self, password: &str) -> keyring::Result<()> {
+		self
+			.entries
+			.lock()
+			.unwrap()
+			.insert(self.key.clone(), password.to_string());
+		Ok(())
+	}
+
+	fn get_password(&self) -> keyring::Result<String> {
+		self
+			.entries
+			.lock()
+			.unwrap()
+			.get(&self.key)
+			.cloned()
+			.ok_or(keyring::Error::NoEntry)
+	}
+
+	fn delete_password(&self) -> keyring::Result<()> {
+		self
+			.entries
+			.lock()
+			.unwrap()
+			.remove(&self.key)
+			.map(|_| ())
+			.ok_or(keyring::Error::NoEntry)
+	}
+
+	fn as_any(&self) -> &dyn std::any::Any {
+		self
+	}
+}
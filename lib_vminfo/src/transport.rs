@@ -0,0 +1,678 @@
+//!
+//!
+//! Provides the HTTP transport used by the vminfo Client to send requests to Azure Resource Graph
+//!
+//! Other transports (ie: for testing) can be used by implementing the Transport trait (or AsyncTransport, with the
+//! async feature)
+//!
+//!
+
+use std::fmt::Debug;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::header::{
+	HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER,
+};
+use reqwest::tls::Version;
+use reqwest::StatusCode;
+
+use crate::error::{self, Kind, VMInfoResult};
+
+///
+/// the number of times a request is retried after a connection-level failure (ie: connection reset, broken pipe or timeout)
+///
+/// this is kept small so that a genuinely unreachable endpoint is still reported promptly
+///
+pub const CONNECTION_RETRIES: u8 = 1;
+
+///
+/// the minimum TLS version negotiated on outbound connections (to Resource Graph and the Azure authorization server)
+/// unless another is configured
+///
+pub const DEFAULT_MIN_TLS_VERSION: Version = Version::TLS_1_2;
+
+///
+/// the header Resource Graph uses to report how long until a throttled caller's quota resets (ie: 00:00:05)
+///
+const QUOTA_RESETS_AFTER: &str = "x-ms-user-quota-resets-after";
+
+///
+/// defines the raw response returned by a Transport
+///
+#[derive(Debug, Clone)]
+pub struct TransportResponse {
+	/// HTTP status code of the response
+	pub status: reqwest::StatusCode,
+	/// raw response body
+	pub body: String,
+	/// how long the server asked the client to wait before another request (ie: from a Retry-After header)
+	pub retry_after: Option<Duration>,
+}
+
+///
+/// defines how requests answered with HTTP 429 (Too Many Requests) or a server error (5xx) are retried. any other
+/// response (including authentication failures) is never retried
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+	/// the number of times a request is retried before its last response is returned
+	pub max_retries: u32,
+	/// the delay before the first retry, which doubles with each retry after it
+	pub base_delay: Duration,
+	/// the longest delay between retries, including any delay asked for by the server
+	pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+	///
+	/// a policy which never retries
+	///
+	pub fn none() -> Self {
+		Self {
+			max_retries: 0,
+			..Self::default()
+		}
+	}
+
+	///
+	/// whether a response with this status is retried
+	///
+	pub fn is_retryable(status: StatusCode) -> bool {
+		status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+	}
+
+	///
+	/// the delay before a retry (counting from 0). a delay asked for by the server is used as it is, otherwise the
+	/// exponential backoff is randomized between half and all of itself so that throttled clients do not retry in step
+	///
+	pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+		if let Some(retry_after) = retry_after {
+			return retry_after.min(self.max_delay);
+		}
+
+		let backoff = self
+			.base_delay
+			.saturating_mul(2u32.saturating_pow(retry))
+			.min(self.max_delay);
+		let half = backoff / 2;
+
+		half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+	}
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_retries: 3,
+			base_delay: Duration::from_secs(1),
+			max_delay: Duration::from_secs(60),
+		}
+	}
+}
+
+///
+/// defines a method for sending an authenticated JSON request body to a URL
+///
+pub trait Transport: Debug + Send + Sync {
+	///
+	/// sends a JSON request body as a POST to the provided URL using a bearer access token and any additional headers
+	///
+	fn post_json(
+		&self,
+		url: &str,
+		access_token: &str,
+		headers: &[(String, String)],
+		body: &str,
+	) -> VMInfoResult<TransportResponse>;
+}
+
+///
+/// the future returned by an AsyncTransport
+///
+#[cfg(feature = "async")]
+pub type TransportFuture<'a> =
+	Pin<Box<dyn Future<Output = VMInfoResult<TransportResponse>> + Send + 'a>>;
+
+///
+/// defines a non-blocking method for sending an authenticated JSON request body to a URL
+///
+#[cfg(feature = "async")]
+pub trait AsyncTransport: Debug + Send + Sync {
+	///
+	/// sends a JSON request body as a POST to the provided URL using a bearer access token and any additional headers
+	///
+	fn post_json<'a>(
+		&'a self,
+		url: &'a str,
+		access_token: &'a str,
+		headers: &'a [(String, String)],
+		body: &'a str,
+	) -> TransportFuture<'a>;
+}
+
+///
+/// the default Transport which sends requests using a blocking reqwest client
+///
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+	///
+	/// the minimum TLS version allowed for connections to Resource Graph
+	///
+	min_tls_version: Version,
+}
+
+impl HttpTransport {
+	///
+	/// creates a new HttpTransport which refuses to connect using a TLS version older than min_tls_version
+	///
+	/// fails if the TLS backend does not support the requested minimum (ie: TLS 1.3 with native-tls)
+	///
+	pub fn new(min_tls_version: Version) -> VMInfoResult<Self> {
+		http_client_builder(min_tls_version)
+			.build()
+			.map_err(|err| error::client_config(Some(err), "unsupported minimum TLS version"))?;
+
+		Ok(Self { min_tls_version })
+	}
+}
+
+impl Default for HttpTransport {
+	fn default() -> Self {
+		Self {
+			min_tls_version: DEFAULT_MIN_TLS_VERSION,
+		}
+	}
+}
+
+impl Transport for HttpTransport {
+	fn post_json(
+		&self,
+		url: &str,
+		access_token: &str,
+		headers: &[(String, String)],
+		body: &str,
+	) -> VMInfoResult<TransportResponse> {
+		let http_client: reqwest::blocking::Client = http_client_builder(self.min_tls_version)
+			.build()
+			.map_err(|err| error::client_config(Some(err), "failed to create HTTP client"))?;
+
+		let mut req = http_client
+			.post(url)
+			.bearer_auth(access_token)
+			.header(CONTENT_TYPE, "application/json");
+		for (name, value) in headers {
+			req = req.header(name.as_str(), value.as_str());
+		}
+
+		let resp = req.body(body.to_string()).send().map_err(|err| {
+			let status = err.status();
+			error::request(
+				Some(err),
+				status,
+				"request for vm info from Resource Graph failed",
+			)
+		})?;
+
+		let status = resp.status();
+		let retry_after = retry_after(resp.headers());
+		let body = resp.text().map_err(|err| {
+			error::request(
+				Some(err),
+				Some(status),
+				"could not read response body from Resource Graph",
+			)
+		})?;
+
+		Ok(TransportResponse {
+			status,
+			body,
+			retry_after,
+		})
+	}
+}
+
+///
+/// the default AsyncTransport which sends requests using a non-blocking reqwest client
+///
+#[cfg(feature = "async")]
+#[derive(Debug, Clone)]
+pub struct AsyncHttpTransport {
+	///
+	/// the minimum TLS version allowed for connections to Resource Graph
+	///
+	min_tls_version: Version,
+}
+
+#[cfg(feature = "async")]
+impl AsyncHttpTransport {
+	///
+	/// creates a new AsyncHttpTransport which refuses to connect using a TLS version older than min_tls_version
+	///
+	/// fails if the TLS backend does not support the requested minimum (ie: TLS 1.3 with native-tls)
+	///
+	pub fn new(min_tls_version: Version) -> VMInfoResult<Self> {
+		async_http_client_builder(min_tls_version)
+			.build()
+			.map_err(|err| error::client_config(Some(err), "unsupported minimum TLS version"))?;
+
+		Ok(Self { min_tls_version })
+	}
+}
+
+#[cfg(feature = "async")]
+impl Default for AsyncHttpTransport {
+	fn default() -> Self {
+		Self {
+			min_tls_version: DEFAULT_MIN_TLS_VERSION,
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl AsyncTransport for AsyncHttpTransport {
+	fn post_json<'a>(
+		&'a self,
+		url: &'a str,
+		access_token: &'a str,
+		headers: &'a [(String, String)],
+		body: &'a str,
+	) -> TransportFuture<'a> {
+		Box::pin(async move {
+			let http_client: reqwest::Client = async_http_client_builder(self.min_tls_version)
+				.build()
+				.map_err(|err| error::client_config(Some(err), "failed to create HTTP client"))?;
+
+			let mut req = http_client
+				.post(url)
+				.bearer_auth(access_token)
+				.header(CONTENT_TYPE, "application/json");
+			for (name, value) in headers {
+				req = req.header(name.as_str(), value.as_str());
+			}
+
+			let resp = req.body(body.to_string()).send().await.map_err(|err| {
+				let status = err.status();
+				error::request(
+					Some(err),
+					status,
+					"request for vm info from Resource Graph failed",
+				)
+			})?;
+
+			let status = resp.status();
+			let retry_after = retry_after(resp.headers());
+			let body = resp.text().await.map_err(|err| {
+				error::request(
+					Some(err),
+					Some(status),
+					"could not read response body from Resource Graph",
+				)
+			})?;
+
+			Ok(TransportResponse {
+				status,
+				body,
+				retry_after,
+			})
+		})
+	}
+}
+
+///
+/// how long a response asks the client to wait, from its Retry-After header (in seconds) or the quota reset time
+/// reported by Resource Graph (as hh:mm:ss)
+///
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+	let header = |name: &str| {
+		headers
+			.get(name)
+			.and_then(|v| v.to_str().ok())
+			.map(str::trim)
+	};
+
+	if let Some(seconds) = header(RETRY_AFTER.as_str()).and_then(|v| v.parse::<u64>().ok()) {
+		return Some(Duration::from_secs(seconds));
+	}
+
+	let parts: Vec<u64> = header(QUOTA_RESETS_AFTER)?
+		.split(':')
+		.map(|p| p.parse::<u64>().ok())
+		.collect::<Option<Vec<u64>>>()?;
+	match parts.as_slice() {
+		[hours, minutes, seconds] => Some(Duration::from_secs(hours * 3600 + minutes * 60 + seconds)),
+		_ => None,
+	}
+}
+
+///
+/// the builder used for every outbound HTTP client, enforcing a minimum TLS version
+///
+pub fn http_client_builder(min_tls_version: Version) -> reqwest::blocking::ClientBuilder {
+	reqwest::blocking::Client::builder().min_tls_version(min_tls_version)
+}
+
+///
+/// the builder used for every non-blocking outbound HTTP client, enforcing a minimum TLS version
+///
+#[cfg(feature = "async")]
+pub(crate) fn async_http_client_builder(min_tls_version: Version) -> reqwest::ClientBuilder {
+	reqwest::Client::builder().min_tls_version(min_tls_version)
+}
+
+///
+/// parses a TLS version (ie: "1.2" or "TLS1.3") for use as the minimum TLS version of outbound connections
+///
+pub fn parse_tls_version(version: &str) -> VMInfoResult<Version> {
+	let number = version.trim();
+	let number = number
+		.strip_prefix("TLS")
+		.or_else(|| number.strip_prefix("tls"))
+		.unwrap_or(number);
+
+	match number.trim() {
+		"1.0" => Ok(Version::TLS_1_0),
+		"1.1" => Ok(Version::TLS_1_1),
+		"1.2" => Ok(Version::TLS_1_2),
+		"1.3" => Ok(Version::TLS_1_3),
+		_ => Err(error::client_config(
+			None::<error::Error>,
+			format!(
+				"'{}' is not a TLS version. expected one of 1.0, 1.1, 1.2 or 1.3",
+				version
+			)
+			.as_str(),
+		)),
+	}
+}
+
+///
+/// checks that a list of additional headers are valid HTTP headers which do not replace the headers set by vminfo
+///
+pub fn validate_headers(headers: &[(String, String)]) -> VMInfoResult<()> {
+	for (name, value) in headers {
+		let header_name = HeaderName::from_bytes(name.as_bytes())
+			.map_err(|err| error::client_config(Some(err), "invalid HTTP header name"))?;
+		HeaderValue::from_str(value)
+			.map_err(|err| error::client_config(Some(err), "invalid HTTP header value"))?;
+
+		if header_name == AUTHORIZATION || header_name == CONTENT_TYPE {
+			return Err(error::client_config(
+				None::<error::Error>,
+				format!(
+					"the '{}' header is set by vminfo and can not be overridden",
+					header_name
+				)
+				.as_str(),
+			));
+		}
+	}
+
+	Ok(())
+}
+
+///
+/// sends a request with the provided Transport, retrying (up to CONNECTION_RETRIES times) when it fails before any
+/// HTTP response was received. a failed request never reuses its connection, so each retry is made on a fresh one
+///
+/// errors which carry an HTTP status are returned immediately
+///
+pub(crate) fn post_json_with_retry(
+	transport: &dyn Transport,
+	url: &str,
+	access_token: &str,
+	headers: &[(String, String)],
+	body: &str,
+) -> VMInfoResult<TransportResponse> {
+	let mut attempt: u8 = 0;
+	loop {
+		match transport.post_json(url, access_token, headers, body) {
+			Err(err) if err.kind() == Kind::RequestError(None) && attempt < CONNECTION_RETRIES => {
+				attempt += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+///
+/// sends a request like post_json_with_retry, sending it again (after calling sleep with the delay from the retry
+/// policy) while it is answered with HTTP 429 or a server error. the last response is returned once the retries are
+/// used up
+///
+pub(crate) fn post_json_with_backoff(
+	transport: &dyn Transport,
+	policy: &RetryPolicy,
+	mut sleep: impl FnMut(Duration),
+	url: &str,
+	access_token: &str,
+	headers: &[(String, String)],
+	body: &str,
+) -> VMInfoResult<TransportResponse> {
+	let mut retry: u32 = 0;
+	loop {
+		let resp = post_json_with_retry(transport, url, access_token, headers, body)?;
+		if retry >= policy.max_retries || !RetryPolicy::is_retryable(resp.status) {
+			return Ok(resp);
+		}
+
+		sleep(policy.delay(retry, resp.retry_after));
+		retry += 1;
+	}
+}
+
+///
+/// sends a request with the provided AsyncTransport, retrying on connection-level failures like post_json_with_retry
+///
+#[cfg(feature = "async")]
+pub(crate) async fn post_json_with_retry_async(
+	transport: &dyn AsyncTransport,
+	url: &str,
+	access_token: &str,
+	headers: &[(String, String)],
+	body: &str,
+) -> VMInfoResult<TransportResponse> {
+	let mut attempt: u8 = 0;
+	loop {
+		match transport.post_json(url, access_token, headers, body).await {
+			Err(err) if err.kind() == Kind::RequestError(None) && attempt < CONNECTION_RETRIES => {
+				attempt += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+#[cfg(test)]
+mod transport_tests {
+	#[test]
+	fn min_tls_version_is_applied() {
+		use super::{parse_tls_version, HttpTransport, DEFAULT_MIN_TLS_VERSION};
+		use crate::error::Kind;
+		use reqwest::tls::Version;
+
+		assert_eq!(
+			HttpTransport::default().min_tls_version,
+			DEFAULT_MIN_TLS_VERSION
+		);
+		assert_eq!(DEFAULT_MIN_TLS_VERSION, Version::TLS_1_2);
+
+		let transport = HttpTransport::new(parse_tls_version("1.2").unwrap()).unwrap();
+		assert_eq!(transport.min_tls_version, Version::TLS_1_2);
+
+		assert_eq!(parse_tls_version("TLS1.3").unwrap(), Version::TLS_1_3);
+		assert_eq!(
+			parse_tls_version("1.4").unwrap_err().kind(),
+			Kind::ClientCreateError
+		);
+	}
+
+	#[test]
+	fn retries_once_after_connection_reset() {
+		use crate::error::{self, Error};
+		use crate::testing::{client, query_response, vm, MockTransport};
+
+		let transport = MockTransport::new(vec![
+			Err(error::request(
+				None::<Error>,
+				None,
+				"connection reset by peer",
+			)),
+			query_response(&[vm("linux-01")]),
+		]);
+
+		let resp = client(transport.clone(), None)
+			.query_vminfo(
+				&vec!["linux-01".to_string()],
+				false,
+				false,
+				false,
+				true,
+				None,
+				None,
+			)
+			.unwrap();
+
+		assert_eq!(resp.total_results, 1);
+		assert_eq!(transport.requests().len(), 2);
+	}
+
+	#[test]
+	fn retry_is_bounded() {
+		use super::post_json_with_retry;
+		use crate::error::{self, Error, Kind};
+		use crate::testing::MockTransport;
+
+		let transport = MockTransport::new(vec![
+			Err(error::request(
+				None::<Error>,
+				None,
+				"connection reset by peer",
+			)),
+			Err(error::request(
+				None::<Error>,
+				None,
+				"connection reset by peer",
+			)),
+			Err(error::request(
+				None::<Error>,
+				None,
+				"connection reset by peer",
+			)),
+		]);
+
+		let err =
+			post_json_with_retry(&transport, "https://example.com", "token", &[], "{}").unwrap_err();
+
+		assert_eq!(err.kind(), Kind::RequestError(None));
+		assert_eq!(transport.requests().len(), 2);
+	}
+
+	#[test]
+	fn http_errors_are_not_retried() {
+		use super::post_json_with_retry;
+		use crate::error::{self, Error};
+		use crate::testing::MockTransport;
+
+		let transport = MockTransport::new(vec![Err(error::request(
+			None::<Error>,
+			Some(reqwest::StatusCode::BAD_REQUEST),
+			"could not read response body from Resource Graph",
+		))]);
+
+		assert!(post_json_with_retry(&transport, "https://example.com", "token", &[], "{}").is_err());
+		assert_eq!(transport.requests().len(), 1);
+	}
+
+	#[test]
+	fn throttled_requests_are_retried_with_backoff() {
+		use std::time::Duration;
+
+		use super::{post_json_with_backoff, RetryPolicy, TransportResponse};
+		use crate::testing::{query_response, vm, MockTransport};
+
+		let status = |code: u16, retry_after: Option<u64>| {
+			Ok(TransportResponse {
+				status: reqwest::StatusCode::from_u16(code).unwrap(),
+				body: r#"{"error": {"code": "RateLimiting", "message": "throttled"}}"#.to_string(),
+				retry_after: retry_after.map(Duration::from_secs),
+			})
+		};
+		let post = |transport: &MockTransport, sleeps: &mut Vec<Duration>| {
+			post_json_with_backoff(
+				transport,
+				&RetryPolicy::default(),
+				|delay| sleeps.push(delay),
+				"https://example.com",
+				"token",
+				&[],
+				"{}",
+			)
+			.unwrap()
+		};
+
+		// a Retry-After is respected, otherwise the delay doubles (with jitter) from the base delay
+		let transport = MockTransport::new(vec![
+			status(429, Some(5)),
+			status(503, None),
+			status(500, None),
+			query_response(&[vm("linux-01")]),
+		]);
+		let mut sleeps = Vec::new();
+		assert!(post(&transport, &mut sleeps).status.is_success());
+		assert_eq!(transport.requests().len(), 4);
+		assert_eq!(sleeps[0], Duration::from_secs(5));
+		assert!((Duration::from_secs(1)..=Duration::from_secs(2)).contains(&sleeps[1]));
+		assert!((Duration::from_secs(2)..=Duration::from_secs(4)).contains(&sleeps[2]));
+
+		// the last response is returned once the retries are used up
+		let transport = MockTransport::new((0..4).map(|_| status(429, None)).collect());
+		let mut sleeps = Vec::new();
+		assert_eq!(post(&transport, &mut sleeps).status.as_u16(), 429);
+		assert_eq!(transport.requests().len(), 4);
+
+		// other client errors (ie: a rejected token) are not retried
+		let transport = MockTransport::new(vec![status(401, None), status(401, None)]);
+		let mut sleeps = Vec::new();
+		assert_eq!(post(&transport, &mut sleeps).status.as_u16(), 401);
+		assert_eq!(transport.requests().len(), 1);
+		assert!(sleeps.is_empty());
+	}
+
+	#[test]
+	fn retry_after_is_read_from_headers() {
+		use std::time::Duration;
+
+		use reqwest::header::{HeaderMap, HeaderValue};
+
+		use super::retry_after;
+
+		let mut headers = HeaderMap::new();
+		assert_eq!(retry_after(&headers), None);
+
+		headers.insert(
+			"x-ms-user-quota-resets-after",
+			HeaderValue::from_static("00:01:05"),
+		);
+		assert_eq!(retry_after(&headers), Some(Duration::from_secs(65)));
+
+		headers.insert("retry-after", HeaderValue::from_static("7"));
+		assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+	}
+
+	#[test]
+	fn invalid_headers_are_rejected() {
+		use super::validate_headers;
+
+		let header = |n: &str, v: &str| vec![(n.to_string(), v.to_string())];
+
+		assert!(validate_headers(&header("X-Gateway-Key", "abc123")).is_ok());
+		assert!(validate_headers(&header("bad header", "abc123")).is_err());
+		assert!(validate_headers(&header("X-Gateway-Key", "abc\n123")).is_err());
+		assert!(validate_headers(&header("Authorization", "Bearer xyz")).is_err());
+	}
+}
@@ -3,14 +3,22 @@
 //! Provides a model for Virtual Machines
 //!
 //!
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 
 use redis::{from_redis_value, FromRedisValue, ToRedisArgs};
 use serde::de::Deserializer;
 use serde::{Deserialize, Serialize};
 
+use crate::error::{self, VMInfoResult};
+use crate::os::{self, CanonicalOs};
+use crate::sku;
+
 ///
 /// Defines the fields that a host result should contain.
 /// This is Serializable from the Resource Graph response and to json for consumption outside of vminfo
@@ -35,6 +43,16 @@ pub struct VirtualMachine {
 	///
 	sub: Option<String>,
 	///
+	/// The ID of the subscription the Virtual Machine belongs to (None if not reported)
+	///
+	#[serde(
+		alias = "subscriptionId",
+		rename(serialize = "subscriptionId"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	subscription_id: Option<String>,
+	///
 	/// The datacentre location where this Virtual Machine resides
 	///
 	location: Option<String>,
@@ -48,23 +66,40 @@ pub struct VirtualMachine {
 	#[serde(
 		alias = "privateIp",
 		rename(serialize = "privateIp"),
-		deserialize_with = "parse_ipv4_address"
+		deserialize_with = "parse_ipv4_address",
+		default = "unspecified_ipv4_address"
 	)]
 	private_ip: std::net::Ipv4Addr,
 	///
-	/// The OS Type for this Virtual Machine (can be: Linux or Windows)
+	/// The OS Type for this Virtual Machine (can be: Linux or Windows). None while the VM has no OS disk (ie: a
+	/// specialized VM whose disk is attached later)
 	///
-	#[serde(alias = "osType", rename(serialize = "osType"))]
+	#[serde(
+		alias = "osType",
+		rename(serialize = "osType"),
+		default,
+		deserialize_with = "parse_non_empty_string"
+	)]
 	os_type: Option<String>,
 	///
 	/// The OS Distribution Name for this Virtual Machine (ie: Ubuntu, RedHat, etc.)
 	///
-	#[serde(alias = "osName", rename(serialize = "osName"))]
+	#[serde(
+		alias = "osName",
+		rename(serialize = "osName"),
+		default,
+		deserialize_with = "parse_non_empty_string"
+	)]
 	os_name: Option<String>,
 	///
 	/// The version fo the OS Distribution being run on the Virtual Machine
 	///
-	#[serde(alias = "osVersion", rename(serialize = "osVersion"))]
+	#[serde(
+		alias = "osVersion",
+		rename(serialize = "osVersion"),
+		default,
+		deserialize_with = "parse_non_empty_string"
+	)]
 	os_version: Option<String>,
 	///
 	/// The current power state for this Virtual Machine
@@ -85,15 +120,175 @@ pub struct VirtualMachine {
 	///
 	subnet: Option<String>,
 	///
-	/// A List of Azure Virtual Machine Extensions that are installed for this VM (None if not requested)
+	/// The resource ID of the primary Azure subnet, which virtualNetwork and subnet are parsed from (None if the VM has
+	/// no network interface). Use this to correlate the VM with other resources in its subnet (ie: Azure Bastion)
 	///
-	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[serde(
+		alias = "subnetId",
+		rename(serialize = "subnetId"),
+		default,
+		deserialize_with = "parse_non_empty_string",
+		skip_serializing_if = "Option::is_none"
+	)]
+	subnet_id: Option<String>,
+	///
+	/// A List of Azure Virtual Machine Extensions that are installed for this VM, sorted by name (None if not requested)
+	///
+	#[serde(
+		default,
+		deserialize_with = "parse_sorted_extensions",
+		skip_serializing_if = "Option::is_none"
+	)]
 	extensions: Option<Vec<VirtualMachineExtension>>,
 	///
-	/// A list of Azure resource tags associated with an Azure Virtual Machine
+	/// A list of Azure resource tags associated with an Azure Virtual Machine, ordered by key
+	///
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	tags: Option<BTreeMap<String, String>>,
+	///
+	/// Whether the OS disk for this Virtual Machine is encrypted (None if not requested)
+	///
+	#[serde(
+		alias = "osDiskEncrypted",
+		rename(serialize = "osDiskEncrypted"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	os_disk_encrypted: Option<bool>,
+	///
+	/// Whether this Virtual Machine can be hibernated (None if not requested or the capability is not set)
+	///
+	#[serde(
+		alias = "hibernationEnabled",
+		rename(serialize = "hibernationEnabled"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	hibernation_enabled: Option<bool>,
+	///
+	/// Whether the OS disk of this Virtual Machine is ephemeral (None if not requested)
+	///
+	#[serde(
+		alias = "ephemeralOsDisk",
+		rename(serialize = "ephemeralOsDisk"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	ephemeral_os_disk: Option<bool>,
+	///
+	/// The pending or last platform maintenance of this Virtual Machine (None if not requested or never maintained)
+	///
+	#[serde(
+		alias = "maintenanceStatus",
+		rename(serialize = "maintenanceStatus"),
+		default,
+		deserialize_with = "parse_non_empty_string",
+		skip_serializing_if = "Option::is_none"
+	)]
+	maintenance_status: Option<String>,
+	///
+	/// The resource ID of the proximity placement group of this Virtual Machine (None if not requested or not assigned)
+	///
+	#[serde(
+		alias = "proximityPlacementGroupId",
+		rename(serialize = "proximityPlacementGroupId"),
+		default,
+		deserialize_with = "parse_non_empty_string",
+		skip_serializing_if = "Option::is_none"
+	)]
+	proximity_placement_group_id: Option<String>,
+	///
+	/// The resource ID of the dedicated host this Virtual Machine is placed on (None if not requested or not assigned)
+	///
+	#[serde(
+		alias = "hostId",
+		rename(serialize = "hostId"),
+		default,
+		deserialize_with = "parse_non_empty_string",
+		skip_serializing_if = "Option::is_none"
+	)]
+	host_id: Option<String>,
+	///
+	/// The resource ID of the dedicated host group this Virtual Machine is placed in (None if not requested or not
+	/// assigned)
+	///
+	#[serde(
+		alias = "hostGroupId",
+		rename(serialize = "hostGroupId"),
+		default,
+		deserialize_with = "parse_non_empty_string",
+		skip_serializing_if = "Option::is_none"
+	)]
+	host_group_id: Option<String>,
+	///
+	/// The IOPS limit of the managed OS disk of this Virtual Machine (None if not requested or the disk is unmanaged)
+	///
+	#[serde(
+		alias = "osDiskIOPSReadWrite",
+		rename(serialize = "osDiskIOPSReadWrite"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	os_disk_iops: Option<u64>,
+	///
+	/// The throughput limit (MBps) of the managed OS disk of this Virtual Machine (None if not requested or the disk is
+	/// unmanaged)
+	///
+	#[serde(
+		alias = "osDiskMBpsReadWrite",
+		rename(serialize = "osDiskMBpsReadWrite"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	os_disk_mbps: Option<u64>,
+	///
+	/// The total IOPS limit of the managed data disks of this Virtual Machine (None if not requested or it has no
+	/// managed disks)
+	///
+	#[serde(
+		alias = "dataDiskIOPSReadWrite",
+		rename(serialize = "dataDiskIOPSReadWrite"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	data_disk_iops: Option<u64>,
+	///
+	/// The total throughput limit (MBps) of the managed data disks of this Virtual Machine (None if not requested or it
+	/// has no managed disks)
+	///
+	#[serde(
+		alias = "dataDiskMBpsReadWrite",
+		rename(serialize = "dataDiskMBpsReadWrite"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	data_disk_mbps: Option<u64>,
+	///
+	/// The number of vCPUs of this Virtual Machine's size (None if not requested or the size is not known)
 	///
 	#[serde(default, skip_serializing_if = "Option::is_none")]
-	tags: Option<HashMap<String, String>>,
+	vcpus: Option<u32>,
+	///
+	/// The memory (GiB) of this Virtual Machine's size (None if not requested or the size is not known)
+	///
+	#[serde(
+		alias = "memoryGb",
+		rename(serialize = "memoryGb"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	memory_gb: Option<f64>,
+	///
+	/// The values of any extra projections (ie: named tags) requested for this Virtual Machine, keyed by column name
+	/// (None if not requested)
+	///
+	#[serde(
+		alias = "extraColumns",
+		rename(serialize = "extraColumns"),
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	extra_columns: Option<BTreeMap<String, String>>,
 }
 
 impl Default for VirtualMachine {
@@ -103,6 +298,7 @@ impl Default for VirtualMachine {
 			vm_name: None,
 			created: None,
 			sub: None,
+			subscription_id: None,
 			location: None,
 			rg: None,
 			private_ip: std::net::Ipv4Addr::new(0, 0, 0, 0),
@@ -113,8 +309,132 @@ impl Default for VirtualMachine {
 			vm_size: None,
 			virtual_network: None,
 			subnet: None,
+			subnet_id: None,
 			extensions: None,
 			tags: None,
+			os_disk_encrypted: None,
+			hibernation_enabled: None,
+			ephemeral_os_disk: None,
+			maintenance_status: None,
+			proximity_placement_group_id: None,
+			host_id: None,
+			host_group_id: None,
+			os_disk_iops: None,
+			os_disk_mbps: None,
+			data_disk_iops: None,
+			data_disk_mbps: None,
+			vcpus: None,
+			memory_gb: None,
+			extra_columns: None,
+		}
+	}
+}
+
+impl VirtualMachine {
+	///
+	/// the power state of this Virtual Machine, if one was reported
+	///
+	pub fn power_state(&self) -> Option<PowerState> {
+		self.powerstate.as_deref().and_then(|p| p.parse().ok())
+	}
+
+	///
+	/// when this Virtual Machine was created, if a valid creation timestamp was reported
+	///
+	pub fn created_at(&self) -> Option<DateTime<Utc>> {
+		self
+			.created
+			.as_deref()
+			.and_then(|c| DateTime::parse_from_rfc3339(c).ok())
+			.map(|c| c.with_timezone(&Utc))
+	}
+
+	///
+	/// how long ago this Virtual Machine was created (None if the creation time is unknown)
+	///
+	pub fn age(&self) -> Option<Duration> {
+		self.age_at(Utc::now())
+	}
+
+	///
+	/// how long this Virtual Machine had existed at `now` (None if the creation time is unknown or after `now`)
+	///
+	pub fn age_at(&self, now: DateTime<Utc>) -> Option<Duration> {
+		(now - self.created_at()?).to_std().ok()
+	}
+
+	///
+	/// the ID of the subscription this Virtual Machine belongs to, if one was reported
+	///
+	pub fn subscription_id(&self) -> Option<&str> {
+		self.subscription_id.as_deref()
+	}
+
+	///
+	/// the OS type of this Virtual Machine (ie: Linux or Windows), if one was reported
+	///
+	pub fn os_type(&self) -> Option<&str> {
+		self.os_type.as_deref()
+	}
+
+	///
+	/// the canonical distribution and major version of the OS reported by this Virtual Machine (ie: rhel 8 for an OS
+	/// name of Red Hat Enterprise Linux and version of 8.6), if its OS name is known. the reported OS name and version
+	/// are left as they are
+	///
+	pub fn canonical_os(&self) -> Option<CanonicalOs> {
+		os::canonical_os(self.os_name.as_deref()?, self.os_version.as_deref())
+	}
+
+	///
+	/// the datacentre location of this Virtual Machine, if one was reported
+	///
+	pub fn location(&self) -> Option<&str> {
+		self.location.as_deref()
+	}
+
+	///
+	/// the size of this Virtual Machine (ie: Standard_D2s_v3), if one was reported
+	///
+	pub fn vm_size(&self) -> Option<&str> {
+		self.vm_size.as_deref()
+	}
+
+	///
+	/// attributes this Virtual Machine to a subscription when Resource Graph did not report one
+	///
+	pub(crate) fn set_subscription_id(&mut self, subscription_id: &str) {
+		if self.subscription_id.is_none() {
+			self.subscription_id = Some(subscription_id.to_string());
+		}
+	}
+
+	///
+	/// the components of this Virtual Machine's resource ID (vmId), or None if it is missing or malformed
+	///
+	pub fn resource_id_parts(&self) -> Option<ResourceId> {
+		self.vm_id.as_deref().and_then(|id| id.parse().ok())
+	}
+
+	///
+	/// sets the vCPUs and memory of this Virtual Machine from the bundled table of VM sizes (if its size is known)
+	///
+	pub(crate) fn resolve_sku(&mut self) {
+		if let Some(details) = self.vm_size.as_deref().and_then(sku::sku_details) {
+			self.vcpus = Some(details.vcpus);
+			self.memory_gb = Some(details.memory_gb);
+		}
+	}
+
+	///
+	/// sets the virtual network and subnet for this Virtual Machine from its subnet resource ID (if one was provided)
+	///
+	/// segments that cannot be found in the ID are left empty rather than guessed at
+	///
+	pub(crate) fn resolve_network(&mut self) {
+		if let Some(id) = &self.subnet_id {
+			self.virtual_network = resource_id_segment(id, "virtualNetworks");
+			self.subnet = resource_id_segment(id, "subnets");
 		}
 	}
 }
@@ -167,6 +487,29 @@ impl FromRedisValue for VirtualMachine {
 	}
 }
 
+///
+/// the `0.0.0.0` address used when a Virtual Machine has no known IPv4 address
+///
+fn unspecified_ipv4_address() -> std::net::Ipv4Addr {
+	std::net::Ipv4Addr::new(0, 0, 0, 0)
+}
+
+///
+/// finds the name following a resource type segment (case-insensitive) in an Azure resource ID
+///
+/// ie: the "subnets" segment of `/subscriptions/.../virtualNetworks/vnet-01/subnets/default` is `default`
+///
+fn resource_id_segment(id: &str, segment: &str) -> Option<String> {
+	let mut parts = id.split('/').filter(|p| !p.is_empty());
+	while let Some(part) = parts.next() {
+		if part.eq_ignore_ascii_case(segment) {
+			return parts.next().map(|name| name.to_string());
+		}
+	}
+
+	None
+}
+
 ///
 /// deserializer that will take a JSON response as a string and pull out a valid IPv4 address
 /// if errors occur, will produce a default `0.0.0.0` address in the resulting struct
@@ -183,6 +526,164 @@ where
 	}
 }
 
+///
+/// deserializer for a list of Virtual Machine extensions which orders them by name, so that output is stable between runs
+///
+fn parse_sorted_extensions<'de, D>(d: D) -> Result<Option<Vec<VirtualMachineExtension>>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	let mut extensions: Option<Vec<VirtualMachineExtension>> = Deserialize::deserialize(d)?;
+	if let Some(e) = extensions.as_mut() {
+		e.sort_by(|a, b| a.name.cmp(&b.name));
+	}
+
+	Ok(extensions)
+}
+
+///
+/// deserializer for an optional string column which treats an empty string (how Resource Graph returns a missing
+/// value projected with tostring()) as None
+///
+fn parse_non_empty_string<'de, D>(d: D) -> Result<Option<String>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	let value: Option<String> = Deserialize::deserialize(d)?;
+
+	Ok(value.filter(|v| !v.is_empty()))
+}
+
+///
+/// Describes the components of an Azure resource ID
+///
+/// ie: `/subscriptions/{subscription_id}/resourceGroups/{resource_group}/providers/{provider}/{resource_type}/{name}`
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceId {
+	/// the ID of the subscription containing the resource
+	pub subscription_id: String,
+	/// the resource group containing the resource
+	pub resource_group: String,
+	/// the resource provider namespace (ie: Microsoft.Compute)
+	pub provider: String,
+	/// the resource type (ie: virtualMachines). types of child resources include their parent types (ie: virtualMachines/extensions)
+	pub resource_type: String,
+	/// the name of the resource
+	pub name: String,
+}
+
+impl FromStr for ResourceId {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let malformed = || format!("malformed resource ID '{}'", s);
+		let parts: Vec<&str> = s.split('/').filter(|p| !p.is_empty()).collect();
+
+		// expect the subscription, resource group and provider followed by at least one type/name pair
+		if parts.len() < 8 || !parts.len().is_multiple_of(2) {
+			return Err(malformed());
+		}
+		if !parts[0].eq_ignore_ascii_case("subscriptions")
+			|| !parts[2].eq_ignore_ascii_case("resourceGroups")
+			|| !parts[4].eq_ignore_ascii_case("providers")
+		{
+			return Err(malformed());
+		}
+
+		let types: Vec<&str> = parts[6..].iter().step_by(2).copied().collect();
+
+		Ok(Self {
+			subscription_id: parts[1].to_string(),
+			resource_group: parts[3].to_string(),
+			provider: parts[5].to_string(),
+			resource_type: types.join("/"),
+			name: parts[parts.len() - 1].to_string(),
+		})
+	}
+}
+
+///
+/// Describes the power state of a Virtual Machine
+///
+/// **note**: a `Stopped` VM is still allocated (and billed for compute) while a `Deallocated` VM is not
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+	/// the VM is starting up
+	Starting,
+	/// the VM is running
+	Running,
+	/// the VM is shutting down
+	Stopping,
+	/// the VM is shut down but remains allocated
+	Stopped,
+	/// the VM is being deallocated
+	Deallocating,
+	/// the VM is shut down and its compute resources have been released
+	Deallocated,
+}
+
+impl PowerState {
+	///
+	/// the name of every power state, as accepted when parsing a PowerState
+	///
+	pub const NAMES: [&'static str; 6] = [
+		"starting",
+		"running",
+		"stopping",
+		"stopped",
+		"deallocating",
+		"deallocated",
+	];
+
+	///
+	/// the name of this power state (ie: `running`)
+	///
+	pub fn name(&self) -> &'static str {
+		&self.code()[11..]
+	}
+
+	///
+	/// the code Azure reports for this power state (ie: `PowerState/running`)
+	///
+	pub fn code(&self) -> &'static str {
+		match self {
+			Self::Starting => "PowerState/starting",
+			Self::Running => "PowerState/running",
+			Self::Stopping => "PowerState/stopping",
+			Self::Stopped => "PowerState/stopped",
+			Self::Deallocating => "PowerState/deallocating",
+			Self::Deallocated => "PowerState/deallocated",
+		}
+	}
+}
+
+impl FromStr for PowerState {
+	type Err = String;
+
+	///
+	/// parses a power state from either its name (ie: `running`) or its Azure code (ie: `PowerState/running`)
+	///
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let name = s.trim();
+		let name = match name.get(..11) {
+			Some(prefix) if prefix.eq_ignore_ascii_case("PowerState/") => &name[11..],
+			_ => name,
+		};
+
+		match name.to_lowercase().as_str() {
+			"starting" => Ok(Self::Starting),
+			"running" => Ok(Self::Running),
+			"stopping" => Ok(Self::Stopping),
+			"stopped" => Ok(Self::Stopped),
+			"deallocating" => Ok(Self::Deallocating),
+			"deallocated" => Ok(Self::Deallocated),
+			_ => Err(format!("unknown power state '{}'", s)),
+		}
+	}
+}
+
 // TODO: implement custom extensions deserializer that is more accepting of null keys in extension lists ([Github Issue](https://github.com/SystemFiles/azure-vminfo/issues/1))
 
 /// Describes a virtual machine extension in Azure
@@ -200,3 +701,419 @@ impl Default for VirtualMachineExtension {
 		}
 	}
 }
+
+///
+/// indexes Virtual Machines by their lowercased name. VMs without a name are left out
+///
+/// when more than one VM shares a name, the last one is kept unless `strict` is set, in which case it is an error
+///
+pub fn index_by_name(
+	vms: Vec<VirtualMachine>,
+	strict: bool,
+) -> VMInfoResult<HashMap<String, VirtualMachine>> {
+	let mut index: HashMap<String, VirtualMachine> = HashMap::with_capacity(vms.len());
+
+	for vm in vms {
+		let name = match &vm.vm_name {
+			Some(n) => n.to_lowercase(),
+			None => continue,
+		};
+
+		if index.insert(name.clone(), vm).is_some() && strict {
+			return Err(error::other(
+				None::<error::Error>,
+				format!("more than one virtual machine is named '{}'", name).as_str(),
+			));
+		}
+	}
+
+	Ok(index)
+}
+
+///
+/// orders Virtual Machines by the position of the query operand (a name or resource ID, compared case-insensitively)
+/// that matched each of them. VMs which match no operand are kept at the end, in their existing order
+///
+pub fn sort_by_operands(vms: &mut [VirtualMachine], operands: &[String]) {
+	let mut positions: HashMap<String, usize> = HashMap::with_capacity(operands.len());
+	for (position, operand) in operands.iter().enumerate() {
+		positions.entry(operand.to_lowercase()).or_insert(position);
+	}
+
+	vms.sort_by_key(|vm| {
+		[&vm.vm_name, &vm.vm_id]
+			.iter()
+			.filter_map(|v| v.as_ref().and_then(|v| positions.get(&v.to_lowercase())))
+			.min()
+			.copied()
+			.unwrap_or(usize::MAX)
+	});
+}
+
+#[cfg(test)]
+mod vm_tests {
+	#[test]
+	fn age_from_created() {
+		use super::VirtualMachine;
+		use chrono::{TimeZone, Utc};
+		use std::time::Duration;
+
+		let vm: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "created": "2025-10-16T12:00:00.1234567Z"}"#,
+		)
+		.unwrap();
+		let now = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+
+		let age = vm.age_at(now).unwrap();
+		assert_eq!(age.as_secs(), 365 * 24 * 60 * 60 - 1);
+		assert!(age > Duration::from_secs(364 * 24 * 60 * 60));
+
+		// created after `now`
+		let earlier = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+		assert_eq!(vm.age_at(earlier), None);
+
+		let unknown: VirtualMachine =
+			serde_json::from_str(r#"{"vmId": "/vm/linux-02", "created": "yesterday"}"#).unwrap();
+		assert_eq!(unknown.created_at(), None);
+		assert_eq!(unknown.age(), None);
+	}
+
+	#[test]
+	fn sku_details_resolved_from_size() {
+		use super::VirtualMachine;
+
+		let mut vm: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "vmSize": "Standard_D4s_v3"}"#,
+		)
+		.unwrap();
+		vm.resolve_sku();
+
+		assert_eq!(vm.vcpus, Some(4));
+		assert_eq!(vm.memory_gb, Some(16.0));
+		let value = serde_json::to_value(&vm).unwrap();
+		assert_eq!(value["vcpus"], serde_json::json!(4));
+		assert_eq!(value["memoryGb"], serde_json::json!(16.0));
+	}
+
+	#[test]
+	fn deserialize_maintenance_status() {
+		use super::VirtualMachine;
+
+		let pending: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "maintenanceStatus": "Pending (window ends 2026-11-02T00:00:00Z)"}"#,
+		)
+		.unwrap();
+		assert_eq!(
+			pending.maintenance_status.as_deref(),
+			Some("Pending (window ends 2026-11-02T00:00:00Z)")
+		);
+		assert_eq!(
+			serde_json::to_value(&pending).unwrap()["maintenanceStatus"],
+			serde_json::json!("Pending (window ends 2026-11-02T00:00:00Z)")
+		);
+
+		let never: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-02", "vmName": "linux-02", "maintenanceStatus": ""}"#,
+		)
+		.unwrap();
+		assert_eq!(never.maintenance_status, None);
+		assert!(serde_json::to_value(&never)
+			.unwrap()
+			.get("maintenanceStatus")
+			.is_none());
+	}
+
+	#[test]
+	fn deserialize_placement() {
+		use super::VirtualMachine;
+
+		let placed: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/trading-01", "vmName": "trading-01", "proximityPlacementGroupId": "/subscriptions/sub-01/resourceGroups/trading-rg/providers/Microsoft.Compute/proximityPlacementGroups/trading-ppg", "hostId": "", "hostGroupId": "/subscriptions/sub-01/resourceGroups/trading-rg/providers/Microsoft.Compute/hostGroups/trading-hosts"}"#,
+		)
+		.unwrap();
+		assert_eq!(
+			placed.proximity_placement_group_id.as_deref(),
+			Some("/subscriptions/sub-01/resourceGroups/trading-rg/providers/Microsoft.Compute/proximityPlacementGroups/trading-ppg")
+		);
+		assert_eq!(placed.host_id, None);
+		assert_eq!(
+			serde_json::to_value(&placed).unwrap()["hostGroupId"],
+			serde_json::json!("/subscriptions/sub-01/resourceGroups/trading-rg/providers/Microsoft.Compute/hostGroups/trading-hosts")
+		);
+
+		let unplaced: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "proximityPlacementGroupId": "", "hostId": "", "hostGroupId": ""}"#,
+		)
+		.unwrap();
+		assert_eq!(unplaced.proximity_placement_group_id, None);
+		assert_eq!(unplaced.host_group_id, None);
+	}
+
+	#[test]
+	fn canonical_os_keeps_reported_os() {
+		use super::VirtualMachine;
+		use crate::os::CanonicalOs;
+
+		let vm: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "osName": "Red Hat Enterprise Linux", "osVersion": "8.6"}"#,
+		)
+		.unwrap();
+		assert_eq!(
+			vm.canonical_os(),
+			Some(CanonicalOs {
+				distro: "rhel",
+				major_version: Some(8)
+			})
+		);
+		assert_eq!(vm.os_name.as_deref(), Some("Red Hat Enterprise Linux"));
+		assert_eq!(VirtualMachine::default().canonical_os(), None);
+	}
+
+	#[test]
+	fn deserialize_disk_perf() {
+		use super::VirtualMachine;
+
+		let managed: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "osDiskIOPSReadWrite": 500, "osDiskMBpsReadWrite": 100, "dataDiskIOPSReadWrite": 5000, "dataDiskMBpsReadWrite": 200}"#,
+		)
+		.unwrap();
+		assert_eq!(managed.os_disk_iops, Some(500));
+		assert_eq!(managed.os_disk_mbps, Some(100));
+		assert_eq!(managed.data_disk_iops, Some(5000));
+		assert_eq!(managed.data_disk_mbps, Some(200));
+		assert_eq!(
+			serde_json::to_value(&managed).unwrap()["osDiskIOPSReadWrite"],
+			serde_json::json!(500)
+		);
+
+		// unmanaged disks are not matched by the disk join
+		let unmanaged: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-02", "vmName": "linux-02", "osDiskIOPSReadWrite": null, "osDiskMBpsReadWrite": null}"#,
+		)
+		.unwrap();
+		assert_eq!(unmanaged.os_disk_iops, None);
+		assert!(serde_json::to_value(&unmanaged)
+			.unwrap()
+			.get("osDiskIOPSReadWrite")
+			.is_none());
+	}
+
+	#[test]
+	fn deserialize_vm_without_os_disk() {
+		use super::VirtualMachine;
+		use std::net::Ipv4Addr;
+
+		// tostring() of the missing storageProfile.osDisk.osType is an empty string, while the instance view is absent
+		let vm: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/specialized-01", "vmName": "specialized-01", "location": "canadacentral", "privateIp": "10.0.0.4", "osType": "", "powerstate": "PowerState/stopped"}"#,
+		)
+		.unwrap();
+		assert_eq!(vm.os_type(), None);
+		assert_eq!(vm.os_name, None);
+		assert_eq!(vm.os_version, None);
+		assert_eq!(vm.vm_name.as_deref(), Some("specialized-01"));
+		assert_eq!(vm.location(), Some("canadacentral"));
+		assert_eq!(vm.private_ip, Ipv4Addr::new(10, 0, 0, 4));
+
+		let value = serde_json::to_value(&vm).unwrap();
+		assert_eq!(value["osType"], serde_json::Value::Null);
+		assert_eq!(value["privateIp"], serde_json::json!("10.0.0.4"));
+	}
+
+	#[test]
+	fn deserialize_ephemeral_os_disk() {
+		use super::VirtualMachine;
+
+		let ephemeral: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/scaleout-01", "vmName": "scaleout-01", "ephemeralOsDisk": true}"#,
+		)
+		.unwrap();
+		assert_eq!(ephemeral.ephemeral_os_disk, Some(true));
+		assert_eq!(
+			serde_json::to_value(&ephemeral).unwrap()["ephemeralOsDisk"],
+			serde_json::json!(true)
+		);
+
+		let managed: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "ephemeralOsDisk": false}"#,
+		)
+		.unwrap();
+		assert_eq!(managed.ephemeral_os_disk, Some(false));
+	}
+
+	#[test]
+	fn deserialize_hibernation_capability() {
+		use super::VirtualMachine;
+
+		let capable: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "hibernationEnabled": true}"#,
+		)
+		.unwrap();
+		assert_eq!(capable.hibernation_enabled, Some(true));
+		assert_eq!(
+			serde_json::to_value(&capable).unwrap()["hibernationEnabled"],
+			serde_json::json!(true)
+		);
+
+		let unset: VirtualMachine = serde_json::from_str(
+			r#"{"vmId": "/vm/linux-02", "vmName": "linux-02", "hibernationEnabled": null}"#,
+		)
+		.unwrap();
+		assert_eq!(unset.hibernation_enabled, None);
+	}
+
+	#[test]
+	fn deserialize_os_disk_encryption() {
+		use super::VirtualMachine;
+
+		let encrypted: VirtualMachine =
+			serde_json::from_str(r#"{"vmId": "/vm/linux-01", "vmName": "linux-01", "osDiskEncrypted": true}"#)
+				.unwrap();
+		assert_eq!(encrypted.os_disk_encrypted, Some(true));
+		assert_eq!(
+			serde_json::to_value(&encrypted).unwrap()["osDiskEncrypted"],
+			serde_json::json!(true)
+		);
+
+		let unrequested: VirtualMachine =
+			serde_json::from_str(r#"{"vmId": "/vm/linux-02", "vmName": "linux-02"}"#).unwrap();
+		assert_eq!(unrequested.os_disk_encrypted, None);
+		assert!(serde_json::to_value(&unrequested)
+			.unwrap()
+			.get("osDiskEncrypted")
+			.is_none());
+	}
+
+	#[test]
+	fn network_parsed_from_subnet_id() {
+		use crate::query::QueryResponse;
+
+		let resp: QueryResponse = serde_json::from_value(serde_json::json!({
+			"totalRecords": 4,
+			"data": [
+				{ "vmName": "linux-01", "subnetId": "/subscriptions/0000/resourceGroups/net-rg/providers/Microsoft.Network/virtualNetworks/vnet-01/subnets/app" },
+				{ "vmName": "linux-02", "subnetId": "/subscriptions/0000/resourcegroups/net-rg/providers/microsoft.network/virtualnetworks/vnet-02/subnets/delegated-sql" },
+				{ "vmName": "linux-03", "subnetId": "/subscriptions/0000/resourceGroups/paas-rg/providers/Microsoft.Network/virtualNetworks/paas-vnet" },
+				{ "vmName": "linux-04", "subnetId": "" }
+			]
+		}))
+		.unwrap();
+
+		let network: Vec<(Option<&str>, Option<&str>)> = resp
+			.data
+			.iter()
+			.map(|vm| (vm.virtual_network.as_deref(), vm.subnet.as_deref()))
+			.collect();
+
+		assert_eq!(network[0], (Some("vnet-01"), Some("app")));
+		assert_eq!(network[1], (Some("vnet-02"), Some("delegated-sql")));
+		assert_eq!(network[2], (Some("paas-vnet"), None));
+		assert_eq!(network[3], (None, None));
+	}
+
+	#[test]
+	fn deserialize_subnet_id() {
+		use super::VirtualMachine;
+
+		let subnet_id =
+			"/subscriptions/0000/resourceGroups/net-rg/providers/Microsoft.Network/virtualNetworks/vnet-01/subnets/app";
+		let vm: VirtualMachine = serde_json::from_value(serde_json::json!({
+			"vmId": "/vm/linux-01", "vmName": "linux-01", "subnetId": subnet_id
+		}))
+		.unwrap();
+		assert_eq!(vm.subnet_id.as_deref(), Some(subnet_id));
+		assert_eq!(serde_json::to_value(&vm).unwrap()["subnetId"], subnet_id);
+
+		let no_nic: VirtualMachine =
+			serde_json::from_str(r#"{"vmId": "/vm/linux-02", "vmName": "linux-02", "subnetId": ""}"#)
+				.unwrap();
+		assert_eq!(no_nic.subnet_id, None);
+		assert!(serde_json::to_value(&no_nic)
+			.unwrap()
+			.get("subnetId")
+			.is_none());
+	}
+
+	#[test]
+	fn stopped_and_deallocated_are_distinct() {
+		use super::{PowerState, VirtualMachine};
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmName": "linux-01", "powerstate": "PowerState/stopped" },
+			{ "vmName": "linux-02", "powerstate": "PowerState/deallocated" },
+			{ "vmName": "linux-03", "powerstate": "PowerState/running" },
+			{ "vmName": "linux-04" }
+		]))
+		.unwrap();
+
+		let stopped: Vec<&str> = vms
+			.iter()
+			.filter(|vm| vm.power_state() == Some(PowerState::Stopped))
+			.map(|vm| vm.vm_name.as_deref().unwrap())
+			.collect();
+
+		assert_eq!(stopped, vec!["linux-01"]);
+		assert_eq!(vms[1].power_state(), Some(PowerState::Deallocated));
+		assert_eq!(vms[3].power_state(), None);
+		assert_eq!("Deallocated".parse(), Ok(PowerState::Deallocated));
+	}
+
+	#[test]
+	fn parse_resource_id_parts() {
+		use super::{ResourceId, VirtualMachine};
+
+		let vm: VirtualMachine = serde_json::from_value(serde_json::json!({
+			"vmId": "/subscriptions/0000-1111/resourceGroups/prod-rg/providers/Microsoft.Compute/virtualMachines/linux-01"
+		}))
+		.unwrap();
+
+		assert_eq!(
+			vm.resource_id_parts(),
+			Some(ResourceId {
+				subscription_id: "0000-1111".to_string(),
+				resource_group: "prod-rg".to_string(),
+				provider: "Microsoft.Compute".to_string(),
+				resource_type: "virtualMachines".to_string(),
+				name: "linux-01".to_string(),
+			})
+		);
+
+		let malformed: VirtualMachine = serde_json::from_value(serde_json::json!({
+			"vmId": "/subscriptions/0000-1111/resourceGroups/prod-rg/virtualMachines/linux-01"
+		}))
+		.unwrap();
+		assert_eq!(malformed.resource_id_parts(), None);
+		assert_eq!(VirtualMachine::default().resource_id_parts(), None);
+	}
+
+	#[test]
+	fn tags_and_extensions_are_sorted() {
+		use super::VirtualMachine;
+
+		let vm: VirtualMachine = serde_json::from_str(
+			r#"{
+				"vmId": "/vm/linux-01",
+				"extensions": [
+					{"name": "OmsAgentForLinux", "version": "1.14"},
+					{"name": "AzureMonitorLinuxAgent", "version": "1.22"},
+					{"name": "CustomScript", "version": "2.1"}
+				],
+				"tags": {"owner": "ops", "env": "prod", "cost-centre": "1234"}
+			}"#,
+		)
+		.unwrap();
+
+		let out = serde_json::to_string(&vm).unwrap();
+		let extensions: Vec<usize> = ["AzureMonitorLinuxAgent", "CustomScript", "OmsAgentForLinux"]
+			.iter()
+			.map(|n| out.find(n).unwrap())
+			.collect();
+		let tags: Vec<usize> = ["cost-centre", "env", "owner"]
+			.iter()
+			.map(|k| out.find(k).unwrap())
+			.collect();
+
+		assert!(extensions.windows(2).all(|w| w[0] < w[1]));
+		assert!(tags.windows(2).all(|w| w[0] < w[1]));
+	}
+}
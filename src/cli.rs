@@ -1,12 +1,90 @@
-use clap::Parser;
+use std::time::Duration;
+
+use clap::{
+	builder::{PossibleValuesParser, TypedValueParser},
+	Parser, ValueEnum,
+};
+use lib_vminfo::query::{SortBy, OUTPUT_COLUMNS};
+use lib_vminfo::vm::PowerState;
+use lib_vminfo::DEFAULT_BATCH_SIZE;
+
+use crate::output::{parse_indent, DEFAULT_INDENT};
+use crate::util::parse_age;
+
+/// Supported output formats for query results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+	/// Pretty-printed JSON array of virtual machines
+	Json,
+	/// Ansible dynamic inventory JSON
+	Ansible,
+	/// Pretty-printed JSON object of virtual machines keyed by lowercased VM name
+	Map,
+	/// One concise line per virtual machine: name, private IP, size, power state and (location/resource group)
+	Line,
+	/// Comma separated values with a header row and one row per virtual machine
+	Csv,
+	/// An aligned table of virtual machines (the columns are limited by '--fields')
+	Table,
+	/// YAML list of virtual machines
+	Yaml,
+	/// Parquet file with one flat column per field (requires '--output-file')
+	#[cfg(feature = "parquet")]
+	Parquet,
+}
+
+impl OutputFormat {
+	///
+	/// the media type of results rendered in this format, sent as the Content-Type of '--webhook' deliveries
+	///
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			Self::Json | Self::Ansible | Self::Map => "application/json",
+			Self::Line | Self::Table => "text/plain; charset=utf-8",
+			Self::Csv => "text/csv; charset=utf-8",
+			Self::Yaml => "application/yaml",
+			#[cfg(feature = "parquet")]
+			Self::Parquet => "application/vnd.apache.parquet",
+		}
+	}
+}
+
+/// Supported compression of output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+	/// gzip (.gz)
+	Gzip,
+	/// Zstandard (.zst)
+	Zstd,
+}
+
+/// Supported casings for the keys of JSON output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KeyCase {
+	/// camelCase keys (ie: vmName, privateIp)
+	Camel,
+	/// snake_case keys (ie: vm_name, private_ip)
+	Snake,
+}
 
 /// A Rust utility to pull useful virtual machine data from a configured Azure tenant using the Azure Resource Graph APIs
 #[derive(Debug, Parser)]
 pub struct Cli {
-	/// Specifies one or more VM name(s) or a regular expression to match VM(s)
+	/// Specifies one or more VM name(s) and/or full VM resource ID(s), or a regular expression to match VM(s)
 	#[arg(value_name = "vm_name_or_regexp")]
-	#[arg(required_unless_present("perform_login"))]
-	#[arg(required_unless_present("perform_logout"))]
+	#[arg(required_unless_present_any([
+		"perform_login",
+		"perform_logout",
+		"export_credentials",
+		"import_credentials",
+		"print_token",
+		"dry_run_auth",
+		"list_subscriptions",
+		"contains",
+		"from_file",
+		"ids_from_file",
+		"diff",
+	]))]
 	pub vm_operand: Vec<String>,
 
 	/// Specifies whether to prompt for credentials manually (will exit). Will default to user authentication method.
@@ -17,15 +95,99 @@ pub struct Cli {
 	#[arg(long = "logout", required = false)]
 	pub perform_logout: bool,
 
+	/// Export the stored credentials (including secrets and tokens) as JSON to stdout for migration to another machine
+	#[arg(long = "export-creds", required = false)]
+	pub export_credentials: bool,
+
+	/// Import credentials from a file previously created with '--export-creds'
+	#[arg(long = "import-creds", value_name = "path", required = false)]
+	pub import_credentials: Option<String>,
+
+	/// Print a valid access token (refreshing it first if needed) to stdout for use with other tools (ie: curl). Asks
+	/// for confirmation unless VMINFO_ALLOW_PRINT_TOKEN=1 is set
+	#[arg(long = "print-token", required = false)]
+	pub print_token: bool,
+
+	/// Trace how the client authenticates (the method chosen, whether the stored token is present and expired, and any
+	/// refresh or re-authentication with its outcome) to stderr. Token values are never printed
+	#[arg(long = "explain-auth", required = false)]
+	pub explain_auth: bool,
+
+	/// Print which flow '--login' would use with the other flags and config (and what it would prompt for) without
+	/// contacting Azure
+	#[arg(
+		long = "dry-run-auth",
+		conflicts_with_all = ["perform_logout", "export_credentials", "import_credentials", "print_token"],
+		required = false
+	)]
+	pub dry_run_auth: bool,
+
+	/// List every subscription the signed in identity can access (ignoring the configured subscriptions) as JSON, and warn
+	/// about configured subscriptions it can not access
+	#[arg(long = "list-subscriptions", conflicts_with_all = ["vm_operand", "contains"], required = false)]
+	pub list_subscriptions: bool,
+
+	/// Compare two result files saved with '--format json' and print the added, removed and changed VMs as JSON (a
+	/// summary is also printed to stderr). No Azure access is needed
+	#[arg(
+		long = "diff",
+		value_names = ["old.json", "new.json"],
+		num_args = 2,
+		conflicts_with_all = ["vm_operand", "contains"],
+		required = false
+	)]
+	pub diff: Option<Vec<String>>,
+
+	/// Only output the matched VMs which are new or changed since a result file saved with '--format json' (compared
+	/// with the same '--fields' and '--case' it was saved with). Unchanged VMs are left out
+	#[arg(long = "since-snapshot", value_name = "path", conflicts_with = "diff", required = false)]
+	pub since_snapshot: Option<String>,
+
+	/// Load the config from this TOML file instead of the default location
+	#[arg(long = "config", value_name = "path", env = "VMINFO_CONFIG", required = false)]
+	pub config: Option<String>,
+
+	/// Selects the named profile (config and credentials) to login, logout, query, export or import with (default: "default")
+	#[arg(long = "profile", value_name = "name", env = "VMINFO_PROFILE", required = false)]
+	pub profile: Option<String>,
+
 	/// Specifies that azure-vminfo should use a service-principal (client_id and client_secret) to authenticate
 	#[arg(long = "service-principal", required = false)]
 	pub use_service_principal: bool,
 
+	/// With '--login', authenticate the service principal with this PEM encoded certificate (and private key) instead of
+	/// a client secret. Only the path of the certificate is stored
+	#[arg(
+		long = "certificate",
+		value_name = "path",
+		requires = "perform_login",
+		required = false
+	)]
+	pub certificate: Option<String>,
+
+	/// Read the private key of '--certificate' from this PEM file instead of the certificate file
+	#[arg(
+		long = "certificate-key",
+		value_name = "path",
+		requires = "certificate",
+		required = false
+	)]
+	pub certificate_key: Option<String>,
+
 	/// Specifies that azure-vminfo should use an interactive (client_id and login challenge) authentication method
 	#[arg(long = "interactive", required = false)]
 	pub interactive_login: bool,
 
-	/// Specifies whether to ignore the cache and force data to be pulled from Resource Graph API directly
+	/// Query with the access token of the account logged in to the Azure CLI ('az login') instead of the stored
+	/// credentials
+	#[arg(
+		long = "azure-cli",
+		conflicts_with_all = ["perform_login", "perform_logout", "export_credentials", "import_credentials", "print_token"],
+		required = false
+	)]
+	pub azure_cli: bool,
+
+	/// Specifies whether to ignore the caches (neither reading nor writing them) and force data to be pulled from Resource Graph API directly
 	#[arg(short = 'c', long = "no-cache", required = false)]
 	pub no_cache: bool,
 
@@ -33,6 +195,39 @@ pub struct Cli {
 	#[arg(short = 'r', long = "match-regexp", required = false)]
 	pub match_regexp: bool,
 
+	/// With '-r', refuse to query with a regular expression which matches every VM (ie: '.*') or nests quantifiers (ie:
+	/// '(a+)+') instead of only warning about it
+	#[arg(long = "strict-regex", requires = "match_regexp", required = false)]
+	pub strict_regex: bool,
+
+	/// Match every VM whose name contains this (case-insensitive) substring instead of providing VM name(s)
+	#[arg(
+		long = "contains",
+		value_name = "substring",
+		conflicts_with_all = ["vm_operand", "match_regexp"],
+		required = false
+	)]
+	pub contains: Option<String>,
+
+	/// Read the VM names (or regular expressions, with '-r') from this file, one per line. Blank lines and lines starting
+	/// with '#' are ignored. VMs matching any of the regular expressions are returned
+	#[arg(
+		long = "from-file",
+		value_name = "path",
+		conflicts_with_all = ["vm_operand", "contains"],
+		required = false
+	)]
+	pub from_file: Option<String>,
+
+	/// Read VM resource IDs from this file (one per line, or a JSON array) and query them in batches of '--batch-size'
+	#[arg(
+		long = "ids-from-file",
+		value_name = "path",
+		conflicts_with_all = ["vm_operand", "contains", "from_file", "match_regexp"],
+		required = false
+	)]
+	pub ids_from_file: Option<String>,
+
 	/// Specifies whether or not to display Azure tags associated with each VM
 	#[arg(short = 't', long = "tags", required = false)]
 	pub show_tags: bool,
@@ -40,6 +235,216 @@ pub struct Cli {
 	/// Specifies whether or not to display Azure extensions for each VM
 	#[arg(short = 'e', long = "extensions", required = false)]
 	pub show_extensions: bool,
+
+	/// Specifies whether or not to display whether the OS disk of each VM is encrypted
+	#[arg(long = "encryption", required = false)]
+	pub show_encryption: bool,
+
+	/// Specifies whether or not to display the optional capabilities (ie: hibernation) of each VM
+	#[arg(long = "capabilities", required = false)]
+	pub show_capabilities: bool,
+
+	/// Specifies whether or not to display details of the disks (ie: an ephemeral OS disk) of each VM
+	#[arg(long = "disks", required = false)]
+	pub show_disks: bool,
+
+	/// Specifies whether or not to display any pending (or the last) platform maintenance of each VM
+	#[arg(long = "maintenance", required = false)]
+	pub show_maintenance: bool,
+
+	/// Specifies whether or not to display the proximity placement group and dedicated host (group) of each VM
+	#[arg(long = "placement", required = false)]
+	pub show_placement: bool,
+
+	/// Specifies whether or not to display the IOPS and throughput limits of the managed OS and data disks of each VM
+	#[arg(long = "disk-perf", required = false)]
+	pub show_disk_perf: bool,
+
+	/// Specifies whether or not to display the vCPUs and memory of each VM's size (common sizes only)
+	#[arg(long = "sku-details", required = false)]
+	pub show_sku_details: bool,
+
+	/// Sort the results by this field instead of by subnet (or in the order VM names were provided)
+	#[arg(
+		long = "sort",
+		value_name = "field",
+		value_parser = PossibleValuesParser::new(SortBy::NAMES).map(|s| s.parse::<SortBy>().unwrap()),
+		required = false
+	)]
+	pub sort: Option<SortBy>,
+
+	/// Sort the results by '--sort' in descending order
+	#[arg(long = "sort-desc", requires = "sort", required = false)]
+	pub sort_desc: bool,
+
+	/// Output the value of this Azure tag as its own field, named after the tag (ie: --tag-column env --tag-column owner).
+	/// May be repeated
+	#[arg(long = "tag-column", value_name = "tag_name", required = false)]
+	pub tag_columns: Vec<String>,
+
+	/// Specifies that VM names should be queried exactly as provided instead of being lowercased (matching remains case-insensitive)
+	#[arg(long = "preserve-case", required = false)]
+	pub preserve_case: bool,
+
+	/// Only return VMs whose OS name matches this case-insensitive regular expression (ie: ubuntu). Stopped VMs do not report an OS name and will not match
+	#[arg(long = "os", value_name = "os_name", required = false)]
+	pub os_filter: Option<String>,
+
+	/// Only return VMs in exactly this power state. 'stopped' VMs are still allocated (and billed), unlike 'deallocated' VMs
+	#[arg(
+		long = "power-state",
+		value_name = "state",
+		value_parser = PossibleValuesParser::new(PowerState::NAMES).map(|s| s.parse::<PowerState>().unwrap()),
+		required = false
+	)]
+	pub power_state: Option<PowerState>,
+
+	/// Only return VMs created more than this long ago (ie: 365d, 12h, 1y). Applied once the VMs are fetched, so VMs
+	/// without a creation time never match
+	#[arg(long = "older-than", value_name = "age", value_parser = parse_age, required = false)]
+	pub older_than: Option<Duration>,
+
+	/// Only return VMs created less than this long ago (ie: 30d, 12h, 2w). Applied once the VMs are fetched, so VMs
+	/// without a creation time never match
+	#[arg(long = "newer-than", value_name = "age", value_parser = parse_age, required = false)]
+	pub newer_than: Option<Duration>,
+
+	/// Limits the fields requested from Azure and included in the output to this comma separated list (vmId is always included)
+	#[arg(long = "fields", value_delimiter = ',', value_parser = PossibleValuesParser::new(OUTPUT_COLUMNS))]
+	pub fields: Option<Vec<String>>,
+
+	/// Prints the distribution of values of these comma separated fields across every matched VM to stderr (ie: --facet location)
+	#[arg(long = "facet", value_delimiter = ',', value_parser = PossibleValuesParser::new(OUTPUT_COLUMNS))]
+	pub facets: Vec<String>,
+
+	/// Only output the first matching VM as a single JSON object (fails if no VMs matched)
+	#[arg(long = "first", required = false)]
+	pub first: bool,
+
+	/// Output a summary report of the matched VMs (the total, counts by power state, OS type, location and size, and the
+	/// oldest and newest VM) as JSON instead of the VMs themselves. The report is also printed to stderr
+	#[arg(long = "summary", conflicts_with_all = ["first", "format", "fields", "webhook"], required = false)]
+	pub summary: bool,
+
+	/// Print a curl command which sends the same Resource Graph request (with a $TOKEN placeholder for the access token)
+	/// instead of querying, for reproducing a query outside of azure-vminfo
+	#[arg(long = "emit-curl", conflicts_with_all = ["fan_out", "batch_delay_ms"], required = false)]
+	pub emit_curl: bool,
+
+	/// Do not print the summary of matched / returned VMs to stderr after a query
+	#[arg(short = 'q', long = "quiet", required = false)]
+	pub quiet: bool,
+
+	/// Fetch every page of results instead of only the first (up to 1000 VMs)
+	#[arg(long = "all", required = false)]
+	pub all: bool,
+
+	/// Return at most this many VMs (up to 1000) from a single page of results, for sampling a few VMs quickly
+	#[arg(
+		long = "top",
+		value_name = "n",
+		value_parser = clap::value_parser!(u16).range(1..=1000),
+		conflicts_with_all = ["all", "fan_out", "batch_delay_ms", "ids_from_file"],
+		required = false
+	)]
+	pub top: Option<u16>,
+
+	/// Skip this many of the matched VMs before returning any
+	#[arg(
+		long = "skip",
+		value_name = "n",
+		conflicts_with_all = ["all", "fan_out", "batch_delay_ms", "ids_from_file"],
+		required = false
+	)]
+	pub skip: Option<u64>,
+
+	/// Query each configured subscription separately (and concurrently), attributing each VM to its subscription.
+	/// Every page is fetched for each subscription and a subscription that fails does not fail the others
+	#[arg(long = "fan-out", required = false)]
+	pub fan_out: bool,
+
+	/// Fail instead of holding more than this many VMs in memory while collecting every page with '--all', '--fan-out'
+	/// or '--batch-delay-ms'
+	#[arg(long = "max-vms", value_name = "n", required = false)]
+	pub max_vms: Option<usize>,
+
+	/// The maximum number of subscriptions queried at once with '--fan-out'
+	#[arg(long = "concurrency", value_name = "n", default_value_t = 4)]
+	pub concurrency: usize,
+
+	/// Query the VM names in batches of '--batch-size', waiting this many milliseconds between batches (every page of
+	/// each batch is fetched). Keeps large lists of VM names under the Resource Graph request quota
+	#[arg(
+		long = "batch-delay-ms",
+		value_name = "ms",
+		conflicts_with_all = ["match_regexp", "contains", "fan_out"],
+		required = false
+	)]
+	pub batch_delay_ms: Option<u64>,
+
+	/// The maximum number of VM names matched by each query with '--batch-delay-ms'
+	#[arg(long = "batch-size", value_name = "n", default_value_t = DEFAULT_BATCH_SIZE)]
+	pub batch_size: usize,
+
+	/// Print whether every matched VM was fetched (or more pages are available) to stderr after a query
+	#[arg(long = "show-pagination", required = false)]
+	pub show_pagination: bool,
+
+	/// On failure, print the error to stderr as JSON ({"error": {"kind", "message", "status", "oauth_error"}}) instead of a human readable message
+	#[arg(long = "json-errors", required = false)]
+	pub json_errors: bool,
+
+	/// Specifies the format used to output results
+	#[arg(long = "format", value_enum, default_value_t = OutputFormat::Json)]
+	pub format: OutputFormat,
+
+	/// Write the results to this file instead of stdout (required with '--format parquet')
+	#[arg(
+		long = "output-file",
+		value_name = "path",
+		required_if_eq("format", "parquet"),
+		required = false
+	)]
+	pub output_file: Option<String>,
+
+	/// Compress the output (of any format) with gzip or zstd. The matching extension is appended to '--output-file'
+	#[arg(long = "compress", value_enum, required = false)]
+	pub compress: Option<Compression>,
+
+	/// Also POST the results to this URL (with a Content-Type matching '--format'), retrying if the endpoint is unavailable
+	#[arg(long = "webhook", value_name = "url", required = false)]
+	pub webhook: Option<String>,
+
+	/// The Authorization header sent with '--webhook' (ie: "Bearer <token>")
+	#[arg(
+		long = "webhook-auth",
+		value_name = "header_value",
+		env = "VMINFO_WEBHOOK_AUTH",
+		hide_env_values = true,
+		requires = "webhook",
+		required = false
+	)]
+	pub webhook_auth: Option<String>,
+
+	/// Only POST the results to '--webhook' instead of also writing them to stdout or '--output-file'
+	#[arg(long = "webhook-only", requires = "webhook", required = false)]
+	pub webhook_only: bool,
+
+	/// With '--format map', fail when more than one VM has the same name instead of keeping the last one
+	#[arg(long = "strict", required = false)]
+	pub strict: bool,
+
+	/// With '--format table', also include the long columns (subscription name, subnet ID and VM ID)
+	#[arg(long = "wide", required = false)]
+	pub wide: bool,
+
+	/// Specifies the casing used for the keys of each VM in JSON output
+	#[arg(long = "case", value_enum, default_value_t = KeyCase::Camel)]
+	pub case: KeyCase,
+
+	/// The indentation of pretty-printed JSON output: a number of spaces or 'tab'
+	#[arg(long = "indent", value_name = "n|tab", value_parser = parse_indent, default_value = "2")]
+	pub indent: String,
 }
 
 impl std::fmt::Display for Cli {
@@ -57,13 +462,71 @@ impl Default for Cli {
 		Self {
 			vm_operand: vec!["".to_string()],
 			match_regexp: false,
+			strict_regex: false,
 			show_extensions: false,
 			show_tags: false,
 			perform_login: false,
 			perform_logout: false,
+			export_credentials: false,
+			import_credentials: None,
+			print_token: false,
+			explain_auth: false,
+			dry_run_auth: false,
+			list_subscriptions: false,
+			diff: None,
+			since_snapshot: None,
+			config: None,
+			profile: None,
 			no_cache: false,
+			contains: None,
+			from_file: None,
+			ids_from_file: None,
 			use_service_principal: false,
+			certificate: None,
+			certificate_key: None,
 			interactive_login: true,
+			azure_cli: false,
+			show_encryption: false,
+			show_capabilities: false,
+			show_disks: false,
+			show_maintenance: false,
+			show_placement: false,
+			show_disk_perf: false,
+			show_sku_details: false,
+			sort: None,
+			sort_desc: false,
+			tag_columns: vec![],
+			preserve_case: false,
+			os_filter: None,
+			power_state: None,
+			older_than: None,
+			newer_than: None,
+			fields: None,
+			facets: vec![],
+			first: false,
+			summary: false,
+			emit_curl: false,
+			quiet: false,
+			all: false,
+			top: None,
+			skip: None,
+			fan_out: false,
+			max_vms: None,
+			concurrency: 4,
+			batch_delay_ms: None,
+			batch_size: DEFAULT_BATCH_SIZE,
+			show_pagination: false,
+			json_errors: false,
+			format: OutputFormat::Json,
+			output_file: None,
+			compress: None,
+			webhook: None,
+			webhook_auth: None,
+			webhook_only: false,
+			strict: false,
+			wide: false,
+			case: KeyCase::Camel,
+			indent: DEFAULT_INDENT.to_string(),
 		}
 	}
 }
@@ -0,0 +1,172 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+
+use anyhow::Result;
+use flate2::write::GzEncoder;
+
+use crate::cli::Compression;
+
+///
+/// A destination for command output (a file or stdout), optionally compressed
+///
+/// finish() must be called once everything is written so the compressed stream is completed
+///
+pub enum OutputWriter {
+	Plain(Box<dyn Write + Send>),
+	Gzip(GzEncoder<Box<dyn Write + Send>>),
+	Zstd(zstd::Encoder<'static, Box<dyn Write + Send>>),
+}
+
+impl OutputWriter {
+	///
+	/// opens the output file (or stdout when no path is provided), compressing anything written to it when requested.
+	/// the extension of the compression (ie: .gz) is appended to the path unless it is already present
+	///
+	pub fn create(path: Option<&str>, compression: Option<Compression>) -> Result<Self> {
+		let inner: Box<dyn Write + Send> = match path {
+			Some(path) => Box::new(File::create(compressed_path(path, compression))?),
+			None => Box::new(io::stdout()),
+		};
+
+		Self::new(inner, compression)
+	}
+
+	///
+	/// wraps a writer, compressing anything written to it when requested
+	///
+	pub fn new(inner: Box<dyn Write + Send>, compression: Option<Compression>) -> Result<Self> {
+		Ok(match compression {
+			None => Self::Plain(inner),
+			Some(Compression::Gzip) => Self::Gzip(GzEncoder::new(inner, flate2::Compression::default())),
+			Some(Compression::Zstd) => {
+				Self::Zstd(zstd::Encoder::new(inner, zstd::DEFAULT_COMPRESSION_LEVEL)?)
+			}
+		})
+	}
+
+	///
+	/// completes any compressed stream and flushes the output
+	///
+	pub fn finish(self) -> Result<()> {
+		let mut inner = match self {
+			Self::Plain(w) => w,
+			Self::Gzip(e) => e.finish()?,
+			Self::Zstd(e) => e.finish()?,
+		};
+
+		Ok(inner.flush()?)
+	}
+}
+
+impl Write for OutputWriter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self {
+			Self::Plain(w) => w.write(buf),
+			Self::Gzip(e) => e.write(buf),
+			Self::Zstd(e) => e.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match self {
+			Self::Plain(w) => w.flush(),
+			Self::Gzip(e) => e.flush(),
+			Self::Zstd(e) => e.flush(),
+		}
+	}
+}
+
+///
+/// opens a file previously written by vminfo, decompressing it when its extension is .gz or .zst
+///
+pub fn open_input(path: &str) -> Result<Box<dyn Read>> {
+	let file = BufReader::new(File::open(path)?);
+
+	Ok(if path.ends_with(".gz") {
+		Box::new(flate2::read::GzDecoder::new(file))
+	} else if path.ends_with(".zst") {
+		Box::new(zstd::Decoder::with_buffer(file)?)
+	} else {
+		Box::new(file)
+	})
+}
+
+///
+/// appends the file extension of the compression to a path, unless it is already present
+///
+fn compressed_path(path: &str, compression: Option<Compression>) -> String {
+	let extension = match compression {
+		None => return path.to_string(),
+		Some(Compression::Gzip) => ".gz",
+		Some(Compression::Zstd) => ".zst",
+	};
+
+	if path.ends_with(extension) {
+		path.to_string()
+	} else {
+		format!("{}{}", path, extension)
+	}
+}
+
+#[cfg(test)]
+mod compress_tests {
+	use std::io::{BufRead, BufReader, Read, Write};
+
+	use lib_vminfo::vm::VirtualMachine;
+
+	use super::OutputWriter;
+	use crate::cli::Compression;
+
+	fn vms() -> Vec<VirtualMachine> {
+		serde_json::from_value(serde_json::json!([
+			{ "vmId": "/vm/web-01", "vmName": "web-01", "privateIp": "10.0.0.4", "tags": { "env": "prod" } },
+			{ "vmId": "/vm/web-02", "vmName": "web-02", "privateIp": "10.0.0.5" }
+		]))
+		.unwrap()
+	}
+
+	fn decompress(compression: Compression, data: &[u8]) -> Box<dyn Read + '_> {
+		match compression {
+			Compression::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+			Compression::Zstd => Box::new(zstd::Decoder::new(data).unwrap()),
+		}
+	}
+
+	#[test]
+	fn compressed_ndjson_round_trip() {
+		for compression in [Compression::Gzip, Compression::Zstd] {
+			let file = tempfile::NamedTempFile::new().unwrap();
+			let mut out = OutputWriter::new(Box::new(file.reopen().unwrap()), Some(compression)).unwrap();
+			for vm in vms() {
+				writeln!(out, "{}", serde_json::to_string(&vm).unwrap()).unwrap();
+			}
+			out.finish().unwrap();
+
+			let data = std::fs::read(file.path()).unwrap();
+			let records: Vec<VirtualMachine> = BufReader::new(decompress(compression, &data))
+				.lines()
+				.map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+				.collect();
+
+			assert_eq!(
+				serde_json::to_value(&records).unwrap(),
+				serde_json::to_value(vms()).unwrap()
+			);
+		}
+	}
+
+	#[test]
+	fn compression_extension_is_appended() {
+		use super::compressed_path;
+
+		assert_eq!(
+			compressed_path("vms.json", Some(Compression::Gzip)),
+			"vms.json.gz"
+		);
+		assert_eq!(
+			compressed_path("vms.json.zst", Some(Compression::Zstd)),
+			"vms.json.zst"
+		);
+		assert_eq!(compressed_path("vms.json", None), "vms.json");
+	}
+}
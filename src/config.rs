@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+///
+/// Settings that can be overridden for a named profile. Any setting not specified falls back to the top-level config
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ProfileConfig {
+	pub use_cache: Option<bool>,
+	pub redis_host: Option<String>,
+	pub redis_port: Option<u16>,
+	pub redis_password: Option<String>,
+	pub redis_use_tls: Option<bool>,
+	pub redis_db: Option<u8>,
+	pub redis_sentinels: Option<Vec<String>>,
+	pub redis_sentinel_master: Option<String>,
+	pub subscriptions: Option<Vec<String>>,
+	pub azure_environment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AppConfig {
+	pub use_cache: bool,
+	pub redis_host: String,
+	pub redis_port: u16,
+	pub redis_password: Option<String>,
+	pub redis_use_tls: bool,
+	pub redis_db: Option<u8>,
+	pub redis_sentinels: Option<Vec<String>>,
+	pub redis_sentinel_master: Option<String>,
+	pub subscriptions: Option<Vec<String>>,
+	pub subscription_denylist: Option<Vec<String>>,
+	pub log_level: String,
+	pub audit_log_path: Option<String>,
+	pub clock_skew_secs: Option<u64>,
+	pub min_tls_version: Option<String>,
+	pub device_code_poll_secs: Option<u64>,
+	pub use_v2_token_endpoint: Option<bool>,
+	pub response_cache_ttl_secs: Option<u64>,
+	pub cache_ttl_secs: Option<u64>,
+	pub cache_ttl_jitter_percent: Option<u8>,
+	pub extra_headers: Option<BTreeMap<String, String>>,
+	pub azure_environment: Option<String>,
+	pub management_endpoint: Option<String>,
+	pub profiles: Option<BTreeMap<String, ProfileConfig>>,
+}
+
+impl Default for AppConfig {
+	fn default() -> Self {
+		Self {
+			use_cache: true,
+			redis_host: "127.0.0.1".to_string(),
+			redis_port: 6379u16,
+			redis_password: None,
+			redis_use_tls: false,
+			redis_db: None,
+			redis_sentinels: None,
+			redis_sentinel_master: None,
+			subscriptions: None,
+			subscription_denylist: None,
+			log_level: "INFO".to_string(),
+			audit_log_path: None,
+			clock_skew_secs: None,
+			min_tls_version: None,
+			device_code_poll_secs: None,
+			use_v2_token_endpoint: None,
+			response_cache_ttl_secs: None,
+			cache_ttl_secs: None,
+			cache_ttl_jitter_percent: None,
+			extra_headers: None,
+			azure_environment: None,
+			management_endpoint: None,
+			profiles: None,
+		}
+	}
+}
+
+impl AppConfig {
+	///
+	/// Loads the config from the provided TOML file, or from the platform default location for the app when no path is
+	/// provided (creating a default config there if none exists)
+	pub fn load(app_name: &str, path: Option<&str>) -> Result<AppConfig> {
+		match path {
+			Some(path) if !Path::new(path).exists() => {
+				Err(anyhow!("config file '{}' does not exist", path))
+			}
+			Some(path) => Ok(confy::load_path(path)?),
+			None => Ok(confy::load(app_name, "config")?),
+		}
+	}
+
+	///
+	/// Resolves the config for the selected profile by applying its overrides to the top-level config.
+	/// The top-level config is used as-is when no profile is selected or the profile has no overrides configured
+	pub fn for_profile(&self, profile: Option<&str>) -> AppConfig {
+		let mut resolved = self.clone();
+		resolved.profiles = None;
+
+		let overrides = match (profile, &self.profiles) {
+			(Some(name), Some(profiles)) => match profiles.get(name) {
+				Some(p) => p.clone(),
+				None => return resolved,
+			},
+			_ => return resolved,
+		};
+
+		if let Some(use_cache) = overrides.use_cache {
+			resolved.use_cache = use_cache;
+		}
+		if let Some(redis_host) = overrides.redis_host {
+			resolved.redis_host = redis_host;
+		}
+		if let Some(redis_port) = overrides.redis_port {
+			resolved.redis_port = redis_port;
+		}
+		if overrides.redis_password.is_some() {
+			resolved.redis_password = overrides.redis_password;
+		}
+		if let Some(redis_use_tls) = overrides.redis_use_tls {
+			resolved.redis_use_tls = redis_use_tls;
+		}
+		if overrides.redis_db.is_some() {
+			resolved.redis_db = overrides.redis_db;
+		}
+		if overrides.redis_sentinels.is_some() {
+			resolved.redis_sentinels = overrides.redis_sentinels;
+		}
+		if overrides.redis_sentinel_master.is_some() {
+			resolved.redis_sentinel_master = overrides.redis_sentinel_master;
+		}
+		if overrides.subscriptions.is_some() {
+			resolved.subscriptions = overrides.subscriptions;
+		}
+		if overrides.azure_environment.is_some() {
+			resolved.azure_environment = overrides.azure_environment;
+		}
+
+		resolved
+	}
+}
+
+#[cfg(test)]
+mod config_tests {
+	use super::{AppConfig, ProfileConfig};
+
+	fn config() -> AppConfig {
+		AppConfig {
+			subscriptions: Some(vec!["prod-sub".to_string()]),
+			profiles: Some(
+				[(
+					"gov".to_string(),
+					ProfileConfig {
+						redis_host: Some("redis.gov.internal".to_string()),
+						redis_db: Some(2),
+						subscriptions: Some(vec!["gov-sub".to_string()]),
+						azure_environment: Some("usgov".to_string()),
+						..Default::default()
+					},
+				)]
+				.into_iter()
+				.collect(),
+			),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn selected_profile_overrides_top_level() {
+		let resolved = config().for_profile(Some("gov"));
+
+		assert_eq!(resolved.redis_host, "redis.gov.internal");
+		assert_eq!(resolved.redis_db, Some(2));
+		assert_eq!(resolved.subscriptions, Some(vec!["gov-sub".to_string()]));
+		assert_eq!(resolved.azure_environment, Some("usgov".to_string()));
+		// settings the profile does not override are kept
+		assert_eq!(resolved.redis_port, 6379);
+		assert!(resolved.use_cache);
+	}
+
+	#[test]
+	fn loads_from_explicit_path() {
+		let file = tempfile::NamedTempFile::new().unwrap();
+		std::fs::write(
+			file.path(),
+			r#"
+use_cache = false
+redis_host = "redis.staging.internal"
+redis_port = 6380
+redis_use_tls = true
+log_level = "DEBUG"
+subscriptions = ["staging-sub"]
+"#,
+		)
+		.unwrap();
+
+		let loaded = AppConfig::load("azure-vminfo", file.path().to_str()).unwrap();
+
+		assert!(!loaded.use_cache);
+		assert_eq!(loaded.redis_host, "redis.staging.internal");
+		assert_eq!(loaded.redis_port, 6380);
+		assert_eq!(loaded.subscriptions, Some(vec!["staging-sub".to_string()]));
+
+		assert!(AppConfig::load("azure-vminfo", Some("/nonexistent/vminfo.toml")).is_err());
+	}
+
+	#[test]
+	fn falls_back_to_default() {
+		let mut expected = config();
+		expected.profiles = None;
+
+		assert_eq!(config().for_profile(None), expected);
+		assert_eq!(config().for_profile(Some("unknown")), expected);
+	}
+}
@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::compress;
+
+///
+/// the differences between two saved inventory snapshots, matching VMs by their ID
+///
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct InventoryDiff {
+	/// VMs only in the new snapshot
+	pub added: Vec<Value>,
+	/// VMs only in the old snapshot
+	pub removed: Vec<Value>,
+	/// VMs in both snapshots with at least one field changed
+	pub changed: Vec<ChangedVm>,
+}
+
+///
+/// a VM which appears in both snapshots with different fields
+///
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ChangedVm {
+	pub vm_id: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub vm_name: Option<String>,
+	pub changes: Vec<FieldChange>,
+}
+
+///
+/// a field whose value differs between snapshots (null when the field is missing from one of them)
+///
+#[derive(Debug, Serialize, PartialEq)]
+pub struct FieldChange {
+	pub field: String,
+	pub old: Value,
+	pub new: Value,
+}
+
+///
+/// reads the VMs from a file saved with '--format json' (or '--format map'), which may be compressed
+///
+pub fn load_snapshot(path: &str) -> Result<Vec<Value>> {
+	let value: Value = serde_json::from_reader(compress::open_input(path)?)
+		.map_err(|err| anyhow!("could not read inventory snapshot '{}': {}", path, err))?;
+
+	match value {
+		Value::Array(vms) => Ok(vms),
+		// saved with '--format map', keyed by VM name
+		Value::Object(map) if id_of(&map).is_none() => Ok(map.into_iter().map(|(_, vm)| vm).collect()),
+		// saved with '--first'
+		vm @ Value::Object(_) => Ok(vec![vm]),
+		_ => Err(anyhow!(
+			"inventory snapshot '{}' does not contain a list of VMs",
+			path
+		)),
+	}
+}
+
+///
+/// compares two snapshots of VMs, matching VMs by ID (case-insensitively). every VM must have an ID
+///
+pub fn diff(old: &[Value], new: &[Value]) -> Result<InventoryDiff> {
+	let old = index_by_id(old)?;
+	let mut new = index_by_id(new)?;
+	let mut result = InventoryDiff::default();
+
+	for (id, old_vm) in old {
+		let new_vm = match new.remove(&id) {
+			Some(vm) => vm,
+			None => {
+				result.removed.push(Value::Object(old_vm.clone()));
+				continue;
+			}
+		};
+
+		let changes = field_changes(old_vm, new_vm);
+		if !changes.is_empty() {
+			result.changed.push(ChangedVm {
+				vm_id: id_of(new_vm).unwrap_or_default().to_string(),
+				vm_name: name_of(new_vm).map(|n| n.to_string()),
+				changes,
+			});
+		}
+	}
+	result.added = new
+		.into_values()
+		.map(|vm| Value::Object(vm.clone()))
+		.collect();
+
+	Ok(result)
+}
+
+///
+/// keeps the VMs which are new or changed since the old snapshot, comparing each VM as rendered by `record` (ie: with the
+/// fields and key case the snapshot was saved with). VMs are kept in their original order
+///
+pub fn changed_since<T>(
+	old: &[Value],
+	new: Vec<T>,
+	record: impl Fn(&T) -> Result<Value>,
+) -> Result<Vec<T>> {
+	let records: Vec<Value> = new.iter().map(record).collect::<Result<_>>()?;
+	let delta = diff(old, &records)?;
+
+	let ids: HashSet<String> = delta
+		.added
+		.iter()
+		.filter_map(|vm| vm.as_object().and_then(id_of))
+		.chain(delta.changed.iter().map(|vm| vm.vm_id.as_str()))
+		.map(str::to_lowercase)
+		.collect();
+
+	let is_new_or_changed = |record: &Value| {
+		record
+			.as_object()
+			.and_then(id_of)
+			.is_some_and(|id| ids.contains(&id.to_lowercase()))
+	};
+
+	let kept: Vec<T> = new
+		.into_iter()
+		.zip(records)
+		.filter_map(|(vm, record)| is_new_or_changed(&record).then_some(vm))
+		.collect();
+
+	Ok(kept)
+}
+
+///
+/// keys VMs by their lowercased ID
+///
+fn index_by_id(vms: &[Value]) -> Result<BTreeMap<String, &Map<String, Value>>> {
+	let mut index = BTreeMap::new();
+	for vm in vms {
+		let fields = vm
+			.as_object()
+			.ok_or_else(|| anyhow!("inventory snapshot contains a VM which is not a JSON object"))?;
+		let id =
+			id_of(fields).ok_or_else(|| anyhow!("inventory snapshot contains a VM without a vmId"))?;
+
+		index.insert(id.to_lowercase(), fields);
+	}
+
+	Ok(index)
+}
+
+///
+/// the fields which differ between two versions of a VM, in field name order
+///
+fn field_changes(old: &Map<String, Value>, new: &Map<String, Value>) -> Vec<FieldChange> {
+	let mut fields: Vec<&String> = old.keys().chain(new.keys()).collect();
+	fields.sort();
+	fields.dedup();
+
+	fields
+		.into_iter()
+		.filter_map(|field| {
+			let old = old.get(field).cloned().unwrap_or(Value::Null);
+			let new = new.get(field).cloned().unwrap_or(Value::Null);
+
+			// IDs are matched case-insensitively, so a change of case alone is not reported
+			let same_id = matches!(
+				(field.as_str(), &old, &new),
+				("vmId" | "vm_id", Value::String(o), Value::String(n)) if o.eq_ignore_ascii_case(n)
+			);
+
+			(old != new && !same_id).then(|| FieldChange {
+				field: field.clone(),
+				old,
+				new,
+			})
+		})
+		.collect()
+}
+
+///
+/// the ID of a VM saved with either camelCase or snake_case keys
+///
+fn id_of(vm: &Map<String, Value>) -> Option<&str> {
+	vm.get("vmId")
+		.or_else(|| vm.get("vm_id"))
+		.and_then(Value::as_str)
+}
+
+///
+/// the name of a VM saved with either camelCase or snake_case keys
+///
+fn name_of(vm: &Map<String, Value>) -> Option<&str> {
+	vm.get("vmName")
+		.or_else(|| vm.get("vm_name"))
+		.and_then(Value::as_str)
+}
+
+impl Display for InventoryDiff {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let label = |vm: &Value| {
+			let fields = vm.as_object();
+			match fields.and_then(name_of) {
+				Some(name) => name.to_string(),
+				None => fields.and_then(id_of).unwrap_or_default().to_string(),
+			}
+		};
+
+		for vm in &self.added {
+			writeln!(f, "+ {}", label(vm))?;
+		}
+		for vm in &self.removed {
+			writeln!(f, "- {}", label(vm))?;
+		}
+		for vm in &self.changed {
+			writeln!(f, "~ {}", vm.vm_name.as_deref().unwrap_or(&vm.vm_id))?;
+			for change in &vm.changes {
+				writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+			}
+		}
+
+		write!(
+			f,
+			"{} added, {} removed, {} changed",
+			self.added.len(),
+			self.removed.len(),
+			self.changed.len()
+		)
+	}
+}
+
+#[cfg(test)]
+mod diff_tests {
+	use serde_json::json;
+
+	fn old_snapshot() -> serde_json::Value {
+		json!([
+			{ "vmId": "/vm/web-01", "vmName": "web-01", "privateIp": "10.0.0.4", "powerstate": "PowerState/running" },
+			{ "vmId": "/vm/web-02", "vmName": "web-02", "privateIp": "10.0.0.5", "powerstate": "PowerState/running" },
+			{ "vmId": "/vm/db-01", "vmName": "db-01", "privateIp": "10.0.1.4", "powerstate": "PowerState/running" }
+		])
+	}
+
+	fn new_snapshot() -> serde_json::Value {
+		json!([
+			{ "vmId": "/VM/WEB-01", "vmName": "web-01", "privateIp": "10.0.0.4", "powerstate": "PowerState/running" },
+			{ "vmId": "/vm/web-02", "vmName": "web-02", "privateIp": "10.0.0.9", "powerstate": "PowerState/stopped" },
+			{ "vmId": "/vm/web-03", "vmName": "web-03", "privateIp": "10.0.0.6", "powerstate": "PowerState/running" }
+		])
+	}
+
+	#[test]
+	fn only_new_and_changed_vms_since_snapshot() {
+		use super::changed_since;
+
+		let live: Vec<serde_json::Value> = new_snapshot().as_array().unwrap().clone();
+		let delta = changed_since(old_snapshot().as_array().unwrap(), live, |vm| {
+			Ok(vm.clone())
+		})
+		.unwrap();
+
+		// web-01 is unchanged and db-01 was removed, so only the changed web-02 and the new web-03 are kept
+		let names: Vec<&str> = delta
+			.iter()
+			.map(|vm| vm["vmName"].as_str().unwrap())
+			.collect();
+		assert_eq!(names, ["web-02", "web-03"]);
+		assert_eq!(delta[0], new_snapshot()[1]);
+
+		let unchanged = changed_since(
+			old_snapshot().as_array().unwrap(),
+			old_snapshot().as_array().unwrap().clone(),
+			|vm| Ok(vm.clone()),
+		)
+		.unwrap();
+		assert!(unchanged.is_empty());
+	}
+
+	#[test]
+	fn snapshots_with_known_differences() {
+		use super::{diff, load_snapshot, FieldChange};
+
+		let dir = tempfile::tempdir().unwrap();
+		let old_path = dir.path().join("old.json");
+		let new_path = dir.path().join("new.json");
+		std::fs::write(&old_path, old_snapshot().to_string()).unwrap();
+		std::fs::write(&new_path, new_snapshot().to_string()).unwrap();
+
+		let result = diff(
+			&load_snapshot(old_path.to_str().unwrap()).unwrap(),
+			&load_snapshot(new_path.to_str().unwrap()).unwrap(),
+		)
+		.unwrap();
+
+		assert_eq!(result.added, vec![new_snapshot()[2].clone()]);
+		assert_eq!(result.removed, vec![old_snapshot()[2].clone()]);
+		// IDs are matched case-insensitively, so web-01 is unchanged
+		assert_eq!(result.changed.len(), 1);
+		assert_eq!(result.changed[0].vm_name.as_deref(), Some("web-02"));
+		assert_eq!(
+			result.changed[0].changes,
+			vec![
+				FieldChange {
+					field: "powerstate".to_string(),
+					old: json!("PowerState/running"),
+					new: json!("PowerState/stopped"),
+				},
+				FieldChange {
+					field: "privateIp".to_string(),
+					old: json!("10.0.0.5"),
+					new: json!("10.0.0.9"),
+				},
+			]
+		);
+
+		let summary = result.to_string();
+		assert!(summary.contains("+ web-03"));
+		assert!(summary.contains("- db-01"));
+		assert!(summary.contains("    powerstate: \"PowerState/running\" -> \"PowerState/stopped\""));
+		assert!(summary.ends_with("1 added, 1 removed, 1 changed"));
+	}
+
+	#[test]
+	fn map_snapshots_are_read() {
+		use super::load_snapshot;
+
+		let file = tempfile::NamedTempFile::new().unwrap();
+		std::fs::write(
+			file.path(),
+			json!({ "web-01": old_snapshot()[0], "web-02": old_snapshot()[1] }).to_string(),
+		)
+		.unwrap();
+
+		assert_eq!(
+			load_snapshot(file.path().to_str().unwrap()).unwrap().len(),
+			2
+		);
+	}
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arrow::array::{ArrayRef, BooleanArray, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use serde_json::Value;
+
+use lib_vminfo::query::OUTPUT_COLUMNS;
+use lib_vminfo::vm::VirtualMachine;
+
+///
+/// output columns which are stored as booleans. every other column is stored as a string
+///
+const BOOLEAN_COLUMNS: [&str; 3] = ["osDiskEncrypted", "hibernationEnabled", "ephemeralOsDisk"];
+
+///
+/// Writes VMs as a Parquet file with one flat column per output field (limited to the requested fields when provided,
+/// vmId is always kept) and for each extra column, returning the writer once the file is complete. Nested fields
+/// (extensions and tags) are stored as JSON strings
+///
+pub fn write_parquet<W: Write + Send>(
+	vms: &[VirtualMachine],
+	fields: Option<&[String]>,
+	writer: W,
+) -> Result<W> {
+	let rows: Vec<Value> = vms
+		.iter()
+		.map(serde_json::to_value)
+		.collect::<serde_json::Result<Vec<Value>>>()?;
+
+	let mut schema_fields: Vec<Field> = Vec::new();
+	let mut columns: Vec<ArrayRef> = Vec::new();
+	for column in OUTPUT_COLUMNS {
+		if let Some(fields) = fields {
+			if column != "vmId" && !fields.iter().any(|f| f == column) {
+				continue;
+			}
+		}
+
+		if BOOLEAN_COLUMNS.contains(&column) {
+			schema_fields.push(Field::new(column, DataType::Boolean, true));
+			columns.push(Arc::new(
+				rows
+					.iter()
+					.map(|r| r.get(column).and_then(Value::as_bool))
+					.collect::<BooleanArray>(),
+			));
+		} else {
+			schema_fields.push(Field::new(column, DataType::Utf8, true));
+			columns.push(Arc::new(
+				rows
+					.iter()
+					.map(|r| flatten(r.get(column)))
+					.collect::<StringArray>(),
+			));
+		}
+	}
+
+	// extra columns (ie: named tags) follow the output fields, in name order
+	let extra_columns: BTreeSet<&str> = rows
+		.iter()
+		.filter_map(|r| r.get("extraColumns").and_then(Value::as_object))
+		.flat_map(|c| c.keys().map(String::as_str))
+		.collect();
+	for column in extra_columns {
+		schema_fields.push(Field::new(column, DataType::Utf8, true));
+		columns.push(Arc::new(
+			rows
+				.iter()
+				.map(|r| flatten(r.get("extraColumns").and_then(|c| c.get(column))))
+				.collect::<StringArray>(),
+		));
+	}
+
+	let batch = RecordBatch::try_new(Arc::new(Schema::new(schema_fields)), columns)?;
+	let mut writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+	writer.write(&batch)?;
+
+	Ok(writer.into_inner()?)
+}
+
+///
+/// converts a serialized VM field to a string column value. nested values are kept as JSON
+///
+fn flatten(value: Option<&Value>) -> Option<String> {
+	match value? {
+		Value::Null => None,
+		Value::String(s) => Some(s.clone()),
+		v => Some(v.to_string()),
+	}
+}
+
+#[cfg(test)]
+mod export_tests {
+	#[test]
+	fn parquet_round_trip() {
+		use super::write_parquet;
+		use arrow::array::{Array, BooleanArray, StringArray};
+		use lib_vminfo::vm::VirtualMachine;
+		use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmId": "/vm/web-01", "vmName": "web-01", "privateIp": "10.0.0.4", "tags": { "env": "prod" }, "osDiskEncrypted": true, "extraColumns": { "env": "prod" } },
+			{ "vmId": "/vm/web-02", "vmName": "web-02", "privateIp": "10.0.0.5" }
+		]))
+		.unwrap();
+
+		let file = tempfile::NamedTempFile::new().unwrap();
+		write_parquet(&vms, None, file.reopen().unwrap()).unwrap();
+
+		let batch = ParquetRecordBatchReaderBuilder::try_new(file.reopen().unwrap())
+			.unwrap()
+			.build()
+			.unwrap()
+			.next()
+			.unwrap()
+			.unwrap();
+		let strings = |name: &str| {
+			batch
+				.column_by_name(name)
+				.unwrap()
+				.as_any()
+				.downcast_ref::<StringArray>()
+				.unwrap()
+				.clone()
+		};
+
+		assert_eq!(batch.num_rows(), 2);
+		assert_eq!(strings("vmName").value(1), "web-02");
+		assert_eq!(strings("privateIp").value(0), "10.0.0.4");
+		assert_eq!(strings("tags").value(0), r#"{"env":"prod"}"#);
+		assert!(strings("tags").is_null(1));
+		assert_eq!(strings("env").value(0), "prod");
+		assert!(strings("env").is_null(1));
+
+		let encrypted = batch.column_by_name("osDiskEncrypted").unwrap();
+		let encrypted = encrypted.as_any().downcast_ref::<BooleanArray>().unwrap();
+		assert!(encrypted.value(0));
+		assert!(encrypted.is_null(1));
+	}
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use lib_vminfo::vm::VirtualMachine;
+
+///
+/// the value counted for VMs which did not report a field
+///
+const UNKNOWN: &str = "unknown";
+
+///
+/// aggregate counts over the VMs matched by a query, for inventory reports
+///
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct InventorySummary {
+	/// the number of VMs summarized
+	pub total: usize,
+	/// the number of VMs in each power state
+	pub power_states: BTreeMap<String, usize>,
+	/// the number of VMs of each OS type
+	pub os_types: BTreeMap<String, usize>,
+	/// the number of VMs in each location
+	pub locations: BTreeMap<String, usize>,
+	/// the number of VMs of each size
+	pub sizes: BTreeMap<String, usize>,
+	/// the VM created first (null when no VM reported a creation time)
+	pub oldest: Option<CreatedVm>,
+	/// the VM created last (null when no VM reported a creation time)
+	pub newest: Option<CreatedVm>,
+}
+
+///
+/// a VM and when it was created
+///
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CreatedVm {
+	pub vm_name: Option<String>,
+	pub created: String,
+}
+
+///
+/// counts the VMs by power state, OS type, location and size, and finds the oldest and newest of them
+///
+pub fn summarize(vms: &[VirtualMachine]) -> InventorySummary {
+	let mut summary = InventorySummary {
+		total: vms.len(),
+		..Default::default()
+	};
+
+	for vm in vms {
+		count(
+			&mut summary.power_states,
+			vm.power_state().map(|p| p.name()),
+		);
+		count(&mut summary.os_types, vm.os_type());
+		count(&mut summary.locations, vm.location());
+		count(&mut summary.sizes, vm.vm_size());
+	}
+
+	let created = || {
+		vms.iter().filter_map(|vm| {
+			let created = vm.created_at()?;
+			Some((
+				created,
+				CreatedVm {
+					vm_name: vm.vm_name.clone(),
+					created: created.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+				},
+			))
+		})
+	};
+	summary.oldest = created()
+		.min_by_key(|(created, _)| *created)
+		.map(|(_, vm)| vm);
+	summary.newest = created()
+		.max_by_key(|(created, _)| *created)
+		.map(|(_, vm)| vm);
+
+	summary
+}
+
+///
+/// counts one VM against its value of a field (or 'unknown' when it did not report one)
+///
+fn count(counts: &mut BTreeMap<String, usize>, value: Option<&str>) {
+	let value = value.filter(|v| !v.is_empty()).unwrap_or(UNKNOWN);
+	*counts.entry(value.to_string()).or_default() += 1;
+}
+
+impl Display for InventorySummary {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{} VM{}",
+			self.total,
+			if self.total == 1 { "" } else { "s" }
+		)?;
+
+		for (label, counts) in [
+			("power state", &self.power_states),
+			("os type", &self.os_types),
+			("location", &self.locations),
+			("size", &self.sizes),
+		] {
+			let counts: Vec<String> = counts
+				.iter()
+				.map(|(value, count)| format!("{}={}", value, count))
+				.collect();
+			write!(f, "\n{}: {}", label, counts.join(", "))?;
+		}
+
+		for (label, vm) in [("oldest", &self.oldest), ("newest", &self.newest)] {
+			if let Some(vm) = vm {
+				write!(
+					f,
+					"\n{}: {} ({})",
+					label,
+					vm.vm_name.as_deref().unwrap_or(UNKNOWN),
+					vm.created
+				)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod inventory_tests {
+	#[test]
+	fn summary_of_known_distribution() {
+		use super::summarize;
+		use lib_vminfo::vm::VirtualMachine;
+		use std::collections::BTreeMap;
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmName": "web-01", "powerstate": "PowerState/running", "osType": "Linux", "location": "canadacentral", "vmSize": "Standard_D2s_v3", "created": "2021-06-01T12:00:00Z" },
+			{ "vmName": "web-02", "powerstate": "PowerState/running", "osType": "Linux", "location": "canadaeast", "vmSize": "Standard_D2s_v3", "created": "2019-03-15T08:30:00Z" },
+			{ "vmName": "ad-01", "powerstate": "PowerState/deallocated", "osType": "Windows", "location": "canadacentral", "vmSize": "Standard_B2ms", "created": "2023-11-20T00:00:00Z" },
+			{ "vmName": "tmp-01", "osType": "Linux", "location": "canadacentral", "vmSize": "Standard_B2ms" }
+		]))
+		.unwrap();
+
+		let counts = |pairs: &[(&str, usize)]| -> BTreeMap<String, usize> {
+			pairs.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+		};
+
+		let summary = summarize(&vms);
+		assert_eq!(summary.total, 4);
+		assert_eq!(
+			summary.power_states,
+			counts(&[("running", 2), ("deallocated", 1), ("unknown", 1)])
+		);
+		assert_eq!(summary.os_types, counts(&[("Linux", 3), ("Windows", 1)]));
+		assert_eq!(
+			summary.locations,
+			counts(&[("canadacentral", 3), ("canadaeast", 1)])
+		);
+		assert_eq!(
+			summary.sizes,
+			counts(&[("Standard_D2s_v3", 2), ("Standard_B2ms", 2)])
+		);
+
+		let oldest = summary.oldest.as_ref().unwrap();
+		assert_eq!(oldest.vm_name.as_deref(), Some("web-02"));
+		assert_eq!(oldest.created, "2019-03-15T08:30:00Z");
+		let newest = summary.newest.as_ref().unwrap();
+		assert_eq!(newest.vm_name.as_deref(), Some("ad-01"));
+
+		let report = summary.to_string();
+		assert!(report.starts_with("4 VMs\npower state: deallocated=1, running=2, unknown=1"));
+		assert!(report
+			.ends_with("oldest: web-02 (2019-03-15T08:30:00Z)\nnewest: ad-01 (2023-11-20T00:00:00Z)"));
+
+		let json = serde_json::to_value(&summary).unwrap();
+		assert_eq!(json["os_types"]["Windows"], 1);
+		assert_eq!(json["newest"]["vm_name"], "ad-01");
+	}
+
+	#[test]
+	fn empty_summary_has_no_oldest_or_newest() {
+		use super::summarize;
+
+		let summary = summarize(&[]);
+		assert_eq!(summary.total, 0);
+		assert!(summary.oldest.is_none() && summary.newest.is_none());
+		assert_eq!(summary.to_string().lines().next(), Some("0 VMs"));
+	}
+}
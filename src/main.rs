@@ -1,58 +1,155 @@
 mod cli;
+mod compress;
+mod config;
 mod credentials;
+mod diff;
+#[cfg(feature = "parquet")]
+mod export;
+mod inventory;
+mod output;
 mod util;
+mod webhook;
 
+use std::fs;
+use std::io::{IsTerminal, Write};
 use std::process;
+use std::time::Duration;
 
 use clap::Parser;
-use lib_vminfo::error::auth;
+use lib_vminfo::audit::AuditLog;
+use lib_vminfo::caching::redis_cache::{VMResultsCacheRedis, DEFAULT_TTL_JITTER_PERCENT};
+use lib_vminfo::caching::response_cache::ResponseCache;
+use lib_vminfo::cloud::AzureEnvironment;
+use lib_vminfo::error::{auth, Kind};
+use lib_vminfo::persistance::{FileTokenStore, PersistantStorage};
+use lib_vminfo::query::{QueryOptions, OUTPUT_COLUMNS};
+use lib_vminfo::transport::{self, DEFAULT_MIN_TLS_VERSION};
 use lib_vminfo::vm::VirtualMachine;
-use lib_vminfo::{auth::Method, error::AuthErrorKind};
+use lib_vminfo::{
+	auth::{ClientCertificate, Method},
+	error::AuthErrorKind,
+};
 
-use cli::Cli;
+use cli::{Cli, OutputFormat};
+use compress::OutputWriter;
+use config::AppConfig;
 use lib_vminfo::LocalClient;
-use serde::{Deserialize, Serialize};
-use util::get_vminfo_from_remote;
-
-use crate::util::ask_credentials;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct AppConfig {
-	use_cache: bool,
-	redis_host: String,
-	redis_port: u16,
-	redis_password: Option<String>,
-	redis_use_tls: bool,
-	subscriptions: Option<Vec<String>>,
-	log_level: String,
-}
+use util::{
+	curl_command, describe_login, get_vminfo_from_remote, inaccessible_subscriptions, query_options,
+};
+
+use crate::util::{ask_credentials, ask_valid_credentials, confirm_print_token, LOGIN_ATTEMPTS};
+
+const APP_NAME: &str = "azure-vminfo";
 
-impl Default for AppConfig {
-	fn default() -> Self {
-		Self {
-			use_cache: true,
-			redis_host: "127.0.0.1".to_string(),
-			redis_port: 6379u16,
-			redis_password: None,
-			redis_use_tls: false,
-			subscriptions: None,
-			log_level: "INFO".to_string(),
+fn main() {
+	let args: Cli = Cli::parse();
+	let json_errors = args.json_errors;
+
+	if let Err(err) = run(args) {
+		if json_errors {
+			eprintln!("{}", output::json_error(&err));
+		} else {
+			eprintln!("Error: {:?}", err);
 		}
+
+		process::exit(1)
 	}
 }
 
-fn main() -> anyhow::Result<()> {
-	const APP_NAME: &str = "azure-vminfo";
-	let config: AppConfig = confy::load(APP_NAME, "config")?;
-	let args: Cli = Cli::parse();
+fn run(args: Cli) -> anyhow::Result<()> {
+	if let Some(snapshots) = &args.diff {
+		let result = diff::diff(
+			&diff::load_snapshot(&snapshots[0])?,
+			&diff::load_snapshot(&snapshots[1])?,
+		)?;
+		if !args.quiet {
+			eprintln!("{}", result);
+		}
+
+		let mut out = OutputWriter::create(args.output_file.as_deref(), args.compress)?;
+		writeln!(out, "{}", output::to_string_pretty(&result, &args.indent)?)?;
+		return out.finish();
+	}
 
-	let client: LocalClient;
+	let config: AppConfig =
+		AppConfig::load(APP_NAME, args.config.as_deref())?.for_profile(args.profile.as_deref());
+	let min_tls_version = match &config.min_tls_version {
+		Some(v) => transport::parse_tls_version(v)?,
+		None => DEFAULT_MIN_TLS_VERSION,
+	};
+	let poll_interval = config.device_code_poll_secs.map(Duration::from_secs);
+	// service principals use the v1.0 token endpoint unless the v2.0 endpoint (as used by device code login) is enabled
+	let use_v2_endpoint = config.use_v2_token_endpoint.unwrap_or(false);
+	let azure_environment: AzureEnvironment = match &config.azure_environment {
+		Some(name) => name.parse().map_err(|err: String| anyhow::anyhow!(err))?,
+		None => AzureEnvironment::Public,
+	};
+
+	if args.dry_run_auth {
+		let stored = FileTokenStore::new(APP_NAME, args.profile.as_deref())
+			.and_then(|store| store.read())
+			.ok();
+		println!(
+			"{}",
+			describe_login(&args, stored.as_ref(), use_v2_endpoint)
+		);
+
+		return Ok(());
+	}
+
+	let mut client: LocalClient;
 	if args.perform_login {
-		if args.use_service_principal {
-			let creds = ask_credentials(Method::ClientCredentials)?;
+		if let Some(certificate_path) = &args.certificate {
+			// the certificate is used to sign in, so no client secret is asked for (or stored)
+			let creds = ask_valid_credentials(
+				Method::ClientCertificate,
+				LOGIN_ATTEMPTS,
+				ask_credentials,
+				|_| Ok(()),
+			)?;
+			let _ = LocalClient::new(
+				APP_NAME,
+				args.profile.as_deref(),
+				&creds.tenant_id,
+				&creds.client_id,
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+				Some(azure_environment),
+			)?
+			.with_min_tls_version(min_tls_version)?
+			.with_v2_endpoint(use_v2_endpoint)
+			.with_client_certificate(Some(ClientCertificate::new(
+				certificate_path,
+				args.certificate_key.as_deref(),
+			)))
+			.login_client_credentials_cert(true)?;
+		} else if args.use_service_principal {
+			let creds = ask_valid_credentials(
+				Method::ClientCredentials,
+				LOGIN_ATTEMPTS,
+				ask_credentials,
+				|c| {
+					LocalClient::validate_credentials(
+						&c.tenant_id,
+						&c.client_id,
+						c.client_secret.as_deref().unwrap_or(""),
+						min_tls_version,
+						use_v2_endpoint,
+						azure_environment,
+					)
+				},
+			)?;
+			println!("credentials validated");
 			if config.use_cache {
 				let _ = LocalClient::new(
 					APP_NAME,
+					args.profile.as_deref(),
 					&creds.tenant_id,
 					&creds.client_id,
 					creds.client_secret,
@@ -60,12 +157,17 @@ fn main() -> anyhow::Result<()> {
 					Some(config.redis_port),
 					config.redis_password,
 					Some(config.redis_use_tls),
+					config.redis_db,
 					None,
+					Some(azure_environment),
 				)?
+				.with_min_tls_version(min_tls_version)?
+				.with_v2_endpoint(use_v2_endpoint)
 				.login_client_credentials(true)?;
 			} else {
 				let _ = LocalClient::new(
 					APP_NAME,
+					args.profile.as_deref(),
 					&creds.tenant_id,
 					&creds.client_id,
 					creds.client_secret,
@@ -74,14 +176,22 @@ fn main() -> anyhow::Result<()> {
 					None,
 					None,
 					None,
+					None,
+					Some(azure_environment),
 				)?
+				.with_min_tls_version(min_tls_version)?
+				.with_v2_endpoint(use_v2_endpoint)
 				.login_client_credentials(true)?;
 			}
 		} else {
-			let creds = ask_credentials(Method::DeviceCode)?;
+			let creds =
+				ask_valid_credentials(Method::DeviceCode, LOGIN_ATTEMPTS, ask_credentials, |_| {
+					Ok(())
+				})?;
 			if config.use_cache {
 				let _ = LocalClient::new(
 					APP_NAME,
+					args.profile.as_deref(),
 					&creds.tenant_id,
 					&creds.client_id,
 					creds.client_secret,
@@ -89,12 +199,17 @@ fn main() -> anyhow::Result<()> {
 					Some(config.redis_port),
 					config.redis_password,
 					Some(config.redis_use_tls),
+					config.redis_db,
 					None,
+					Some(azure_environment),
 				)?
+				.with_min_tls_version(min_tls_version)?
+				.with_poll_interval(poll_interval)
 				.login_device_code(true)?;
 			} else {
 				let _ = LocalClient::new(
 					APP_NAME,
+					args.profile.as_deref(),
 					&creds.tenant_id,
 					&creds.client_id,
 					creds.client_secret,
@@ -103,7 +218,11 @@ fn main() -> anyhow::Result<()> {
 					None,
 					None,
 					None,
+					None,
+					Some(azure_environment),
 				)?
+				.with_min_tls_version(min_tls_version)?
+				.with_poll_interval(poll_interval)
 				.login_device_code(true)?;
 			}
 		}
@@ -112,46 +231,396 @@ fn main() -> anyhow::Result<()> {
 		process::exit(0)
 	} else if args.perform_logout {
 		println!("clearing stored credentials");
-		LocalClient::new(APP_NAME, "", "", None, None, None, None, None, None)?
+		LocalClient::new(
+			APP_NAME,
+			args.profile.as_deref(),
+			"",
+			"",
+			None,
+			None,
+			None,
+			None,
+			None,
+			None,
+			None,
+			Some(azure_environment),
+		)?
 			.clear_credential_cache()?;
 		println!("stored credentials have been removed and client has been deauthenticated");
 
+		process::exit(0)
+	} else if args.export_credentials {
+		let exported = LocalClient::from_store(
+			APP_NAME,
+			args.profile.as_deref(),
+			None,
+			None,
+			None,
+			None,
+			None,
+		)?
+		.export_credentials()?;
+
+		eprintln!("WARNING: the exported credentials contain live secrets and access tokens. Anyone with this data can authenticate as you. Store it securely and delete it once imported");
+		println!("{}", exported);
+
+		process::exit(0)
+	} else if args.print_token {
+		confirm_print_token()?;
+		let token = LocalClient::from_store(
+			APP_NAME,
+			args.profile.as_deref(),
+			None,
+			None,
+			None,
+			None,
+			None,
+		)?
+		.with_min_tls_version(min_tls_version)?
+		.with_v2_endpoint(use_v2_endpoint)
+		.with_azure_environment(azure_environment)
+		.with_explain_auth(args.explain_auth)
+		.valid_access_token()?;
+
+		eprintln!("WARNING: the access token grants access to Azure as you until it expires. Do not share or log it");
+		println!("{}", token);
+
+		process::exit(0)
+	} else if let Some(import_path) = &args.import_credentials {
+		let data = fs::read_to_string(import_path)?;
+		LocalClient::new(
+			APP_NAME,
+			args.profile.as_deref(),
+			"",
+			"",
+			None,
+			None,
+			None,
+			None,
+			None,
+			None,
+			None,
+			Some(azure_environment),
+		)?
+			.import_credentials(&data)?;
+		println!("credentials imported successfully. remember to delete '{}'", import_path);
+
 		process::exit(0)
 	}
 
-	if config.use_cache {
+	if args.azure_cli {
+		// the Azure CLI keeps its own tokens, so the stored credentials are neither read nor replaced
+		client = LocalClient::new(
+			APP_NAME,
+			args.profile.as_deref(),
+			"",
+			"",
+			None,
+			None,
+			None,
+			None,
+			None,
+			None,
+			None,
+			Some(azure_environment),
+		)?
+		.login_azure_cli(true)?;
+	} else if config.use_cache {
 		client = match LocalClient::from_store(
 			APP_NAME,
+			args.profile.as_deref(),
 			Some(config.redis_host.as_str()),
 			Some(config.redis_port),
-			config.redis_password,
+			config.redis_password.clone(),
 			Some(config.redis_use_tls),
+			config.redis_db,
 		) {
 			Ok(c) => c,
-			Err(_) => {
-				return Err(auth(
-					None::<lib_vminfo::error::Error>,
-					AuthErrorKind::MissingToken,
-					"missing credentials for client. re-run with '--login' to authenticate",
-				))?
-			}
+			Err(err) => return Err(stored_credentials_error(err)),
 		}
 	} else {
-		client = match LocalClient::from_store(APP_NAME, None, None, None, None) {
+		client = match LocalClient::from_store(
+			APP_NAME,
+			args.profile.as_deref(),
+			None,
+			None,
+			None,
+			None,
+			None,
+		) {
 			Ok(c) => c,
-			Err(_) => {
-				return Err(auth(
-					None::<lib_vminfo::error::Error>,
-					AuthErrorKind::MissingToken,
-					"missing credentials for client. re-run with '--login' to authenticate",
-				))?
+			Err(err) => return Err(stored_credentials_error(err)),
+		}
+	}
+
+	client = client.with_explain_auth(args.explain_auth);
+
+	// fail fast (before any request is made) when the stored credentials can not be used without logging in again. the
+	// Azure CLI token was just obtained, so there are no stored credentials to check
+	if !args.azure_cli && !client.is_authenticated() {
+		return Err(auth(
+			None::<lib_vminfo::error::Error>,
+			AuthErrorKind::TokenExpired,
+			"stored credentials have expired. re-run with '--login' to authenticate",
+		)
+		.into());
+	}
+
+	// tag columns are output alongside the other fields of each VM, so they can not share a name with one
+	if let Some(tag) = args
+		.tag_columns
+		.iter()
+		.find(|t| OUTPUT_COLUMNS.contains(&t.as_str()))
+	{
+		return Err(anyhow::anyhow!(
+			"'--tag-column {}' has the same name as an output field",
+			tag
+		));
+	}
+
+	if config.use_cache {
+		let redis_cache = redis_cache(&config)?
+			.with_ttl(config.cache_ttl_secs.map(Duration::from_secs))?
+			.with_ttl_jitter(
+				config
+					.cache_ttl_jitter_percent
+					.unwrap_or(DEFAULT_TTL_JITTER_PERCENT),
+			);
+		client = client.with_result_cache(Some(redis_cache.clone()));
+
+		// identical queries repeated within the TTL are answered with the previous response
+		if let Some(ttl) = config.response_cache_ttl_secs {
+			client = client.with_response_cache(Some(ResponseCache::new(
+				redis_cache,
+				Duration::from_secs(ttl),
+			)));
+		}
+	}
+
+	// denied subscriptions are never queried, even when configured
+	let options = QueryOptions {
+		subscription_denylist: config.subscription_denylist.clone().unwrap_or_default(),
+		..query_options(&args)
+	};
+	let (denied, subscriptions): (Vec<String>, Vec<String>) = config
+		.subscriptions
+		.clone()
+		.unwrap_or_default()
+		.into_iter()
+		.partition(|s| options.denies_subscription(s));
+	for subscription in &denied {
+		eprintln!(
+			"WARNING: subscription {} is on the subscription_denylist and will not be queried",
+			subscription
+		);
+	}
+	if !denied.is_empty() && subscriptions.is_empty() {
+		return Err(anyhow::anyhow!(
+			"every configured subscription is on the subscription_denylist"
+		));
+	}
+
+	client = client
+		.with_subscriptions(config.subscriptions.as_ref().map(|_| subscriptions))
+		.with_query_options(options);
+
+	// a mock of Resource Graph (ie: for testing) is queried in place of the Azure cloud when an endpoint is configured,
+	// either by the VMINFO_MANAGEMENT_ENDPOINT environment variable or the config
+	let management_endpoint = std::env::var("VMINFO_MANAGEMENT_ENDPOINT")
+		.ok()
+		.filter(|endpoint| !endpoint.is_empty())
+		.or_else(|| config.management_endpoint.clone());
+
+	client = client
+		.with_min_tls_version(min_tls_version)?
+		.with_poll_interval(poll_interval)
+		.with_v2_endpoint(use_v2_endpoint)
+		.with_azure_environment(azure_environment)
+		.with_management_endpoint(management_endpoint)?
+		.with_max_buffered_vms(args.max_vms);
+
+	if let Some(clock_skew_secs) = config.clock_skew_secs {
+		client = client.with_clock_skew(Duration::from_secs(clock_skew_secs));
+	}
+
+	if let Some(extra_headers) = &config.extra_headers {
+		client = client.with_headers(extra_headers.clone().into_iter().collect())?;
+	}
+
+	if let Some(audit_log_path) = &config.audit_log_path {
+		client = client.with_audit_log(AuditLog::new(audit_log_path));
+	}
+
+	if args.emit_curl {
+		println!("{}", curl_command(&client, &args)?);
+		return Ok(());
+	}
+
+	if args.list_subscriptions {
+		let visible = client.subscriptions_for_identity()?;
+		for subscription in
+			inaccessible_subscriptions(&config.subscriptions.clone().unwrap_or_default(), &visible)
+		{
+			eprintln!(
+				"WARNING: configured subscription {} is not accessible to the signed in identity",
+				subscription
+			);
+		}
+		println!("{}", output::to_string_pretty(&visible, &args.indent)?);
+		return Ok(());
+	}
+
+	let (mut virtual_machines, summary) = get_vminfo_from_remote(&client, &args)?;
+	client.flush()?;
+	if !args.quiet || !args.facets.is_empty() {
+		eprintln!("{}", summary);
+	}
+	for failure in &summary.failures {
+		eprintln!("WARNING: failed to query subscription {}", failure);
+	}
+	if args.show_pagination || summary.more_pages {
+		eprintln!("{}", summary.pagination());
+	}
+
+	if let Some(snapshot) = &args.since_snapshot {
+		let matched = virtual_machines.len();
+		virtual_machines =
+			diff::changed_since(&diff::load_snapshot(snapshot)?, virtual_machines, |vm| {
+				output::json_record(vm, args.fields.as_deref(), args.case)
+			})?;
+		if !args.quiet {
+			eprintln!(
+				"{} of {} VMs are new or changed since '{}'",
+				virtual_machines.len(),
+				matched,
+				snapshot
+			);
+		}
+	}
+
+	// the summary report is written in place of the VMs
+	if args.summary {
+		let report = inventory::summarize(&virtual_machines);
+		if !args.quiet {
+			eprintln!("{}", report);
+		}
+
+		let mut out = OutputWriter::create(args.output_file.as_deref(), args.compress)?;
+		writeln!(out, "{}", output::to_string_pretty(&report, &args.indent)?)?;
+		return out.finish();
+	}
+
+	let virtual_machines: &[VirtualMachine] = if args.first {
+		&virtual_machines[..virtual_machines.len().min(1)]
+	} else {
+		&virtual_machines
+	};
+
+	let result = match args.format {
+		OutputFormat::Json => output::json(
+			&output::records(virtual_machines, args.fields.as_deref())?,
+			args.first,
+			args.case,
+			&args.indent,
+		)?,
+		OutputFormat::Ansible => {
+			output::to_string_pretty(&output::ansible_inventory(virtual_machines)?, &args.indent)?
+		}
+		OutputFormat::Line => output::lines(virtual_machines)?,
+		OutputFormat::Csv => output::csv(virtual_machines)?,
+		OutputFormat::Yaml => output::yaml(
+			virtual_machines,
+			args.fields.as_deref(),
+			args.case,
+			args.show_extensions,
+			args.show_tags,
+		)?,
+		OutputFormat::Table => output::table(
+			virtual_machines,
+			args.fields.as_deref(),
+			&args.tag_columns,
+			args.wide,
+			// power states are only colored when written straight to a terminal
+			args.output_file.is_none()
+				&& args.webhook.is_none()
+				&& args.compress.is_none()
+				&& std::io::stdout().is_terminal(),
+		)?,
+		OutputFormat::Map => output::map(
+			virtual_machines,
+			args.fields.as_deref(),
+			args.strict,
+			args.case,
+			&args.indent,
+		)?,
+		#[cfg(feature = "parquet")]
+		OutputFormat::Parquet => {
+			let path = args
+				.output_file
+				.as_deref()
+				.ok_or_else(|| anyhow::anyhow!("'--format parquet' requires '--output-file'"))?;
+			if args.webhook.is_some() {
+				return Err(anyhow::anyhow!("'--webhook' can not be used with '--format parquet'"));
 			}
+			let out = OutputWriter::create(Some(path), args.compress)?;
+			return export::write_parquet(virtual_machines, args.fields.as_deref(), out)?.finish();
+		}
+	};
+
+	if let Some(url) = &args.webhook {
+		webhook::post_results(
+			url,
+			args.webhook_auth.as_deref(),
+			args.format.content_type(),
+			&result,
+			min_tls_version,
+		)?;
+		if args.webhook_only {
+			return Ok(());
 		}
 	}
 
-	let virtual_machines: Vec<VirtualMachine> = get_vminfo_from_remote(&client, &args)?;
-	let result = serde_json::to_string_pretty(&virtual_machines)?;
+	let mut out = OutputWriter::create(args.output_file.as_deref(), args.compress)?;
+	writeln!(out, "{}", result)?;
+	out.finish()
+}
+
+///
+/// describes why the stored credentials could not be loaded. a corrupt token file is reported as such (logging in
+/// again replaces it), while any other failure is treated as the credentials being missing
+///
+fn stored_credentials_error(err: lib_vminfo::error::Error) -> anyhow::Error {
+	match err.kind() {
+		Kind::AuthenticationError(AuthErrorKind::CorruptTokenStore) => anyhow::Error::from(err)
+			.context("stored credentials are corrupt. re-run with '--login' to replace them"),
+		_ => auth(
+			None::<lib_vminfo::error::Error>,
+			AuthErrorKind::MissingToken,
+			"missing credentials for client. re-run with '--login' to authenticate",
+		)
+		.into(),
+	}
+}
 
-	println!("{}", result);
-	Ok(())
+///
+/// creates the redis cache described by the config. a Redis Sentinel deployment (when configured) is used instead of
+/// the single redis host
+///
+fn redis_cache(config: &AppConfig) -> anyhow::Result<VMResultsCacheRedis> {
+	Ok(match (&config.redis_sentinels, &config.redis_sentinel_master) {
+		(Some(sentinels), Some(master)) => VMResultsCacheRedis::with_sentinel(
+			sentinels,
+			master,
+			config.redis_password.clone(),
+			config.redis_use_tls,
+			config.redis_db,
+		)?,
+		_ => VMResultsCacheRedis::new(
+			&config.redis_host,
+			config.redis_port,
+			config.redis_password.clone(),
+			config.redis_use_tls,
+			config.redis_db,
+		)?,
+	})
 }
@@ -0,0 +1,881 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{json, ser::PrettyFormatter, Map, Serializer, Value};
+
+use crate::cli::KeyCase;
+use lib_vminfo::error::{Error, Kind};
+use lib_vminfo::vm::{self, VirtualMachine};
+
+///
+/// the indentation of pretty printed JSON when none is requested
+///
+pub const DEFAULT_INDENT: &str = "  ";
+
+///
+/// Parses a JSON indentation: a number of spaces (ie: 4) or 'tab'
+///
+pub fn parse_indent(value: &str) -> Result<String, String> {
+	if value.eq_ignore_ascii_case("tab") {
+		return Ok("\t".to_string());
+	}
+
+	value
+		.parse::<usize>()
+		.map(|spaces| " ".repeat(spaces))
+		.map_err(|_| format!("'{}' is neither a number of spaces nor 'tab'", value))
+}
+
+///
+/// pretty prints a value as JSON, indenting each level with the provided indent (ie: DEFAULT_INDENT)
+///
+pub fn to_string_pretty<T: Serialize + ?Sized>(value: &T, indent: &str) -> Result<String> {
+	let mut ser =
+		Serializer::with_formatter(Vec::new(), PrettyFormatter::with_indent(indent.as_bytes()));
+	value.serialize(&mut ser)?;
+
+	Ok(String::from_utf8(ser.into_inner())?)
+}
+
+///
+/// Serializes each VM keeping only the requested fields, when provided (vmId is always kept). Any extra columns (ie:
+/// named tags) are output as top-level fields and are always kept
+///
+pub fn records(vms: &[VirtualMachine], fields: Option<&[String]>) -> Result<Vec<Value>> {
+	let mut records: Vec<Value> = Vec::new();
+
+	for vm in vms {
+		if let Value::Object(mut o) = serde_json::to_value(vm)? {
+			let extra_columns = match o.remove("extraColumns") {
+				Some(Value::Object(extra_columns)) => extra_columns,
+				_ => Map::new(),
+			};
+
+			if let Some(fields) = fields {
+				o.retain(|k, _| k == "vmId" || fields.iter().any(|f| f == k));
+			}
+			for (column, value) in extra_columns {
+				o.entry(column).or_insert(value);
+			}
+
+			records.push(Value::Object(o));
+		}
+	}
+
+	Ok(records)
+}
+
+///
+/// Renders records as pretty JSON with keys in the requested case and the provided indent. When `first` is set, only the first record is
+/// rendered as a single object (not wrapped in an array) and it is an error for there to be no records
+///
+pub fn json<T: Serialize>(
+	records: &[T],
+	first: bool,
+	case: KeyCase,
+	indent: &str,
+) -> Result<String> {
+	if first && records.is_empty() {
+		return Err(anyhow!("no virtual machines matched the query"));
+	}
+	let records: &[T] = if first { &records[..1] } else { records };
+
+	match case {
+		KeyCase::Camel => pretty(records, first, indent),
+		KeyCase::Snake => {
+			let rekeyed = records
+				.iter()
+				.map(|r| Ok(snake_case_keys(serde_json::to_value(r)?)))
+				.collect::<Result<Vec<Value>>>()?;
+			pretty(&rekeyed, first, indent)
+		}
+	}
+}
+
+///
+/// renders a VM as it is written by '--format json' with the same fields and key case, for comparing live results with
+/// a saved snapshot
+///
+pub fn json_record(vm: &VirtualMachine, fields: Option<&[String]>, case: KeyCase) -> Result<Value> {
+	let record = records(std::slice::from_ref(vm), fields)?
+		.pop()
+		.unwrap_or(Value::Null);
+
+	Ok(match case {
+		KeyCase::Camel => record,
+		KeyCase::Snake => snake_case_keys(record),
+	})
+}
+
+///
+/// pretty prints records as a JSON array, or the first record as an object when `single` is set
+///
+fn pretty<T: Serialize>(records: &[T], single: bool, indent: &str) -> Result<String> {
+	if single {
+		return to_string_pretty(&records[0], indent);
+	}
+
+	to_string_pretty(records, indent)
+}
+
+///
+/// Renders VMs as a pretty JSON object keyed by lowercased VM name, keeping only the requested fields (when provided)
+/// and with keys in the requested case and the provided indent. Duplicate names keep the last VM unless `strict` is set
+///
+pub fn map(
+	vms: &[VirtualMachine],
+	fields: Option<&[String]>,
+	strict: bool,
+	case: KeyCase,
+	indent: &str,
+) -> Result<String> {
+	let mut by_name: Map<String, Value> = Map::new();
+
+	for (name, vm) in vm::index_by_name(vms.to_vec(), strict)? {
+		let mut value = records(&[vm], fields)?.remove(0);
+		if case == KeyCase::Snake {
+			value = snake_case_keys(value);
+		}
+
+		by_name.insert(name, value);
+	}
+
+	to_string_pretty(&by_name, indent)
+}
+
+///
+/// Renders VMs as a YAML list, keeping only the requested fields (when provided) and with keys in the requested case.
+/// The extensions and tags of VMs which have none are empty collections when requested, rather than being omitted, so
+/// that every VM has the same keys
+///
+pub fn yaml(
+	vms: &[VirtualMachine],
+	fields: Option<&[String]>,
+	case: KeyCase,
+	show_extensions: bool,
+	show_tags: bool,
+) -> Result<String> {
+	let requested = |key: &str| fields.is_none_or(|f| f.iter().any(|f| f == key));
+
+	let mut items: Vec<Value> = Vec::new();
+	for mut record in records(vms, fields)? {
+		if let Value::Object(o) = &mut record {
+			if show_extensions && requested("extensions") {
+				o.entry("extensions").or_insert_with(|| json!([]));
+			}
+			if show_tags && requested("tags") {
+				o.entry("tags").or_insert_with(|| json!({}));
+			}
+		}
+		if case == KeyCase::Snake {
+			record = snake_case_keys(record);
+		}
+
+		items.push(record);
+	}
+
+	// serde_yaml starts each document with a '---' marker, which a single document doesn't need
+	let out = serde_yaml::to_string(&items)?;
+	let out = out.strip_prefix("---\n").unwrap_or(&out);
+
+	Ok(out.trim_end_matches('\n').to_string())
+}
+
+///
+/// the widest (in characters) each padded column of the line format may be: name, private IP, size and power state
+///
+const LINE_COLUMN_WIDTHS: [usize; 4] = [32, 15, 24, 12];
+
+///
+/// Renders one concise line per VM (ie: `web-01  10.0.0.4  Standard_D2s_v3  running  (eastus/prod-rg)`). Columns are
+/// aligned across VMs and values too wide for their column are truncated. Unknown values are shown as '-'
+///
+pub fn lines(vms: &[VirtualMachine]) -> Result<String> {
+	let mut rows: Vec<[String; 5]> = Vec::new();
+	for vm in vms {
+		let value = serde_json::to_value(vm)?;
+		let field = |key: &str| -> String {
+			match value.get(key).and_then(Value::as_str) {
+				Some(v) if !v.is_empty() && v != "0.0.0.0" => v.to_string(),
+				_ => "-".to_string(),
+			}
+		};
+
+		// power states are reported as codes (ie: PowerState/running)
+		let power_state = field("powerstate");
+		let power_state = power_state.rsplit('/').next().unwrap_or("-").to_string();
+
+		rows.push([
+			field("vmName"),
+			field("privateIp"),
+			field("vmSize"),
+			power_state,
+			format!("({}/{})", field("location"), field("rg")),
+		]);
+	}
+
+	let mut widths = [0usize; 4];
+	for row in &rows {
+		for (i, width) in widths.iter_mut().enumerate() {
+			*width = (*width)
+				.max(row[i].chars().count())
+				.min(LINE_COLUMN_WIDTHS[i]);
+		}
+	}
+
+	let lines: Vec<String> = rows
+		.iter()
+		.map(|row| {
+			let mut line: Vec<String> = widths
+				.iter()
+				.enumerate()
+				.map(|(i, width)| format!("{:<width$}", truncate(&row[i], *width), width = width))
+				.collect();
+			line.push(row[4].clone());
+			line.join("  ")
+		})
+		.collect();
+
+	Ok(lines.join("\n"))
+}
+
+///
+/// the columns of the CSV format, in order
+///
+const CSV_COLUMNS: [&str; 12] = [
+	"vmName",
+	"privateIp",
+	"osType",
+	"osName",
+	"osVersion",
+	"powerstate",
+	"vmSize",
+	"rg",
+	"sub",
+	"location",
+	"virtualNetwork",
+	"subnet",
+];
+
+///
+/// Renders VMs as CSV: a header row followed by one row per VM. Unknown values are empty cells and values containing
+/// a comma, quote or line break are quoted
+///
+pub fn csv(vms: &[VirtualMachine]) -> Result<String> {
+	let mut writer = csv::WriterBuilder::new()
+		.terminator(csv::Terminator::Any(b'\n'))
+		.from_writer(Vec::new());
+
+	writer.write_record(CSV_COLUMNS)?;
+	for vm in vms {
+		let value = serde_json::to_value(vm)?;
+		writer.write_record(CSV_COLUMNS.iter().map(
+			|key| match value.get(*key).and_then(Value::as_str) {
+				Some(v) if v != "0.0.0.0" => v,
+				_ => "",
+			},
+		))?;
+	}
+
+	let out = String::from_utf8(writer.into_inner().map_err(|err| err.into_error())?)?;
+
+	Ok(out.trim_end_matches('\n').to_string())
+}
+
+///
+/// the columns of the table format, unless limited by the requested fields
+///
+const TABLE_COLUMNS: [&str; 9] = [
+	"vmName",
+	"privateIp",
+	"powerstate",
+	"vmSize",
+	"osType",
+	"osName",
+	"osVersion",
+	"location",
+	"rg",
+];
+
+///
+/// the long columns which are only added to the table format when it is wide
+///
+const WIDE_TABLE_COLUMNS: [&str; 3] = ["sub", "subnetId", "vmId"];
+
+///
+/// Renders VMs as an aligned table with a header row, showing the requested fields (when provided) in place of the
+/// default columns, followed by any tag columns. Long columns (ie: vmId) are only added when `wide` is set. Power
+/// states are shown by name, and when `color` is set are green while running and red while stopped or deallocated
+///
+pub fn table(
+	vms: &[VirtualMachine],
+	fields: Option<&[String]>,
+	tag_columns: &[String],
+	wide: bool,
+	color: bool,
+) -> Result<String> {
+	let mut columns: Vec<&str> = match fields {
+		Some(fields) => fields.iter().map(String::as_str).collect(),
+		None => TABLE_COLUMNS.to_vec(),
+	};
+	if wide {
+		for column in WIDE_TABLE_COLUMNS {
+			if !columns.contains(&column) {
+				columns.push(column);
+			}
+		}
+	}
+	for column in tag_columns {
+		if !columns.contains(&column.as_str()) {
+			columns.push(column);
+		}
+	}
+
+	let mut rows: Vec<Vec<String>> = Vec::new();
+	for value in records(vms, None)? {
+		rows.push(
+			columns
+				.iter()
+				.map(|column| match value.get(*column) {
+					Some(Value::String(v)) if *column == "powerstate" => {
+						v.rsplit('/').next().unwrap_or_default().to_string()
+					}
+					Some(Value::String(v)) if v != "0.0.0.0" => v.to_string(),
+					Some(Value::String(_)) | Some(Value::Null) | None => String::new(),
+					Some(v) => v.to_string(),
+				})
+				.collect(),
+		);
+	}
+
+	let widths: Vec<usize> = columns
+		.iter()
+		.enumerate()
+		.map(|(i, column)| {
+			rows
+				.iter()
+				.map(|row| row[i].chars().count())
+				.fold(column.chars().count(), usize::max)
+		})
+		.collect();
+
+	let render = |cells: Vec<String>| -> String {
+		let line: Vec<String> = cells
+			.iter()
+			.zip(&widths)
+			.map(|(cell, width)| format!("{:<width$}", cell, width = width))
+			.collect();
+		line.join("  ").trim_end().to_string()
+	};
+
+	let mut lines: Vec<String> = vec![
+		render(columns.iter().map(|c| c.to_string()).collect()),
+		render(widths.iter().map(|w| "-".repeat(*w)).collect()),
+	];
+	for row in rows {
+		let mut line = render(row.clone());
+		if color {
+			if let Some(i) = columns.iter().position(|c| *c == "powerstate") {
+				line = colorize_power_state(&line, &row[i], &widths[..i]);
+			}
+		}
+		lines.push(line);
+	}
+
+	Ok(lines.join("\n"))
+}
+
+///
+/// colors the power state cell of a rendered table row (which follows the cells of the provided widths), green while
+/// running and red while stopped or deallocated
+///
+fn colorize_power_state(line: &str, power_state: &str, preceding_widths: &[usize]) -> String {
+	let code = match power_state {
+		"running" => "32",
+		"stopped" | "deallocated" => "31",
+		_ => return line.to_string(),
+	};
+
+	// every preceding cell is padded to its width and followed by two spaces
+	let start: usize = preceding_widths.iter().map(|w| w + 2).sum();
+	let start = line
+		.char_indices()
+		.nth(start)
+		.map(|(i, _)| i)
+		.unwrap_or(line.len());
+	let end = start + power_state.len();
+
+	format!(
+		"{}\x1b[{}m{}\x1b[0m{}",
+		&line[..start],
+		code,
+		&line[start..end],
+		&line[end..]
+	)
+}
+
+///
+/// shortens a value to at most `width` characters, marking a truncated value with a trailing '~'
+///
+fn truncate(value: &str, width: usize) -> String {
+	if value.chars().count() <= width {
+		return value.to_string();
+	}
+
+	let mut truncated: String = value.chars().take(width.saturating_sub(1)).collect();
+	truncated.push('~');
+	truncated
+}
+
+///
+/// converts a camelCase key to snake_case (ie: privateIp -> private_ip)
+///
+fn snake_case(key: &str) -> String {
+	let mut snake = String::with_capacity(key.len() + 4);
+	for c in key.chars() {
+		if c.is_ascii_uppercase() {
+			snake.push('_');
+			snake.push(c.to_ascii_lowercase());
+		} else {
+			snake.push(c);
+		}
+	}
+
+	snake
+}
+
+///
+/// re-keys the top-level fields of a serialized VM to snake_case. nested values (ie: tag names) are left untouched
+///
+fn snake_case_keys(value: Value) -> Value {
+	match value {
+		Value::Object(o) => Value::Object(o.into_iter().map(|(k, v)| (snake_case(&k), v)).collect()),
+		v => v,
+	}
+}
+
+///
+/// Describes an error as JSON ({"error": {"kind": ..., "message": ..., "status": ..., "oauth_error": ...}}) so failures can be
+/// parsed by scripts. The kind and HTTP status are taken from the vminfo error kind when available, and oauth_error is the
+/// OAuth2 error code (ie: consent_required) an authentication request was rejected with
+///
+pub fn json_error(err: &anyhow::Error) -> Value {
+	let vminfo_err = err.downcast_ref::<Error>();
+	let kind = match vminfo_err.map(|e| e.kind()) {
+		Some(Kind::ClientCreateError) => "client_config",
+		Some(Kind::AuthenticationError(_)) => "authentication",
+		Some(Kind::NoneFoundError) => "none_found",
+		Some(Kind::RequestError(_)) => "request",
+		Some(Kind::InvalidQueryError) => "invalid_query",
+		Some(Kind::ResultCacheError) => "caching",
+		Some(Kind::Other) | None => "other",
+	};
+	let status = vminfo_err.and_then(|e| e.status()).map(|s| s.as_u16());
+	let oauth_error = vminfo_err.and_then(|e| e.oauth_error_code());
+
+	json!({
+		"error": {
+			"kind": kind,
+			"message": err.to_string(),
+			"status": status,
+			"oauth_error": oauth_error,
+		}
+	})
+}
+
+///
+/// converts a value into a valid Ansible group name (lowercase alphanumerics and underscores)
+///
+fn group_name(prefix: &str, value: &str) -> String {
+	let name: String = value
+		.to_lowercase()
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+		.collect();
+
+	format!("{}_{}", prefix, name)
+}
+
+///
+/// Transforms a list of VMs into the JSON structure expected from an Ansible dynamic inventory script
+///
+/// hosts are keyed by VM name and grouped by OS type, location and resource group. Each host's attributes are
+/// available under `_meta.hostvars` with `ansible_host` set to the private IP of the VM.
+///
+pub fn ansible_inventory(vms: &[VirtualMachine]) -> Result<Value> {
+	let mut groups: Map<String, Value> = Map::new();
+	let mut hostvars: Map<String, Value> = Map::new();
+
+	for vm in vms {
+		let mut vars = match serde_json::to_value(vm)? {
+			Value::Object(o) => o,
+			_ => continue,
+		};
+
+		let hostname = match vars.get("vmName").and_then(|n| n.as_str()) {
+			Some(n) => n.to_string(),
+			None => continue,
+		};
+
+		if let Some(ip) = vars.get("privateIp").cloned() {
+			vars.insert("ansible_host".to_string(), ip);
+		}
+
+		for (prefix, key) in [("os", "osType"), ("location", "location"), ("rg", "rg")] {
+			if let Some(value) = vars.get(key).and_then(|v| v.as_str()) {
+				let group = groups
+					.entry(group_name(prefix, value))
+					.or_insert_with(|| json!({ "hosts": [] }));
+
+				if let Some(hosts) = group["hosts"].as_array_mut() {
+					hosts.push(Value::String(hostname.clone()));
+				}
+			}
+		}
+
+		hostvars.insert(hostname, Value::Object(vars));
+	}
+
+	let mut inventory: Map<String, Value> = Map::new();
+	inventory.insert(
+		"all".to_string(),
+		json!({ "children": groups.keys().cloned().collect::<Vec<String>>() }),
+	);
+	inventory.extend(groups);
+	inventory.insert("_meta".to_string(), json!({ "hostvars": hostvars }));
+
+	Ok(Value::Object(inventory))
+}
+
+#[cfg(test)]
+mod output_tests {
+	use crate::cli::KeyCase;
+	use lib_vminfo::vm::VirtualMachine;
+
+	fn fixture() -> Vec<VirtualMachine> {
+		serde_json::from_value(serde_json::json!([
+			{ "vmName": "web-01", "privateIp": "10.0.0.4", "osType": "Linux", "location": "canadacentral", "rg": "prod-rg" },
+			{ "vmName": "web-02", "privateIp": "10.0.0.5", "osType": "Linux", "location": "canadaeast", "rg": "prod-rg" },
+			{ "vmName": "ad-01", "privateIp": "10.0.1.4", "osType": "Windows", "location": "canadacentral", "rg": "infra-rg" }
+		]))
+		.unwrap()
+	}
+
+	#[test]
+	fn first_renders_a_single_object() {
+		use super::json;
+
+		let vms = fixture();
+
+		let first: serde_json::Value =
+			serde_json::from_str(&json(&vms, true, KeyCase::Camel, "  ").unwrap()).unwrap();
+		assert!(first.is_object());
+		assert_eq!(first["vmName"], "web-01");
+
+		let all: serde_json::Value =
+			serde_json::from_str(&json(&vms, false, KeyCase::Camel, "  ").unwrap()).unwrap();
+		assert_eq!(all.as_array().unwrap().len(), 3);
+
+		assert!(json::<VirtualMachine>(&[], true, KeyCase::Camel, "  ").is_err());
+	}
+
+	#[test]
+	fn snake_and_camel_case_hold_the_same_data() {
+		use super::json;
+
+		let vms = fixture();
+
+		let camel: Vec<VirtualMachine> =
+			serde_json::from_str(&json(&vms, false, KeyCase::Camel, "  ").unwrap()).unwrap();
+		let snake_out = json(&vms, false, KeyCase::Snake, "  ").unwrap();
+		let snake: Vec<VirtualMachine> = serde_json::from_str(&snake_out).unwrap();
+
+		assert!(snake_out.contains("\"private_ip\": \"10.0.0.4\""));
+		assert!(!snake_out.contains("privateIp"));
+		assert_eq!(
+			serde_json::to_value(&camel).unwrap(),
+			serde_json::to_value(&snake).unwrap()
+		);
+		assert_eq!(
+			serde_json::to_value(&snake).unwrap(),
+			serde_json::to_value(&vms).unwrap()
+		);
+	}
+
+	#[test]
+	fn indent_is_configurable() {
+		use super::{json, parse_indent};
+
+		let vms = fixture();
+		let four = json(&vms, true, KeyCase::Camel, &parse_indent("4").unwrap()).unwrap();
+		assert!(four.contains("\n    \"vmName\": \"web-01\""));
+		assert!(!four.contains("\n     \""));
+
+		let tab = json(&vms, false, KeyCase::Camel, &parse_indent("tab").unwrap()).unwrap();
+		assert!(tab.starts_with("[\n\t{\n\t\t\""));
+		assert!(tab.contains("\n\t\t\"vmName\": \"web-02\""));
+
+		let default = json(&vms, true, KeyCase::Camel, super::DEFAULT_INDENT).unwrap();
+		assert_eq!(default, serde_json::to_string_pretty(&vms[0]).unwrap());
+
+		assert!(parse_indent("four").is_err());
+	}
+
+	#[test]
+	fn map_keyed_by_vm_name() {
+		use super::map;
+
+		let out: serde_json::Value =
+			serde_json::from_str(&map(&fixture(), None, false, KeyCase::Camel, "  ").unwrap()).unwrap();
+		let keys: Vec<&String> = out.as_object().unwrap().keys().collect();
+
+		assert_eq!(keys, vec!["ad-01", "web-01", "web-02"]);
+		assert_eq!(out["web-02"]["privateIp"], "10.0.0.5");
+
+		let fields = vec!["privateIp".to_string()];
+		let out: serde_json::Value =
+			serde_json::from_str(&map(&fixture(), Some(&fields), false, KeyCase::Snake, "  ").unwrap())
+				.unwrap();
+		assert_eq!(out["ad-01"]["private_ip"], "10.0.1.4");
+		assert!(out["ad-01"].get("os_type").is_none());
+	}
+
+	#[test]
+	fn extra_columns_are_top_level_fields() {
+		use super::records;
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmName": "web-01", "osType": "Linux", "extraColumns": { "env": "prod", "owner": "web-team" } },
+			{ "vmName": "web-02", "osType": "Linux", "extraColumns": { "env": "", "owner": "web-team" } }
+		]))
+		.unwrap();
+
+		let fields = vec!["vmName".to_string()];
+		let out = records(&vms, Some(&fields)).unwrap();
+
+		assert_eq!(out[0]["env"], "prod");
+		assert_eq!(out[1]["owner"], "web-team");
+		assert!(out[0].get("extraColumns").is_none());
+		assert!(out[0].get("osType").is_none());
+
+		let out = records(&vms, None).unwrap();
+		assert_eq!(out[1]["env"], "");
+		assert_eq!(out[1]["osType"], "Linux");
+	}
+
+	#[test]
+	fn csv_has_a_header_and_quoted_cells() {
+		use super::csv;
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmName": "web-01", "privateIp": "10.0.0.4", "osType": "Linux", "osName": "ubuntu", "osVersion": "22.04", "powerstate": "PowerState/running", "vmSize": "Standard_D2s_v3", "rg": "prod-rg", "sub": "Prod, Canada", "location": "canadacentral", "virtualNetwork": "prod-vnet", "subnet": "web" },
+			{ "vmName": "web-\"02\"", "privateIp": "0.0.0.0" }
+		]))
+		.unwrap();
+
+		let out = csv(&vms).unwrap();
+		let out: Vec<&str> = out.lines().collect();
+
+		assert_eq!(
+			out[0],
+			"vmName,privateIp,osType,osName,osVersion,powerstate,vmSize,rg,sub,location,virtualNetwork,subnet"
+		);
+		assert_eq!(
+			out[1],
+			"web-01,10.0.0.4,Linux,ubuntu,22.04,PowerState/running,Standard_D2s_v3,prod-rg,\"Prod, Canada\",canadacentral,prod-vnet,web"
+		);
+		assert_eq!(out[2], "\"web-\"\"02\"\"\",,,,,,,,,,,");
+	}
+
+	#[test]
+	fn table_is_aligned_and_omits_long_columns() {
+		use super::table;
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmId": "/subscriptions/sub-01/resourceGroups/prod-rg/providers/Microsoft.Compute/virtualMachines/web-01", "vmName": "web-01", "privateIp": "10.0.0.4", "powerstate": "PowerState/running", "rg": "prod-rg" },
+			{ "vmName": "batch-worker-01", "privateIp": "10.0.0.15", "powerstate": "PowerState/deallocated", "rg": "batch-rg" }
+		]))
+		.unwrap();
+		let fields = vec![
+			"vmName".to_string(),
+			"privateIp".to_string(),
+			"powerstate".to_string(),
+			"rg".to_string(),
+		];
+
+		let out = table(&vms, Some(&fields), &[], false, false).unwrap();
+		let out: Vec<&str> = out.lines().collect();
+		assert_eq!(out[0], "vmName           privateIp  powerstate   rg");
+		assert_eq!(out[1], "---------------  ---------  -----------  --------");
+		assert_eq!(out[2], "web-01           10.0.0.4   running      prod-rg");
+		assert_eq!(out[3], "batch-worker-01  10.0.0.15  deallocated  batch-rg");
+
+		let default = table(&vms, None, &[], false, false).unwrap();
+		assert!(!default.contains("vmId"));
+		assert!(!default.contains("/subscriptions/"));
+
+		let wide = table(&vms, None, &[], true, false).unwrap();
+		assert!(wide.lines().next().unwrap().ends_with("vmId"));
+		assert!(wide.contains("/virtualMachines/web-01"));
+
+		let colored = table(&vms, Some(&fields), &[], false, true).unwrap();
+		let colored: Vec<&str> = colored.lines().collect();
+		assert_eq!(
+			colored[2],
+			"web-01           10.0.0.4   \x1b[32mrunning\x1b[0m      prod-rg"
+		);
+		assert_eq!(
+			colored[3],
+			"batch-worker-01  10.0.0.15  \x1b[31mdeallocated\x1b[0m  batch-rg"
+		);
+	}
+
+	#[test]
+	fn table_shows_tag_columns() {
+		use super::table;
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmName": "web-01", "extraColumns": { "env": "prod" } },
+			{ "vmName": "batch-worker-01" }
+		]))
+		.unwrap();
+		let fields = vec!["vmName".to_string()];
+
+		let out = table(&vms, Some(&fields), &["env".to_string()], false, false).unwrap();
+		let out: Vec<&str> = out.lines().collect();
+
+		assert_eq!(out[0], "vmName           env");
+		assert_eq!(out[2], "web-01           prod");
+		assert_eq!(out[3], "batch-worker-01");
+	}
+
+	#[test]
+	fn yaml_keeps_empty_collections() {
+		use super::yaml;
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmId": "/vm/web-01", "vmName": "web-01", "privateIp": "10.0.0.4", "osVersion": "22.04", "created": "2024-01-23T08:15:00Z", "extensions": [{ "name": "OmsAgentForLinux", "version": "1.14" }], "tags": { "env": "prod: eu", "build": "0x1F", "limit": ".inf" } },
+			{ "vmId": "/vm/web-02", "vmName": "web-02", "privateIp": "10.0.0.5", "osVersion": "" }
+		]))
+		.unwrap();
+		let fields = vec![
+			"vmName".to_string(),
+			"privateIp".to_string(),
+			"osVersion".to_string(),
+			"created".to_string(),
+			"extensions".to_string(),
+			"tags".to_string(),
+		];
+
+		let out = yaml(&vms, Some(&fields), KeyCase::Camel, true, true).unwrap();
+		assert_eq!(
+			out,
+			[
+				"- created: \"2024-01-23T08:15:00Z\"",
+				"  extensions:",
+				"    - name: OmsAgentForLinux",
+				"      version: \"1.14\"",
+				"  osVersion: \"22.04\"",
+				"  privateIp: 10.0.0.4",
+				"  tags:",
+				"    build: \"0x1F\"",
+				"    env: \"prod: eu\"",
+				"    limit: \".inf\"",
+				"  vmId: /vm/web-01",
+				"  vmName: web-01",
+				"- created: ~",
+				"  extensions: []",
+				"  osVersion: ~",
+				"  privateIp: 10.0.0.5",
+				"  tags: {}",
+				"  vmId: /vm/web-02",
+				"  vmName: web-02",
+			]
+			.join("\n")
+		);
+
+		let snake = yaml(&vms, None, KeyCase::Snake, false, false).unwrap();
+		assert!(snake.contains("  private_ip: 10.0.0.5"));
+		assert!(!snake.contains("extensions: []"));
+
+		assert_eq!(yaml(&[], None, KeyCase::Camel, true, true).unwrap(), "[]");
+	}
+
+	#[test]
+	fn one_line_per_vm() {
+		use super::lines;
+
+		let vms: Vec<VirtualMachine> = serde_json::from_value(serde_json::json!([
+			{ "vmName": "web-01", "privateIp": "10.0.0.4", "vmSize": "Standard_D2s_v3", "powerstate": "PowerState/running", "location": "eastus", "rg": "prod-rg" },
+			{ "vmName": "a-very-long-virtual-machine-name-01", "privateIp": "10.0.10.14", "vmSize": "Standard_B1s", "location": "westus" }
+		]))
+		.unwrap();
+
+		let out = lines(&vms).unwrap();
+		let out: Vec<&str> = out.lines().collect();
+
+		assert_eq!(
+			out[0],
+			"web-01                            10.0.0.4    Standard_D2s_v3  running  (eastus/prod-rg)"
+		);
+		assert_eq!(
+			out[1],
+			"a-very-long-virtual-machine-nam~  10.0.10.14  Standard_B1s     -        (westus/-)"
+		);
+	}
+
+	#[test]
+	fn json_error_for_auth_failure() {
+		use super::json_error;
+		use lib_vminfo::error::{auth, AuthErrorKind, Error};
+
+		let err: anyhow::Error = auth(
+			None::<Error>,
+			AuthErrorKind::MissingToken,
+			"missing credentials for client. re-run with '--login' to authenticate",
+		)
+		.into();
+
+		let out = json_error(&err);
+
+		assert_eq!(out["error"]["kind"], "authentication");
+		assert_eq!(out["error"]["status"], 401);
+		assert!(out["error"]["oauth_error"].is_null());
+		assert!(out["error"]["message"]
+			.as_str()
+			.unwrap()
+			.contains("re-run with '--login'"));
+	}
+
+	#[test]
+	fn ansible_inventory_hostvars_and_groups() {
+		use super::ansible_inventory;
+
+		let inventory = ansible_inventory(&fixture()).unwrap();
+
+		assert_eq!(
+			inventory["_meta"]["hostvars"]["web-01"]["ansible_host"],
+			"10.0.0.4"
+		);
+		assert_eq!(inventory["_meta"]["hostvars"]["ad-01"]["osType"], "Windows");
+		assert_eq!(
+			inventory["os_linux"]["hosts"],
+			serde_json::json!(["web-01", "web-02"])
+		);
+		assert_eq!(
+			inventory["os_windows"]["hosts"],
+			serde_json::json!(["ad-01"])
+		);
+		assert_eq!(
+			inventory["location_canadacentral"]["hosts"],
+			serde_json::json!(["web-01", "ad-01"])
+		);
+		assert_eq!(
+			inventory["rg_prod_rg"]["hosts"],
+			serde_json::json!(["web-01", "web-02"])
+		);
+		assert!(inventory["all"]["children"]
+			.as_array()
+			.unwrap()
+			.contains(&serde_json::json!("rg_infra_rg")));
+	}
+}
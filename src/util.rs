@@ -1,13 +1,21 @@
 use crate::cli::Cli;
 use crate::credentials::CliCredentials;
 
-use anyhow::Result;
+use std::time::Duration;
 
-use lib_vminfo::auth::Method;
-use lib_vminfo::LocalClient;
+use anyhow::{anyhow, Result};
 
-use lib_vminfo::query::QueryResponse;
-use lib_vminfo::vm::VirtualMachine;
+use lib_vminfo::auth::{self, AzCredentials, Method};
+use lib_vminfo::caching::Cache;
+use lib_vminfo::error::{AuthErrorKind, Kind, VMInfoResult};
+use lib_vminfo::persistance::PersistantStorage;
+use lib_vminfo::Client;
+
+use lib_vminfo::query::{
+	regex_warning, tag_projection, Facet, MatchMode, QueryOptions, QueryResponse, SortOrder,
+	Subscription,
+};
+use lib_vminfo::vm::{ResourceId, VirtualMachine};
 
 fn prompt(message: &str, dest: &mut String, sensitive: bool) -> Result<()> {
 	use rpassword::prompt_password;
@@ -26,6 +34,29 @@ fn prompt(message: &str, dest: &mut String, sensitive: bool) -> Result<()> {
 	Ok(())
 }
 
+///
+/// Asks the user to confirm that the access token may be printed, unless VMINFO_ALLOW_PRINT_TOKEN=1 is set. The
+/// question is asked on stderr so that stdout only ever holds the token
+///
+pub fn confirm_print_token() -> Result<()> {
+	if std::env::var("VMINFO_ALLOW_PRINT_TOKEN").as_deref() == Ok("1") {
+		return Ok(());
+	}
+
+	eprint!(
+		"the access token is a live credential that grants access to Azure as you. print it? [y/N] "
+	);
+	let mut answer = String::new();
+	std::io::stdin().read_line(&mut answer)?;
+
+	match answer.trim().to_lowercase().as_str() {
+		"y" | "yes" => Ok(()),
+		_ => Err(anyhow!(
+			"not printing the access token (set VMINFO_ALLOW_PRINT_TOKEN=1 to skip this confirmation)"
+		)),
+	}
+}
+
 ///
 /// Will prompt the user for a set of credentials required to authenticate with Azure Resource Graph
 ///
@@ -51,41 +82,913 @@ pub fn ask_credentials(method: Method) -> Result<CliCredentials> {
 }
 
 ///
-/// Pulls all hosts that match the specified query from lib_vminfo.
+/// the number of times the user is prompted for credentials during login before giving up
 ///
-pub fn get_vminfo_from_remote(
-	client: &LocalClient,
-	args: &Cli,
-) -> anyhow::Result<Vec<VirtualMachine>> {
-	let resp: QueryResponse = client.query_vminfo(
-		&args.vm_operand,
+pub const LOGIN_ATTEMPTS: u8 = 3;
+
+///
+/// Prompts the user for credentials with `ask` until a set is entered which is well formed and accepted by `validate`,
+/// explaining what was wrong with each rejected set. Gives up with the last problem after `attempts` prompts
+///
+pub fn ask_valid_credentials<A, V>(
+	method: Method,
+	attempts: u8,
+	mut ask: A,
+	validate: V,
+) -> Result<CliCredentials>
+where
+	A: FnMut(Method) -> Result<CliCredentials>,
+	V: Fn(&CliCredentials) -> VMInfoResult<()>,
+{
+	let mut attempt: u8 = 1;
+	loop {
+		let creds = ask(method.clone())?;
+		let problem = match check_credentials(&creds, &validate) {
+			Ok(()) => return Ok(creds),
+			Err(problem) => problem,
+		};
+
+		if attempt >= attempts {
+			return Err(problem);
+		}
+		eprintln!(
+			"{:#}. please try again ({} of {} attempts)",
+			problem, attempt, attempts
+		);
+		attempt += 1;
+	}
+}
+
+///
+/// checks a set of credentials are well formed before validating them, describing the problem with any that are not
+///
+fn check_credentials<V>(creds: &CliCredentials, validate: &V) -> Result<()>
+where
+	V: Fn(&CliCredentials) -> VMInfoResult<()>,
+{
+	// tenants may also be identified by one of their verified domain names (ie: contoso.onmicrosoft.com)
+	if !auth::is_guid(&creds.tenant_id) && !creds.tenant_id.contains('.') {
+		return Err(anyhow!(
+			"tenant_id '{}' is not a valid GUID or domain name",
+			creds.tenant_id
+		));
+	}
+	if !auth::is_guid(&creds.client_id) {
+		return Err(anyhow!(
+			"client_id '{}' is not a valid GUID",
+			creds.client_id
+		));
+	}
+
+	validate(creds).map_err(|err| {
+		let message = match err.kind() {
+			Kind::AuthenticationError(AuthErrorKind::BadCredentials) => {
+				"the client_secret was not accepted, or the client_id does not exist in the tenant"
+			}
+			Kind::AuthenticationError(AuthErrorKind::AccessDenied) => {
+				"the client does not have permission to sign in to the tenant"
+			}
+			Kind::AuthenticationError(AuthErrorKind::BadRequest) => {
+				"the tenant_id was not recognised by Azure"
+			}
+			_ => "the credentials could not be validated",
+		};
+
+		anyhow::Error::from(err).context(message)
+	})
+}
+
+///
+/// Describes the flow '--login' would use with the provided arguments and what it would prompt for, without contacting
+/// Azure. The credentials currently stored for the profile (if any) are the ones a login would replace
+///
+pub fn describe_login(args: &Cli, stored: Option<&AzCredentials>, use_v2_endpoint: bool) -> String {
+	if args.azure_cli {
+		return "would use the access token of the account logged in to the Azure CLI ('az login'); nothing is prompted for or stored".to_string();
+	}
+
+	// the flags are checked in the same order as '--login' checks them
+	let (flow, prompts) = if let Some(certificate) = &args.certificate {
+		(
+			format!(
+				"ClientCertificate flow with the certificate '{}' (private key read from '{}')",
+				certificate,
+				args.certificate_key.as_deref().unwrap_or(certificate)
+			),
+			"will prompt for the tenant_id and client_id, then sign in with an assertion signed by the certificate",
+		)
+	} else if args.use_service_principal {
+		(
+			format!(
+				"ClientCredentials flow (v{} token endpoint)",
+				if use_v2_endpoint { "2.0" } else { "1.0" }
+			),
+			"will prompt for the tenant_id, client_id and client_secret, checking them with Azure before they are stored",
+		)
+	} else {
+		(
+			"DeviceCode flow".to_string(),
+			"will prompt for the tenant_id and client_id, then ask you to open a browser and enter a device code",
+		)
+	};
+
+	let mut description = format!("would use the {}; {}", flow, prompts);
+	if args.interactive_login && (args.certificate.is_some() || args.use_service_principal) {
+		description.push_str(". '--interactive' is ignored when signing in as a service principal");
+	}
+	match stored {
+		Some(creds) => description.push_str(
+			format!(
+				". replaces the credentials stored for tenant {} client {}",
+				creds.tenant_id, creds.client_id
+			)
+			.as_str(),
+		),
+		None => description.push_str(". no credentials are currently stored"),
+	}
+
+	description
+}
+
+///
+/// Builds the additional query options for lib_vminfo from the CLI arguments
+///
+pub fn query_options(args: &Cli) -> QueryOptions {
+	QueryOptions {
+		preserve_case: args.preserve_case,
+		os_filter: args.os_filter.clone(),
+		fields: args.fields.clone(),
+		show_encryption: args.show_encryption,
+		show_capabilities: args.show_capabilities,
+		show_disks: args.show_disks,
+		show_maintenance: args.show_maintenance,
+		show_placement: args.show_placement,
+		show_disk_perf: args.show_disk_perf,
+		show_sku_details: args.show_sku_details,
+		extra_projections: args.tag_columns.iter().map(|t| tag_projection(t)).collect(),
+		power_state: args.power_state,
+		sort: args.sort.map(|by| {
+			let order = if args.sort_desc {
+				SortOrder::Desc
+			} else {
+				SortOrder::Asc
+			};
+			(by, order)
+		}),
+		facets: args.facets.clone(),
+		match_mode: match args.contains {
+			Some(_) => MatchMode::Contains,
+			None => MatchMode::Exact,
+		},
+		..Default::default()
+	}
+}
+
+///
+/// Parses a human readable age (ie: 45m, 12h, 30d, 2w, 1y) where a year is 365 days
+///
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+	let value = value.trim();
+	let digits = value
+		.find(|c: char| !c.is_ascii_digit())
+		.unwrap_or(value.len());
+	let (count, unit) = value.split_at(digits);
+	let count: u64 = count
+		.parse()
+		.map_err(|_| format!("'{}' does not start with a whole number (ie: 30d)", value))?;
+
+	let unit_secs: u64 = match unit {
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 24 * 60 * 60,
+		"w" => 7 * 24 * 60 * 60,
+		"y" => 365 * 24 * 60 * 60,
+		_ => {
+			return Err(format!(
+				"'{}' has an unknown unit, expected one of m, h, d, w or y (ie: 30d)",
+				value
+			))
+		}
+	};
+
+	count
+		.checked_mul(unit_secs)
+		.map(Duration::from_secs)
+		.ok_or_else(|| format!("'{}' is too long", value))
+}
+
+///
+/// Gets the query operand from the CLI arguments (either VM name(s) / regular expression(s), which may be read from a
+/// file, or a substring)
+///
+fn query_operand(args: &Cli) -> Result<Vec<String>> {
+	if let Some(path) = &args.from_file {
+		return read_operands(path);
+	}
+	if let Some(path) = &args.ids_from_file {
+		return read_resource_ids(path);
+	}
+
+	Ok(match &args.contains {
+		Some(substring) => vec![substring.clone()],
+		None => args.vm_operand.clone(),
+	})
+}
+
+///
+/// Runs the regular expression guardrail against each pattern, returning the warnings. With `strict`, the first
+/// warning is returned as an error instead
+///
+fn check_regex_patterns(patterns: &[String], strict: bool) -> Result<Vec<String>> {
+	let warnings: Vec<String> = patterns.iter().filter_map(|p| regex_warning(p)).collect();
+
+	match warnings.first() {
+		Some(warning) if strict => Err(anyhow!("{} (refused by '--strict-regex')", warning)),
+		_ => Ok(warnings),
+	}
+}
+
+///
+/// Reads one VM name (or regular expression) from each line of a file, skipping blank lines and '#' comments
+///
+fn read_operands(path: &str) -> Result<Vec<String>> {
+	let operands: Vec<String> = std::fs::read_to_string(path)
+		.map_err(|err| anyhow!("could not read '{}': {}", path, err))?
+		.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.map(String::from)
+		.collect();
+
+	if operands.is_empty() {
+		return Err(anyhow!(
+			"'{}' does not contain any VM names or patterns",
+			path
+		));
+	}
+
+	Ok(operands)
+}
+
+///
+/// Reads VM resource IDs from a file holding either a JSON array of IDs or one ID per line (skipping blank lines and
+/// '#' comments). Every ID must be the full resource ID of a virtual machine
+///
+fn read_resource_ids(path: &str) -> Result<Vec<String>> {
+	let contents =
+		std::fs::read_to_string(path).map_err(|err| anyhow!("could not read '{}': {}", path, err))?;
+
+	let ids: Vec<String> = if contents.trim_start().starts_with('[') {
+		serde_json::from_str::<Vec<String>>(&contents)
+			.map_err(|err| anyhow!("'{}' is not a JSON array of resource IDs: {}", path, err))?
+			.into_iter()
+			.map(|id| id.trim().to_string())
+			.collect()
+	} else {
+		contents
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+			.map(String::from)
+			.collect()
+	};
+
+	if ids.is_empty() {
+		return Err(anyhow!("'{}' does not contain any resource IDs", path));
+	}
+	for id in &ids {
+		let is_vm = id.parse::<ResourceId>().is_ok_and(|r| {
+			r.provider.eq_ignore_ascii_case("Microsoft.Compute")
+				&& r.resource_type.eq_ignore_ascii_case("virtualMachines")
+		});
+		if !is_vm {
+			return Err(anyhow!(
+				"'{}' in '{}' is not the resource ID of a virtual machine (ie: /subscriptions/<id>/resourceGroups/<group>/providers/Microsoft.Compute/virtualMachines/<name>)",
+				id,
+				path
+			));
+		}
+	}
+
+	Ok(ids)
+}
+
+///
+/// Describes how the results of a query were assembled
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuerySummary {
+	pub matched: u64,
+	pub returned: usize,
+	pub pages: u64,
+	pub from_cache: u64,
+	pub facets: Vec<Facet>,
+	pub more_pages: bool,
+	pub failures: Vec<String>,
+}
+
+impl QuerySummary {
+	///
+	/// Describes whether every matched record was fetched, or whether more pages are available
+	///
+	pub fn pagination(&self) -> String {
+		if self.more_pages {
+			return format!(
+				"WARNING: results truncated, more pages available ({} of {} records fetched). use --all to fetch every page",
+				self.returned, self.matched
+			);
+		}
+
+		format!(
+			"fetched {} page{}, {} of {} records, no more pages",
+			self.pages,
+			if self.pages == 1 { "" } else { "s" },
+			self.returned,
+			self.matched
+		)
+	}
+}
+
+impl std::fmt::Display for QuerySummary {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"matched {}, returned {} across {} page{}, {} from cache",
+			self.matched,
+			self.returned,
+			self.pages,
+			if self.pages == 1 { "" } else { "s" },
+			self.from_cache
+		)?;
+
+		for facet in &self.facets {
+			let counts: Vec<String> = facet
+				.counts()
+				.iter()
+				.map(|(value, count)| format!("{}={}", value, count))
+				.collect();
+			write!(f, "\n{}: {}", facet.expression, counts.join(", "))?;
+		}
+
+		Ok(())
+	}
+}
+
+///
+/// Builds a curl command which sends the Resource Graph request for the first page of the specified query
+///
+pub fn curl_command<PS, RC>(client: &Client<PS, RC>, args: &Cli) -> Result<String>
+where
+	PS: PersistantStorage<AzCredentials>,
+	RC: Cache<VirtualMachine> + Clone,
+{
+	Ok(client.curl_command(
+		&query_operand(args)?,
 		args.match_regexp,
 		args.show_extensions,
 		args.show_tags,
-		args.no_cache,
-		None,
-		None,
-	)?;
-
-	let mut vminfo: Vec<VirtualMachine> = resp.data.clone();
-
-	let page_count: u64 = resp.total_results / 1000;
-	if page_count > 1 {
-		for page in 1..=page_count {
-			let skip_count: u64 = page * 1000;
-			let rnext: QueryResponse = client.query_vminfo(
-				&args.vm_operand,
+		args.skip,
+		args.top,
+	)?)
+}
+
+///
+/// The configured subscriptions which are not among the subscriptions visible to the signed in identity
+///
+pub fn inaccessible_subscriptions(configured: &[String], visible: &[Subscription]) -> Vec<String> {
+	configured
+		.iter()
+		.filter(|id| {
+			!visible
+				.iter()
+				.any(|s| s.subscription_id.eq_ignore_ascii_case(id))
+		})
+		.cloned()
+		.collect()
+}
+
+///
+/// Pulls the hosts that match the specified query from lib_vminfo. Only the first page of results (sized by '--top' and
+/// starting after '--skip') is fetched unless '--all' was provided, in which case every page is fetched
+///
+pub fn get_vminfo_from_remote<PS, RC>(
+	client: &Client<PS, RC>,
+	args: &Cli,
+) -> anyhow::Result<(Vec<VirtualMachine>, QuerySummary)>
+where
+	PS: PersistantStorage<AzCredentials> + Sync,
+	RC: Cache<VirtualMachine> + Clone + Sync,
+{
+	let query_operand: Vec<String> = query_operand(args)?;
+	if args.match_regexp {
+		for warning in check_regex_patterns(&query_operand, args.strict_regex)? {
+			eprintln!("WARNING: {}", warning);
+		}
+	}
+	let mut failures: Vec<String> = Vec::new();
+	let resp: QueryResponse = if args.fan_out {
+		let fan_out = client.query_vminfo_fan_out(
+			&query_operand,
+			args.match_regexp,
+			args.show_extensions,
+			args.show_tags,
+			args.concurrency,
+		)?;
+		failures = fan_out
+			.failures
+			.iter()
+			.map(|(subscription, err)| format!("{}: {}", subscription, err))
+			.collect();
+
+		fan_out.response
+	} else if args.batch_delay_ms.is_some() || args.ids_from_file.is_some() {
+		// resource IDs read from a file are queried in batches, without a delay unless one was requested
+		client.query_vminfo_batch(
+			&query_operand,
+			args.show_extensions,
+			args.show_tags,
+			args.no_cache,
+			args.batch_size,
+			Duration::from_millis(args.batch_delay_ms.unwrap_or(0)),
+		)?
+	} else if args.all {
+		client.query_vminfo_all(
+			&query_operand,
+			args.match_regexp,
+			args.show_extensions,
+			args.show_tags,
+			args.no_cache,
+		)?
+	} else {
+		QueryResponse {
+			pages: 1,
+			..client.query_vminfo(
+				&query_operand,
 				args.match_regexp,
 				args.show_extensions,
 				args.show_tags,
 				args.no_cache,
-				Some(skip_count),
-				None,
-			)?;
+				args.skip,
+				args.top,
+			)?
+		}
+	};
+
+	let mut summary = QuerySummary {
+		matched: resp.total_results,
+		pages: resp.pages,
+		from_cache: resp.cache_hits,
+		facets: resp.facets.clone(),
+		more_pages: resp.truncated || resp.skip_token.is_some(),
+		failures,
+		..Default::default()
+	};
+	let mut vminfo: Vec<VirtualMachine> = resp.data;
+
+	// the age of a VM is relative to the current time, which is awkward to compare in KQL, so it is filtered here
+	if args.older_than.is_some() || args.newer_than.is_some() {
+		vminfo.retain(|vm| match vm.age() {
+			Some(age) => {
+				args.older_than.iter().all(|min| age > *min) && args.newer_than.iter().all(|max| age < *max)
+			}
+			None => false,
+		});
+	}
+
+	summary.returned = vminfo.len();
+	Ok((vminfo, summary))
+}
+
+#[cfg(test)]
+mod util_tests {
+	use std::collections::VecDeque;
+	use std::fmt::Display;
+	use std::sync::{Arc, Mutex};
+
+	use lib_vminfo::auth::AzCredentials;
+	use lib_vminfo::caching::redis_cache::VMResultsCacheRedis;
+	use lib_vminfo::error::{self, AuthErrorKind, Error, VMInfoResult};
+	use lib_vminfo::persistance::PersistantStorage;
+	use lib_vminfo::transport::{Transport, TransportResponse};
+	use lib_vminfo::{AuthTokens, Client};
+
+	use crate::cli::Cli;
+	use crate::credentials::CliCredentials;
+
+	///
+	/// replays queued Resource Graph response bodies in order, recording each request body
+	///
+	#[derive(Debug, Default)]
+	struct ScriptedTransport {
+		responses: Mutex<VecDeque<String>>,
+		requests: Arc<Mutex<Vec<String>>>,
+	}
+
+	impl Transport for ScriptedTransport {
+		fn post_json(
+			&self,
+			_url: &str,
+			_access_token: &str,
+			_headers: &[(String, String)],
+			body: &str,
+		) -> VMInfoResult<TransportResponse> {
+			self.requests.lock().unwrap().push(body.to_string());
+
+			match self.responses.lock().unwrap().pop_front() {
+				Some(body) => Ok(TransportResponse {
+					status: 200u16.try_into().unwrap(),
+					body,
+					retry_after: None,
+				}),
+				None => Err(error::other(None::<Error>, "no responses left")),
+			}
+		}
+	}
+
+	#[derive(Debug, Clone)]
+	struct StaticTokenStore;
+
+	impl PersistantStorage<AzCredentials> for StaticTokenStore {
+		fn write(&self, _data: &AzCredentials) -> VMInfoResult<()> {
+			Ok(())
+		}
+
+		fn read(&self) -> VMInfoResult<AzCredentials> {
+			Ok(AzCredentials {
+				tenant_id: "tenant".to_string(),
+				client_id: "client".to_string(),
+				client_secret: None,
+				certificate: None,
+				method: None,
+				tokens: AuthTokens {
+					access_token: "token".to_string(),
+					refresh_token: None,
+					expires_at: None,
+				},
+			})
+		}
+
+		fn clear(&self) -> VMInfoResult<()> {
+			Err(error::auth(
+				None::<Error>,
+				AuthErrorKind::MissingToken,
+				"static credentials can not be cleared",
+			))
+		}
+	}
 
-			vminfo.extend(rnext.data.into_iter());
+	impl Display for StaticTokenStore {
+		fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+			write!(f, "static token store")
 		}
 	}
 
-	Ok(vminfo)
+	/// a page of 'count' VMs (starting at 'first') out of 1500 matches, with an optional skip token
+	fn page(first: usize, count: usize, skip_token: Option<&str>) -> String {
+		let vms: Vec<serde_json::Value> = (first..first + count)
+			.map(|i| serde_json::json!({ "vmId": format!("/vm/linux-{}", i), "vmName": format!("linux-{}", i), "privateIp": "10.0.0.4" }))
+			.collect();
+		let mut body = serde_json::json!({ "totalRecords": 1500, "count": count, "data": vms });
+		if let Some(t) = skip_token {
+			body["$skipToken"] = serde_json::json!(t);
+		}
+
+		body.to_string()
+	}
+
+	fn client(
+		responses: Vec<String>,
+	) -> (
+		Client<StaticTokenStore, VMResultsCacheRedis>,
+		Arc<Mutex<Vec<String>>>,
+	) {
+		let transport = ScriptedTransport {
+			responses: Mutex::new(responses.into_iter().collect()),
+			..Default::default()
+		};
+		let requests = transport.requests.clone();
+
+		let client = Client::from_components("tenant", "client", None, StaticTokenStore, None, None)
+			.with_transport(transport)
+			.load_credentials()
+			.unwrap();
+
+		(client, requests)
+	}
+
+	fn args(all: bool) -> Cli {
+		Cli {
+			vm_operand: vec!["linux-.*".to_string()],
+			match_regexp: true,
+			no_cache: true,
+			all,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn all_follows_skip_tokens_to_completion() {
+		use super::get_vminfo_from_remote;
+
+		let (client, requests) = client(vec![page(0, 1000, Some("page-2")), page(1000, 500, None)]);
+		let (vms, summary) = get_vminfo_from_remote(&client, &args(true)).unwrap();
+
+		assert_eq!(vms.len(), 1500);
+		assert_eq!(summary.pages, 2);
+		assert!(!summary.more_pages);
+		assert!(requests.lock().unwrap()[1].contains("\"$skipToken\":\"page-2\""));
+	}
+
+	#[test]
+	fn first_page_only_without_all() {
+		use super::get_vminfo_from_remote;
+
+		let (client, requests) = client(vec![page(0, 1000, Some("page-2")), page(1000, 500, None)]);
+		let (vms, summary) = get_vminfo_from_remote(&client, &args(false)).unwrap();
+
+		assert_eq!(vms.len(), 1000);
+		assert_eq!(summary.pages, 1);
+		assert!(summary.more_pages);
+		assert!(summary
+			.pagination()
+			.starts_with("WARNING: results truncated"));
+		assert_eq!(requests.lock().unwrap().len(), 1);
+	}
+
+	#[test]
+	fn regex_patterns_are_read_from_file() {
+		use super::get_vminfo_from_remote;
+
+		let file = tempfile::NamedTempFile::new().unwrap();
+		std::fs::write(
+			file.path(),
+			"# web tier\n^web-[0-9]+$\n\n  ^api-.*  \n# databases\n^db-(primary|replica)$\n",
+		)
+		.unwrap();
+
+		let (client, requests) = client(vec![page(0, 3, None)]);
+		let args = Cli {
+			vm_operand: vec![],
+			from_file: Some(file.path().to_str().unwrap().to_string()),
+			..args(false)
+		};
+		get_vminfo_from_remote(&client, &args).unwrap();
+
+		let requests = requests.lock().unwrap();
+		for pattern in ["^web-[0-9]+$", "^api-.*", "^db-(primary|replica)$"] {
+			assert!(requests[0].contains(&format!("matches regex '{}'", pattern)));
+		}
+		assert!(!requests[0].contains("web tier"));
+	}
+
+	#[test]
+	fn top_and_skip_fetch_a_single_page() {
+		use clap::Parser;
+
+		use super::get_vminfo_from_remote;
+
+		let (client, requests) = client(vec![page(10, 5, Some("next"))]);
+		let args = Cli {
+			top: Some(5),
+			skip: Some(10),
+			..args(false)
+		};
+		let (vms, summary) = get_vminfo_from_remote(&client, &args).unwrap();
+		assert_eq!(vms.len(), 5);
+		assert!(summary.more_pages);
+
+		let requests = requests.lock().unwrap();
+		assert_eq!(requests.len(), 1);
+		assert!(requests[0].contains(r#""$top":5"#));
+		assert!(requests[0].contains(r#""$skip":10"#));
+
+		// a page is at most 1000 VMs, and --top can not be combined with --all
+		assert!(Cli::try_parse_from(["vminfo", "linux-01", "--top", "1001"]).is_err());
+		assert!(Cli::try_parse_from(["vminfo", "linux-01", "--top", "5", "--all"]).is_err());
+		assert!(Cli::try_parse_from(["vminfo", "linux-01", "--top", "1000", "--skip", "5"]).is_ok());
+	}
+
+	#[test]
+	fn resource_ids_are_read_from_file_in_batches() {
+		use super::get_vminfo_from_remote;
+
+		let id = |name: &str| {
+			format!(
+				"/subscriptions/0000/resourceGroups/prod-rg/providers/Microsoft.Compute/virtualMachines/{}",
+				name
+			)
+		};
+		let file = tempfile::NamedTempFile::new().unwrap();
+		std::fs::write(
+			file.path(),
+			format!(
+				"# incident 42\n{}\n\n{}\n{}\n",
+				id("web-01"),
+				id("web-02"),
+				id("db-01")
+			),
+		)
+		.unwrap();
+
+		let (client, requests) = client(vec![page(0, 2, None), page(2, 1, None)]);
+		let args = Cli {
+			vm_operand: vec![],
+			match_regexp: false,
+			ids_from_file: Some(file.path().to_str().unwrap().to_string()),
+			batch_size: 2,
+			..args(false)
+		};
+		let (vms, _) = get_vminfo_from_remote(&client, &args).unwrap();
+		assert_eq!(vms.len(), 3);
+
+		let requests = requests.lock().unwrap();
+		assert_eq!(requests.len(), 2);
+		assert!(requests[0].contains("| where id in~ ("));
+		assert!(requests[0].contains("/virtualmachines/web-02"));
+		assert!(!requests[0].contains("db-01"));
+		assert!(requests[1].contains("/virtualmachines/db-01"));
+
+		// a JSON array is also accepted, but every entry must be a VM resource ID
+		std::fs::write(file.path(), format!(r#"["{}", "web-03"]"#, id("web-01"))).unwrap();
+		let err = get_vminfo_from_remote(&client, &args).unwrap_err();
+		assert!(err.to_string().contains("'web-03' in"));
+	}
+
+	#[test]
+	fn strict_regex_refuses_broad_pattern() {
+		use super::get_vminfo_from_remote;
+
+		let (client, requests) = client(vec![page(0, 3, None)]);
+		let broad = Cli {
+			vm_operand: vec![".*".to_string()],
+			..args(false)
+		};
+		// without '--strict-regex' the query is only warned about
+		get_vminfo_from_remote(&client, &broad).unwrap();
+		assert_eq!(requests.lock().unwrap().len(), 1);
+
+		let strict = Cli {
+			strict_regex: true,
+			..broad
+		};
+		let err = get_vminfo_from_remote(&client, &strict).unwrap_err();
+		assert!(err.to_string().contains("matches every VM name"));
+		assert_eq!(requests.lock().unwrap().len(), 1);
+	}
+
+	#[test]
+	fn age_durations_are_parsed() {
+		use super::parse_age;
+		use std::time::Duration;
+
+		assert_eq!(parse_age("45m"), Ok(Duration::from_secs(45 * 60)));
+		assert_eq!(parse_age("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+		assert_eq!(parse_age("30d"), Ok(Duration::from_secs(30 * 24 * 60 * 60)));
+		assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+		assert_eq!(parse_age("1y"), Ok(Duration::from_secs(365 * 24 * 60 * 60)));
+
+		assert!(parse_age("d").is_err());
+		assert!(parse_age("30").is_err());
+		assert!(parse_age("1.5d").is_err());
+		assert!(parse_age("30 days").is_err());
+		assert!(parse_age("99999999999999999y").is_err());
+	}
+
+	#[test]
+	fn login_reprompts_after_rejected_credentials() {
+		use super::ask_valid_credentials;
+		use lib_vminfo::auth::Method;
+
+		let tenant_id = "72f988bf-86f1-41af-91ab-2d7cd011db47";
+		let client_id = "04b07795-8ddb-461a-bbee-02f9e1bf7b46";
+		let mut entered = vec![
+			CliCredentials::new(
+				tenant_id.to_string(),
+				client_id.to_string(),
+				Some("bad-secret".to_string()),
+			),
+			CliCredentials::new(
+				tenant_id.to_string(),
+				client_id.to_string(),
+				Some("good-secret".to_string()),
+			),
+		]
+		.into_iter();
+		let validated = Mutex::new(Vec::new());
+
+		let creds = ask_valid_credentials(
+			Method::ClientCredentials,
+			3,
+			|_| Ok(entered.next().unwrap()),
+			|c| {
+				let secret = c.client_secret.clone().unwrap();
+				validated.lock().unwrap().push(secret.clone());
+				match secret.as_str() {
+					"good-secret" => Ok(()),
+					_ => Err(error::auth(
+						None::<Error>,
+						AuthErrorKind::BadCredentials,
+						"AADSTS7000215: Invalid client secret provided",
+					)),
+				}
+			},
+		)
+		.unwrap();
+
+		assert_eq!(creds.client_secret.as_deref(), Some("good-secret"));
+		assert_eq!(
+			*validated.lock().unwrap(),
+			vec!["bad-secret", "good-secret"]
+		);
+	}
+
+	#[test]
+	fn dry_run_auth_describes_each_flow() {
+		use super::describe_login;
+		use lib_vminfo::AuthTokens;
+
+		let stored = AzCredentials {
+			tenant_id: "my-tenant".to_string(),
+			client_id: "my-client".to_string(),
+			client_secret: None,
+			certificate: None,
+			method: None,
+			tokens: AuthTokens {
+				access_token: "token".to_string(),
+				refresh_token: None,
+				expires_at: None,
+			},
+		};
+
+		let device_code = describe_login(&Cli::default(), None, false);
+		assert!(device_code.starts_with("would use the DeviceCode flow;"));
+		assert!(device_code.contains("open a browser"));
+		assert!(device_code.ends_with("no credentials are currently stored"));
+
+		let interactive = Cli {
+			interactive_login: true,
+			..Default::default()
+		};
+		let interactive = describe_login(&interactive, Some(&stored), false);
+		assert!(interactive.starts_with("would use the DeviceCode flow;"));
+		assert!(interactive
+			.ends_with("replaces the credentials stored for tenant my-tenant client my-client"));
+
+		let service_principal = Cli {
+			use_service_principal: true,
+			interactive_login: true,
+			..Default::default()
+		};
+		let service_principal = describe_login(&service_principal, None, true);
+		assert!(
+			service_principal.starts_with("would use the ClientCredentials flow (v2.0 token endpoint);")
+		);
+		assert!(service_principal.contains("client_secret"));
+		assert!(service_principal.contains("'--interactive' is ignored"));
+
+		let certificate = Cli {
+			use_service_principal: true,
+			certificate: Some("app.pem".to_string()),
+			..Default::default()
+		};
+		let certificate = describe_login(&certificate, None, false);
+		assert!(certificate.starts_with(
+			"would use the ClientCertificate flow with the certificate 'app.pem' (private key read from 'app.pem');"
+		));
+		assert!(!certificate.contains("client_secret"));
+
+		let azure_cli = Cli {
+			azure_cli: true,
+			..Default::default()
+		};
+		assert!(describe_login(&azure_cli, Some(&stored), false).contains("Azure CLI"));
+	}
+
+	#[test]
+	fn login_gives_up_after_attempts() {
+		use super::ask_valid_credentials;
+		use lib_vminfo::auth::Method;
+
+		let mut prompts = 0;
+		let err = ask_valid_credentials(
+			Method::DeviceCode,
+			2,
+			|_| {
+				prompts += 1;
+				Ok(CliCredentials::new(
+					"my-tenant".to_string(),
+					"not-a-guid".to_string(),
+					None,
+				))
+			},
+			|_| Ok(()),
+		)
+		.unwrap_err();
+
+		// malformed credentials are never sent to Azure
+		assert_eq!(prompts, 2);
+		assert!(err
+			.to_string()
+			.contains("tenant_id 'my-tenant' is not a valid GUID"));
+	}
 }
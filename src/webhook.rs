@@ -0,0 +1,270 @@
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use lib_vminfo::transport;
+use reqwest::blocking::Client;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::tls::Version;
+
+///
+/// the number of times a webhook delivery is retried after a connection failure or a retryable (5xx or 429) response
+///
+pub const WEBHOOK_RETRIES: u8 = 2;
+
+///
+/// the time waited before the first retry of a webhook delivery, doubled before each further retry
+///
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+///
+/// the time allowed for each webhook delivery attempt
+///
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+///
+/// POSTs serialized results to a webhook with the Content-Type of their format (ie: application/json), using
+/// `authorization` as the Authorization header when provided. connections require at least `min_tls_version`
+///
+/// deliveries that fail to connect or are rejected with a 5xx or 429 response are retried up to WEBHOOK_RETRIES
+/// times. any other non-2xx response fails immediately
+///
+pub fn post_results(
+	url: &str,
+	authorization: Option<&str>,
+	content_type: &str,
+	body: &str,
+	min_tls_version: Version,
+) -> Result<()> {
+	let http_client = transport::http_client_builder(min_tls_version)
+		.timeout(REQUEST_TIMEOUT)
+		.build()?;
+
+	post_with_retry(
+		&http_client,
+		url,
+		authorization,
+		content_type,
+		body,
+		RETRY_DELAY,
+	)
+}
+
+fn post_with_retry(
+	http_client: &Client,
+	url: &str,
+	authorization: Option<&str>,
+	content_type: &str,
+	body: &str,
+	delay: Duration,
+) -> Result<()> {
+	let mut attempt: u8 = 0;
+	loop {
+		let mut req = http_client
+			.post(url)
+			.header(CONTENT_TYPE, content_type)
+			.body(body.to_string());
+		if let Some(authorization) = authorization {
+			req = req.header(AUTHORIZATION, authorization);
+		}
+
+		let err = match req.send() {
+			Ok(resp) if resp.status().is_success() => return Ok(()),
+			Ok(resp) => {
+				let status = resp.status();
+				let err = anyhow!(
+					"webhook {} rejected the results with {}: {}",
+					url,
+					status,
+					resp.text().unwrap_or_default().trim()
+				);
+				if !(status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS) {
+					return Err(err);
+				}
+				err
+			}
+			Err(err) => {
+				anyhow::Error::from(err).context(format!("could not deliver results to webhook {}", url))
+			}
+		};
+
+		if attempt >= WEBHOOK_RETRIES {
+			return Err(err.context(format!(
+				"webhook delivery failed after {} attempts",
+				attempt + 1
+			)));
+		}
+		thread::sleep(delay * 2u32.pow(attempt as u32));
+		attempt += 1;
+	}
+}
+
+#[cfg(test)]
+mod webhook_tests {
+	use std::io::{BufRead, BufReader, Read, Write};
+	use std::net::TcpListener;
+	use std::thread;
+	use std::time::Duration;
+
+	///
+	/// a request received by the mock server (lowercased request and header lines, and the body)
+	///
+	type Received = (Vec<String>, String);
+
+	///
+	/// a minimal HTTP server which answers each request with the next queued status, returning the received requests
+	///
+	fn mock_server(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<Received>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/inventory", listener.local_addr().unwrap());
+
+		let handle = thread::spawn(move || {
+			let mut received = Vec::new();
+			for status in statuses {
+				let (stream, _) = listener.accept().unwrap();
+				let mut reader = BufReader::new(stream);
+
+				let mut headers: Vec<String> = Vec::new();
+				loop {
+					let mut line = String::new();
+					reader.read_line(&mut line).unwrap();
+					if line.trim().is_empty() {
+						break;
+					}
+					headers.push(line.trim().to_lowercase());
+				}
+				let length: usize = headers
+					.iter()
+					.find_map(|h| h.strip_prefix("content-length:"))
+					.map(|l| l.trim().parse().unwrap())
+					.unwrap_or(0);
+				let mut body = vec![0u8; length];
+				reader.read_exact(&mut body).unwrap();
+				received.push((headers, String::from_utf8(body).unwrap()));
+
+				write!(
+					reader.get_mut(),
+					"HTTP/1.1 {} Status\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+					status
+				)
+				.unwrap();
+			}
+			received
+		});
+
+		(url, handle)
+	}
+
+	#[test]
+	fn results_are_posted() {
+		use super::post_with_retry;
+		use reqwest::blocking::Client;
+
+		let (url, server) = mock_server(vec![200]);
+		let payload = serde_json::json!([{ "vmId": "/vm/web-01", "vmName": "web-01" }]).to_string();
+
+		post_with_retry(
+			&Client::new(),
+			&url,
+			Some("Bearer abc123"),
+			"application/json",
+			&payload,
+			Duration::ZERO,
+		)
+		.unwrap();
+
+		let received = server.join().unwrap();
+		assert_eq!(received.len(), 1);
+		let (headers, body) = &received[0];
+		assert!(headers.iter().any(|h| h.starts_with("post /inventory ")));
+		assert!(headers.contains(&"authorization: bearer abc123".to_string()));
+		assert!(headers.contains(&"content-type: application/json".to_string()));
+		assert_eq!(
+			serde_json::from_str::<serde_json::Value>(body).unwrap(),
+			serde_json::json!([{ "vmId": "/vm/web-01", "vmName": "web-01" }])
+		);
+	}
+
+	#[test]
+	fn content_type_follows_the_format() {
+		use super::post_results;
+		use lib_vminfo::transport::DEFAULT_MIN_TLS_VERSION;
+
+		let (url, server) = mock_server(vec![200]);
+
+		post_results(
+			&url,
+			None,
+			"text/csv; charset=utf-8",
+			"vmName\nweb-01",
+			DEFAULT_MIN_TLS_VERSION,
+		)
+		.unwrap();
+
+		let (headers, body) = &server.join().unwrap()[0];
+		assert!(headers.contains(&"content-type: text/csv; charset=utf-8".to_string()));
+		assert_eq!(body, "vmName\nweb-01");
+	}
+
+	#[test]
+	fn server_errors_are_retried() {
+		use super::post_with_retry;
+		use reqwest::blocking::Client;
+
+		let (url, server) = mock_server(vec![503, 200]);
+
+		post_with_retry(
+			&Client::new(),
+			&url,
+			None,
+			"application/json",
+			"[]",
+			Duration::ZERO,
+		)
+		.unwrap();
+
+		assert_eq!(server.join().unwrap().len(), 2);
+	}
+
+	#[test]
+	fn client_errors_fail_without_retry() {
+		use super::post_with_retry;
+		use reqwest::blocking::Client;
+
+		let (url, server) = mock_server(vec![401]);
+
+		let err = post_with_retry(
+			&Client::new(),
+			&url,
+			None,
+			"application/json",
+			"[]",
+			Duration::ZERO,
+		)
+		.unwrap_err();
+
+		assert!(err.to_string().contains("401"));
+		assert_eq!(server.join().unwrap().len(), 1);
+	}
+
+	#[test]
+	fn retries_are_bounded() {
+		use super::{post_with_retry, WEBHOOK_RETRIES};
+		use reqwest::blocking::Client;
+
+		let (url, server) = mock_server(vec![500; WEBHOOK_RETRIES as usize + 1]);
+
+		let err = post_with_retry(
+			&Client::new(),
+			&url,
+			None,
+			"application/json",
+			"[]",
+			Duration::ZERO,
+		)
+		.unwrap_err();
+
+		assert!(format!("{:#}", err).contains("500"));
+		assert_eq!(server.join().unwrap().len(), WEBHOOK_RETRIES as usize + 1);
+	}
+}